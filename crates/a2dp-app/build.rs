@@ -7,13 +7,14 @@ use std::path::PathBuf;
 
 fn main() {
     // Put `memory.x` in our output directory and ensure it's
-    // on the linker search path.
+    // on the linker search path. Only for our own binary: the boot
+    // stage links against this crate with a `memory.x` of its own.
     let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
         .write_all(include_bytes!("../../memory.x"))
         .unwrap();
-    println!("cargo:rustc-link-search={}", out.display());
+    println!("cargo:rustc-link-arg-bins=-L{}", out.display());
 
     // Re-run when memory.x changes.
     println!("cargo:rerun-if-changed=../../memory.x");
@@ -1,25 +1,60 @@
 //! Application configuration
 
+use audio_pipeline::{AudioFormat, EqPreset, GeneratorConfig, SilenceConfig};
+use heapless::String;
+
+use crate::volume::VolumeRoute;
+
+/// Longest Bluetooth device name (HCI local name, without terminator)
+pub const MAX_DEVICE_NAME: usize = 247;
+
+/// Where the streamed audio comes from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioSource {
+    /// PCM received from the USB host
+    Usb,
+    /// Built-in test signal generator, for lab use without a host
+    Generator(GeneratorConfig),
+}
+
 /// Application configuration
 #[derive(Debug, Clone)]
 pub struct AppConfig {
     /// Bluetooth device name
-    pub device_name: &'static str,
+    pub device_name: String<MAX_DEVICE_NAME>,
     /// Default SBC bitpool value
     pub default_bitpool: u8,
     /// Auto-reconnect on disconnect
     pub auto_reconnect: bool,
     /// Audio buffer size in milliseconds
     pub audio_buffer_ms: u32,
+    /// Equalizer preset applied before SBC encoding
+    pub eq_preset: EqPreset,
+    /// Audio input feeding the encoder
+    pub audio_source: AudioSource,
+    /// Suspend the stream while the audio is silent (`None` = always stream)
+    pub auto_suspend: Option<SilenceConfig>,
+    /// Play audio prompts on connection and mode changes
+    pub prompts_enabled: bool,
+    /// Main stream attenuation while a prompt plays, in tenths of a dB
+    pub prompt_duck_db_x10: i16,
+    /// Where the USB host's volume control is applied
+    pub volume_route: VolumeRoute,
 }
 
 impl Default for AppConfig {
     fn default() -> Self {
         Self {
-            device_name: "Pico A2DP Audio",
+            device_name: String::try_from("Pico A2DP Audio").unwrap(),
             default_bitpool: 53,
             auto_reconnect: true,
             audio_buffer_ms: 100,
+            eq_preset: EqPreset::flat(),
+            audio_source: AudioSource::Usb,
+            auto_suspend: None,
+            prompts_enabled: true,
+            prompt_duck_db_x10: -120,
+            volume_route: VolumeRoute::Pipeline,
         }
     }
 }
@@ -39,6 +74,30 @@ impl AppConfig {
             return Err("Audio buffer must be between 20 and 500 ms");
         }
 
+        if self
+            .eq_preset
+            .validate(AudioFormat::default().sample_rate)
+            .is_err()
+        {
+            return Err("Invalid equalizer preset");
+        }
+
+        if self.prompt_duck_db_x10 > 0 {
+            return Err("Prompt ducking must not boost the stream");
+        }
+
+        if let Some(silence) = self.auto_suspend {
+            if silence.validate().is_err() {
+                return Err("Invalid silence detector settings");
+            }
+        }
+
+        if let AudioSource::Generator(generator) = self.audio_source {
+            if generator.validate(AudioFormat::default()).is_err() {
+                return Err("Invalid test signal");
+            }
+        }
+
         Ok(())
     }
 }
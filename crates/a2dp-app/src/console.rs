@@ -0,0 +1,507 @@
+//! Serial command console
+//!
+//! A line-based shell on the USB CDC-ACM port for inspecting and changing
+//! [`AppConfig`] at runtime. [`Console`] edits and echoes the input, parses
+//! each line into a [`Command`] and writes the reply; whatever the
+//! Bluetooth side has to do about it comes back as a [`ConsoleAction`].
+//! Nothing here touches USB, so the shell runs on the host in tests.
+
+use core::fmt::{self, Write};
+
+use bt_classic::a2dp::A2dpState;
+use bt_classic::BdAddr;
+use heapless::{String, Vec};
+use usb_audio::AudioStats;
+
+use crate::config::AppConfig;
+use crate::latency::LatencyBreakdown;
+use crate::state_machine::Event;
+
+/// Longest command line
+pub const MAX_LINE: usize = 96;
+
+const PROMPT: &str = "> ";
+
+const HELP: [&str; 10] = [
+    "help                this list",
+    "status              connection and configuration",
+    "scan                search for headphones",
+    "pair                become discoverable",
+    "connect <addr>      connect to aa:bb:cc:dd:ee:ff",
+    "disconnect          drop the connection",
+    "set bitpool <n>     SBC bitpool for the next stream",
+    "set name <name>     Bluetooth device name",
+    "stats               USB audio counters and latency",
+    "save                store the configuration",
+];
+
+/// Console error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConsoleError {
+    /// Command not recognised
+    UnknownCommand,
+    /// Command needs an argument
+    MissingArgument,
+    /// Argument didn't parse
+    InvalidArgument,
+    /// Line longer than [`MAX_LINE`] or not UTF-8
+    InvalidLine,
+    /// Change rejected by [`AppConfig::validate`]
+    InvalidConfig(&'static str),
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownCommand => f.write_str("unknown command, try help"),
+            Self::MissingArgument => f.write_str("missing argument"),
+            Self::InvalidArgument => f.write_str("invalid argument"),
+            Self::InvalidLine => f.write_str("invalid line"),
+            Self::InvalidConfig(reason) => f.write_str(reason),
+        }
+    }
+}
+
+/// A parsed command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    /// `help`: list the commands
+    Help,
+    /// `status`: connection state and settings
+    Status,
+    /// `scan`: inquiry for headphones
+    Scan,
+    /// `pair`: become discoverable
+    Pair,
+    /// `connect <addr>`
+    Connect(BdAddr),
+    /// `disconnect`
+    Disconnect,
+    /// `set bitpool <n>`
+    SetBitpool(u8),
+    /// `set name <name>`
+    SetName(&'a str),
+    /// `stats`: USB counters and latency
+    Stats,
+    /// `save`: store the configuration
+    Save,
+}
+
+impl<'a> Command<'a> {
+    /// Parse a command line; `None` for a blank line
+    pub fn parse(line: &'a str) -> Result<Option<Self>, ConsoleError> {
+        let line = line.trim();
+        let (word, rest) = split_word(line);
+        let command = match word {
+            "" => return Ok(None),
+            "help" | "?" => Self::Help,
+            "status" => Self::Status,
+            "scan" => Self::Scan,
+            "pair" => Self::Pair,
+            "connect" => Self::Connect(parse_addr(argument(rest)?)?),
+            "disconnect" => Self::Disconnect,
+            "stats" => Self::Stats,
+            "save" => Self::Save,
+            "set" => match split_word(rest) {
+                ("bitpool", value) => Self::SetBitpool(
+                    argument(value)?
+                        .parse()
+                        .map_err(|_| ConsoleError::InvalidArgument)?,
+                ),
+                ("name", name) => Self::SetName(argument(name)?),
+                ("", _) => return Err(ConsoleError::MissingArgument),
+                _ => return Err(ConsoleError::UnknownCommand),
+            },
+            _ => return Err(ConsoleError::UnknownCommand),
+        };
+        Ok(Some(command))
+    }
+}
+
+/// Split off the first whitespace-separated word
+fn split_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+fn argument(text: &str) -> Result<&str, ConsoleError> {
+    match text.trim() {
+        "" => Err(ConsoleError::MissingArgument),
+        text => Ok(text),
+    }
+}
+
+/// Parse `aa:bb:cc:dd:ee:ff`, most significant byte first as Bluetooth
+/// addresses are written, into the HCI byte order of [`BdAddr`]
+fn parse_addr(text: &str) -> Result<BdAddr, ConsoleError> {
+    let mut bytes = [0u8; 6];
+    let mut parts = text.split(':');
+    for byte in bytes.iter_mut().rev() {
+        let part = parts.next().ok_or(ConsoleError::InvalidArgument)?;
+        if part.len() != 2 {
+            return Err(ConsoleError::InvalidArgument);
+        }
+        *byte = u8::from_str_radix(part, 16).map_err(|_| ConsoleError::InvalidArgument)?;
+    }
+    if parts.next().is_some() {
+        return Err(ConsoleError::InvalidArgument);
+    }
+    Ok(BdAddr::new(bytes))
+}
+
+/// Formats a [`BdAddr`] the way [`parse_addr`] reads it
+struct AddrDisplay(BdAddr);
+
+impl fmt::Display for AddrDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [b0, b1, b2, b3, b4, b5] = *self.0.bytes();
+        write!(f, "{b5:02x}:{b4:02x}:{b3:02x}:{b2:02x}:{b1:02x}:{b0:02x}")
+    }
+}
+
+/// What the application must do after a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleAction {
+    /// Feed an event to the connection state machine
+    Event(Event),
+    /// Run an inquiry for nearby headphones
+    Scan,
+    /// [`AppConfig`] changed; apply the new device name and bitpool
+    ConfigChanged,
+    /// Store [`AppConfig`]
+    Save,
+}
+
+/// Live state shown by `status` and `stats`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ConsoleStatus {
+    /// Connection state
+    pub state: A2dpState,
+    /// Connected or connecting headphones
+    pub remote_addr: Option<BdAddr>,
+    /// Bitpool of the running stream, if any
+    pub bitpool: Option<u8>,
+    /// USB audio counters
+    pub usb: AudioStats,
+    /// End-to-end latency estimate
+    pub latency: LatencyBreakdown,
+}
+
+/// Run a parsed command, writing the reply to `out`
+pub fn execute(
+    command: Command,
+    config: &mut AppConfig,
+    status: &ConsoleStatus,
+    out: &mut impl Write,
+) -> Result<Option<ConsoleAction>, ConsoleError> {
+    let action = match command {
+        Command::Help => {
+            for line in HELP {
+                write!(out, "{line}\r\n").ok();
+            }
+            None
+        }
+        Command::Status => {
+            write!(out, "state: {:?}\r\n", status.state).ok();
+            if let Some(addr) = status.remote_addr {
+                write!(out, "remote: {}\r\n", AddrDisplay(addr)).ok();
+            }
+            write!(out, "name: {}\r\n", config.device_name).ok();
+            write!(out, "bitpool: {}", config.default_bitpool).ok();
+            if let Some(bitpool) = status.bitpool.filter(|&b| b != config.default_bitpool) {
+                write!(out, " (streaming at {bitpool})").ok();
+            }
+            out.write_str("\r\n").ok();
+            None
+        }
+        Command::Scan => Some(ConsoleAction::Scan),
+        Command::Pair => Some(ConsoleAction::Event(Event::MakeDiscoverable)),
+        Command::Connect(addr) => Some(ConsoleAction::Event(Event::Connect(addr))),
+        Command::Disconnect => Some(ConsoleAction::Event(Event::Disconnect)),
+        Command::SetBitpool(bitpool) => {
+            let mut changed = config.clone();
+            changed.default_bitpool = bitpool;
+            changed.validate().map_err(ConsoleError::InvalidConfig)?;
+            *config = changed;
+            Some(ConsoleAction::ConfigChanged)
+        }
+        Command::SetName(name) => {
+            let mut changed = config.clone();
+            changed.device_name =
+                String::try_from(name).map_err(|_| ConsoleError::InvalidArgument)?;
+            changed.validate().map_err(ConsoleError::InvalidConfig)?;
+            *config = changed;
+            Some(ConsoleAction::ConfigChanged)
+        }
+        Command::Stats => {
+            let usb = &status.usb;
+            write!(
+                out,
+                "usb: {} packets, {} samples, {} underruns, {} overruns\r\n",
+                usb.packets_received, usb.samples_received, usb.underruns, usb.overruns
+            )
+            .ok();
+            write!(out, "latency: {}\r\n", status.latency).ok();
+            None
+        }
+        Command::Save => Some(ConsoleAction::Save),
+    };
+    if action.is_some() {
+        out.write_str("ok\r\n").ok();
+    }
+    Ok(action)
+}
+
+/// Line editor and command shell
+pub struct Console {
+    line: Vec<u8, MAX_LINE>,
+    overflow: bool,
+    /// Last byte was a CR, so a following LF ends nothing
+    after_cr: bool,
+}
+
+impl Console {
+    /// Create a console with an empty line
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            overflow: false,
+            after_cr: false,
+        }
+    }
+
+    /// Write the prompt, e.g. when a terminal connects
+    pub fn prompt(&self, out: &mut impl Write) {
+        out.write_str(PROMPT).ok();
+    }
+
+    /// Handle one received byte, echoing it to `out`
+    ///
+    /// Runs the command when the byte ends a line and returns its action.
+    pub fn input(
+        &mut self,
+        byte: u8,
+        config: &mut AppConfig,
+        status: &ConsoleStatus,
+        out: &mut impl Write,
+    ) -> Option<ConsoleAction> {
+        let after_cr = core::mem::replace(&mut self.after_cr, byte == b'\r');
+        match byte {
+            b'\n' if after_cr => None,
+            b'\r' | b'\n' => {
+                out.write_str("\r\n").ok();
+                let action = self.run_line(config, status, out);
+                self.line.clear();
+                self.overflow = false;
+                self.prompt(out);
+                action
+            }
+            // Backspace and DEL remove a whole character
+            0x08 | 0x7F => {
+                if let Some(mut last) = self.line.pop() {
+                    while is_continuation(last) {
+                        match self.line.pop() {
+                            Some(byte) => last = byte,
+                            None => break,
+                        }
+                    }
+                    out.write_str("\x08 \x08").ok();
+                }
+                None
+            }
+            0x20..=0x7E => {
+                if self.line.push(byte).is_ok() {
+                    out.write_char(byte as char).ok();
+                } else {
+                    self.overflow = true;
+                }
+                None
+            }
+            // UTF-8 sequence bytes, echoed once the character is complete;
+            // `run_line` rejects malformed sequences
+            0x80..=0xFF => {
+                if self.line.push(byte).is_err() {
+                    self.overflow = true;
+                    return None;
+                }
+                let start = self
+                    .line
+                    .iter()
+                    .rposition(|&byte| !is_continuation(byte))
+                    .unwrap_or(0);
+                if let Ok(ch) = core::str::from_utf8(&self.line[start..]) {
+                    out.write_str(ch).ok();
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn run_line(
+        &self,
+        config: &mut AppConfig,
+        status: &ConsoleStatus,
+        out: &mut impl Write,
+    ) -> Option<ConsoleAction> {
+        let result = match core::str::from_utf8(&self.line) {
+            Ok(line) if !self.overflow => Command::parse(line),
+            _ => Err(ConsoleError::InvalidLine),
+        };
+        match result.and_then(|command| match command {
+            Some(command) => execute(command, config, status, out),
+            None => Ok(None),
+        }) {
+            Ok(action) => action,
+            Err(err) => {
+                write!(out, "error: {err}\r\n").ok();
+                None
+            }
+        }
+    }
+}
+
+/// Check for a UTF-8 continuation byte
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+impl Default for Console {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: BdAddr = BdAddr::new([0x66, 0x55, 0x44, 0x33, 0x22, 0x11]);
+
+    #[test]
+    fn test_parse_commands() {
+        assert_eq!(Command::parse("  "), Ok(None));
+        assert_eq!(Command::parse("status"), Ok(Some(Command::Status)));
+        assert_eq!(
+            Command::parse("connect 11:22:33:44:55:66"),
+            Ok(Some(Command::Connect(ADDR)))
+        );
+        assert_eq!(
+            Command::parse("set  bitpool 35 "),
+            Ok(Some(Command::SetBitpool(35)))
+        );
+        assert_eq!(
+            Command::parse("set name Living Room"),
+            Ok(Some(Command::SetName("Living Room")))
+        );
+
+        assert_eq!(Command::parse("play"), Err(ConsoleError::UnknownCommand));
+        assert_eq!(
+            Command::parse("connect"),
+            Err(ConsoleError::MissingArgument)
+        );
+        assert_eq!(
+            Command::parse("connect 11:22:33:44:55"),
+            Err(ConsoleError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("connect 11:22:33:44:55:66:77"),
+            Err(ConsoleError::InvalidArgument)
+        );
+        assert_eq!(
+            Command::parse("set bitpool 300"),
+            Err(ConsoleError::InvalidArgument)
+        );
+        assert_eq!(Command::parse("set"), Err(ConsoleError::MissingArgument));
+    }
+
+    #[test]
+    fn test_commands_change_config() {
+        let mut config = AppConfig::default();
+        let status = ConsoleStatus {
+            state: A2dpState::Streaming,
+            remote_addr: Some(ADDR),
+            bitpool: Some(53),
+            ..Default::default()
+        };
+        let mut out: String<512> = String::new();
+
+        let set = execute(Command::SetBitpool(35), &mut config, &status, &mut out);
+        assert_eq!(set, Ok(Some(ConsoleAction::ConfigChanged)));
+        assert_eq!(config.default_bitpool, 35);
+        // Validation failures leave the config alone
+        assert_eq!(
+            execute(Command::SetBitpool(1), &mut config, &status, &mut out),
+            Err(ConsoleError::InvalidConfig(
+                "Bitpool must be between 2 and 250"
+            ))
+        );
+        assert_eq!(
+            execute(Command::SetName(""), &mut config, &status, &mut out),
+            Err(ConsoleError::InvalidConfig("Device name cannot be empty"))
+        );
+        execute(Command::SetName("Desk"), &mut config, &status, &mut out).unwrap();
+
+        out.clear();
+        execute(Command::Status, &mut config, &status, &mut out).unwrap();
+        assert_eq!(
+            out,
+            "state: Streaming\r\nremote: 11:22:33:44:55:66\r\nname: Desk\r\n\
+             bitpool: 35 (streaming at 53)\r\n"
+        );
+        assert_eq!(
+            execute(Command::Connect(ADDR), &mut config, &status, &mut out),
+            Ok(Some(ConsoleAction::Event(Event::Connect(ADDR))))
+        );
+    }
+
+    #[test]
+    fn test_line_editing() {
+        let mut console = Console::new();
+        let mut config = AppConfig::default();
+        let status = ConsoleStatus::default();
+        let mut out: String<512> = String::new();
+        let mut type_line = |console: &mut Console, text: &[u8], out: &mut String<512>| {
+            let mut actions = text
+                .iter()
+                .filter_map(|&byte| console.input(byte, &mut config, &status, out));
+            let action = actions.next();
+            assert_eq!(actions.next(), None);
+            action
+        };
+
+        // Typo fixed with backspace, CRLF ends one line
+        let action = type_line(&mut console, b"sace\x08\x08ve\r\n", &mut out);
+        assert_eq!(action, Some(ConsoleAction::Save));
+        assert_eq!(out, "sace\x08 \x08\x08 \x08ve\r\nok\r\n> ");
+
+        out.clear();
+        assert_eq!(type_line(&mut console, b"scna\r", &mut out), None);
+        assert_eq!(out, "scna\r\nerror: unknown command, try help\r\n> ");
+
+        // Overlong lines are dropped, not truncated
+        out.clear();
+        let mut long = [b'x'; MAX_LINE + 2];
+        long[MAX_LINE + 1] = b'\n';
+        assert_eq!(type_line(&mut console, &long, &mut out), None);
+        assert!(out.ends_with("error: invalid line\r\n> "));
+        out.clear();
+        assert_eq!(
+            type_line(&mut console, b"pair\n", &mut out),
+            Some(ConsoleAction::Event(Event::MakeDiscoverable))
+        );
+
+        // Multibyte characters echo whole and erase whole
+        out.clear();
+        let action = type_line(&mut console, "set name Café€\x08\r".as_bytes(), &mut out);
+        assert_eq!(action, Some(ConsoleAction::ConfigChanged));
+        assert!(out.starts_with("set name Café€\x08 \x08\r\n"));
+        out.clear();
+        assert_eq!(type_line(&mut console, b"set name \xFF\r", &mut out), None);
+        assert!(out.ends_with("error: invalid line\r\n> "));
+    }
+}
@@ -0,0 +1,234 @@
+//! End-to-end latency accounting
+//!
+//! Sums the delay each stage adds between the USB host handing us a sample
+//! and the headphones playing it. The audio tasks update their stage as
+//! buffers fill and drain; the total is what customers should enter as
+//! their lip-sync offset, and the source-side part is what
+//! `AppConfig::audio_buffer_ms` tunes.
+
+use core::fmt;
+
+use audio_pipeline::AudioFormat;
+use bt_classic::a2dp::NegotiatedConfig;
+
+/// Latency of each pipeline stage, in microseconds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LatencyBreakdown {
+    /// Audio held in USB endpoint buffers
+    pub usb_us: u32,
+    /// PCM waiting in the ring buffer between USB and the encoder
+    pub pcm_buffer_us: u32,
+    /// Look-ahead and filter delay of the DSP stages
+    pub dsp_us: u32,
+    /// One SBC frame of audio accumulated before it can be encoded
+    pub encoder_us: u32,
+    /// Encoded frames queued for the radio
+    pub packet_queue_us: u32,
+    /// Playback delay reported by the sink (0 if it sent no report)
+    pub sink_us: u32,
+}
+
+impl LatencyBreakdown {
+    /// Latency added on our side of the link
+    pub fn source_us(&self) -> u32 {
+        self.usb_us
+            .saturating_add(self.pcm_buffer_us)
+            .saturating_add(self.dsp_us)
+            .saturating_add(self.encoder_us)
+            .saturating_add(self.packet_queue_us)
+    }
+
+    /// Total latency from USB to the headphones
+    pub fn total_us(&self) -> u32 {
+        self.source_us().saturating_add(self.sink_us)
+    }
+}
+
+impl fmt::Display for LatencyBreakdown {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "usb {} + pcm {} + dsp {} + sbc {} + queue {} + sink {} = {} us",
+            self.usb_us,
+            self.pcm_buffer_us,
+            self.dsp_us,
+            self.encoder_us,
+            self.packet_queue_us,
+            self.sink_us,
+            self.total_us()
+        )
+    }
+}
+
+/// Running latency model of the audio pipeline
+pub struct LatencyModel {
+    format: AudioFormat,
+    breakdown: LatencyBreakdown,
+    sink_reported: bool,
+}
+
+impl LatencyModel {
+    /// Create a model for PCM in `format`, with every stage at zero
+    pub const fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            breakdown: LatencyBreakdown {
+                usb_us: 0,
+                pcm_buffer_us: 0,
+                dsp_us: 0,
+                encoder_us: 0,
+                packet_queue_us: 0,
+                sink_us: 0,
+            },
+            sink_reported: false,
+        }
+    }
+
+    /// Change the PCM format used to convert frame counts
+    ///
+    /// Stages already recorded in microseconds are kept.
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.format = format;
+    }
+
+    /// Record audio held in USB buffers, in frames
+    pub fn set_usb_frames(&mut self, frames: usize) {
+        self.breakdown.usb_us = self.frames_to_us(frames);
+    }
+
+    /// Record the PCM ring buffer fill level in interleaved samples
+    /// (e.g. `RingBuffer::available_read`)
+    pub fn set_pcm_fill(&mut self, samples: usize) {
+        let channels = (self.format.channels as usize).max(1);
+        self.breakdown.pcm_buffer_us = self.frames_to_us(samples / channels);
+    }
+
+    /// Record the combined delay of the DSP stages in frames
+    /// (e.g. `Limiter::latency_frames`)
+    pub fn set_dsp_frames(&mut self, frames: usize) {
+        self.breakdown.dsp_us = self.frames_to_us(frames);
+    }
+
+    /// Record the negotiated codec configuration
+    pub fn set_codec(&mut self, config: &NegotiatedConfig) {
+        self.breakdown.encoder_us = if config.sample_rate == 0 {
+            0
+        } else {
+            config.frame_duration_us()
+        };
+    }
+
+    /// Record the duration of encoded audio waiting for the radio
+    /// (e.g. `FrameQueue::queued_duration_us`)
+    pub fn set_packet_queue_us(&mut self, us: u32) {
+        self.breakdown.packet_queue_us = us;
+    }
+
+    /// Record the sink's AVDTP delay report (e.g. `A2dpSource::sink_delay_us`)
+    pub fn set_sink_delay_us(&mut self, delay_us: Option<u32>) {
+        self.sink_reported = delay_us.is_some();
+        self.breakdown.sink_us = delay_us.unwrap_or(0);
+    }
+
+    /// Whether the sink has reported its playback delay
+    ///
+    /// Without a report the total only covers our side of the link.
+    pub fn sink_reported(&self) -> bool {
+        self.sink_reported
+    }
+
+    /// Current latency of each stage
+    pub fn breakdown(&self) -> LatencyBreakdown {
+        self.breakdown
+    }
+
+    /// Current total latency in microseconds
+    pub fn total_us(&self) -> u32 {
+        self.breakdown.total_us()
+    }
+
+    /// Current total latency rounded to whole milliseconds, as entered
+    /// in a player's lip-sync setting
+    pub fn lip_sync_offset_ms(&self) -> u32 {
+        self.total_us().saturating_add(500) / 1000
+    }
+
+    /// Clear the per-connection stages (buffers, codec, sink report)
+    pub fn reset_link(&mut self) {
+        self.breakdown.encoder_us = 0;
+        self.breakdown.packet_queue_us = 0;
+        self.breakdown.sink_us = 0;
+        self.sink_reported = false;
+    }
+
+    fn frames_to_us(&self, frames: usize) -> u32 {
+        if self.format.sample_rate == 0 {
+            return 0;
+        }
+        let us = frames as u64 * 1_000_000 / self.format.sample_rate as u64;
+        us.min(u32::MAX as u64) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo_48k() -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    fn sbc_48k() -> NegotiatedConfig {
+        NegotiatedConfig {
+            sample_rate: 48000,
+            channels: 2,
+            blocks: 16,
+            subbands: 8,
+            bitpool: 53,
+            joint_stereo: true,
+            loudness: true,
+        }
+    }
+
+    #[test]
+    fn test_sums_all_stages() {
+        let mut model = LatencyModel::new(stereo_48k());
+        model.set_usb_frames(48);
+        model.set_pcm_fill(4800);
+        model.set_dsp_frames(48);
+        model.set_codec(&sbc_48k());
+        model.set_packet_queue_us(16_000);
+        model.set_sink_delay_us(Some(150_000));
+
+        let breakdown = model.breakdown();
+        assert_eq!(breakdown.usb_us, 1000);
+        assert_eq!(breakdown.pcm_buffer_us, 50_000);
+        assert_eq!(breakdown.dsp_us, 1000);
+        assert_eq!(breakdown.encoder_us, 2666);
+        assert_eq!(breakdown.source_us(), 70_666);
+        assert_eq!(model.total_us(), 220_666);
+        assert_eq!(model.lip_sync_offset_ms(), 221);
+        assert!(model.sink_reported());
+    }
+
+    #[test]
+    fn test_reset_link_keeps_local_stages() {
+        let mut model = LatencyModel::new(stereo_48k());
+        model.set_pcm_fill(960);
+        model.set_codec(&sbc_48k());
+        model.set_sink_delay_us(Some(100_000));
+
+        model.reset_link();
+        assert!(!model.sink_reported());
+        assert_eq!(model.total_us(), 10_000);
+
+        // Unconfigured codec contributes nothing
+        model.set_codec(&NegotiatedConfig::default());
+        assert_eq!(model.breakdown().encoder_us, 0);
+    }
+}
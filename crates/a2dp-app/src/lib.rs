@@ -10,8 +10,20 @@
 #![deny(unsafe_op_in_unsafe_fn)]
 
 pub mod config;
+pub mod console;
+pub mod latency;
+pub mod negotiation;
+pub mod prompts;
 pub mod state_machine;
+pub mod update;
+pub mod volume;
 
 pub use bt_classic::a2dp::A2dpState;
-pub use config::AppConfig;
+pub use config::{AppConfig, AudioSource};
+pub use console::{Command, Console, ConsoleAction, ConsoleError, ConsoleStatus};
+pub use latency::{LatencyBreakdown, LatencyModel};
+pub use negotiation::{negotiate, NegotiationError};
+pub use prompts::{Prompt, PromptController};
 pub use state_machine::StateMachine;
+pub use update::FLASH_LAYOUT;
+pub use volume::{HostVolume, VolumeAction, VolumeRoute};
@@ -0,0 +1,215 @@
+//! Stream format negotiation between USB and Bluetooth
+//!
+//! The USB host picks a sample rate and format from the ones our descriptors
+//! offer, while the headphones pick theirs during AVDTP configuration.
+//! Neither side knows about the other, so [`negotiate`] compares the two and
+//! builds a [`FormatConverter`] running whatever conversion, channel mapping
+//! and resampling stages are needed between them. Call it again whenever
+//! either side changes.
+
+use audio_pipeline::{AudioFormat, ConvertError, FormatConverter};
+use bt_classic::a2dp::NegotiatedConfig;
+use usb_audio::{StreamFormat, Uac2Config, STREAM_BITS_PER_SAMPLE};
+
+/// Format negotiation errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum NegotiationError {
+    /// Host selected a rate the USB descriptors don't offer
+    RateNotOffered,
+    /// Host selected a format the USB descriptors don't offer
+    FormatNotOffered,
+    /// Codec has not been configured yet
+    CodecNotConfigured,
+    /// No conversion exists between the two formats
+    Unsupported(ConvertError),
+}
+
+impl From<ConvertError> for NegotiationError {
+    fn from(err: ConvertError) -> Self {
+        NegotiationError::Unsupported(err)
+    }
+}
+
+/// PCM format the speaker stream delivers at `sample_rate`
+///
+/// The stream narrows every host format to 16 bits, so only the rate and
+/// channel count vary.
+pub fn usb_format(usb: &Uac2Config, sample_rate: u32) -> AudioFormat {
+    AudioFormat {
+        sample_rate,
+        channels: usb.channels,
+        bits_per_sample: STREAM_BITS_PER_SAMPLE,
+    }
+}
+
+/// PCM format the SBC encoder expects for `codec`
+pub fn codec_format(codec: &NegotiatedConfig) -> AudioFormat {
+    AudioFormat {
+        sample_rate: codec.sample_rate,
+        channels: codec.channels,
+        bits_per_sample: 16,
+    }
+}
+
+/// Build the converter from the host's selected rate and format to the
+/// codec format
+pub fn negotiate(
+    usb: &Uac2Config,
+    usb_rate: u32,
+    usb_stream: StreamFormat,
+    codec: &NegotiatedConfig,
+) -> Result<FormatConverter, NegotiationError> {
+    if !usb.sample_rates.contains(&usb_rate) {
+        return Err(NegotiationError::RateNotOffered);
+    }
+    if !usb.formats.contains(&usb_stream) {
+        return Err(NegotiationError::FormatNotOffered);
+    }
+    if codec.sample_rate == 0 || codec.channels == 0 {
+        return Err(NegotiationError::CodecNotConfigured);
+    }
+    Ok(FormatConverter::new(
+        usb_format(usb, usb_rate),
+        codec_format(codec),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio_pipeline::{ChannelMap, GeneratorConfig, Signal, SignalGenerator};
+
+    const RATES: [u32; 4] = [16000, 32000, 44100, 48000];
+
+    fn usb_config(channels: u8) -> Uac2Config {
+        Uac2Config {
+            channels,
+            sample_rates: &RATES,
+            ..Default::default()
+        }
+    }
+
+    fn codec(sample_rate: u32, channels: u8) -> NegotiatedConfig {
+        NegotiatedConfig {
+            sample_rate,
+            channels,
+            blocks: 16,
+            subbands: 8,
+            bitpool: 53,
+            joint_stereo: channels == 2,
+            loudness: true,
+        }
+    }
+
+    /// Generate 100 ms of a 1 kHz tone as USB bytes
+    fn tone(format: AudioFormat, buf: &mut [u8]) -> usize {
+        let config = GeneratorConfig {
+            signal: Signal::Sine { freq_hz: 1000 },
+            level_db_x10: -60,
+        };
+        let mut generator = SignalGenerator::new(config, format).unwrap();
+        let len = format.bytes_per_second() / 10;
+        generator.fill_bytes(&mut buf[..len]);
+        len
+    }
+
+    /// Count rising zero crossings of the first channel
+    fn rising_crossings(samples: &[i16], channels: usize) -> usize {
+        let first = samples.iter().step_by(channels);
+        first
+            .clone()
+            .zip(first.skip(1))
+            .filter(|(prev, next)| **prev < 0 && **next >= 0)
+            .count()
+    }
+
+    #[test]
+    fn test_matrix() {
+        let mut input = [0u8; 48000 * 2 * 4 / 10];
+        let mut output = [0i16; 4800 * 2 + 16];
+
+        for usb_rate in RATES {
+            for usb_channels in [1, 2] {
+                for usb_stream in [
+                    StreamFormat::PCM16,
+                    StreamFormat::PCM24_3,
+                    StreamFormat::PCM24_4,
+                    StreamFormat::PCM32,
+                ] {
+                    for bt_rate in RATES {
+                        for bt_channels in [1, 2] {
+                            let usb = usb_config(usb_channels);
+                            let codec = codec(bt_rate, bt_channels);
+                            let mut conv = negotiate(&usb, usb_rate, usb_stream, &codec).unwrap();
+
+                            let stages = conv.stages();
+                            assert_eq!(stages.resample, usb_rate != bt_rate);
+                            assert_eq!(stages.anti_alias, bt_rate < usb_rate);
+                            let expected_map = match (usb_channels, bt_channels) {
+                                (2, 1) => ChannelMap::Downmix,
+                                (1, 2) => ChannelMap::Upmix,
+                                _ => ChannelMap::Direct,
+                            };
+                            assert_eq!(stages.channel_map, expected_map);
+
+                            let len = tone(conv.input(), &mut input);
+                            assert!(conv.max_output_len(len) <= output.len());
+                            let (consumed, written) = conv.process(&input[..len], &mut output);
+                            assert_eq!(consumed, len);
+
+                            // 100 ms in, 100 ms out at the codec rate
+                            let channels = bt_channels as usize;
+                            let frames = written / channels;
+                            assert!(
+                                frames.abs_diff(bt_rate as usize / 10) <= 1,
+                                "{usb_rate}/{usb_channels}/{usb_stream:?} -> \
+                                 {bt_rate}/{bt_channels}: {frames} frames"
+                            );
+
+                            // The tone keeps its pitch and level
+                            let crossings = rising_crossings(&output[..written], channels);
+                            assert!((99..=101).contains(&crossings), "{crossings} crossings");
+                            let peak = output[..written]
+                                .iter()
+                                .map(|s| s.unsigned_abs())
+                                .max()
+                                .unwrap();
+                            assert!((14500..=16500).contains(&peak), "peak {peak}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_negotiation_errors() {
+        let usb = Uac2Config::default();
+        assert_eq!(
+            negotiate(&usb, 32000, StreamFormat::PCM16, &codec(48000, 2)).err(),
+            Some(NegotiationError::RateNotOffered)
+        );
+        let packed_20 = StreamFormat::new(3, 20);
+        assert_eq!(
+            negotiate(&usb, 48000, packed_20, &codec(48000, 2)).err(),
+            Some(NegotiationError::FormatNotOffered)
+        );
+        assert_eq!(
+            negotiate(
+                &usb,
+                48000,
+                StreamFormat::PCM16,
+                &NegotiatedConfig::default()
+            )
+            .err(),
+            Some(NegotiationError::CodecNotConfigured)
+        );
+        assert_eq!(
+            negotiate(&usb_config(6), 48000, StreamFormat::PCM16, &codec(48000, 2)).err(),
+            Some(NegotiationError::Unsupported(
+                ConvertError::UnsupportedChannels
+            ))
+        );
+    }
+}
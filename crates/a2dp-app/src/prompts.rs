@@ -0,0 +1,242 @@
+//! Audible feedback on connection and mode changes
+//!
+//! Clips live in the `PROMPTS` flash region reserved in `memory.x` and are
+//! looked up by [`Prompt`] id. The controller renders the playing clip into
+//! a mixer input and ducks the main stream underneath it.
+
+use audio_pipeline::{AudioFormat, Mixer, PromptBank, PromptPlayer};
+use bt_classic::a2dp::A2dpState;
+
+/// Prompt clips, by their id in the prompt bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum Prompt {
+    /// Entered pairing mode
+    Discoverable = 1,
+    /// Headphones connected and ready to stream
+    Connected = 2,
+    /// Link to the headphones lost or closed
+    Disconnected = 3,
+    /// Connection attempt failed
+    ConnectionFailed = 4,
+}
+
+impl Prompt {
+    /// Id of the clip in the prompt bank
+    pub const fn id(self) -> u8 {
+        self as u8
+    }
+
+    /// Prompt announcing a state machine transition, if any
+    pub fn for_transition(from: A2dpState, to: A2dpState) -> Option<Self> {
+        match (from, to) {
+            (_, A2dpState::Discoverable) if from != to => Some(Prompt::Discoverable),
+            (A2dpState::Configuring, A2dpState::Open) => Some(Prompt::Connected),
+            (A2dpState::Connecting, A2dpState::Disconnected) => Some(Prompt::ConnectionFailed),
+            (
+                A2dpState::Open | A2dpState::Streaming | A2dpState::Suspended,
+                A2dpState::Disconnecting | A2dpState::Disconnected,
+            ) => Some(Prompt::Disconnected),
+            _ => None,
+        }
+    }
+}
+
+/// Prompt bank stored in the `PROMPTS` flash region
+///
+/// Returns `None` if the region holds no valid bank (e.g. it was never
+/// programmed).
+#[cfg(target_os = "none")]
+pub fn flash_prompt_bank() -> Option<PromptBank<'static>> {
+    extern "C" {
+        static __prompts_start: u8;
+        static __prompts_end: u8;
+    }
+
+    // Safety: The linker symbols delimit a read-only, memory-mapped flash
+    // region that nothing writes to while the application runs.
+    let bank = unsafe {
+        let start = core::ptr::addr_of!(__prompts_start);
+        let end = core::ptr::addr_of!(__prompts_end);
+        core::slice::from_raw_parts(start, end as usize - start as usize)
+    };
+    PromptBank::parse(bank).ok()
+}
+
+/// Plays prompts and ducks the main stream while they sound
+pub struct PromptController<'a> {
+    bank: Option<PromptBank<'a>>,
+    player: PromptPlayer<'a>,
+    duck_db_x10: i16,
+    /// Main input gain from before the current duck
+    ducked_from: Option<i16>,
+}
+
+impl<'a> PromptController<'a> {
+    /// Create a controller for `bank`, ducking the main stream by
+    /// `duck_db_x10` tenths of a dB during prompts
+    pub fn new(bank: Option<PromptBank<'a>>, format: AudioFormat, duck_db_x10: i16) -> Self {
+        Self {
+            bank,
+            player: PromptPlayer::new(format),
+            duck_db_x10: duck_db_x10.min(0),
+            ducked_from: None,
+        }
+    }
+
+    /// Change the stream format, stopping any playing prompt
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.player.set_format(format);
+    }
+
+    /// Start `prompt`, replacing any playing one
+    ///
+    /// Returns `false` if the bank has no playable clip for it.
+    pub fn play(&mut self, prompt: Prompt) -> bool {
+        match self.bank.and_then(|bank| bank.get(prompt.id())) {
+            Some(clip) => self.player.play(clip).is_ok(),
+            None => false,
+        }
+    }
+
+    /// Play the prompt for a state machine transition, if there is one
+    pub fn on_transition(&mut self, from: A2dpState, to: A2dpState) -> bool {
+        Prompt::for_transition(from, to).is_some_and(|prompt| self.play(prompt))
+    }
+
+    /// Check if a prompt is playing
+    pub fn is_playing(&self) -> bool {
+        self.player.is_playing()
+    }
+
+    /// Render prompt audio for the prompt mixer input
+    ///
+    /// Returns the number of samples written (zero when idle).
+    pub fn render(&mut self, out: &mut [i16]) -> usize {
+        self.player.fill(out)
+    }
+
+    /// Duck the `main` mixer input while a prompt plays and restore its
+    /// previous gain afterwards
+    ///
+    /// Call once per mixed block; the mixer ramps the gain change. A muted
+    /// main input stays muted.
+    pub fn apply_ducking<const INPUTS: usize, const BLOCK: usize>(
+        &mut self,
+        mixer: &mut Mixer<INPUTS, BLOCK>,
+        main: usize,
+    ) {
+        let current = mixer.gain_db_x10(main);
+        match (self.is_playing(), self.ducked_from) {
+            (true, None) => {
+                if let Some(gain) = current {
+                    self.ducked_from = Some(gain);
+                    let _ = mixer.set_gain_db_x10(main, gain.saturating_add(self.duck_db_x10));
+                }
+            }
+            (false, Some(gain)) => {
+                self.ducked_from = None;
+                // Setting the gain unmutes, so re-apply a mute from meanwhile
+                let _ = mixer.set_gain_db_x10(main, gain);
+                if current.is_none() {
+                    let _ = mixer.mute(main);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use audio_pipeline::{PROMPT_BANK_MAGIC, PROMPT_BANK_VERSION};
+
+    fn stereo() -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    /// Bank with one 4-frame mono PCM clip for `Prompt::Connected`
+    fn bank_image() -> [u8; 32] {
+        let mut image = [0u8; 32];
+        image[0..4].copy_from_slice(&PROMPT_BANK_MAGIC);
+        image[4] = PROMPT_BANK_VERSION;
+        image[5] = 1;
+        image[8] = Prompt::Connected.id();
+        image[10] = 1;
+        image[12..16].copy_from_slice(&48000u32.to_le_bytes());
+        image[16..20].copy_from_slice(&24u32.to_le_bytes());
+        image[20..24].copy_from_slice(&8u32.to_le_bytes());
+        for (i, chunk) in image[24..32].chunks_exact_mut(2).enumerate() {
+            chunk.copy_from_slice(&(1000 * (i as i16 + 1)).to_le_bytes());
+        }
+        image
+    }
+
+    #[test]
+    fn test_transition_prompts() {
+        assert_eq!(
+            Prompt::for_transition(A2dpState::Configuring, A2dpState::Open),
+            Some(Prompt::Connected)
+        );
+        assert_eq!(
+            Prompt::for_transition(A2dpState::Streaming, A2dpState::Disconnecting),
+            Some(Prompt::Disconnected)
+        );
+        assert_eq!(
+            Prompt::for_transition(A2dpState::Disconnected, A2dpState::Discoverable),
+            Some(Prompt::Discoverable)
+        );
+        assert_eq!(
+            Prompt::for_transition(A2dpState::Open, A2dpState::Streaming),
+            None
+        );
+    }
+
+    #[test]
+    fn test_plays_and_ducks() {
+        let image = bank_image();
+        let bank = PromptBank::parse(&image).unwrap();
+        let mut controller = PromptController::new(Some(bank), stereo(), -120);
+        let mut mixer: Mixer<2, 16> = Mixer::new(stereo());
+        mixer.set_gain_db_x10(0, -60).unwrap();
+
+        // No clip for this transition in the bank
+        assert!(!controller.on_transition(A2dpState::Connecting, A2dpState::Disconnected));
+        assert!(controller.on_transition(A2dpState::Configuring, A2dpState::Open));
+        controller.apply_ducking(&mut mixer, 0);
+        assert_eq!(mixer.gain_db_x10(0), Some(-180));
+
+        let mut block = [0i16; 16];
+        let written = controller.render(&mut block);
+        assert_eq!(written, 8);
+        assert_eq!(block[..4], [1000, 1000, 2000, 2000]);
+
+        assert!(!controller.is_playing());
+        controller.apply_ducking(&mut mixer, 0);
+        assert_eq!(mixer.gain_db_x10(0), Some(-60));
+
+        // Muted while ducked: the old gain comes back on unmute
+        assert!(controller.play(Prompt::Connected));
+        controller.apply_ducking(&mut mixer, 0);
+        mixer.mute(0).unwrap();
+        controller.render(&mut block);
+        controller.apply_ducking(&mut mixer, 0);
+        assert_eq!(mixer.gain_db_x10(0), None);
+        mixer.unmute(0).unwrap();
+        assert_eq!(mixer.gain_db_x10(0), Some(-60));
+    }
+
+    #[test]
+    fn test_without_bank() {
+        let mut controller = PromptController::new(None, stereo(), -120);
+        assert!(!controller.play(Prompt::Connected));
+        let mut block = [0i16; 8];
+        assert_eq!(controller.render(&mut block), 0);
+    }
+}
@@ -1,10 +1,11 @@
 //! Connection state machine for A2DP Source
 
+use audio_pipeline::SilenceEvent;
 use bt_classic::a2dp::A2dpState;
 use bt_classic::BdAddr;
 
 /// Events that trigger state transitions
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum Event {
     /// User requested to become discoverable
@@ -39,6 +40,18 @@ pub enum Event {
     Timeout,
     /// Error occurred
     Error(u8),
+    /// USB host switched the sample rate (Hz)
+    UsbRateChanged(u32),
+}
+
+impl From<SilenceEvent> for Event {
+    /// Suspend the stream on silence and restart it when audio returns
+    fn from(event: SilenceEvent) -> Self {
+        match event {
+            SilenceEvent::SilenceDetected => Event::PauseStream,
+            SilenceEvent::AudioResumed => Event::StartStream,
+        }
+    }
 }
 
 /// Actions to perform after state transition
@@ -71,6 +84,8 @@ pub enum Action {
     InitiateDisconnect,
     /// Update LED pattern
     UpdateLed,
+    /// Rebuild the format converter for the host's new sample rate
+    Renegotiate { usb_rate: u32 },
 }
 
 /// State machine for A2DP connection management
@@ -212,6 +227,12 @@ impl StateMachine {
                 Action::UpdateLed
             }
 
+            // Host rate switches only matter once a codec is configured
+            (
+                A2dpState::Open | A2dpState::Streaming | A2dpState::Suspended,
+                Event::UsbRateChanged(usb_rate),
+            ) => Action::Renegotiate { usb_rate },
+
             // Global error handling
             (_, Event::ConnectionFailed) | (_, Event::Error(_)) => {
                 self.state = A2dpState::Disconnected;
@@ -280,4 +301,46 @@ mod tests {
         sm.process(Event::AvdtpConfigured);
         assert_eq!(sm.state(), A2dpState::Open);
     }
+
+    #[test]
+    fn test_silence_suspends_and_resumes_stream() {
+        let mut sm = StateMachine::new();
+        let addr = BdAddr::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        sm.process(Event::Connect(addr));
+        sm.process(Event::ConnectionComplete { handle: 0x0001 });
+        sm.process(Event::L2capConnected);
+        sm.process(Event::AvdtpConfigured);
+        sm.process(Event::StartStream);
+        sm.process(Event::StreamStarted);
+        assert_eq!(sm.state(), A2dpState::Streaming);
+
+        let action = sm.process(SilenceEvent::SilenceDetected.into());
+        assert!(matches!(action, Action::SendSuspend));
+        sm.process(Event::StreamSuspended);
+        assert_eq!(sm.state(), A2dpState::Suspended);
+
+        let action = sm.process(SilenceEvent::AudioResumed.into());
+        assert!(matches!(action, Action::SendStart));
+        sm.process(Event::StreamStarted);
+        assert_eq!(sm.state(), A2dpState::Streaming);
+    }
+
+    #[test]
+    fn test_usb_rate_change_renegotiates() {
+        let mut sm = StateMachine::new();
+        let action = sm.process(Event::UsbRateChanged(48000));
+        assert!(matches!(action, Action::None));
+
+        let addr = BdAddr::new([0x11, 0x22, 0x33, 0x44, 0x55, 0x66]);
+        sm.process(Event::Connect(addr));
+        sm.process(Event::ConnectionComplete { handle: 0x0001 });
+        sm.process(Event::L2capConnected);
+        sm.process(Event::AvdtpConfigured);
+        sm.process(Event::StartStream);
+        sm.process(Event::StreamStarted);
+
+        let action = sm.process(Event::UsbRateChanged(48000));
+        assert!(matches!(action, Action::Renegotiate { usb_rate: 48000 }));
+        assert_eq!(sm.state(), A2dpState::Streaming);
+    }
 }
@@ -0,0 +1,111 @@
+//! Firmware updates over USB DFU
+//!
+//! Flash layout and the board glue for `usb_audio`'s DFU support. The
+//! running image sits in the `FLASH` region, behind the boot stage in
+//! `BOOT`, and a new one is staged in the `DFU` region (see `memory.x`).
+//! A DFU_DETACH from the host reboots the device with [`UPDATE_REQUEST`]
+//! left in a watchdog scratch register, which tells the next boot to come
+//! up in update mode. Once an image is staged, the boot stage
+//! (`crates/a2dp-boot`) installs it on the next reset.
+
+use usb_audio::FlashLayout;
+
+/// Offset of the `FLASH` region, right after the boot stage
+pub const ACTIVE_OFFSET: u32 = 0x1_0000;
+
+/// Offset of the `DFU` staging region from the start of flash
+pub const STAGING_OFFSET: u32 = 0x1C_8000;
+
+/// Size of the `FLASH` and `DFU` regions in `memory.x`
+pub const REGION_SIZE: u32 = 0x1B_8000;
+
+/// Offset of the `PROMPTS` region, which updates must leave alone
+pub const PROMPTS_OFFSET: u32 = 0x38_0000;
+
+/// Where the running and staged firmware live
+pub const FLASH_LAYOUT: FlashLayout = FlashLayout {
+    active: ACTIVE_OFFSET,
+    staging: STAGING_OFFSET,
+    size: REGION_SIZE,
+};
+
+/// Watchdog scratch register that survives the reboot into update mode
+pub const UPDATE_SCRATCH: usize = 0;
+
+/// Scratch value requesting update mode ("DFU!")
+pub const UPDATE_REQUEST: u32 = 0x4446_5521;
+
+#[cfg(target_os = "none")]
+mod board {
+    use embassy_rp::flash::{Blocking, Flash, ERASE_SIZE};
+    use embassy_rp::peripherals::FLASH;
+    use embassy_rp::watchdog::Watchdog;
+    use usb_audio::{DfuError, DfuFlash};
+
+    use super::{UPDATE_REQUEST, UPDATE_SCRATCH};
+
+    /// Pico 2 W flash size
+    pub const FLASH_SIZE: usize = 4 * 1024 * 1024;
+
+    /// On-board flash for the DFU image writer
+    pub struct UpdateFlash<'d>(pub Flash<'d, FLASH, Blocking, FLASH_SIZE>);
+
+    impl DfuFlash for UpdateFlash<'_> {
+        const ERASE_SIZE: usize = ERASE_SIZE;
+
+        fn erase(&mut self, offset: u32, len: u32) -> Result<(), DfuError> {
+            self.0
+                .blocking_erase(offset, offset + len)
+                .map_err(|_| DfuError::Erase)
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), DfuError> {
+            self.0
+                .blocking_write(offset, data)
+                .map_err(|_| DfuError::Flash)
+        }
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), DfuError> {
+            self.0
+                .blocking_read(offset, buf)
+                .map_err(|_| DfuError::Flash)
+        }
+    }
+
+    /// Reboot into update mode, e.g. once `DfuRuntime::wait_detach`
+    /// returns
+    pub fn reboot_into_update_mode(watchdog: &mut Watchdog) -> ! {
+        watchdog.set_scratch(UPDATE_SCRATCH, UPDATE_REQUEST);
+        watchdog.trigger_reset();
+        loop {
+            cortex_m::asm::wfi();
+        }
+    }
+
+    /// Check for and clear an update mode request left by
+    /// [`reboot_into_update_mode`]
+    pub fn take_update_request(watchdog: &mut Watchdog) -> bool {
+        let requested = watchdog.get_scratch(UPDATE_SCRATCH) == UPDATE_REQUEST;
+        watchdog.set_scratch(UPDATE_SCRATCH, 0);
+        requested
+    }
+}
+
+#[cfg(target_os = "none")]
+pub use board::{reboot_into_update_mode, take_update_request, UpdateFlash, FLASH_SIZE};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout_fits_flash() {
+        // Sector aligned, with the staging region between the firmware and
+        // the prompt bank
+        assert_eq!(ACTIVE_OFFSET % 4096, 0);
+        assert_eq!(STAGING_OFFSET % 4096, 0);
+        assert_eq!(REGION_SIZE % 4096, 0);
+        assert_eq!(FLASH_LAYOUT.active + REGION_SIZE, STAGING_OFFSET);
+        assert_eq!(STAGING_OFFSET + REGION_SIZE, PROMPTS_OFFSET);
+    }
+}
@@ -0,0 +1,183 @@
+//! Host volume routing
+//!
+//! The USB host's volume slider and mute button arrive as feature unit
+//! [`ControlEvent`]s. [`HostVolume`] folds the master and per-channel
+//! controls into one level and turns it into either a gain for the audio
+//! pipeline or an AVRCP absolute volume for the headphones, so the host's
+//! slider and the headphones' own buttons don't fight over the level.
+
+use usb_audio::{ControlEvent, VOLUME_MIN_DB_X256};
+
+/// Where the host volume is applied
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VolumeRoute {
+    /// Scale the PCM before encoding
+    #[default]
+    Pipeline,
+    /// Forward to the headphones as AVRCP absolute volume, keeping the full
+    /// SBC resolution
+    Remote,
+}
+
+/// Largest AVRCP absolute volume
+pub const REMOTE_VOLUME_MAX: u8 = 0x7F;
+
+/// What to do with a host volume change
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum VolumeAction {
+    /// Set the pipeline gain
+    SetGain { db_x10: i16, muted: bool },
+    /// Send an AVRCP absolute volume (0..=[`REMOTE_VOLUME_MAX`])
+    SetRemoteVolume(u8),
+}
+
+/// Master plus left and right
+const CONTROLS: usize = 3;
+
+/// Tracks the host's volume and mute controls
+pub struct HostVolume {
+    route: VolumeRoute,
+    channels: usize,
+    /// Volume per control (0 = master), in 1/256 dB
+    volume: [i16; CONTROLS],
+    muted: [bool; CONTROLS],
+    last: Option<VolumeAction>,
+}
+
+impl HostVolume {
+    /// Create a tracker for a `channels`-channel stream at full volume
+    pub fn new(route: VolumeRoute, channels: u8) -> Self {
+        Self {
+            route,
+            channels: (channels as usize).clamp(1, CONTROLS - 1),
+            volume: [0; CONTROLS],
+            muted: [false; CONTROLS],
+            last: None,
+        }
+    }
+
+    /// Apply a control event
+    ///
+    /// Returns the action to take, or `None` if the event doesn't change
+    /// the output level.
+    pub fn apply(&mut self, event: ControlEvent) -> Option<VolumeAction> {
+        match event {
+            ControlEvent::Volume {
+                channel,
+                volume_db_x256,
+            } => *self.volume.get_mut(channel as usize)? = volume_db_x256,
+            ControlEvent::Mute { channel, muted } => *self.muted.get_mut(channel as usize)? = muted,
+            _ => return None,
+        }
+
+        let action = self.action();
+        if self.last == Some(action) {
+            return None;
+        }
+        self.last = Some(action);
+        Some(action)
+    }
+
+    /// Combined level in 1/256 dB: the master plus the channel average
+    pub fn level_db_x256(&self) -> i16 {
+        let channels = &self.volume[1..=self.channels];
+        let average = channels.iter().map(|&v| v as i32).sum::<i32>() / self.channels as i32;
+        (self.volume[0] as i32 + average).max(i16::MIN as i32) as i16
+    }
+
+    /// Check if the master or every channel is muted
+    pub fn is_muted(&self) -> bool {
+        self.muted[0] || self.muted[1..=self.channels].iter().all(|&m| m)
+    }
+
+    fn action(&self) -> VolumeAction {
+        let level = self.level_db_x256() as i32;
+        match self.route {
+            VolumeRoute::Pipeline => VolumeAction::SetGain {
+                db_x10: (level * 10 + 128).div_euclid(256) as i16,
+                muted: self.is_muted(),
+            },
+            VolumeRoute::Remote if self.is_muted() => VolumeAction::SetRemoteVolume(0),
+            VolumeRoute::Remote => {
+                // Linear over the host's range, silent at its bottom
+                let min = VOLUME_MIN_DB_X256 as i32;
+                let step = (level.max(min) - min) * REMOTE_VOLUME_MAX as i32 / -min;
+                VolumeAction::SetRemoteVolume(step as u8)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn volume(channel: u8, db: i16) -> ControlEvent {
+        ControlEvent::Volume {
+            channel,
+            volume_db_x256: db * 256,
+        }
+    }
+
+    #[test]
+    fn test_pipeline_gain() {
+        let mut host = HostVolume::new(VolumeRoute::Pipeline, 2);
+        assert_eq!(
+            host.apply(volume(0, -12)),
+            Some(VolumeAction::SetGain {
+                db_x10: -120,
+                muted: false
+            })
+        );
+        // Channel trims average into the level
+        host.apply(volume(1, -4));
+        assert_eq!(host.level_db_x256(), -14 * 256);
+        assert_eq!(host.apply(ControlEvent::SampleRate(48000)), None);
+
+        // Muting one channel isn't muting the stream
+        host.apply(ControlEvent::Mute {
+            channel: 1,
+            muted: true,
+        });
+        assert!(!host.is_muted());
+        assert_eq!(
+            host.apply(ControlEvent::Mute {
+                channel: 2,
+                muted: true,
+            }),
+            Some(VolumeAction::SetGain {
+                db_x10: -140,
+                muted: true
+            })
+        );
+        // Same level again: nothing to do
+        assert_eq!(host.apply(volume(1, -4)), None);
+    }
+
+    #[test]
+    fn test_remote_volume() {
+        let mut host = HostVolume::new(VolumeRoute::Remote, 2);
+        assert_eq!(
+            host.apply(volume(0, 0)),
+            Some(VolumeAction::SetRemoteVolume(REMOTE_VOLUME_MAX))
+        );
+        assert_eq!(
+            host.apply(volume(0, -30)),
+            Some(VolumeAction::SetRemoteVolume(63))
+        );
+        assert_eq!(
+            host.apply(volume(0, -100)),
+            Some(VolumeAction::SetRemoteVolume(0))
+        );
+        host.apply(volume(0, -6));
+        assert_eq!(
+            host.apply(ControlEvent::Mute {
+                channel: 0,
+                muted: true,
+            }),
+            Some(VolumeAction::SetRemoteVolume(0))
+        );
+    }
+}
@@ -0,0 +1,62 @@
+//! Boot stage for the Pico 2 W A2DP Source
+//!
+//! Lives in the `BOOT` region at the start of flash, ahead of the
+//! firmware. Installs an update staged over USB DFU, if there is one, and
+//! then starts the firmware. Copying the staged image rewrites the whole
+//! `FLASH` region, which is why the firmware can't do it itself.
+
+#![no_std]
+#![no_main]
+#![deny(unsafe_op_in_unsafe_fn)]
+
+use core::panic::PanicInfo;
+
+use a2dp_app::update::UpdateFlash;
+use a2dp_app::FLASH_LAYOUT;
+use cortex_m::peripheral::SCB;
+use cortex_m_rt::entry;
+use embassy_rp::flash::Flash;
+use usb_audio::{apply_update, DfuError};
+
+/// Address of the firmware's vector table
+const FIRMWARE: u32 = 0x1000_0000 + FLASH_LAYOUT.active;
+
+#[entry]
+fn main() -> ! {
+    let p = embassy_rp::init(Default::default());
+    let mut flash = UpdateFlash(Flash::new_blocking(p.FLASH));
+
+    match apply_update(&mut flash, &FLASH_LAYOUT) {
+        // Nothing staged, the update went in, or a corrupt staged image
+        // was dropped without touching the firmware
+        Ok(_) | Err(DfuError::Crc) => {}
+        // The copy failed partway. The update is still pending, so start
+        // over rather than run a half-written image.
+        Err(_) => SCB::sys_reset(),
+    }
+
+    // SAFETY: the firmware's vector table sits at the start of `FLASH`
+    // and nothing of ours runs after the jump.
+    unsafe { start_firmware(FIRMWARE) }
+}
+
+/// Jump to the image whose vector table is at `addr`
+///
+/// # Safety
+///
+/// `addr` must hold a valid vector table.
+unsafe fn start_firmware(addr: u32) -> ! {
+    // SAFETY: we're the only code running and never return.
+    let mut core = unsafe { cortex_m::Peripherals::steal() };
+    core.SCB.invalidate_icache();
+    // SAFETY: the caller guarantees a vector table at `addr`.
+    unsafe {
+        core.SCB.vtor.write(addr);
+        cortex_m::asm::bootload(addr as *const u32)
+    }
+}
+
+#[panic_handler]
+fn panic(_: &PanicInfo) -> ! {
+    SCB::sys_reset()
+}
@@ -0,0 +1,317 @@
+//! Async wrapper around the SPSC ring buffer
+//!
+//! Lets embassy tasks `await` readable data or free space instead of
+//! polling. Wakers are stored in critical-section protected cells, which
+//! embassy-rp implements with a hardware spinlock, so producer and consumer
+//! may run on different RP2350 cores.
+
+use core::future::poll_fn;
+use core::mem::MaybeUninit;
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+
+use crate::{OverflowMode, RingBuffer};
+
+/// SPSC ring buffer with async waits for the producer and consumer
+///
+/// All writes and reads must go through this wrapper (not through
+/// [`AsyncRingBuffer::inner`]) so the opposite side gets woken.
+pub struct AsyncRingBuffer<T, const N: usize> {
+    buffer: RingBuffer<T, N>,
+    /// Consumer waiting for data
+    read_waker: AtomicWaker,
+    /// Producer waiting for space
+    write_waker: AtomicWaker,
+}
+
+impl<T: Copy, const N: usize> AsyncRingBuffer<T, N> {
+    /// Create a new empty buffer
+    ///
+    /// N must be a power of 2.
+    pub const fn new() -> Self {
+        Self::with_mode(OverflowMode::Reject)
+    }
+
+    /// Create a new empty buffer with the given overflow behavior
+    ///
+    /// N must be a power of 2.
+    pub const fn with_mode(mode: OverflowMode) -> Self {
+        Self {
+            buffer: RingBuffer::with_mode(mode),
+            read_waker: AtomicWaker::new(),
+            write_waker: AtomicWaker::new(),
+        }
+    }
+
+    /// Maximum number of items the buffer can hold
+    pub const fn capacity(&self) -> usize {
+        N - 1
+    }
+
+    /// Underlying ring buffer, for fill-level, watermark and drop queries
+    pub fn inner(&self) -> &RingBuffer<T, N> {
+        &self.buffer
+    }
+
+    /// Number of items that can be read
+    pub fn available_read(&self) -> usize {
+        self.buffer.available_read()
+    }
+
+    /// Number of items that can be written
+    pub fn available_write(&self) -> usize {
+        self.buffer.available_write()
+    }
+
+    /// Write items without waiting (producer only)
+    ///
+    /// Returns the number of items written.
+    pub fn write(&self, data: &[T]) -> usize {
+        let written = self.buffer.write(data);
+        if written > 0 {
+            self.read_waker.wake();
+        }
+        written
+    }
+
+    /// Read items without waiting (consumer only)
+    ///
+    /// Returns the number of items read.
+    pub fn read(&self, buf: &mut [T]) -> usize {
+        let read = self.buffer.read(buf);
+        if read > 0 {
+            self.write_waker.wake();
+        }
+        read
+    }
+
+    /// Free writable region as up to two slices (producer only)
+    ///
+    /// # Safety
+    /// See [`RingBuffer::write_slices`].
+    #[allow(clippy::mut_from_ref)] // Exclusivity is the caller's contract
+    pub unsafe fn write_slices(&self) -> (&mut [MaybeUninit<T>], &mut [MaybeUninit<T>]) {
+        // Safety: Forwarded to the caller
+        unsafe { self.buffer.write_slices() }
+    }
+
+    /// Publish items written through [`AsyncRingBuffer::write_slices`]
+    /// (producer only)
+    ///
+    /// # Safety
+    /// See [`RingBuffer::commit`].
+    pub unsafe fn commit(&self, count: usize) -> usize {
+        // Safety: Forwarded to the caller
+        let committed = unsafe { self.buffer.commit(count) };
+        if committed > 0 {
+            self.read_waker.wake();
+        }
+        committed
+    }
+
+    /// Readable region as up to two slices (consumer only)
+    ///
+    /// # Safety
+    /// See [`RingBuffer::read_slices`].
+    pub unsafe fn read_slices(&self) -> (&[T], &[T]) {
+        // Safety: Forwarded to the caller
+        unsafe { self.buffer.read_slices() }
+    }
+
+    /// Release items obtained from [`AsyncRingBuffer::read_slices`]
+    /// (consumer only)
+    pub fn consume(&self, count: usize) -> usize {
+        let consumed = self.buffer.consume(count);
+        if consumed > 0 {
+            self.write_waker.wake();
+        }
+        consumed
+    }
+
+    /// Wait until at least `count` items are readable (consumer only)
+    ///
+    /// `count` is clamped to the buffer capacity.
+    pub async fn wait_readable(&self, count: usize) {
+        let count = count.min(self.capacity());
+        poll_fn(|cx| {
+            if self.buffer.available_read() >= count {
+                return Poll::Ready(());
+            }
+            self.read_waker.register(cx.waker());
+            // Re-check in case the producer wrote before registration
+            if self.buffer.available_read() >= count {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Wait until at least `count` slots are writable (producer only)
+    ///
+    /// `count` is clamped to the buffer capacity.
+    pub async fn wait_writable(&self, count: usize) {
+        let count = count.min(self.capacity());
+        poll_fn(|cx| {
+            if self.buffer.available_write() >= count {
+                return Poll::Ready(());
+            }
+            self.write_waker.register(cx.waker());
+            // Re-check in case the consumer read before registration
+            if self.buffer.available_write() >= count {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Fill `buf` completely, waiting for data as needed (consumer only)
+    ///
+    /// A buffer no larger than the capacity is filled in a single read, so
+    /// e.g. a full SBC frame of PCM is always taken atomically.
+    pub async fn read_exact(&self, buf: &mut [T]) {
+        let mut pos = 0;
+        while pos < buf.len() {
+            self.wait_readable(buf.len() - pos).await;
+            pos += self.read(&mut buf[pos..]);
+        }
+    }
+
+    /// Write all of `data`, waiting for space as needed (producer only)
+    pub async fn write_all(&self, data: &[T]) {
+        let mut pos = 0;
+        while pos < data.len() {
+            self.wait_writable(data.len() - pos).await;
+            pos += self.write(&data[pos..]);
+        }
+    }
+
+    /// Clear the buffer (both producer and consumer must be idle)
+    pub fn clear(&self) {
+        self.buffer.clear();
+        self.write_waker.wake();
+    }
+}
+
+impl<T: Copy, const N: usize> Default for AsyncRingBuffer<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::future::Future;
+    use core::pin::pin;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::{Context, RawWaker, RawWakerVTable, Waker};
+
+    static WAKES: AtomicUsize = AtomicUsize::new(0);
+
+    fn counting_waker() -> Waker {
+        fn clone(_: *const ()) -> RawWaker {
+            RawWaker::new(core::ptr::null(), &VTABLE)
+        }
+        fn wake(_: *const ()) {
+            WAKES.fetch_add(1, Ordering::SeqCst);
+        }
+        fn drop(_: *const ()) {}
+        static VTABLE: RawWakerVTable = RawWakerVTable::new(clone, wake, wake, drop);
+
+        // Safety: The vtable functions ignore the data pointer
+        unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) }
+    }
+
+    #[test]
+    fn test_wait_readable_wakes_on_write() {
+        let buffer: AsyncRingBuffer<i16, 16> = AsyncRingBuffer::new();
+        let waker = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut fut = pin!(buffer.wait_readable(4));
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+
+        let before = WAKES.load(Ordering::SeqCst);
+        buffer.write(&[1, 2]);
+        assert!(WAKES.load(Ordering::SeqCst) > before);
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+
+        buffer.write(&[3, 4]);
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_wait_writable_wakes_on_read() {
+        let buffer: AsyncRingBuffer<u8, 8> = AsyncRingBuffer::new();
+        buffer.write(&[0; 7]);
+
+        let waker = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(buffer.wait_writable(3));
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+
+        let before = WAKES.load(Ordering::SeqCst);
+        let mut out = [0u8; 3];
+        buffer.read(&mut out);
+        assert!(WAKES.load(Ordering::SeqCst) > before);
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_wait_clamps_to_capacity() {
+        let buffer: AsyncRingBuffer<u8, 8> = AsyncRingBuffer::new();
+        buffer.write(&[0; 7]);
+
+        let waker = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+        let mut fut = pin!(buffer.wait_readable(100));
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+    }
+
+    #[test]
+    fn test_read_exact_across_writes() {
+        let buffer: AsyncRingBuffer<i16, 16> = AsyncRingBuffer::new();
+        let waker = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut out = [0i16; 6];
+        {
+            let mut fut = pin!(buffer.read_exact(&mut out));
+            buffer.write(&[1, 2, 3]);
+            assert!(fut.as_mut().poll(&mut cx).is_pending());
+            buffer.write(&[4, 5, 6, 7]);
+            assert!(fut.as_mut().poll(&mut cx).is_ready());
+        }
+
+        assert_eq!(out, [1, 2, 3, 4, 5, 6]);
+        assert_eq!(buffer.available_read(), 1);
+    }
+
+    #[test]
+    fn test_commit_and_consume_wake() {
+        let buffer: AsyncRingBuffer<u8, 8> = AsyncRingBuffer::new();
+        let waker = counting_waker();
+        let mut cx = Context::from_waker(&waker);
+
+        let mut fut = pin!(buffer.wait_readable(2));
+        assert!(fut.as_mut().poll(&mut cx).is_pending());
+
+        // Safety: Sole producer, slices dropped before the commit
+        let (first, _) = unsafe { buffer.write_slices() };
+        first[0] = MaybeUninit::new(1);
+        first[1] = MaybeUninit::new(2);
+        // Safety: two slots initialized above
+        assert_eq!(unsafe { buffer.commit(2) }, 2);
+        assert!(fut.as_mut().poll(&mut cx).is_ready());
+
+        // Safety: Sole consumer, slices dropped before the consume
+        let (first, _) = unsafe { buffer.read_slices() };
+        assert_eq!(first, &[1, 2]);
+        assert_eq!(buffer.consume(2), 2);
+    }
+}
@@ -0,0 +1,352 @@
+//! Underrun concealment and click-free transitions
+//!
+//! [`Concealer`] sits between the PCM ring buffer and the encoder. When the
+//! buffer runs dry it continues the last output played backwards while
+//! fading it out, so the waveform never jumps; when audio returns it fades
+//! back in over the same length. Source switches and resets get a
+//! crossfade built from the same pieces.
+
+use crate::AudioFormat;
+
+/// Maximum channels handled by the concealer
+const MAX_CHANNELS: usize = 2;
+
+/// Longest supported fade in frames (~10.7 ms at 48 kHz)
+pub const MAX_FADE_FRAMES: usize = 512;
+
+/// History length: a tail reads back at most one fade while new output is
+/// written ahead of it, so two fades never overlap
+const HISTORY_FRAMES: usize = 2 * MAX_FADE_FRAMES;
+
+/// Unity gain in Q15
+const UNITY: i32 = 1 << 15;
+
+/// Concealer configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConcealError {
+    /// Fade time zero or longer than [`MAX_FADE_FRAMES`]
+    InvalidTime,
+    /// More channels than the concealer handles
+    TooManyChannels,
+}
+
+/// Underrun concealer with fade-in, fade-out and crossfades
+pub struct Concealer {
+    format: AudioFormat,
+    fade_frames: usize,
+    /// Gain change per frame in Q15
+    step: i32,
+    /// Gain applied to incoming audio (Q15)
+    live_gain: i32,
+    /// Gain applied to the reversed history (Q15)
+    tail_gain: i32,
+    /// Most recent output frames, written as a ring
+    history: [[i16; MAX_CHANNELS]; HISTORY_FRAMES],
+    /// Next history frame to write
+    history_pos: usize,
+    /// History frame the tail plays next
+    tail_pos: usize,
+    /// Whether the previous frame had incoming audio
+    was_live: bool,
+    crossfade_pending: bool,
+    underruns: u32,
+    concealed_frames: u32,
+}
+
+impl Concealer {
+    /// Create a concealer fading over `fade_us` microseconds
+    ///
+    /// Output starts silent, so the first audio fades in.
+    pub fn new(fade_us: u32, format: AudioFormat) -> Result<Self, ConcealError> {
+        let mut concealer = Self {
+            format,
+            fade_frames: 1,
+            step: UNITY,
+            live_gain: 0,
+            tail_gain: 0,
+            history: [[0; MAX_CHANNELS]; HISTORY_FRAMES],
+            history_pos: 0,
+            tail_pos: 0,
+            was_live: false,
+            crossfade_pending: false,
+            underruns: 0,
+            concealed_frames: 0,
+        };
+        concealer.configure(fade_us, format)?;
+        Ok(concealer)
+    }
+
+    /// Change the fade time and stream format
+    ///
+    /// Resets the concealer.
+    pub fn configure(&mut self, fade_us: u32, format: AudioFormat) -> Result<(), ConcealError> {
+        if format.channels as usize > MAX_CHANNELS {
+            return Err(ConcealError::TooManyChannels);
+        }
+        let frames = (format.sample_rate as u64 * fade_us as u64 / 1_000_000) as usize;
+        if frames == 0 || frames > MAX_FADE_FRAMES {
+            return Err(ConcealError::InvalidTime);
+        }
+
+        self.format = format;
+        self.fade_frames = frames;
+        self.step = (UNITY + frames as i32 - 1) / frames as i32;
+        self.reset();
+        Ok(())
+    }
+
+    /// Fade length in frames
+    pub fn fade_frames(&self) -> usize {
+        self.fade_frames
+    }
+
+    /// Number of underruns (transitions into concealment) seen
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+
+    /// Number of frames filled by concealment
+    pub fn concealed_frames(&self) -> u32 {
+        self.concealed_frames
+    }
+
+    /// Return and reset the underrun and concealed-frame counters
+    pub fn take_stats(&mut self) -> (u32, u32) {
+        (
+            core::mem::take(&mut self.underruns),
+            core::mem::take(&mut self.concealed_frames),
+        )
+    }
+
+    /// Check if the output has fully faded to silence
+    pub fn is_silent(&self) -> bool {
+        self.live_gain == 0 && self.tail_gain == 0
+    }
+
+    /// Return to silence; the next audio fades in
+    pub fn reset(&mut self) {
+        self.live_gain = 0;
+        self.tail_gain = 0;
+        self.history = [[0; MAX_CHANNELS]; HISTORY_FRAMES];
+        self.history_pos = 0;
+        self.tail_pos = 0;
+        self.was_live = false;
+        self.crossfade_pending = false;
+    }
+
+    /// Crossfade from the previous output into the next incoming audio
+    ///
+    /// Call when switching sources or after resetting an upstream stage,
+    /// i.e. whenever the incoming audio is not a continuation of what was
+    /// played before.
+    pub fn crossfade(&mut self) {
+        self.crossfade_pending = true;
+    }
+
+    /// Process a block of interleaved samples in place
+    ///
+    /// The first `valid` samples hold incoming audio; the rest of the block
+    /// is missing (underrun) and gets concealed.
+    pub fn process(&mut self, block: &mut [i16], valid: usize) {
+        let channels = self.format.channels as usize;
+        if channels == 0 {
+            return;
+        }
+
+        let valid_frames = valid.min(block.len()) / channels;
+        for (index, frame) in block.chunks_exact_mut(channels).enumerate() {
+            let live = index < valid_frames;
+
+            if live && self.crossfade_pending {
+                self.crossfade_pending = false;
+                self.start_tail();
+                self.live_gain = 0;
+            } else if !live && self.was_live {
+                self.start_tail();
+                self.live_gain = 0;
+                self.underruns += 1;
+            }
+            self.was_live = live;
+
+            let tail = self.history[self.tail_pos];
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let live_sample = if live { *sample as i32 } else { 0 };
+                let mixed =
+                    live_sample * self.live_gain + tail[ch] as i32 * self.tail_gain + (1 << 14);
+                *sample = (mixed >> 15).clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            }
+
+            if live {
+                self.live_gain = (self.live_gain + self.step).min(UNITY);
+            } else {
+                self.concealed_frames += 1;
+            }
+
+            if self.tail_gain > 0 {
+                self.tail_gain = (self.tail_gain - self.step).max(0);
+                self.tail_pos = (self.tail_pos + HISTORY_FRAMES - 1) % HISTORY_FRAMES;
+            }
+
+            let slot = &mut self.history[self.history_pos];
+            slot[..channels].copy_from_slice(frame);
+            self.history_pos = (self.history_pos + 1) % HISTORY_FRAMES;
+        }
+    }
+
+    /// Start playing the history backwards from the newest frame
+    ///
+    /// History holds the mixed output, so restarting during a running tail
+    /// continues from what was last played rather than dropping the live
+    /// part of the mix.
+    fn start_tail(&mut self) {
+        self.tail_pos = (self.history_pos + HISTORY_FRAMES - 1) % HISTORY_FRAMES;
+        self.tail_gain = UNITY;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 48 frames = 1 ms at 48 kHz
+    const FADE_US: u32 = 1000;
+
+    fn stereo() -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    /// Concealer already faded in on a constant level
+    fn running(level: i16) -> Concealer {
+        let mut concealer = Concealer::new(FADE_US, stereo()).unwrap();
+        let mut block = [level; 192];
+        concealer.process(&mut block, 192);
+        concealer
+    }
+
+    fn max_step(samples: &[i16], channels: usize) -> i32 {
+        samples
+            .iter()
+            .zip(samples.iter().skip(channels))
+            .map(|(a, b)| (*a as i32 - *b as i32).abs())
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn test_fades_in_then_passes_through() {
+        let mut concealer = Concealer::new(FADE_US, stereo()).unwrap();
+        assert_eq!(concealer.fade_frames(), 48);
+        assert!(concealer.is_silent());
+
+        let mut block = [8000i16; 192];
+        concealer.process(&mut block, 192);
+        assert_eq!(block[0], 0);
+        assert!(block[40] > 0 && block[40] < 8000);
+        assert!(block[96..].iter().all(|&s| s == 8000));
+
+        let mut block = [1234i16; 64];
+        concealer.process(&mut block, 64);
+        assert!(block.iter().all(|&s| s == 1234));
+    }
+
+    #[test]
+    fn test_underrun_fades_out_without_step() {
+        let mut concealer = running(10_000);
+
+        // Half a block of audio, then nothing
+        let mut block = [10_000i16; 192];
+        concealer.process(&mut block, 40);
+        assert_eq!(block[38], 10_000);
+        assert_eq!(block[40], 10_000);
+        assert!(max_step(&block, 2) <= 10_000 / 48 + 2);
+        assert!(block[40 + 48 * 2..].iter().all(|&s| s == 0));
+        assert!(concealer.is_silent());
+
+        assert_eq!(concealer.underruns(), 1);
+        assert_eq!(concealer.concealed_frames(), 76);
+    }
+
+    #[test]
+    fn test_recovery_fades_in() {
+        let mut concealer = running(10_000);
+        let mut block = [0i16; 192];
+        concealer.process(&mut block, 0);
+        assert!(concealer.is_silent());
+
+        let mut block = [-6000i16; 192];
+        concealer.process(&mut block, 192);
+        assert_eq!(block[0], 0);
+        assert!(max_step(&block, 2) <= 6000 / 48 + 2);
+        assert_eq!(block[191], -6000);
+    }
+
+    #[test]
+    fn test_short_dropout_crossfades_back() {
+        let mut concealer = running(10_000);
+
+        // Two missing frames, then audio at a different level
+        let mut block = [10_000i16; 4];
+        concealer.process(&mut block, 0);
+        let mut block = [-10_000i16; 192];
+        concealer.process(&mut block, 192);
+        assert!(max_step(&block, 2) <= 2 * 20_000 / 48 + 2);
+        assert_eq!(block[191], -10_000);
+    }
+
+    #[test]
+    fn test_crossfade_on_source_switch() {
+        let mut concealer = running(12_000);
+        concealer.crossfade();
+
+        let mut block = [-12_000i16; 192];
+        concealer.process(&mut block, 192);
+        assert_eq!(block[0], 12_000);
+        assert!(max_step(&block, 2) <= 2 * 24_000 / 48 + 2);
+        assert_eq!(block[191], -12_000);
+        assert_eq!(concealer.underruns(), 0);
+    }
+
+    #[test]
+    fn test_underrun_during_crossfade() {
+        let mut concealer = running(12_000);
+        concealer.crossfade();
+
+        // Ten frames into the crossfade the new source runs dry
+        let mut block = [-12_000i16; 192];
+        concealer.process(&mut block, 20);
+        assert!(max_step(&block, 2) <= 2 * 24_000 / 48 + 2);
+        assert!(concealer.is_silent());
+        assert_eq!(concealer.underruns(), 1);
+    }
+
+    #[test]
+    fn test_take_stats_and_validation() {
+        let mut concealer = running(100);
+        let mut block = [0i16; 20];
+        concealer.process(&mut block, 0);
+        assert_eq!(concealer.take_stats(), (1, 10));
+        assert_eq!(concealer.take_stats(), (0, 0));
+
+        assert_eq!(
+            Concealer::new(0, stereo()).err(),
+            Some(ConcealError::InvalidTime)
+        );
+        assert_eq!(
+            Concealer::new(20_000, stereo()).err(),
+            Some(ConcealError::InvalidTime)
+        );
+        let surround = AudioFormat {
+            channels: 6,
+            ..stereo()
+        };
+        assert_eq!(
+            Concealer::new(FADE_US, surround).err(),
+            Some(ConcealError::TooManyChannels)
+        );
+    }
+}
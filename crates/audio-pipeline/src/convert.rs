@@ -0,0 +1,406 @@
+//! Sample format, channel and rate conversion
+//!
+//! [`FormatConverter`] turns the little-endian PCM the USB host sends into
+//! interleaved i16 in the format the encoder was configured for. Only the
+//! stages the two formats actually need are run: 24- and 32-bit input is
+//! requantized to 16 bits, mono and stereo are mapped onto each other, and
+//! differing rates are bridged by a linear-interpolating resampler with a
+//! low-pass in front of it when decimating.
+
+use crate::eq::BiquadState;
+use crate::{AudioFormat, BiquadCoeffs, EqBand, FilterType};
+
+/// Maximum channels on either side of the converter
+const MAX_CHANNELS: usize = 2;
+
+/// Anti-aliasing corner as a fraction of the output rate, in percent
+const ANTI_ALIAS_CORNER_PCT: u32 = 45;
+
+/// Q of the two sections of a 4th-order Butterworth low-pass, in hundredths
+const ANTI_ALIAS_Q_X100: [u16; 2] = [54, 131];
+
+/// Converter configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConvertError {
+    /// Input not 16, 24 or 32 bits, or output not 16 bits
+    UnsupportedBitDepth,
+    /// Channel count other than mono or stereo
+    UnsupportedChannels,
+    /// Sample rate out of range
+    InvalidSampleRate,
+}
+
+/// How input channels are mapped onto output channels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ChannelMap {
+    /// Same channel count on both sides
+    Direct,
+    /// Stereo averaged down to mono
+    Downmix,
+    /// Mono copied to both stereo channels
+    Upmix,
+}
+
+/// Stages a converter runs between its input and output format
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ConversionStages {
+    /// Input wider than 16 bits is rounded down to 16
+    pub requantize: bool,
+    /// Channel mapping
+    pub channel_map: ChannelMap,
+    /// Sample rates differ
+    pub resample: bool,
+    /// Low-pass ahead of the resampler (output rate below input rate)
+    pub anti_alias: bool,
+}
+
+impl ConversionStages {
+    /// Check if the input is copied through unchanged
+    pub fn is_passthrough(&self) -> bool {
+        !self.requantize && self.channel_map == ChannelMap::Direct && !self.resample
+    }
+}
+
+/// Streaming linear-interpolation resampler
+///
+/// The position of the next output frame between the previous and the
+/// current input frame is tracked in units of `1 / out_rate` input frames,
+/// so the ratio is exact and the output never drifts against the input.
+#[derive(Debug, Clone, Copy)]
+struct Resampler {
+    in_rate: u32,
+    out_rate: u32,
+    phase: u32,
+    prev: [i32; MAX_CHANNELS],
+}
+
+impl Resampler {
+    fn new(in_rate: u32, out_rate: u32) -> Self {
+        Self {
+            in_rate,
+            out_rate,
+            phase: 0,
+            prev: [0; MAX_CHANNELS],
+        }
+    }
+
+    /// Most output frames produced by one input frame
+    fn max_frames_per_push(&self) -> usize {
+        self.out_rate.div_ceil(self.in_rate) as usize
+    }
+
+    /// Feed one input frame, writing the output frames that fall before it
+    fn push(&mut self, frame: &[i32], out: &mut [i16]) -> usize {
+        let channels = frame.len();
+        let mut written = 0;
+        while self.phase < self.out_rate {
+            for (ch, &sample) in frame.iter().enumerate() {
+                let prev = self.prev[ch] as i64;
+                let delta = (sample as i64 - prev) * self.phase as i64 / self.out_rate as i64;
+                out[written + ch] = (prev + delta) as i16;
+            }
+            written += channels;
+            self.phase += self.in_rate;
+        }
+        self.phase -= self.out_rate;
+        self.prev[..channels].copy_from_slice(frame);
+        written
+    }
+
+    fn reset(&mut self) {
+        self.phase = 0;
+        self.prev = [0; MAX_CHANNELS];
+    }
+}
+
+/// Converts interleaved PCM bytes between two formats
+pub struct FormatConverter {
+    input: AudioFormat,
+    output: AudioFormat,
+    stages: ConversionStages,
+    resampler: Resampler,
+    anti_alias: [BiquadCoeffs; 2],
+    filter: [[BiquadState; 2]; MAX_CHANNELS],
+}
+
+impl FormatConverter {
+    /// Create a converter from `input` to 16-bit `output`
+    pub fn new(input: AudioFormat, output: AudioFormat) -> Result<Self, ConvertError> {
+        if !matches!(input.bits_per_sample, 16 | 24 | 32) || output.bits_per_sample != 16 {
+            return Err(ConvertError::UnsupportedBitDepth);
+        }
+        for format in [input, output] {
+            if !(1..=MAX_CHANNELS as u8).contains(&format.channels) {
+                return Err(ConvertError::UnsupportedChannels);
+            }
+            if !(8000..=192_000).contains(&format.sample_rate) {
+                return Err(ConvertError::InvalidSampleRate);
+            }
+        }
+
+        let stages = ConversionStages {
+            requantize: input.bits_per_sample != 16,
+            channel_map: match (input.channels, output.channels) {
+                (2, 1) => ChannelMap::Downmix,
+                (1, 2) => ChannelMap::Upmix,
+                _ => ChannelMap::Direct,
+            },
+            resample: input.sample_rate != output.sample_rate,
+            anti_alias: output.sample_rate < input.sample_rate,
+        };
+
+        let mut anti_alias = [BiquadCoeffs::IDENTITY; 2];
+        if stages.anti_alias {
+            let corner = output.sample_rate / 100 * ANTI_ALIAS_CORNER_PCT;
+            for (coeffs, q_x100) in anti_alias.iter_mut().zip(ANTI_ALIAS_Q_X100) {
+                let band = EqBand::new(FilterType::LowPass, corner, q_x100, 0);
+                *coeffs = BiquadCoeffs::design(&band, input.sample_rate)
+                    .map_err(|_| ConvertError::InvalidSampleRate)?;
+            }
+        }
+
+        Ok(Self {
+            input,
+            output,
+            stages,
+            resampler: Resampler::new(input.sample_rate, output.sample_rate),
+            anti_alias,
+            filter: [[BiquadState::default(); 2]; MAX_CHANNELS],
+        })
+    }
+
+    /// Format of the input bytes
+    pub fn input(&self) -> AudioFormat {
+        self.input
+    }
+
+    /// Format of the output samples
+    pub fn output(&self) -> AudioFormat {
+        self.output
+    }
+
+    /// Stages this converter runs
+    pub fn stages(&self) -> ConversionStages {
+        self.stages
+    }
+
+    /// Delay added by the converter in output frames
+    /// (for `LatencyModel::set_dsp_frames`)
+    pub fn latency_frames(&self) -> usize {
+        // The resampler interpolates towards the newest input frame
+        if self.stages.resample {
+            self.resampler.max_frames_per_push()
+        } else {
+            0
+        }
+    }
+
+    /// Output buffer length, in samples, that always holds the conversion
+    /// of `input_bytes` bytes
+    pub fn max_output_len(&self, input_bytes: usize) -> usize {
+        let frames = input_bytes / self.input.bytes_per_sample();
+        let out_frames = frames as u64 * self.output.sample_rate as u64
+            / self.input.sample_rate as u64
+            + self.resampler.max_frames_per_push() as u64;
+        out_frames as usize * self.output.channels as usize
+    }
+
+    /// Clear filter and resampler history
+    pub fn reset(&mut self) {
+        self.resampler.reset();
+        self.filter = [[BiquadState::default(); 2]; MAX_CHANNELS];
+    }
+
+    /// Convert whole input frames from `input` into `output`
+    ///
+    /// Stops early when `output` might not hold the next frame's
+    /// conversion. Returns the number of input bytes consumed and output
+    /// samples written.
+    pub fn process(&mut self, input: &[u8], output: &mut [i16]) -> (usize, usize) {
+        let out_channels = self.output.channels as usize;
+        let bytes_per_frame = self.input.bytes_per_sample();
+        let width = self.input.bits_per_sample as usize / 8;
+        let room = if self.stages.resample {
+            self.resampler.max_frames_per_push() * out_channels
+        } else {
+            out_channels
+        };
+
+        let mut consumed = 0;
+        let mut written = 0;
+        for bytes in input.chunks_exact(bytes_per_frame) {
+            if output.len() - written < room {
+                break;
+            }
+
+            let mut decoded = [0i32; MAX_CHANNELS];
+            for (sample, raw) in decoded.iter_mut().zip(bytes.chunks_exact(width)) {
+                *sample = decode(raw);
+            }
+
+            let mut frame = [0i32; MAX_CHANNELS];
+            match self.stages.channel_map {
+                ChannelMap::Direct => frame = decoded,
+                ChannelMap::Downmix => frame[0] = (decoded[0] + decoded[1]) >> 1,
+                ChannelMap::Upmix => frame = [decoded[0]; MAX_CHANNELS],
+            }
+            let frame = &mut frame[..out_channels];
+
+            if self.stages.anti_alias {
+                for (sample, filter) in frame.iter_mut().zip(self.filter.iter_mut()) {
+                    let mut x = *sample;
+                    for (state, coeffs) in filter.iter_mut().zip(&self.anti_alias) {
+                        x = state.process(coeffs, x);
+                    }
+                    *sample = x.clamp(i16::MIN as i32, i16::MAX as i32);
+                }
+            }
+
+            if self.stages.resample {
+                written += self.resampler.push(frame, &mut output[written..]);
+            } else {
+                for (out, &sample) in output[written..].iter_mut().zip(frame.iter()) {
+                    *out = sample as i16;
+                }
+                written += out_channels;
+            }
+            consumed += bytes_per_frame;
+        }
+
+        (consumed, written)
+    }
+}
+
+/// Decode one little-endian sample, rounding it to 16 bits
+fn decode(raw: &[u8]) -> i32 {
+    match *raw {
+        [lo, hi] => i16::from_le_bytes([lo, hi]) as i32,
+        [b0, b1, b2] => {
+            let value = i32::from_le_bytes([0, b0, b1, b2]) >> 8;
+            ((value + 0x80) >> 8).min(i16::MAX as i32)
+        }
+        [b0, b1, b2, b3] => {
+            let value = i32::from_le_bytes([b0, b1, b2, b3]) as i64;
+            ((value + 0x8000) >> 16).min(i16::MAX as i64) as i32
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(sample_rate: u32, channels: u8, bits_per_sample: u8) -> AudioFormat {
+        AudioFormat {
+            sample_rate,
+            channels,
+            bits_per_sample,
+        }
+    }
+
+    fn to_bytes(samples: &[i16], buf: &mut [u8]) -> usize {
+        for (chunk, sample) in buf.chunks_exact_mut(2).zip(samples) {
+            chunk.copy_from_slice(&sample.to_le_bytes());
+        }
+        samples.len() * 2
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let fmt = format(48000, 2, 16);
+        let mut conv = FormatConverter::new(fmt, fmt).unwrap();
+        assert!(conv.stages().is_passthrough());
+        assert_eq!(conv.latency_frames(), 0);
+
+        let samples = [1i16, -2, 300, -400, i16::MAX, i16::MIN];
+        let mut bytes = [0u8; 12];
+        to_bytes(&samples, &mut bytes);
+        let mut out = [0i16; 6];
+        assert_eq!(conv.process(&bytes, &mut out), (12, 6));
+        assert_eq!(out, samples);
+    }
+
+    #[test]
+    fn test_requantizes_wide_samples() {
+        let mut conv = FormatConverter::new(format(48000, 1, 24), format(48000, 1, 16)).unwrap();
+        // 0x123480 rounds up, -1 (0xFFFFFF) rounds to zero, full scale saturates
+        let bytes = [0x80, 0x34, 0x12, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x7F];
+        let mut out = [0i16; 3];
+        assert_eq!(conv.process(&bytes, &mut out), (9, 3));
+        assert_eq!(out, [0x1235, 0, i16::MAX]);
+
+        let mut conv = FormatConverter::new(format(48000, 1, 32), format(48000, 1, 16)).unwrap();
+        let bytes = [0x00, 0x00, 0x00, 0x80, 0x00, 0x40, 0x34, 0x12];
+        let mut out = [0i16; 2];
+        conv.process(&bytes, &mut out);
+        assert_eq!(out, [i16::MIN, 0x1234]);
+    }
+
+    #[test]
+    fn test_channel_mapping() {
+        let mut bytes = [0u8; 8];
+        let len = to_bytes(&[1000, 3000, -500, 500], &mut bytes);
+
+        let mut down = FormatConverter::new(format(44100, 2, 16), format(44100, 1, 16)).unwrap();
+        assert_eq!(down.stages().channel_map, ChannelMap::Downmix);
+        let mut out = [0i16; 2];
+        assert_eq!(down.process(&bytes[..len], &mut out), (8, 2));
+        assert_eq!(out, [2000, 0]);
+
+        let mut up = FormatConverter::new(format(44100, 1, 16), format(44100, 2, 16)).unwrap();
+        assert_eq!(up.stages().channel_map, ChannelMap::Upmix);
+        let mut out = [0i16; 8];
+        assert_eq!(up.process(&bytes[..len], &mut out), (8, 8));
+        assert_eq!(out, [1000, 1000, 3000, 3000, -500, -500, 500, 500]);
+    }
+
+    #[test]
+    fn test_resample_ratio_is_exact() {
+        let mut conv = FormatConverter::new(format(44100, 2, 16), format(48000, 2, 16)).unwrap();
+        let bytes = [0u8; 441 * 4];
+        let mut out = [0i16; 2 * 480 + 8];
+        let mut produced = 0;
+        for _ in 0..100 {
+            let (consumed, written) = conv.process(&bytes, &mut out);
+            assert_eq!(consumed, bytes.len());
+            assert!(written <= conv.max_output_len(bytes.len()));
+            produced += written / 2;
+        }
+        // One second in, one second out
+        assert_eq!(produced, 48000);
+    }
+
+    #[test]
+    fn test_stops_when_output_full() {
+        let mut conv = FormatConverter::new(format(16000, 1, 16), format(48000, 1, 16)).unwrap();
+        let bytes = [0u8; 20];
+        let mut out = [0i16; 10];
+        // Each input frame yields three output frames
+        assert_eq!(conv.process(&bytes, &mut out), (6, 9));
+    }
+
+    #[test]
+    fn test_rejects_unsupported_formats() {
+        let ok = format(48000, 2, 16);
+        assert_eq!(
+            FormatConverter::new(format(48000, 2, 8), ok).err(),
+            Some(ConvertError::UnsupportedBitDepth)
+        );
+        assert_eq!(
+            FormatConverter::new(ok, format(48000, 2, 24)).err(),
+            Some(ConvertError::UnsupportedBitDepth)
+        );
+        assert_eq!(
+            FormatConverter::new(format(48000, 6, 16), ok).err(),
+            Some(ConvertError::UnsupportedChannels)
+        );
+        assert_eq!(
+            FormatConverter::new(ok, format(0, 2, 16)).err(),
+            Some(ConvertError::InvalidSampleRate)
+        );
+    }
+}
@@ -0,0 +1,677 @@
+//! Dynamic range compressor and look-ahead limiter
+//!
+//! Both stages are stereo-linked: a single gain is derived from the loudest
+//! channel and applied to all channels so the stereo image does not shift.
+//! The control path runs in f32 (the Cortex-M33 has a single-precision FPU);
+//! audio stays in interleaved i16 blocks, the same layout `SbcEncoder`
+//! consumes.
+
+use crate::math;
+use crate::AudioFormat;
+
+/// Maximum channels processed by the dynamics stages
+const MAX_CHANNELS: usize = 2;
+
+/// Maximum look-ahead in frames (~1.3 ms at 48 kHz)
+pub const MAX_LOOKAHEAD_FRAMES: usize = 64;
+
+/// Taps of the true-peak interpolation filter
+const TP_TAPS: usize = 8;
+
+/// Oversampling factor of the true-peak detector
+const TP_OVERSAMPLE: usize = 4;
+
+/// Detection delay introduced by the true-peak interpolator, in frames
+const TP_DELAY: usize = TP_TAPS / 2;
+
+/// Full-scale amplitude of an i16 sample
+const FULL_SCALE: f32 = 32768.0;
+
+/// Dynamics configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DynamicsError {
+    /// Threshold or ceiling above 0 dBFS
+    InvalidThreshold,
+    /// Ratio below 1:1
+    InvalidRatio,
+    /// Attack or release time out of range
+    InvalidTime,
+    /// Makeup gain out of range
+    InvalidGain,
+}
+
+/// Compressor parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CompressorConfig {
+    /// Threshold in tenths of a dBFS
+    pub threshold_db_x10: i16,
+    /// Compression ratio in tenths (e.g. 40 = 4:1)
+    pub ratio_x10: u16,
+    /// Soft knee width in tenths of a dB (0 = hard knee)
+    pub knee_db_x10: u16,
+    /// Attack time constant in microseconds
+    pub attack_us: u32,
+    /// Release time constant in milliseconds
+    pub release_ms: u32,
+    /// Gain applied after compression, in tenths of a dB
+    pub makeup_db_x10: i16,
+}
+
+impl Default for CompressorConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db_x10: -180,
+            ratio_x10: 30,
+            knee_db_x10: 60,
+            attack_us: 5_000,
+            release_ms: 100,
+            makeup_db_x10: 0,
+        }
+    }
+}
+
+impl CompressorConfig {
+    /// Check parameter ranges
+    pub fn validate(&self) -> Result<(), DynamicsError> {
+        if self.threshold_db_x10 > 0 || self.threshold_db_x10 < -600 {
+            return Err(DynamicsError::InvalidThreshold);
+        }
+
+        if self.ratio_x10 < 10 {
+            return Err(DynamicsError::InvalidRatio);
+        }
+
+        if self.attack_us == 0 || self.attack_us > 500_000 {
+            return Err(DynamicsError::InvalidTime);
+        }
+
+        if self.release_ms == 0 || self.release_ms > 5_000 {
+            return Err(DynamicsError::InvalidTime);
+        }
+
+        if self.makeup_db_x10 < -240 || self.makeup_db_x10 > 240 {
+            return Err(DynamicsError::InvalidGain);
+        }
+
+        Ok(())
+    }
+}
+
+/// Limiter parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LimiterConfig {
+    /// Maximum true-peak output level in tenths of a dBTP
+    pub ceiling_db_x10: i16,
+    /// Look-ahead time in microseconds
+    pub lookahead_us: u32,
+    /// Release time constant in milliseconds
+    pub release_ms: u32,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self {
+            ceiling_db_x10: -10,
+            lookahead_us: 1_000,
+            release_ms: 50,
+        }
+    }
+}
+
+impl LimiterConfig {
+    /// Check parameter ranges
+    pub fn validate(&self) -> Result<(), DynamicsError> {
+        if self.ceiling_db_x10 > 0 || self.ceiling_db_x10 < -200 {
+            return Err(DynamicsError::InvalidThreshold);
+        }
+
+        if self.release_ms == 0 || self.release_ms > 5_000 {
+            return Err(DynamicsError::InvalidTime);
+        }
+
+        Ok(())
+    }
+
+    /// Look-ahead length in frames for a sample rate (at least 1)
+    pub fn lookahead_frames(&self, sample_rate: u32) -> usize {
+        let frames = (self.lookahead_us as u64 * sample_rate as u64 / 1_000_000) as usize;
+        frames.clamp(1, MAX_LOOKAHEAD_FRAMES)
+    }
+}
+
+/// Gain reduction meter
+///
+/// Tracks the current reduction and the largest reduction since the last
+/// [`GainReductionMeter::reset`].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct GainReductionMeter {
+    current_db: f32,
+    peak_db: f32,
+}
+
+impl GainReductionMeter {
+    /// Current gain reduction in tenths of a dB
+    pub fn current_db_x10(&self) -> u16 {
+        (self.current_db * 10.0 + 0.5) as u16
+    }
+
+    /// Largest gain reduction since the last reset, in tenths of a dB
+    pub fn peak_db_x10(&self) -> u16 {
+        (self.peak_db * 10.0 + 0.5) as u16
+    }
+
+    /// Reset the peak reading
+    pub fn reset(&mut self) {
+        self.peak_db = self.current_db;
+    }
+
+    fn update(&mut self, reduction_db: f32) {
+        self.current_db = reduction_db;
+        if reduction_db > self.peak_db {
+            self.peak_db = reduction_db;
+        }
+    }
+}
+
+/// One-pole smoothing coefficient for a time constant
+fn time_coeff(time_us: f32, sample_rate: u32) -> f32 {
+    math::exp(-1_000_000.0 / (time_us * sample_rate as f32))
+}
+
+/// Round and saturate to i16
+fn to_i16(value: f32) -> i16 {
+    let rounded = if value < 0.0 {
+        value - 0.5
+    } else {
+        value + 0.5
+    };
+    rounded.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Stereo-linked feed-forward compressor
+///
+/// Uses a soft-knee gain computer with attack/release smoothing applied to
+/// the gain reduction in the log domain.
+pub struct Compressor {
+    config: CompressorConfig,
+    format: AudioFormat,
+    threshold: f32,
+    slope: f32,
+    knee: f32,
+    makeup: f32,
+    attack: f32,
+    release: f32,
+    reduction_db: f32,
+    meter: GainReductionMeter,
+}
+
+impl Compressor {
+    /// Create a compressor for a stream format
+    pub fn new(config: CompressorConfig, format: AudioFormat) -> Result<Self, DynamicsError> {
+        let mut compressor = Self {
+            config,
+            format,
+            threshold: 0.0,
+            slope: 0.0,
+            knee: 0.0,
+            makeup: 1.0,
+            attack: 0.0,
+            release: 0.0,
+            reduction_db: 0.0,
+            meter: GainReductionMeter::default(),
+        };
+        compressor.apply(config, format)?;
+        Ok(compressor)
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> &CompressorConfig {
+        &self.config
+    }
+
+    /// Replace the configuration
+    ///
+    /// On error the previous configuration stays active.
+    pub fn set_config(&mut self, config: CompressorConfig) -> Result<(), DynamicsError> {
+        self.apply(config, self.format)
+    }
+
+    /// Change the stream format, recomputing time constants
+    pub fn set_format(&mut self, format: AudioFormat) -> Result<(), DynamicsError> {
+        self.apply(self.config, format)
+    }
+
+    /// Gain reduction meter
+    pub fn meter(&self) -> &GainReductionMeter {
+        &self.meter
+    }
+
+    /// Mutable gain reduction meter (for resetting the peak)
+    pub fn meter_mut(&mut self) -> &mut GainReductionMeter {
+        &mut self.meter
+    }
+
+    /// Clear envelope state
+    pub fn reset(&mut self) {
+        self.reduction_db = 0.0;
+        self.meter = GainReductionMeter::default();
+    }
+
+    /// Process interleaved samples in place
+    pub fn process(&mut self, samples: &mut [i16]) {
+        let channels = (self.format.channels as usize).clamp(1, MAX_CHANNELS);
+
+        for frame in samples.chunks_mut(channels) {
+            let peak = frame
+                .iter()
+                .map(|&s| (s as i32).unsigned_abs())
+                .max()
+                .unwrap_or(0);
+            let level_db = math::linear_to_db((peak as f32 / FULL_SCALE).max(1e-6));
+            let target = self.gain_computer(level_db);
+
+            let coeff = if target > self.reduction_db {
+                self.attack
+            } else {
+                self.release
+            };
+            self.reduction_db = coeff * self.reduction_db + (1.0 - coeff) * target;
+
+            let gain = math::db_to_linear(-self.reduction_db) * self.makeup;
+            for sample in frame.iter_mut() {
+                *sample = to_i16(*sample as f32 * gain);
+            }
+        }
+
+        self.meter.update(self.reduction_db);
+    }
+
+    /// Static gain reduction in dB for an input level in dBFS
+    fn gain_computer(&self, level_db: f32) -> f32 {
+        let over = level_db - self.threshold;
+
+        if 2.0 * over <= -self.knee {
+            0.0
+        } else if 2.0 * over < self.knee {
+            let x = over + self.knee / 2.0;
+            self.slope * x * x / (2.0 * self.knee)
+        } else {
+            self.slope * over
+        }
+    }
+
+    fn apply(
+        &mut self,
+        config: CompressorConfig,
+        format: AudioFormat,
+    ) -> Result<(), DynamicsError> {
+        config.validate()?;
+
+        self.config = config;
+        self.format = format;
+        self.threshold = config.threshold_db_x10 as f32 / 10.0;
+        self.slope = 1.0 - 10.0 / config.ratio_x10 as f32;
+        self.knee = config.knee_db_x10 as f32 / 10.0;
+        self.makeup = math::db_to_linear(config.makeup_db_x10 as f32 / 10.0);
+        self.attack = time_coeff(config.attack_us as f32, format.sample_rate);
+        self.release = time_coeff(config.release_ms as f32 * 1000.0, format.sample_rate);
+        Ok(())
+    }
+}
+
+/// Stereo-linked true-peak look-ahead limiter
+///
+/// Inter-sample peaks are estimated with a 4x windowed-sinc interpolator.
+/// The required gain is held over the look-ahead window and smoothed with a
+/// moving average of the same length, so the gain has fully ramped down by
+/// the time a peak leaves the delay line. Output is additionally clamped at
+/// the ceiling, so sample peaks never exceed it.
+pub struct Limiter {
+    config: LimiterConfig,
+    format: AudioFormat,
+    ceiling: f32,
+    release: f32,
+    lookahead: usize,
+    /// Interpolation coefficients for the 3 points between two samples
+    interp: [[f32; TP_TAPS]; TP_OVERSAMPLE - 1],
+    /// Per-channel input history for the true-peak detector
+    history: [[f32; TP_TAPS]; MAX_CHANNELS],
+    /// Inter-sample peak of the previous interval
+    prev_interval: f32,
+    /// Delay line of samples awaiting output
+    delay: [[i16; MAX_CHANNELS]; MAX_LOOKAHEAD_FRAMES],
+    /// Required gain per frame over the look-ahead window
+    required: [f32; MAX_LOOKAHEAD_FRAMES],
+    /// Held (windowed minimum) gain per frame over the look-ahead window
+    held: [f32; MAX_LOOKAHEAD_FRAMES],
+    pos: usize,
+    gain: f32,
+    meter: GainReductionMeter,
+}
+
+impl Limiter {
+    /// Create a limiter for a stream format
+    pub fn new(config: LimiterConfig, format: AudioFormat) -> Result<Self, DynamicsError> {
+        let mut limiter = Self {
+            config,
+            format,
+            ceiling: 1.0,
+            release: 0.0,
+            lookahead: 1,
+            interp: Self::design_interpolator(),
+            history: [[0.0; TP_TAPS]; MAX_CHANNELS],
+            prev_interval: 0.0,
+            delay: [[0; MAX_CHANNELS]; MAX_LOOKAHEAD_FRAMES],
+            required: [1.0; MAX_LOOKAHEAD_FRAMES],
+            held: [1.0; MAX_LOOKAHEAD_FRAMES],
+            pos: 0,
+            gain: 1.0,
+            meter: GainReductionMeter::default(),
+        };
+        limiter.apply(config, format)?;
+        Ok(limiter)
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> &LimiterConfig {
+        &self.config
+    }
+
+    /// Replace the configuration
+    ///
+    /// On error the previous configuration stays active.
+    pub fn set_config(&mut self, config: LimiterConfig) -> Result<(), DynamicsError> {
+        self.apply(config, self.format)
+    }
+
+    /// Change the stream format, recomputing the look-ahead length
+    pub fn set_format(&mut self, format: AudioFormat) -> Result<(), DynamicsError> {
+        self.apply(self.config, format)
+    }
+
+    /// Gain reduction meter
+    pub fn meter(&self) -> &GainReductionMeter {
+        &self.meter
+    }
+
+    /// Mutable gain reduction meter (for resetting the peak)
+    pub fn meter_mut(&mut self) -> &mut GainReductionMeter {
+        &mut self.meter
+    }
+
+    /// Processing delay in frames (look-ahead plus detector delay)
+    pub fn latency_frames(&self) -> usize {
+        self.lookahead - 1 + TP_DELAY
+    }
+
+    /// Clear delay line and gain state
+    pub fn reset(&mut self) {
+        self.history = [[0.0; TP_TAPS]; MAX_CHANNELS];
+        self.prev_interval = 0.0;
+        self.delay = [[0; MAX_CHANNELS]; MAX_LOOKAHEAD_FRAMES];
+        self.required = [1.0; MAX_LOOKAHEAD_FRAMES];
+        self.held = [1.0; MAX_LOOKAHEAD_FRAMES];
+        self.pos = 0;
+        self.gain = 1.0;
+        self.meter = GainReductionMeter::default();
+    }
+
+    /// Process interleaved samples in place
+    ///
+    /// Output is delayed by [`Limiter::latency_frames`].
+    pub fn process(&mut self, samples: &mut [i16]) {
+        let channels = (self.format.channels as usize).clamp(1, MAX_CHANNELS);
+        let limit = to_i16(self.ceiling * FULL_SCALE).max(1);
+        let mut max_reduction = 0.0f32;
+
+        for frame in samples.chunks_mut(channels) {
+            // True-peak estimate around the sample leaving the detector
+            let mut peak = self.prev_interval;
+            let mut interval = 0.0f32;
+            let mut center = [0i16; MAX_CHANNELS];
+
+            for (ch, &sample) in frame.iter().enumerate() {
+                let hist = &mut self.history[ch];
+                hist.copy_within(1.., 0);
+                hist[TP_TAPS - 1] = sample as f32;
+
+                center[ch] = hist[TP_TAPS - 1 - TP_DELAY] as i16;
+                peak = peak.max(math::abs(hist[TP_TAPS - 1 - TP_DELAY]));
+
+                for coeffs in &self.interp {
+                    let value: f32 = coeffs.iter().zip(hist.iter()).map(|(c, x)| c * x).sum();
+                    interval = interval.max(math::abs(value));
+                }
+            }
+            peak = peak.max(interval);
+            self.prev_interval = interval;
+
+            let required = if peak > self.ceiling * FULL_SCALE {
+                self.ceiling * FULL_SCALE / peak
+            } else {
+                1.0
+            };
+
+            // Hold the minimum over the window, then average the held values
+            let n = self.lookahead;
+            self.required[self.pos] = required;
+            self.held[self.pos] = self.required[..n].iter().fold(1.0f32, |a, &b| a.min(b));
+            let smoothed = self.held[..n].iter().sum::<f32>() / n as f32;
+
+            self.gain = if smoothed < self.gain {
+                smoothed
+            } else {
+                self.release * self.gain + (1.0 - self.release) * smoothed
+            };
+
+            // Oldest frame in the window is the one to output
+            self.delay[self.pos] = center;
+            self.pos = (self.pos + 1) % n;
+            let delayed = self.delay[self.pos];
+
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let value = to_i16(delayed[ch] as f32 * self.gain);
+                *sample = value.clamp(-limit, limit);
+            }
+
+            max_reduction = max_reduction.max(-math::linear_to_db(self.gain));
+        }
+
+        self.meter.update(max_reduction);
+    }
+
+    /// Windowed-sinc coefficients for the points at 1/4, 2/4 and 3/4
+    /// between the two center taps
+    fn design_interpolator() -> [[f32; TP_TAPS]; TP_OVERSAMPLE - 1] {
+        let mut interp = [[0.0; TP_TAPS]; TP_OVERSAMPLE - 1];
+        let center = (TP_TAPS / 2 - 1) as f32;
+        let half_width = (TP_TAPS / 2) as f32;
+
+        for (phase, coeffs) in interp.iter_mut().enumerate() {
+            let position = center + (phase + 1) as f32 / TP_OVERSAMPLE as f32;
+            let mut sum = 0.0;
+
+            for (tap, coeff) in coeffs.iter_mut().enumerate() {
+                let d = position - tap as f32;
+                let x = math::PI * d;
+                let sinc = math::sin(x) / x;
+                let window = 0.5 * (1.0 + math::cos(math::PI * d / half_width));
+                *coeff = sinc * window;
+                sum += *coeff;
+            }
+
+            for coeff in coeffs.iter_mut() {
+                *coeff /= sum;
+            }
+        }
+
+        interp
+    }
+
+    fn apply(&mut self, config: LimiterConfig, format: AudioFormat) -> Result<(), DynamicsError> {
+        config.validate()?;
+
+        self.config = config;
+        self.format = format;
+        self.ceiling = math::db_to_linear(config.ceiling_db_x10 as f32 / 10.0);
+        self.release = time_coeff(config.release_ms as f32 * 1000.0, format.sample_rate);
+        self.lookahead = config.lookahead_frames(format.sample_rate);
+        self.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo() -> AudioFormat {
+        AudioFormat::default()
+    }
+
+    /// Deterministic pseudo-random full-scale signal
+    fn noise(seed: &mut u32) -> i16 {
+        *seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+        (*seed >> 16) as i16
+    }
+
+    fn tone(block: &mut [i16], start: usize, amplitude: f32) {
+        for (i, frame) in block.chunks_mut(2).enumerate() {
+            let t = (start + i) as f32;
+            let s = (amplitude * math::sin(2.0 * math::PI * 1000.0 * t / 44100.0)) as i16;
+            frame[0] = s;
+            frame[1] = s;
+        }
+    }
+
+    #[test]
+    fn test_compressor_below_threshold_is_transparent() {
+        let mut comp = Compressor::new(CompressorConfig::default(), stereo()).unwrap();
+        let mut block = [0i16; 256];
+        tone(&mut block, 0, 1000.0); // About -30 dBFS
+        let expected = block;
+
+        comp.process(&mut block);
+        assert_eq!(block, expected);
+        assert_eq!(comp.meter().current_db_x10(), 0);
+    }
+
+    #[test]
+    fn test_compressor_reduces_loud_signal() {
+        let config = CompressorConfig {
+            threshold_db_x10: -200,
+            ratio_x10: 40,
+            knee_db_x10: 0,
+            ..Default::default()
+        };
+        let mut comp = Compressor::new(config, stereo()).unwrap();
+
+        // Full scale is 20 dB over threshold: 4:1 leaves 5 dB, so 15 dB reduction
+        let mut block = [0i16; 256];
+        for n in 0..200 {
+            tone(&mut block, n * 128, 32000.0);
+            comp.process(&mut block);
+        }
+
+        let reduction = comp.meter().current_db_x10();
+        assert!((140..=160).contains(&reduction), "reduction {}", reduction);
+        assert!(comp.meter().peak_db_x10() >= reduction);
+    }
+
+    #[test]
+    fn test_compressor_is_stereo_linked() {
+        let mut comp = Compressor::new(CompressorConfig::default(), stereo()).unwrap();
+        let mut block = [0i16; 256];
+        for _ in 0..100 {
+            for frame in block.chunks_mut(2) {
+                frame[0] = 30000;
+                frame[1] = 3000;
+            }
+            comp.process(&mut block);
+        }
+
+        // The quiet right channel is reduced by the same factor as the left
+        let ratio = block[0] as f32 / block[1] as f32;
+        assert!(ratio > 9.9 && ratio < 10.1);
+        assert!(block[1] < 3000);
+    }
+
+    #[test]
+    fn test_limiter_never_exceeds_ceiling() {
+        let config = LimiterConfig::default();
+        let mut limiter = Limiter::new(config, stereo()).unwrap();
+        let limit = to_i16(math::db_to_linear(-1.0) * FULL_SCALE);
+
+        let mut seed = 1;
+        let mut block = [0i16; 256];
+        for _ in 0..50 {
+            for sample in block.iter_mut() {
+                *sample = noise(&mut seed);
+            }
+            limiter.process(&mut block);
+            assert!(block.iter().all(|&s| s >= -limit && s <= limit));
+        }
+        assert!(limiter.meter().peak_db_x10() > 0);
+    }
+
+    #[test]
+    fn test_limiter_delays_quiet_signal_unchanged() {
+        let mut limiter = Limiter::new(LimiterConfig::default(), stereo()).unwrap();
+        let latency = limiter.latency_frames();
+        assert_eq!(latency, 44 - 1 + TP_DELAY);
+
+        let mut input = [0i16; 256];
+        tone(&mut input, 0, 8000.0);
+        let mut block = input;
+        limiter.process(&mut block);
+
+        for i in 0..(128 - latency) {
+            assert_eq!(block[2 * (i + latency)], input[2 * i]);
+        }
+        assert_eq!(limiter.meter().current_db_x10(), 0);
+    }
+
+    #[test]
+    fn test_limiter_catches_intersample_peak() {
+        // Alternating samples at fs/4 phase-shifted: samples sit at 0.707
+        // of the true waveform peak
+        let config = LimiterConfig {
+            ceiling_db_x10: -10,
+            ..Default::default()
+        };
+        let mut limiter = Limiter::new(config, stereo()).unwrap();
+        let mut block = [0i16; 256];
+        for n in 0..10 {
+            for (i, frame) in block.chunks_mut(2).enumerate() {
+                let t = (n * 128 + i) as f32;
+                let s = (32000.0 * math::sin(math::PI / 2.0 * t + math::PI / 4.0)) as i16;
+                frame[0] = s;
+                frame[1] = s;
+            }
+            limiter.process(&mut block);
+        }
+
+        // Sample peak 22627 is below the -1 dB ceiling (29204), but the
+        // true peak of 32000 is not, so the limiter must still engage
+        assert!(limiter.meter().current_db_x10() >= 5);
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let config = CompressorConfig {
+            ratio_x10: 5,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(DynamicsError::InvalidRatio));
+
+        let config = LimiterConfig {
+            ceiling_db_x10: 10,
+            ..Default::default()
+        };
+        assert_eq!(config.validate(), Err(DynamicsError::InvalidThreshold));
+        assert_eq!(LimiterConfig::default().lookahead_frames(48000), 48);
+    }
+}
@@ -0,0 +1,628 @@
+//! Multi-band parametric equalizer
+//!
+//! Cascaded biquad filters in fixed point, designed from
+//! frequency/Q/gain parameters using the RBJ Audio EQ Cookbook formulas.
+//! Coefficients are computed once per sample rate; the per-sample path is
+//! pure integer arithmetic on interleaved i16 blocks.
+
+use crate::math;
+use crate::AudioFormat;
+
+/// Maximum number of bands in an EQ preset
+pub const MAX_EQ_BANDS: usize = 8;
+
+/// Maximum channels processed by the equalizer
+const MAX_CHANNELS: usize = 2;
+
+/// Fractional bits of the coefficient format (Q3.28)
+const COEFF_SHIFT: u32 = 28;
+
+/// Serialized size of one band in bytes
+const BAND_BYTES: usize = 9;
+
+/// Serialized size of an [`EqPreset`] in bytes
+pub const EQ_PRESET_BYTES: usize = 3 + MAX_EQ_BANDS * BAND_BYTES;
+
+/// Equalizer errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum EqError {
+    /// Frequency is zero or above Nyquist for the sample rate
+    InvalidFrequency,
+    /// Q is outside the supported range
+    InvalidQ,
+    /// Gain is outside the supported range
+    InvalidGain,
+    /// More bands than [`MAX_EQ_BANDS`]
+    TooManyBands,
+    /// Serialized data is truncated or malformed
+    InvalidData,
+    /// Output buffer too small
+    BufferTooSmall,
+}
+
+/// Biquad filter shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum FilterType {
+    /// Peaking (bell) filter
+    #[default]
+    Peaking = 0,
+    /// Low shelf
+    LowShelf = 1,
+    /// High shelf
+    HighShelf = 2,
+    /// 2nd-order low-pass
+    LowPass = 3,
+    /// 2nd-order high-pass
+    HighPass = 4,
+    /// 1st-order DC blocker (frequency sets the corner, Q and gain unused)
+    DcBlocker = 5,
+}
+
+impl FilterType {
+    /// Parse from the serialized byte value
+    pub const fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Self::Peaking),
+            1 => Some(Self::LowShelf),
+            2 => Some(Self::HighShelf),
+            3 => Some(Self::LowPass),
+            4 => Some(Self::HighPass),
+            5 => Some(Self::DcBlocker),
+            _ => None,
+        }
+    }
+}
+
+/// One equalizer band
+///
+/// Parameters are stored as integers so presets serialize losslessly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EqBand {
+    /// Filter shape
+    pub filter_type: FilterType,
+    /// Center/corner frequency in Hz
+    pub freq_hz: u32,
+    /// Quality factor in hundredths (e.g. 71 = 0.71)
+    pub q_x100: u16,
+    /// Gain in tenths of a dB (peaking and shelves only)
+    pub gain_db_x10: i16,
+}
+
+impl EqBand {
+    /// Minimum Q (0.10)
+    pub const MIN_Q_X100: u16 = 10;
+    /// Maximum Q (20.00)
+    pub const MAX_Q_X100: u16 = 2000;
+    /// Maximum boost/cut (+/-18 dB)
+    pub const MAX_GAIN_DB_X10: i16 = 180;
+
+    /// Create a new band
+    pub const fn new(filter_type: FilterType, freq_hz: u32, q_x100: u16, gain_db_x10: i16) -> Self {
+        Self {
+            filter_type,
+            freq_hz,
+            q_x100,
+            gain_db_x10,
+        }
+    }
+
+    /// Check the band parameters against a sample rate
+    pub fn validate(&self, sample_rate: u32) -> Result<(), EqError> {
+        if self.freq_hz == 0 || self.freq_hz >= sample_rate / 2 {
+            return Err(EqError::InvalidFrequency);
+        }
+
+        if self.q_x100 < Self::MIN_Q_X100 || self.q_x100 > Self::MAX_Q_X100 {
+            return Err(EqError::InvalidQ);
+        }
+
+        if self.gain_db_x10 < -Self::MAX_GAIN_DB_X10 || self.gain_db_x10 > Self::MAX_GAIN_DB_X10 {
+            return Err(EqError::InvalidGain);
+        }
+
+        Ok(())
+    }
+
+    fn to_bytes(self, buf: &mut [u8]) {
+        buf[0] = self.filter_type as u8;
+        buf[1..5].copy_from_slice(&self.freq_hz.to_le_bytes());
+        buf[5..7].copy_from_slice(&self.q_x100.to_le_bytes());
+        buf[7..9].copy_from_slice(&self.gain_db_x10.to_le_bytes());
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self {
+            filter_type: FilterType::from_u8(bytes[0])?,
+            freq_hz: u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]),
+            q_x100: u16::from_le_bytes([bytes[5], bytes[6]]),
+            gain_db_x10: i16::from_le_bytes([bytes[7], bytes[8]]),
+        })
+    }
+}
+
+/// Equalizer preset
+///
+/// A fixed-size, serializable set of bands plus a preamp gain, suitable
+/// for storing in the application configuration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct EqPreset {
+    /// Gain applied before the bands, in tenths of a dB
+    pub preamp_db_x10: i16,
+    /// Number of active bands
+    pub num_bands: u8,
+    /// Band parameters (only the first `num_bands` are used)
+    pub bands: [EqBand; MAX_EQ_BANDS],
+}
+
+impl Default for EqPreset {
+    fn default() -> Self {
+        Self::flat()
+    }
+}
+
+impl EqPreset {
+    /// Preset with no bands and unity gain
+    pub const fn flat() -> Self {
+        Self {
+            preamp_db_x10: 0,
+            num_bands: 0,
+            bands: [EqBand::new(FilterType::Peaking, 1000, 71, 0); MAX_EQ_BANDS],
+        }
+    }
+
+    /// Preset for a typical small speaker: protective high-pass, low-mid
+    /// cut and a gentle treble lift, with preamp headroom for the boost
+    pub const fn small_speaker() -> Self {
+        let mut preset = Self::flat();
+        preset.preamp_db_x10 = -30;
+        preset.num_bands = 3;
+        preset.bands[0] = EqBand::new(FilterType::HighPass, 80, 71, 0);
+        preset.bands[1] = EqBand::new(FilterType::Peaking, 400, 100, -30);
+        preset.bands[2] = EqBand::new(FilterType::HighShelf, 6000, 71, 30);
+        preset
+    }
+
+    /// Append a band
+    pub fn push(&mut self, band: EqBand) -> Result<(), EqError> {
+        let idx = self.num_bands as usize;
+        if idx >= MAX_EQ_BANDS {
+            return Err(EqError::TooManyBands);
+        }
+        self.bands[idx] = band;
+        self.num_bands += 1;
+        Ok(())
+    }
+
+    /// Active bands
+    pub fn active_bands(&self) -> &[EqBand] {
+        &self.bands[..(self.num_bands as usize).min(MAX_EQ_BANDS)]
+    }
+
+    /// Check every band against a sample rate
+    pub fn validate(&self, sample_rate: u32) -> Result<(), EqError> {
+        if self.num_bands as usize > MAX_EQ_BANDS {
+            return Err(EqError::TooManyBands);
+        }
+
+        if self.preamp_db_x10 < -EqBand::MAX_GAIN_DB_X10
+            || self.preamp_db_x10 > EqBand::MAX_GAIN_DB_X10
+        {
+            return Err(EqError::InvalidGain);
+        }
+
+        for band in self.active_bands() {
+            band.validate(sample_rate)?;
+        }
+
+        Ok(())
+    }
+
+    /// Serialize to bytes
+    ///
+    /// Returns the number of bytes written ([`EQ_PRESET_BYTES`]).
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<usize, EqError> {
+        if buf.len() < EQ_PRESET_BYTES {
+            return Err(EqError::BufferTooSmall);
+        }
+
+        buf[0] = self.num_bands;
+        buf[1..3].copy_from_slice(&self.preamp_db_x10.to_le_bytes());
+
+        for (i, band) in self.bands.iter().enumerate() {
+            let start = 3 + i * BAND_BYTES;
+            band.to_bytes(&mut buf[start..start + BAND_BYTES]);
+        }
+
+        Ok(EQ_PRESET_BYTES)
+    }
+
+    /// Parse from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, EqError> {
+        if bytes.len() < EQ_PRESET_BYTES || bytes[0] as usize > MAX_EQ_BANDS {
+            return Err(EqError::InvalidData);
+        }
+
+        let mut preset = Self::flat();
+        preset.num_bands = bytes[0];
+        preset.preamp_db_x10 = i16::from_le_bytes([bytes[1], bytes[2]]);
+
+        for i in 0..MAX_EQ_BANDS {
+            let start = 3 + i * BAND_BYTES;
+            preset.bands[i] = EqBand::from_bytes(&bytes[start..start + BAND_BYTES])
+                .ok_or(EqError::InvalidData)?;
+        }
+
+        Ok(preset)
+    }
+}
+
+/// Normalized biquad coefficients in Q3.28 (`a0` is implicitly 1)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct BiquadCoeffs {
+    /// Feed-forward coefficient for x[n]
+    pub b0: i32,
+    /// Feed-forward coefficient for x[n-1]
+    pub b1: i32,
+    /// Feed-forward coefficient for x[n-2]
+    pub b2: i32,
+    /// Feedback coefficient for y[n-1]
+    pub a1: i32,
+    /// Feedback coefficient for y[n-2]
+    pub a2: i32,
+}
+
+impl BiquadCoeffs {
+    /// Pass-through filter
+    pub const IDENTITY: Self = Self {
+        b0: 1 << COEFF_SHIFT,
+        b1: 0,
+        b2: 0,
+        a1: 0,
+        a2: 0,
+    };
+
+    /// Design coefficients for a band at the given sample rate
+    pub fn design(band: &EqBand, sample_rate: u32) -> Result<Self, EqError> {
+        band.validate(sample_rate)?;
+
+        let fs = sample_rate as f32;
+        let w0 = 2.0 * math::PI * band.freq_hz as f32 / fs;
+        let cos_w0 = math::cos(w0);
+        let alpha = math::sin(w0) / (2.0 * band.q_x100 as f32 / 100.0);
+        let gain_db = band.gain_db_x10 as f32 / 10.0;
+        // A = 10^(gain/40), sqrt(A) = 10^(gain/80)
+        let a = math::db_to_linear(gain_db / 2.0);
+        let sqrt_a_alpha = 2.0 * math::db_to_linear(gain_db / 4.0) * alpha;
+
+        let (b0, b1, b2, a0, a1, a2) = match band.filter_type {
+            FilterType::Peaking => (
+                1.0 + alpha * a,
+                -2.0 * cos_w0,
+                1.0 - alpha * a,
+                1.0 + alpha / a,
+                -2.0 * cos_w0,
+                1.0 - alpha / a,
+            ),
+            FilterType::LowShelf => (
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos_w0),
+                a * ((a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos_w0),
+                (a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+            FilterType::HighShelf => (
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 + sqrt_a_alpha),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos_w0),
+                a * ((a + 1.0) + (a - 1.0) * cos_w0 - sqrt_a_alpha),
+                (a + 1.0) - (a - 1.0) * cos_w0 + sqrt_a_alpha,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos_w0),
+                (a + 1.0) - (a - 1.0) * cos_w0 - sqrt_a_alpha,
+            ),
+            FilterType::LowPass => (
+                (1.0 - cos_w0) / 2.0,
+                1.0 - cos_w0,
+                (1.0 - cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+            FilterType::HighPass => (
+                (1.0 + cos_w0) / 2.0,
+                -(1.0 + cos_w0),
+                (1.0 + cos_w0) / 2.0,
+                1.0 + alpha,
+                -2.0 * cos_w0,
+                1.0 - alpha,
+            ),
+            FilterType::DcBlocker => {
+                // y[n] = x[n] - x[n-1] + R * y[n-1]
+                let r = 1.0 - w0;
+                (1.0, -1.0, 0.0, 1.0, -r, 0.0)
+            }
+        };
+
+        Ok(Self {
+            b0: to_fixed(b0 / a0),
+            b1: to_fixed(b1 / a0),
+            b2: to_fixed(b2 / a0),
+            a1: to_fixed(a1 / a0),
+            a2: to_fixed(a2 / a0),
+        })
+    }
+}
+
+/// Convert a coefficient to Q3.28
+fn to_fixed(value: f32) -> i32 {
+    (value * (1u32 << COEFF_SHIFT) as f32) as i32
+}
+
+/// Direct Form I filter history for one channel
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct BiquadState {
+    x1: i32,
+    x2: i32,
+    y1: i32,
+    y2: i32,
+}
+
+impl BiquadState {
+    #[inline]
+    pub(crate) fn process(&mut self, c: &BiquadCoeffs, x: i32) -> i32 {
+        let acc = (c.b0 as i64) * (x as i64)
+            + (c.b1 as i64) * (self.x1 as i64)
+            + (c.b2 as i64) * (self.x2 as i64)
+            - (c.a1 as i64) * (self.y1 as i64)
+            - (c.a2 as i64) * (self.y2 as i64);
+
+        // Keep internal headroom well beyond i16 so cascaded boosts don't wrap
+        let y = (acc >> COEFF_SHIFT).clamp(-(1 << 20), 1 << 20) as i32;
+
+        self.x2 = self.x1;
+        self.x1 = x;
+        self.y2 = self.y1;
+        self.y1 = y;
+        y
+    }
+}
+
+/// Multi-band equalizer
+///
+/// Processes interleaved i16 blocks in place. All state is pre-allocated.
+pub struct Equalizer {
+    preset: EqPreset,
+    format: AudioFormat,
+    preamp: i32,
+    coeffs: [BiquadCoeffs; MAX_EQ_BANDS],
+    state: [[BiquadState; MAX_EQ_BANDS]; MAX_CHANNELS],
+}
+
+impl Equalizer {
+    /// Create an equalizer for a preset and stream format
+    pub fn new(preset: EqPreset, format: AudioFormat) -> Result<Self, EqError> {
+        let mut eq = Self {
+            preset: EqPreset::flat(),
+            format,
+            preamp: 1 << COEFF_SHIFT,
+            coeffs: [BiquadCoeffs::IDENTITY; MAX_EQ_BANDS],
+            state: [[BiquadState::default(); MAX_EQ_BANDS]; MAX_CHANNELS],
+        };
+        eq.set_preset(preset)?;
+        Ok(eq)
+    }
+
+    /// Current preset
+    pub fn preset(&self) -> &EqPreset {
+        &self.preset
+    }
+
+    /// Current stream format
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Replace the preset, recomputing coefficients
+    ///
+    /// On error the previous preset stays active.
+    pub fn set_preset(&mut self, preset: EqPreset) -> Result<(), EqError> {
+        self.apply(preset, self.format)
+    }
+
+    /// Change the stream format, recomputing coefficients for the new rate
+    pub fn set_format(&mut self, format: AudioFormat) -> Result<(), EqError> {
+        self.apply(self.preset, format)
+    }
+
+    /// Coefficients of an active band
+    pub fn coeffs(&self, band: usize) -> Option<&BiquadCoeffs> {
+        self.coeffs[..self.preset.num_bands as usize].get(band)
+    }
+
+    /// Clear filter history
+    pub fn reset(&mut self) {
+        self.state = [[BiquadState::default(); MAX_EQ_BANDS]; MAX_CHANNELS];
+    }
+
+    /// Process interleaved samples in place
+    pub fn process(&mut self, samples: &mut [i16]) {
+        let channels = (self.format.channels as usize).clamp(1, MAX_CHANNELS);
+        let num_bands = self.preset.num_bands as usize;
+
+        for frame in samples.chunks_mut(channels) {
+            for (ch, sample) in frame.iter_mut().enumerate() {
+                let mut x = ((*sample as i64 * self.preamp as i64) >> COEFF_SHIFT) as i32;
+                for band in 0..num_bands {
+                    x = self.state[ch][band].process(&self.coeffs[band], x);
+                }
+                *sample = x.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+            }
+        }
+    }
+
+    fn apply(&mut self, preset: EqPreset, format: AudioFormat) -> Result<(), EqError> {
+        preset.validate(format.sample_rate)?;
+
+        let mut coeffs = [BiquadCoeffs::IDENTITY; MAX_EQ_BANDS];
+        for (coeff, band) in coeffs.iter_mut().zip(preset.active_bands()) {
+            *coeff = BiquadCoeffs::design(band, format.sample_rate)?;
+        }
+
+        self.preset = preset;
+        self.format = format;
+        self.coeffs = coeffs;
+        self.preamp = to_fixed(math::db_to_linear(preset.preamp_db_x10 as f32 / 10.0));
+        self.reset();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RATES: [u32; 4] = [16000, 32000, 44100, 48000];
+
+    fn stereo(rate: u32) -> AudioFormat {
+        AudioFormat {
+            sample_rate: rate,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    /// Peak amplitude of the left channel after running a sine through `eq`
+    fn sine_response(eq: &mut Equalizer, freq: u32, amplitude: f32) -> i32 {
+        let rate = eq.format().sample_rate as f32;
+        let mut peak = 0;
+        let mut block = [0i16; 256];
+        for n in 0..64 {
+            for i in 0..128 {
+                let t = (n * 128 + i) as f32;
+                let s = (amplitude * math::sin(2.0 * math::PI * freq as f32 * t / rate)) as i16;
+                block[2 * i] = s;
+                block[2 * i + 1] = s;
+            }
+            eq.process(&mut block);
+            // Skip the first blocks while the filter settles
+            if n >= 32 {
+                for i in 0..128 {
+                    peak = peak.max((block[2 * i] as i32).abs());
+                }
+            }
+        }
+        peak
+    }
+
+    #[test]
+    fn test_flat_is_transparent() {
+        let mut eq = Equalizer::new(EqPreset::flat(), stereo(44100)).unwrap();
+        let mut block: [i16; 8] = [0, 1, -1, 1000, -32768, 32767, 12345, -54];
+        let expected = block;
+        eq.process(&mut block);
+        assert_eq!(block, expected);
+    }
+
+    #[test]
+    fn test_peaking_boost_at_center() {
+        let mut preset = EqPreset::flat();
+        preset
+            .push(EqBand::new(FilterType::Peaking, 1000, 100, 60))
+            .unwrap();
+
+        for rate in RATES {
+            let mut eq = Equalizer::new(preset, stereo(rate)).unwrap();
+            let peak = sine_response(&mut eq, 1000, 8000.0);
+            // +6 dB is roughly 2x
+            assert!(peak > 15000 && peak < 17000, "rate {} peak {}", rate, peak);
+        }
+    }
+
+    #[test]
+    fn test_lowpass_attenuates_highs() {
+        let mut preset = EqPreset::flat();
+        preset
+            .push(EqBand::new(FilterType::LowPass, 1000, 71, 0))
+            .unwrap();
+        let mut eq = Equalizer::new(preset, stereo(48000)).unwrap();
+
+        let passband = sine_response(&mut eq, 100, 10000.0);
+        eq.reset();
+        let stopband = sine_response(&mut eq, 10000, 10000.0);
+
+        assert!(passband > 9500);
+        assert!(stopband < 200);
+    }
+
+    #[test]
+    fn test_dc_blocker_removes_offset() {
+        let mut preset = EqPreset::flat();
+        preset
+            .push(EqBand::new(FilterType::DcBlocker, 20, 71, 0))
+            .unwrap();
+        let mut eq = Equalizer::new(preset, stereo(44100)).unwrap();
+
+        let mut block = [5000i16; 256];
+        for _ in 0..200 {
+            block = [5000i16; 256];
+            eq.process(&mut block);
+        }
+        assert!(block.iter().all(|&s| s.abs() < 10));
+    }
+
+    #[test]
+    fn test_shelves_design_for_every_rate() {
+        let low = EqBand::new(FilterType::LowShelf, 200, 71, 60);
+        let high = EqBand::new(FilterType::HighShelf, 5000, 71, -60);
+        for rate in RATES {
+            assert!(BiquadCoeffs::design(&low, rate).is_ok());
+            assert!(BiquadCoeffs::design(&high, rate).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_band_validation() {
+        let band = EqBand::new(FilterType::Peaking, 10000, 71, 0);
+        assert_eq!(band.validate(16000), Err(EqError::InvalidFrequency));
+        assert!(band.validate(44100).is_ok());
+
+        let band = EqBand::new(FilterType::Peaking, 1000, 5, 0);
+        assert_eq!(band.validate(44100), Err(EqError::InvalidQ));
+
+        let band = EqBand::new(FilterType::Peaking, 1000, 71, 200);
+        assert_eq!(band.validate(44100), Err(EqError::InvalidGain));
+    }
+
+    #[test]
+    fn test_invalid_preset_keeps_previous() {
+        let mut eq = Equalizer::new(EqPreset::small_speaker(), stereo(44100)).unwrap();
+        let mut bad = EqPreset::flat();
+        bad.push(EqBand::new(FilterType::Peaking, 30000, 71, 0))
+            .unwrap();
+
+        assert_eq!(eq.set_preset(bad), Err(EqError::InvalidFrequency));
+        assert_eq!(*eq.preset(), EqPreset::small_speaker());
+    }
+
+    #[test]
+    fn test_preset_roundtrip() {
+        let preset = EqPreset::small_speaker();
+        let mut buf = [0u8; EQ_PRESET_BYTES];
+        assert_eq!(preset.to_bytes(&mut buf), Ok(EQ_PRESET_BYTES));
+        assert_eq!(EqPreset::from_bytes(&buf), Ok(preset));
+
+        assert_eq!(
+            preset.to_bytes(&mut buf[..10]),
+            Err(EqError::BufferTooSmall)
+        );
+        assert_eq!(EqPreset::from_bytes(&buf[..10]), Err(EqError::InvalidData));
+
+        buf[3] = 0xFF; // Unknown filter type
+        assert_eq!(EqPreset::from_bytes(&buf), Err(EqError::InvalidData));
+    }
+}
@@ -0,0 +1,423 @@
+//! Timestamped queue of encoded frames
+//!
+//! Sits between `SbcEncoder` and the Bluetooth sender. Frames are stored
+//! contiguously in a byte arena so the sender can copy them straight into
+//! a media packet, and each frame keeps its media timestamp and sample
+//! count so the queue can report its depth in time rather than bytes.
+//!
+//! The queue itself is not thread-safe; share it between cores behind an
+//! `embassy_sync::blocking_mutex::Mutex`.
+
+/// Frame queue errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FrameQueueError {
+    /// Frame is empty or larger than the byte arena
+    FrameTooLarge,
+    /// No room for the frame
+    Full,
+}
+
+/// Metadata of a queued frame
+#[derive(Debug, Clone, Copy, Default)]
+struct FrameSlot {
+    offset: usize,
+    len: usize,
+    timestamp: u32,
+    samples: u16,
+}
+
+/// Borrowed view of a queued frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueuedFrame<'a> {
+    /// Encoded frame bytes
+    pub data: &'a [u8],
+    /// Media timestamp of the first sample
+    pub timestamp: u32,
+    /// PCM samples per channel in the frame
+    pub samples: u16,
+}
+
+/// Fixed-capacity queue of variable-length encoded frames
+///
+/// Holds at most `FRAMES` frames and `BYTES` bytes of frame data.
+pub struct FrameQueue<const FRAMES: usize, const BYTES: usize> {
+    data: [u8; BYTES],
+    slots: [FrameSlot; FRAMES],
+    /// Index of the oldest frame in `slots`
+    head: usize,
+    /// Number of queued frames
+    count: usize,
+    /// Total queued PCM samples per channel
+    queued_samples: u32,
+    /// Total queued bytes
+    queued_bytes: usize,
+    /// Frames dropped to make room or trim latency
+    dropped: u32,
+    sample_rate: u32,
+}
+
+impl<const FRAMES: usize, const BYTES: usize> FrameQueue<FRAMES, BYTES> {
+    /// Create an empty queue for a stream at `sample_rate` Hz
+    pub const fn new(sample_rate: u32) -> Self {
+        assert!(FRAMES > 0, "Queue must hold at least one frame");
+
+        Self {
+            data: [0; BYTES],
+            slots: [FrameSlot {
+                offset: 0,
+                len: 0,
+                timestamp: 0,
+                samples: 0,
+            }; FRAMES],
+            head: 0,
+            count: 0,
+            queued_samples: 0,
+            queued_bytes: 0,
+            dropped: 0,
+            sample_rate,
+        }
+    }
+
+    /// Change the sample rate used for duration reporting
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+    }
+
+    /// Number of queued frames
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Check if the queue is empty
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Check if no more frames can be queued regardless of size
+    pub fn is_full(&self) -> bool {
+        self.count == FRAMES
+    }
+
+    /// Total bytes of queued frame data
+    pub fn queued_bytes(&self) -> usize {
+        self.queued_bytes
+    }
+
+    /// Total queued PCM samples per channel
+    pub fn queued_samples(&self) -> u32 {
+        self.queued_samples
+    }
+
+    /// Duration of queued audio in microseconds
+    pub fn queued_duration_us(&self) -> u32 {
+        if self.sample_rate == 0 {
+            return 0;
+        }
+        ((self.queued_samples as u64 * 1_000_000) / self.sample_rate as u64) as u32
+    }
+
+    /// Number of frames dropped since creation or the last
+    /// [`FrameQueue::take_dropped`]
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Return and reset the dropped-frame counter
+    pub fn take_dropped(&mut self) -> u32 {
+        core::mem::take(&mut self.dropped)
+    }
+
+    /// Queue a frame
+    pub fn push(
+        &mut self,
+        frame: &[u8],
+        timestamp: u32,
+        samples: u16,
+    ) -> Result<(), FrameQueueError> {
+        if frame.is_empty() || frame.len() > BYTES {
+            return Err(FrameQueueError::FrameTooLarge);
+        }
+
+        if self.count == FRAMES {
+            return Err(FrameQueueError::Full);
+        }
+
+        let offset = self.find_space(frame.len()).ok_or(FrameQueueError::Full)?;
+        self.data[offset..offset + frame.len()].copy_from_slice(frame);
+
+        let idx = (self.head + self.count) % FRAMES;
+        self.slots[idx] = FrameSlot {
+            offset,
+            len: frame.len(),
+            timestamp,
+            samples,
+        };
+        self.count += 1;
+        self.queued_samples += samples as u32;
+        self.queued_bytes += frame.len();
+
+        Ok(())
+    }
+
+    /// Queue a frame, dropping the oldest frames until it fits
+    ///
+    /// Returns the number of frames dropped.
+    pub fn push_overwrite(
+        &mut self,
+        frame: &[u8],
+        timestamp: u32,
+        samples: u16,
+    ) -> Result<usize, FrameQueueError> {
+        let mut dropped = 0;
+        loop {
+            match self.push(frame, timestamp, samples) {
+                Ok(()) => return Ok(dropped),
+                Err(FrameQueueError::Full) if !self.is_empty() => {
+                    self.discard_front();
+                    dropped += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Oldest queued frame
+    pub fn front(&self) -> Option<QueuedFrame<'_>> {
+        self.get(0)
+    }
+
+    /// Queued frame at position `index` (0 = oldest)
+    pub fn get(&self, index: usize) -> Option<QueuedFrame<'_>> {
+        if index >= self.count {
+            return None;
+        }
+
+        let slot = &self.slots[(self.head + index) % FRAMES];
+        Some(QueuedFrame {
+            data: &self.data[slot.offset..slot.offset + slot.len],
+            timestamp: slot.timestamp,
+            samples: slot.samples,
+        })
+    }
+
+    /// Iterate over queued frames, oldest first
+    pub fn iter(&self) -> impl Iterator<Item = QueuedFrame<'_>> {
+        (0..self.count).filter_map(move |i| self.get(i))
+    }
+
+    /// Remove the oldest frame
+    ///
+    /// Returns its timestamp and sample count.
+    pub fn pop_front(&mut self) -> Option<(u32, u16)> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let slot = self.slots[self.head];
+        self.head = (self.head + 1) % FRAMES;
+        self.count -= 1;
+        self.queued_samples -= slot.samples as u32;
+        self.queued_bytes -= slot.len;
+
+        Some((slot.timestamp, slot.samples))
+    }
+
+    /// How many frames from the front fit in a payload of `max_bytes`
+    ///
+    /// Returns `(frames, bytes)`, capped at `max_frames` frames (A2DP
+    /// allows at most 15 SBC frames per media packet).
+    pub fn frames_fitting(&self, max_bytes: usize, max_frames: usize) -> (usize, usize) {
+        let mut frames = 0;
+        let mut bytes = 0;
+
+        for frame in self.iter().take(max_frames) {
+            if bytes + frame.data.len() > max_bytes {
+                break;
+            }
+            frames += 1;
+            bytes += frame.data.len();
+        }
+
+        (frames, bytes)
+    }
+
+    /// Drop the `count` oldest frames (e.g. when the link stalls)
+    ///
+    /// Returns the number of frames dropped.
+    pub fn drop_oldest(&mut self, count: usize) -> usize {
+        let count = count.min(self.count);
+        for _ in 0..count {
+            self.discard_front();
+        }
+        count
+    }
+
+    /// Drop the oldest frames until at most `max_us` of audio is queued
+    ///
+    /// Returns the number of frames dropped.
+    pub fn trim_to_duration(&mut self, max_us: u32) -> usize {
+        let mut dropped = 0;
+        while self.queued_duration_us() > max_us && !self.is_empty() {
+            self.discard_front();
+            dropped += 1;
+        }
+        dropped
+    }
+
+    /// Remove all frames
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.count = 0;
+        self.queued_samples = 0;
+        self.queued_bytes = 0;
+    }
+
+    fn discard_front(&mut self) {
+        if self.pop_front().is_some() {
+            self.dropped = self.dropped.wrapping_add(1);
+        }
+    }
+
+    /// Find a contiguous free region of `len` bytes in the arena
+    fn find_space(&self, len: usize) -> Option<usize> {
+        if self.count == 0 {
+            return Some(0);
+        }
+
+        let oldest = &self.slots[self.head];
+        let newest = &self.slots[(self.head + self.count - 1) % FRAMES];
+        let write_pos = newest.offset + newest.len;
+
+        if newest.offset >= oldest.offset {
+            // Data occupies [oldest, write_pos); free space at the end, then
+            // at the start up to the oldest frame
+            if write_pos + len <= BYTES {
+                Some(write_pos)
+            } else if len <= oldest.offset {
+                Some(0)
+            } else {
+                None
+            }
+        } else if write_pos + len <= oldest.offset {
+            // Wrapped: free space is the gap before the oldest frame
+            Some(write_pos)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(len: usize, fill: u8) -> [u8; 64] {
+        let mut buf = [0u8; 64];
+        buf[..len].fill(fill);
+        buf
+    }
+
+    #[test]
+    fn test_push_pop_order() {
+        let mut queue: FrameQueue<4, 256> = FrameQueue::new(44100);
+        queue.push(&frame(10, 1)[..10], 0, 128).unwrap();
+        queue.push(&frame(20, 2)[..20], 128, 128).unwrap();
+
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.queued_bytes(), 30);
+
+        let front = queue.front().unwrap();
+        assert_eq!(front.data, &[1u8; 10]);
+        assert_eq!(front.timestamp, 0);
+
+        assert_eq!(queue.pop_front(), Some((0, 128)));
+        assert_eq!(queue.front().unwrap().data, &[2u8; 20]);
+        assert_eq!(queue.pop_front(), Some((128, 128)));
+        assert_eq!(queue.pop_front(), None);
+        assert!(queue.is_empty());
+    }
+
+    #[test]
+    fn test_frames_stay_contiguous_across_wrap() {
+        let mut queue: FrameQueue<8, 100> = FrameQueue::new(44100);
+        queue.push(&frame(40, 1)[..40], 0, 128).unwrap();
+        queue.push(&frame(40, 2)[..40], 128, 128).unwrap();
+        queue.pop_front();
+
+        // 20 bytes left at the end: the frame wraps to the start instead
+        queue.push(&frame(30, 3)[..30], 256, 128).unwrap();
+        assert_eq!(queue.get(1).unwrap().data, &[3u8; 30]);
+
+        // Gap before the oldest frame is now 10 bytes
+        assert_eq!(
+            queue.push(&frame(11, 4)[..11], 384, 128),
+            Err(FrameQueueError::Full)
+        );
+        queue.push(&frame(10, 4)[..10], 384, 128).unwrap();
+        assert_eq!(queue.get(2).unwrap().data, &[4u8; 10]);
+    }
+
+    #[test]
+    fn test_full_and_oversized() {
+        let mut queue: FrameQueue<2, 64> = FrameQueue::new(44100);
+        assert_eq!(
+            queue.push(&[0u8; 65], 0, 128),
+            Err(FrameQueueError::FrameTooLarge)
+        );
+        assert_eq!(queue.push(&[], 0, 128), Err(FrameQueueError::FrameTooLarge));
+
+        queue.push(&[1u8; 8], 0, 128).unwrap();
+        queue.push(&[2u8; 8], 128, 128).unwrap();
+        assert!(queue.is_full());
+        assert_eq!(queue.push(&[3u8; 8], 256, 128), Err(FrameQueueError::Full));
+    }
+
+    #[test]
+    fn test_push_overwrite_drops_oldest() {
+        let mut queue: FrameQueue<3, 64> = FrameQueue::new(44100);
+        for i in 0..3 {
+            queue.push(&[i as u8; 16], i * 128, 128).unwrap();
+        }
+
+        // Needs 30 contiguous bytes: only dropping two frames frees enough
+        assert_eq!(queue.push_overwrite(&[9u8; 30], 384, 128), Ok(2));
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(queue.front().unwrap().timestamp, 256);
+        assert_eq!(queue.get(1).unwrap().data, &[9u8; 30]);
+
+        assert_eq!(queue.take_dropped(), 2);
+        assert_eq!(queue.dropped(), 0);
+    }
+
+    #[test]
+    fn test_queued_duration_and_trim() {
+        let mut queue: FrameQueue<16, 2048> = FrameQueue::new(48000);
+        for i in 0..12 {
+            queue.push(&[0u8; 100], i * 128, 128).unwrap();
+        }
+
+        // 12 * 128 samples at 48 kHz = 32 ms
+        assert_eq!(queue.queued_duration_us(), 32_000);
+
+        // Keep at most 20 ms: 7 frames (18.67 ms) remain
+        assert_eq!(queue.trim_to_duration(20_000), 5);
+        assert_eq!(queue.len(), 7);
+        assert_eq!(queue.front().unwrap().timestamp, 5 * 128);
+        assert_eq!(queue.dropped(), 5);
+    }
+
+    #[test]
+    fn test_frames_fitting() {
+        let mut queue: FrameQueue<16, 2048> = FrameQueue::new(44100);
+        for i in 0..10 {
+            queue.push(&[0u8; 119], i * 128, 128).unwrap();
+        }
+
+        // 672-byte L2CAP MTU minus 12-byte RTP header and 1-byte SBC header
+        assert_eq!(queue.frames_fitting(659, 15), (5, 595));
+        assert_eq!(queue.frames_fitting(659, 3), (3, 357));
+        assert_eq!(queue.drop_oldest(20), 10);
+        assert_eq!(queue.frames_fitting(659, 15), (0, 0));
+    }
+}
@@ -0,0 +1,648 @@
+//! Built-in test signal generator
+//!
+//! Produces calibrated test signals so Bluetooth streaming can be checked
+//! without a USB host. Output is interleaved PCM, either as i16 samples for
+//! the DSP path or as little-endian bytes in any supported `AudioFormat`,
+//! exactly as a USB host would deliver them.
+
+use crate::math;
+use crate::AudioFormat;
+
+/// Maximum channels the generator can produce
+pub const MAX_GENERATOR_CHANNELS: usize = 8;
+
+/// Time each channel plays alone in [`Signal::ChannelId`], in milliseconds
+const CHANNEL_ID_SLOT_MS: u32 = 1000;
+
+/// Phase accumulator units per cycle (2^32)
+const PHASE_PER_CYCLE: f32 = 4_294_967_296.0;
+
+/// Generator configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum GeneratorError {
+    /// Frequency zero or above Nyquist
+    InvalidFrequency,
+    /// Sweep duration zero
+    InvalidDuration,
+    /// Level above 0 dBFS
+    InvalidLevel,
+    /// Unsupported sample width or channel count
+    UnsupportedFormat,
+}
+
+/// Test signal shape
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Signal {
+    /// Digital silence
+    Silence,
+    /// Sine tone on all channels
+    Sine {
+        /// Frequency in Hz
+        freq_hz: u32,
+    },
+    /// Sum of two sine tones, each at half amplitude
+    DualTone {
+        /// First frequency in Hz
+        freq1_hz: u32,
+        /// Second frequency in Hz
+        freq2_hz: u32,
+    },
+    /// Repeating logarithmic sine sweep
+    Sweep {
+        /// Start frequency in Hz
+        start_hz: u32,
+        /// End frequency in Hz
+        end_hz: u32,
+        /// Sweep duration in milliseconds
+        duration_ms: u32,
+    },
+    /// Uniform white noise, uncorrelated between channels
+    WhiteNoise,
+    /// Pink (-3 dB/octave) noise, uncorrelated between channels
+    PinkNoise,
+    /// Tone on one channel at a time, pitch rising with channel index
+    ChannelId {
+        /// Frequency on the first channel in Hz
+        freq_hz: u32,
+    },
+}
+
+impl Signal {
+    /// Check the signal parameters against a sample rate and channel count
+    pub fn validate(&self, sample_rate: u32, channels: u8) -> Result<(), GeneratorError> {
+        let nyquist = sample_rate / 2;
+        let check = |freq: u32| {
+            if freq == 0 || freq >= nyquist {
+                Err(GeneratorError::InvalidFrequency)
+            } else {
+                Ok(())
+            }
+        };
+
+        match *self {
+            Signal::Silence | Signal::WhiteNoise | Signal::PinkNoise => Ok(()),
+            Signal::Sine { freq_hz } => check(freq_hz),
+            Signal::DualTone { freq1_hz, freq2_hz } => {
+                check(freq1_hz)?;
+                check(freq2_hz)
+            }
+            Signal::Sweep {
+                start_hz,
+                end_hz,
+                duration_ms,
+            } => {
+                check(start_hz)?;
+                check(end_hz)?;
+                if duration_ms == 0 {
+                    return Err(GeneratorError::InvalidDuration);
+                }
+                Ok(())
+            }
+            // The last channel plays the highest pitch
+            Signal::ChannelId { freq_hz } => {
+                check(freq_hz)?;
+                check(freq_hz.saturating_mul(channels.max(1) as u32))
+            }
+        }
+    }
+}
+
+/// Generator parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct GeneratorConfig {
+    /// Signal to produce
+    pub signal: Signal,
+    /// Peak level in tenths of a dBFS
+    pub level_db_x10: i16,
+}
+
+impl Default for GeneratorConfig {
+    fn default() -> Self {
+        Self {
+            signal: Signal::Sine { freq_hz: 1000 },
+            level_db_x10: -120,
+        }
+    }
+}
+
+impl GeneratorConfig {
+    /// Check the configuration against a stream format
+    pub fn validate(&self, format: AudioFormat) -> Result<(), GeneratorError> {
+        if self.level_db_x10 > 0 {
+            return Err(GeneratorError::InvalidLevel);
+        }
+        if format.channels == 0
+            || format.channels as usize > MAX_GENERATOR_CHANNELS
+            || !matches!(format.bits_per_sample, 8 | 16 | 24 | 32)
+        {
+            return Err(GeneratorError::UnsupportedFormat);
+        }
+        self.signal.validate(format.sample_rate, format.channels)
+    }
+}
+
+/// Test signal generator
+pub struct SignalGenerator {
+    config: GeneratorConfig,
+    format: AudioFormat,
+    amplitude: f32,
+    /// Phase accumulators (full cycle = 2^32)
+    phase: [u32; 2],
+    /// Phase increments per frame
+    step: [u32; 2],
+    /// Current sweep frequency in Hz
+    sweep_freq: f32,
+    /// Sweep frequency multiplier per frame
+    sweep_ratio: f32,
+    /// Frames generated in the current sweep or channel-ID slot
+    position: u32,
+    /// Frames per sweep or channel-ID slot
+    period: u32,
+    /// Channel currently identified in channel-ID mode
+    id_channel: usize,
+    rng: u32,
+    pink: [[f32; 3]; MAX_GENERATOR_CHANNELS],
+}
+
+impl SignalGenerator {
+    /// Create a generator for `format`
+    pub fn new(config: GeneratorConfig, format: AudioFormat) -> Result<Self, GeneratorError> {
+        let mut generator = Self {
+            config,
+            format,
+            amplitude: 0.0,
+            phase: [0; 2],
+            step: [0; 2],
+            sweep_freq: 0.0,
+            sweep_ratio: 1.0,
+            position: 0,
+            period: 1,
+            id_channel: 0,
+            rng: 0x1234_5678,
+            pink: [[0.0; 3]; MAX_GENERATOR_CHANNELS],
+        };
+        generator.apply(config, format)?;
+        Ok(generator)
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> &GeneratorConfig {
+        &self.config
+    }
+
+    /// Current output format
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Change the signal or level
+    ///
+    /// Restarts the signal from its beginning.
+    pub fn set_config(&mut self, config: GeneratorConfig) -> Result<(), GeneratorError> {
+        self.apply(config, self.format)
+    }
+
+    /// Change the output format
+    ///
+    /// Restarts the signal from its beginning.
+    pub fn set_format(&mut self, format: AudioFormat) -> Result<(), GeneratorError> {
+        self.apply(self.config, format)
+    }
+
+    /// Restart the signal from its beginning
+    pub fn reset(&mut self) {
+        self.phase = [0; 2];
+        self.position = 0;
+        self.id_channel = 0;
+        self.pink = [[0.0; 3]; MAX_GENERATOR_CHANNELS];
+        if let Signal::Sweep { start_hz, .. } = self.config.signal {
+            self.sweep_freq = start_hz as f32;
+        }
+        if let Signal::ChannelId { freq_hz } = self.config.signal {
+            self.step[0] = phase_step(freq_hz as f32, self.format.sample_rate);
+        }
+    }
+
+    /// Fill `out` with interleaved i16 samples
+    ///
+    /// Only whole frames are written; returns the number of frames.
+    pub fn fill(&mut self, out: &mut [i16]) -> usize {
+        let channels = self.format.channels as usize;
+        let mut frames = 0;
+        for frame in out.chunks_exact_mut(channels) {
+            let mut ch = 0;
+            self.next_frame(|value| {
+                frame[ch] = to_i16(value * 32768.0);
+                ch += 1;
+            });
+            frames += 1;
+        }
+        frames
+    }
+
+    /// Fill `out` with little-endian PCM bytes in the output format
+    ///
+    /// 8-bit output is unsigned, wider formats are signed. Only whole
+    /// frames are written; returns the number of frames.
+    pub fn fill_bytes(&mut self, out: &mut [u8]) -> usize {
+        let width = self.format.bits_per_sample as usize / 8;
+        let mut frames = 0;
+        for frame in out.chunks_exact_mut(self.format.bytes_per_sample()) {
+            let mut samples = frame.chunks_exact_mut(width);
+            self.next_frame(|value| {
+                let Some(sample) = samples.next() else {
+                    return;
+                };
+                match width {
+                    1 => sample[0] = (to_i32(value, 8) + 128) as u8,
+                    2 => sample.copy_from_slice(&(to_i32(value, 16) as i16).to_le_bytes()),
+                    3 => sample.copy_from_slice(&to_i32(value, 24).to_le_bytes()[..3]),
+                    _ => sample.copy_from_slice(&to_i32(value, 32).to_le_bytes()),
+                }
+            });
+            frames += 1;
+        }
+        frames
+    }
+
+    /// Produce one frame, calling `emit` once per channel with a value
+    /// in [-1, 1]
+    fn next_frame(&mut self, mut emit: impl FnMut(f32)) {
+        let channels = self.format.channels as usize;
+        let amplitude = self.amplitude;
+
+        match self.config.signal {
+            Signal::Silence => (0..channels).for_each(|_| emit(0.0)),
+            Signal::Sine { .. } => {
+                let value = amplitude * self.oscillator(0);
+                (0..channels).for_each(|_| emit(value));
+            }
+            Signal::DualTone { .. } => {
+                let value = amplitude * 0.5 * (self.oscillator(0) + self.oscillator(1));
+                (0..channels).for_each(|_| emit(value));
+            }
+            Signal::Sweep { start_hz, .. } => {
+                self.step[0] = phase_step(self.sweep_freq, self.format.sample_rate);
+                let value = amplitude * self.oscillator(0);
+                (0..channels).for_each(|_| emit(value));
+
+                self.sweep_freq *= self.sweep_ratio;
+                self.position += 1;
+                if self.position >= self.period {
+                    self.position = 0;
+                    self.phase[0] = 0;
+                    self.sweep_freq = start_hz as f32;
+                }
+            }
+            Signal::WhiteNoise => {
+                for _ in 0..channels {
+                    let value = amplitude * self.white();
+                    emit(value);
+                }
+            }
+            Signal::PinkNoise => {
+                for ch in 0..channels {
+                    let value = amplitude * self.pink(ch);
+                    emit(value);
+                }
+            }
+            Signal::ChannelId { freq_hz } => {
+                let value = amplitude * self.oscillator(0);
+                for ch in 0..channels {
+                    emit(if ch == self.id_channel { value } else { 0.0 });
+                }
+
+                self.position += 1;
+                if self.position >= self.period {
+                    self.position = 0;
+                    self.id_channel = (self.id_channel + 1) % channels;
+                    self.phase[0] = 0;
+                    let freq = freq_hz as f32 * (self.id_channel + 1) as f32;
+                    self.step[0] = phase_step(freq, self.format.sample_rate);
+                }
+            }
+        }
+    }
+
+    /// Next sample of oscillator `index`
+    fn oscillator(&mut self, index: usize) -> f32 {
+        let phase = self.phase[index];
+        self.phase[index] = phase.wrapping_add(self.step[index]);
+        math::sin(phase as f32 * (2.0 * math::PI / PHASE_PER_CYCLE))
+    }
+
+    /// Uniform random value in [-1, 1)
+    fn white(&mut self) -> f32 {
+        // xorshift32
+        let mut x = self.rng;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.rng = x;
+        (x as i32) as f32 / 2_147_483_648.0
+    }
+
+    /// Pink noise sample for `channel`
+    ///
+    /// Paul Kellet's economy filter: three one-pole sections approximate a
+    /// -3 dB/octave slope within 0.5 dB above 10 Hz at 44.1 kHz.
+    fn pink(&mut self, channel: usize) -> f32 {
+        let white = self.white();
+        let b = &mut self.pink[channel];
+        b[0] = 0.99765 * b[0] + white * 0.099_046;
+        b[1] = 0.963 * b[1] + white * 0.296_516_4;
+        b[2] = 0.57 * b[2] + white * 1.052_691_3;
+        // Scale the ~3.5x filter gain back to full scale
+        ((b[0] + b[1] + b[2] + white * 0.1848) * 0.25).clamp(-1.0, 1.0)
+    }
+
+    fn apply(
+        &mut self,
+        config: GeneratorConfig,
+        format: AudioFormat,
+    ) -> Result<(), GeneratorError> {
+        config.validate(format)?;
+
+        self.config = config;
+        self.format = format;
+        self.amplitude = math::db_to_linear(config.level_db_x10 as f32 / 10.0);
+        self.step = [0; 2];
+        self.sweep_ratio = 1.0;
+        self.period = 1;
+
+        let rate = format.sample_rate;
+        match config.signal {
+            Signal::Sine { freq_hz } => {
+                self.step[0] = phase_step(freq_hz as f32, rate);
+            }
+            Signal::DualTone { freq1_hz, freq2_hz } => {
+                self.step = [
+                    phase_step(freq1_hz as f32, rate),
+                    phase_step(freq2_hz as f32, rate),
+                ];
+            }
+            Signal::Sweep {
+                start_hz,
+                end_hz,
+                duration_ms,
+            } => {
+                let frames = (rate as u64 * duration_ms as u64 / 1000).max(1);
+                self.period = frames.min(u32::MAX as u64) as u32;
+                self.sweep_ratio =
+                    math::exp(math::ln(end_hz as f32 / start_hz as f32) / self.period as f32);
+            }
+            Signal::ChannelId { .. } => {
+                self.period = (rate as u64 * CHANNEL_ID_SLOT_MS as u64 / 1000).max(1) as u32;
+            }
+            Signal::Silence | Signal::WhiteNoise | Signal::PinkNoise => {}
+        }
+
+        self.reset();
+        Ok(())
+    }
+}
+
+/// Phase increment per frame for a frequency
+fn phase_step(freq_hz: f32, sample_rate: u32) -> u32 {
+    (freq_hz / sample_rate as f32 * PHASE_PER_CYCLE) as u32
+}
+
+/// Round and saturate to i16
+fn to_i16(value: f32) -> i16 {
+    let rounded = if value < 0.0 {
+        value - 0.5
+    } else {
+        value + 0.5
+    };
+    rounded.clamp(i16::MIN as f32, i16::MAX as f32) as i16
+}
+
+/// Scale a value in [-1, 1] to a signed integer of `bits` bits
+fn to_i32(value: f32, bits: u32) -> i32 {
+    // Go through i16 precision first so every width carries the same signal
+    let sample = to_i16(value * 32768.0) as i32;
+    if bits >= 16 {
+        sample << (bits - 16)
+    } else {
+        sample >> (16 - bits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(channels: u8, bits_per_sample: u8) -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels,
+            bits_per_sample,
+        }
+    }
+
+    fn config(signal: Signal) -> GeneratorConfig {
+        GeneratorConfig {
+            signal,
+            level_db_x10: 0,
+        }
+    }
+
+    /// Count sign changes on channel 0 of an interleaved block
+    fn zero_crossings(samples: &[i16], channels: usize) -> usize {
+        samples
+            .iter()
+            .step_by(channels)
+            .zip(samples.iter().step_by(channels).skip(1))
+            .filter(|(a, b)| (**a < 0) != (**b < 0))
+            .count()
+    }
+
+    #[test]
+    fn test_sine_frequency_and_level() {
+        let mut generator = SignalGenerator::new(
+            GeneratorConfig {
+                signal: Signal::Sine { freq_hz: 1000 },
+                level_db_x10: -60,
+            },
+            format(2, 16),
+        )
+        .unwrap();
+
+        // 100 ms = 100 cycles = 200 zero crossings
+        let mut block = [0i16; 9600];
+        assert_eq!(generator.fill(&mut block), 4800);
+        let crossings = zero_crossings(&block, 2);
+        assert!((199..=201).contains(&crossings), "{}", crossings);
+
+        let peak = block.iter().map(|s| s.unsigned_abs()).max().unwrap();
+        assert!((16300..=16450).contains(&peak), "{}", peak);
+        assert!(block.chunks_exact(2).all(|f| f[0] == f[1]));
+    }
+
+    #[test]
+    fn test_silence() {
+        let mut generator = SignalGenerator::new(config(Signal::Silence), format(2, 16)).unwrap();
+        let mut block = [1i16; 64];
+        generator.fill(&mut block);
+        assert!(block.iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn test_sweep_rises_and_repeats() {
+        let signal = Signal::Sweep {
+            start_hz: 100,
+            end_hz: 10_000,
+            duration_ms: 100,
+        };
+        let mut generator = SignalGenerator::new(config(signal), format(1, 16)).unwrap();
+
+        let mut first = [0i16; 480];
+        let mut middle = [0i16; 3840];
+        let mut last = [0i16; 480];
+        generator.fill(&mut first);
+        generator.fill(&mut middle);
+        generator.fill(&mut last);
+
+        // Low end at the start, high end at the finish
+        assert!(zero_crossings(&first, 1) < 4);
+        assert!(zero_crossings(&last, 1) > 80);
+
+        // Restarted at the low end
+        let mut again = [0i16; 480];
+        generator.fill(&mut again);
+        assert_eq!(again, first);
+    }
+
+    #[test]
+    fn test_noise_is_bounded_and_uncorrelated() {
+        for signal in [Signal::WhiteNoise, Signal::PinkNoise] {
+            let mut generator = SignalGenerator::new(
+                GeneratorConfig {
+                    signal,
+                    level_db_x10: -60,
+                },
+                format(2, 16),
+            )
+            .unwrap();
+
+            let mut block = [0i16; 4800];
+            generator.fill(&mut block);
+            assert!(block.iter().all(|s| s.unsigned_abs() <= 16450));
+            assert!(block.iter().any(|&s| s != 0));
+            assert!(block.chunks_exact(2).any(|f| f[0] != f[1]));
+        }
+    }
+
+    #[test]
+    fn test_pink_noise_has_more_low_end() {
+        let mut white = SignalGenerator::new(config(Signal::WhiteNoise), format(1, 16)).unwrap();
+        let mut pink = SignalGenerator::new(config(Signal::PinkNoise), format(1, 16)).unwrap();
+
+        let mut block = [0i16; 4800];
+        white.fill(&mut block);
+        let white_crossings = zero_crossings(&block, 1);
+        pink.fill(&mut block);
+        let pink_crossings = zero_crossings(&block, 1);
+        assert!(pink_crossings * 2 < white_crossings);
+    }
+
+    #[test]
+    fn test_channel_id_cycles_channels() {
+        let mut generator =
+            SignalGenerator::new(config(Signal::ChannelId { freq_hz: 500 }), format(2, 16))
+                .unwrap();
+
+        // First second: left only at 500 Hz
+        let mut block = [0i16; 96_000];
+        generator.fill(&mut block);
+        assert!(block.chunks_exact(2).all(|f| f[1] == 0));
+        let crossings = zero_crossings(&block, 2);
+        assert!((999..=1001).contains(&crossings), "{}", crossings);
+
+        // Second second: right only at 1 kHz
+        generator.fill(&mut block);
+        assert!(block.chunks_exact(2).all(|f| f[0] == 0));
+        let crossings = zero_crossings(&block[1..], 2);
+        assert!((1999..=2001).contains(&crossings), "{}", crossings);
+    }
+
+    #[test]
+    fn test_fill_bytes_widths() {
+        let signal = Signal::Sine { freq_hz: 1000 };
+        let mut reference = SignalGenerator::new(config(signal), format(2, 16)).unwrap();
+        let mut samples = [0i16; 96];
+        reference.fill(&mut samples);
+
+        for bits in [8u8, 16, 24, 32] {
+            let mut generator = SignalGenerator::new(config(signal), format(2, bits)).unwrap();
+            let width = bits as usize / 8;
+            let mut bytes = [0u8; 96 * 4];
+            let frames = generator.fill_bytes(&mut bytes[..96 * width]);
+            assert_eq!(frames, 48);
+
+            for (i, &expected) in samples.iter().enumerate() {
+                let raw = &bytes[i * width..(i + 1) * width];
+                let value = match width {
+                    1 => ((raw[0] as i32 - 128) << 8) as i16,
+                    2 => i16::from_le_bytes([raw[0], raw[1]]),
+                    3 => i16::from_le_bytes([raw[1], raw[2]]),
+                    _ => i16::from_le_bytes([raw[2], raw[3]]),
+                };
+                let tolerance = if width == 1 { 256 } else { 0 };
+                assert!(
+                    (value as i32 - expected as i32).abs() <= tolerance,
+                    "{} bits sample {}",
+                    bits,
+                    i
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_validation() {
+        let stereo = format(2, 16);
+        let bad = [
+            (
+                Signal::Sine { freq_hz: 0 },
+                GeneratorError::InvalidFrequency,
+            ),
+            (
+                Signal::Sine { freq_hz: 24_000 },
+                GeneratorError::InvalidFrequency,
+            ),
+            (
+                Signal::Sweep {
+                    start_hz: 20,
+                    end_hz: 20_000,
+                    duration_ms: 0,
+                },
+                GeneratorError::InvalidDuration,
+            ),
+            // 12 kHz on the first channel puts the second at Nyquist
+            (
+                Signal::ChannelId { freq_hz: 12_000 },
+                GeneratorError::InvalidFrequency,
+            ),
+        ];
+        for (signal, error) in bad {
+            assert_eq!(
+                SignalGenerator::new(config(signal), stereo).err(),
+                Some(error)
+            );
+        }
+
+        let loud = GeneratorConfig {
+            level_db_x10: 10,
+            ..GeneratorConfig::default()
+        };
+        assert_eq!(loud.validate(stereo), Err(GeneratorError::InvalidLevel));
+        assert_eq!(
+            GeneratorConfig::default().validate(format(2, 12)),
+            Err(GeneratorError::UnsupportedFormat)
+        );
+    }
+}
@@ -1,14 +1,52 @@
 //! Audio pipeline for embedded A2DP
 //!
-//! Provides lock-free ring buffers and format conversion utilities
-//! for streaming audio between USB reception and SBC encoding.
+//! Provides lock-free ring buffers (with async wrappers for embassy tasks),
+//! format conversion utilities, DSP stages (EQ, dynamics, metering, mixing)
+//! and a test signal generator for streaming audio between USB reception
+//! and SBC encoding.
 
 #![no_std]
 #![deny(unsafe_op_in_unsafe_fn)]
 
+mod async_ring_buffer;
+mod conceal;
+mod convert;
+mod dynamics;
+mod eq;
+mod frame_queue;
+mod generator;
+mod math;
+mod meter;
+mod mixer;
+mod prompt;
 mod ring_buffer;
+mod silence;
 
-pub use ring_buffer::RingBuffer;
+pub use async_ring_buffer::AsyncRingBuffer;
+pub use conceal::{ConcealError, Concealer, MAX_FADE_FRAMES};
+pub use convert::{ChannelMap, ConversionStages, ConvertError, FormatConverter};
+pub use dynamics::{
+    Compressor, CompressorConfig, DynamicsError, GainReductionMeter, Limiter, LimiterConfig,
+    MAX_LOOKAHEAD_FRAMES,
+};
+pub use eq::{
+    BiquadCoeffs, EqBand, EqError, EqPreset, Equalizer, FilterType, EQ_PRESET_BYTES, MAX_EQ_BANDS,
+};
+pub use frame_queue::{FrameQueue, FrameQueueError, QueuedFrame};
+pub use generator::{
+    GeneratorConfig, GeneratorError, Signal, SignalGenerator, MAX_GENERATOR_CHANNELS,
+};
+pub use meter::{
+    ChannelLevel, LevelMeter, MeterConfig, MeterError, MeterReadings, MAX_METER_CHANNELS,
+    SILENCE_DB_X10,
+};
+pub use mixer::{MixInput, MixReport, Mixer, MixerError, MAX_MIX_GAIN_DB_X10};
+pub use prompt::{
+    PromptBank, PromptClip, PromptCodec, PromptError, PromptPlayer, PROMPT_BANK_MAGIC,
+    PROMPT_BANK_VERSION,
+};
+pub use ring_buffer::{FillState, OverflowMode, RingBuffer};
+pub use silence::{PreRollBuffer, SilenceConfig, SilenceDetector, SilenceError, SilenceEvent};
 
 /// Audio format description
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
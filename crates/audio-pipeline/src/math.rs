@@ -0,0 +1,157 @@
+//! Minimal floating-point helpers for `no_std`
+//!
+//! `core` does not provide transcendental functions, so the few we need
+//! for filter design and dynamics control are implemented here. Audio
+//! samples themselves stay in integer form.
+
+/// Pi as f32
+pub const PI: f32 = core::f32::consts::PI;
+
+/// Natural log of 10
+const LN_10: f32 = core::f32::consts::LN_10;
+
+/// Sine of `x` (radians)
+///
+/// Accurate to roughly 1e-6 over any input range.
+pub fn sin(x: f32) -> f32 {
+    // Reduce to [-pi, pi]
+    let two_pi = 2.0 * PI;
+    let mut x = x - two_pi * floor(x / two_pi + 0.5);
+
+    // Fold into [-pi/2, pi/2] using sin(pi - x) = sin(x)
+    if x > PI / 2.0 {
+        x = PI - x;
+    } else if x < -PI / 2.0 {
+        x = -PI - x;
+    }
+
+    // Taylor series up to x^11
+    let x2 = x * x;
+    x * (1.0
+        - x2 / 6.0 * (1.0 - x2 / 20.0 * (1.0 - x2 / 42.0 * (1.0 - x2 / 72.0 * (1.0 - x2 / 110.0)))))
+}
+
+/// Cosine of `x` (radians)
+pub fn cos(x: f32) -> f32 {
+    sin(x + PI / 2.0)
+}
+
+/// Absolute value of `x`
+pub fn abs(x: f32) -> f32 {
+    if x < 0.0 {
+        -x
+    } else {
+        x
+    }
+}
+
+/// Largest integer value not greater than `x`
+pub fn floor(x: f32) -> f32 {
+    let t = x as i32 as f32;
+    if t > x {
+        t - 1.0
+    } else {
+        t
+    }
+}
+
+/// e raised to the power `x`
+///
+/// Relative error is below 1e-6; results underflow to 0 below about -87.
+pub fn exp(x: f32) -> f32 {
+    // e^x = 2^(x * log2(e)) = 2^k * 2^f with integer k and f in [0, 1)
+    let t = x * core::f32::consts::LOG2_E;
+    if t < -126.0 {
+        return 0.0;
+    }
+    if t > 127.0 {
+        return f32::INFINITY;
+    }
+    let k = floor(t);
+    let f = (t - k) * core::f32::consts::LN_2;
+
+    // e^f for f in [0, ln 2), Taylor series to f^7
+    let p = 1.0
+        + f * (1.0
+            + f / 2.0
+                * (1.0
+                    + f / 3.0
+                        * (1.0 + f / 4.0 * (1.0 + f / 5.0 * (1.0 + f / 6.0 * (1.0 + f / 7.0))))));
+
+    f32::from_bits(((k as i32 + 127) as u32) << 23) * p
+}
+
+/// Natural logarithm of `x` (returns -inf for x <= 0)
+pub fn ln(x: f32) -> f32 {
+    if x <= 0.0 {
+        return f32::NEG_INFINITY;
+    }
+
+    // Split into mantissa in [1, 2) and binary exponent
+    let bits = x.to_bits();
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    let mantissa = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+
+    // ln(m) = 2 * atanh((m - 1) / (m + 1)), with |s| <= 1/3
+    let s = (mantissa - 1.0) / (mantissa + 1.0);
+    let s2 = s * s;
+    let atanh = s
+        * (1.0
+            + s2 * (1.0 / 3.0
+                + s2 * (1.0 / 5.0 + s2 * (1.0 / 7.0 + s2 * (1.0 / 9.0 + s2 / 11.0)))));
+
+    2.0 * atanh + exponent as f32 * core::f32::consts::LN_2
+}
+
+/// Convert a level in decibels to a linear amplitude factor
+pub fn db_to_linear(db: f32) -> f32 {
+    exp(db * LN_10 / 20.0)
+}
+
+/// Convert a linear amplitude factor to decibels
+pub fn linear_to_db(linear: f32) -> f32 {
+    20.0 * ln(linear) / LN_10
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32, tol: f32) -> bool {
+        abs(a - b) <= tol
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        assert!(close(sin(0.0), 0.0, 1e-6));
+        assert!(close(sin(PI / 2.0), 1.0, 1e-6));
+        assert!(close(sin(-PI / 6.0), -0.5, 1e-6));
+        assert!(close(cos(PI), -1.0, 1e-6));
+        assert!(close(sin(7.0 * PI + 0.3), -sin(0.3), 1e-5));
+    }
+
+    #[test]
+    fn test_exp() {
+        assert!(close(exp(0.0), 1.0, 1e-6));
+        assert!(close(exp(1.0), core::f32::consts::E, 1e-5));
+        assert!(close(exp(-3.0), 0.049_787_07, 1e-6));
+        assert!(close(exp(10.0), 22_026.465, 0.05));
+        assert_eq!(exp(-200.0), 0.0);
+    }
+
+    #[test]
+    fn test_ln() {
+        assert!(close(ln(1.0), 0.0, 1e-6));
+        assert!(close(ln(10.0), LN_10, 1e-5));
+        assert!(close(ln(0.001), -6.907_755, 1e-5));
+        assert_eq!(ln(0.0), f32::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_db_conversion() {
+        assert!(close(db_to_linear(0.0), 1.0, 1e-6));
+        assert!(close(db_to_linear(-6.0206), 0.5, 1e-4));
+        assert!(close(db_to_linear(20.0), 10.0, 1e-4));
+        assert!(close(linear_to_db(0.5), -6.0206, 1e-4));
+    }
+}
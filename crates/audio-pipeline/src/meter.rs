@@ -0,0 +1,580 @@
+//! Peak/RMS level metering and clip detection
+//!
+//! A [`LevelMeter`] runs inside the audio path and accumulates per-channel
+//! peak, mean-square and clip counts over a fixed window. At the end of each
+//! window it publishes the results to a [`MeterReadings`], which holds only
+//! atomics so another core (or a logging task) can read it without locking.
+//!
+//! A window counter that keeps advancing tells field support that audio is
+//! reaching the meter at all; the levels tell whether it is silent.
+
+use core::fmt;
+
+use portable_atomic::{AtomicU16, AtomicU32, Ordering};
+
+use crate::math;
+use crate::AudioFormat;
+
+/// Maximum channels tracked by the meter
+pub const MAX_METER_CHANNELS: usize = 2;
+
+/// Reported level of digital silence, in tenths of a dBFS
+pub const SILENCE_DB_X10: i16 = -999;
+
+/// Full-scale amplitude of an i16 sample
+const FULL_SCALE: f32 = 32768.0;
+
+/// Meter configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MeterError {
+    /// Window or hold time out of range
+    InvalidTime,
+    /// Clip level not positive
+    InvalidLevel,
+    /// More channels than the meter tracks
+    TooManyChannels,
+}
+
+/// Meter parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MeterConfig {
+    /// Integration window in milliseconds
+    pub window_ms: u16,
+    /// Time the peak-hold value is held before decaying, in milliseconds
+    pub peak_hold_ms: u16,
+    /// Peak-hold decay rate in tenths of a dB per second
+    pub peak_decay_db_x10_per_s: u16,
+    /// Absolute sample value counted as a clip
+    pub clip_level: i16,
+}
+
+impl Default for MeterConfig {
+    fn default() -> Self {
+        Self {
+            window_ms: 100,
+            peak_hold_ms: 1000,
+            peak_decay_db_x10_per_s: 200,
+            clip_level: i16::MAX,
+        }
+    }
+}
+
+impl MeterConfig {
+    /// Check that all parameters are in range
+    pub fn validate(&self) -> Result<(), MeterError> {
+        if !(1..=10_000).contains(&self.window_ms) {
+            return Err(MeterError::InvalidTime);
+        }
+        if self.clip_level <= 0 {
+            return Err(MeterError::InvalidLevel);
+        }
+        Ok(())
+    }
+}
+
+/// Level of one channel over a metering window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ChannelLevel {
+    /// Largest absolute sample value in the window
+    pub peak: u16,
+    /// RMS sample value over the window
+    pub rms: u16,
+    /// Decaying peak-hold value
+    pub peak_hold: u16,
+    /// Clipped samples since the counter was last taken
+    pub clips: u32,
+}
+
+impl ChannelLevel {
+    /// Window peak in tenths of a dBFS
+    pub fn peak_db_x10(&self) -> i16 {
+        level_to_db_x10(self.peak)
+    }
+
+    /// Window RMS in tenths of a dBFS
+    pub fn rms_db_x10(&self) -> i16 {
+        level_to_db_x10(self.rms)
+    }
+
+    /// Peak-hold value in tenths of a dBFS
+    pub fn peak_hold_db_x10(&self) -> i16 {
+        level_to_db_x10(self.peak_hold)
+    }
+}
+
+impl fmt::Display for ChannelLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "peak {} rms {} hold {} dBFS clips {}",
+            DbX10(self.peak_db_x10()),
+            DbX10(self.rms_db_x10()),
+            DbX10(self.peak_hold_db_x10()),
+            self.clips
+        )
+    }
+}
+
+/// Formats tenths of a dB as a decimal
+struct DbX10(i16);
+
+impl fmt::Display for DbX10 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0 <= SILENCE_DB_X10 {
+            return f.write_str("-inf");
+        }
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let abs = self.0.unsigned_abs();
+        write!(f, "{}{}.{}", sign, abs / 10, abs % 10)
+    }
+}
+
+/// Convert an absolute sample level to tenths of a dBFS
+fn level_to_db_x10(level: u16) -> i16 {
+    if level == 0 {
+        return SILENCE_DB_X10;
+    }
+    let db_x10 = math::linear_to_db(level as f32 / FULL_SCALE) * 10.0;
+    let rounded = if db_x10 < 0.0 {
+        db_x10 - 0.5
+    } else {
+        db_x10 + 0.5
+    };
+    (rounded as i16).max(SILENCE_DB_X10)
+}
+
+/// Published meter results, readable lock-free from any core
+///
+/// Each field is updated atomically, but a reader may see the fields of
+/// two consecutive windows mixed; use [`MeterReadings::windows`] to detect
+/// an update in between if that matters.
+pub struct MeterReadings {
+    /// Window peak (high half) and RMS (low half) per channel
+    levels: [AtomicU32; MAX_METER_CHANNELS],
+    peak_hold: [AtomicU16; MAX_METER_CHANNELS],
+    clips: [AtomicU32; MAX_METER_CHANNELS],
+    windows: AtomicU32,
+}
+
+impl MeterReadings {
+    /// Create readings with all levels at silence
+    pub const fn new() -> Self {
+        Self {
+            levels: [AtomicU32::new(0), AtomicU32::new(0)],
+            peak_hold: [AtomicU16::new(0), AtomicU16::new(0)],
+            clips: [AtomicU32::new(0), AtomicU32::new(0)],
+            windows: AtomicU32::new(0),
+        }
+    }
+
+    /// Number of windows published so far
+    ///
+    /// If this stops advancing, no audio is reaching the meter.
+    pub fn windows(&self) -> u32 {
+        self.windows.load(Ordering::Acquire)
+    }
+
+    /// Latest level of `channel` (zero for channels beyond the maximum)
+    pub fn channel(&self, channel: usize) -> ChannelLevel {
+        if channel >= MAX_METER_CHANNELS {
+            return ChannelLevel::default();
+        }
+
+        let levels = self.levels[channel].load(Ordering::Relaxed);
+        ChannelLevel {
+            peak: (levels >> 16) as u16,
+            rms: levels as u16,
+            peak_hold: self.peak_hold[channel].load(Ordering::Relaxed),
+            clips: self.clips[channel].load(Ordering::Relaxed),
+        }
+    }
+
+    /// Return and reset the clip counter of `channel`
+    pub fn take_clips(&self, channel: usize) -> u32 {
+        if channel >= MAX_METER_CHANNELS {
+            return 0;
+        }
+        self.clips[channel].swap(0, Ordering::Relaxed)
+    }
+
+    /// Reset all readings to silence
+    pub fn clear(&self) {
+        for ch in 0..MAX_METER_CHANNELS {
+            self.levels[ch].store(0, Ordering::Relaxed);
+            self.peak_hold[ch].store(0, Ordering::Relaxed);
+            self.clips[ch].store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn publish(&self, channel: usize, peak: u16, rms: u16, peak_hold: u16, clips: u32) {
+        self.levels[channel].store(((peak as u32) << 16) | rms as u32, Ordering::Relaxed);
+        self.peak_hold[channel].store(peak_hold, Ordering::Relaxed);
+        if clips > 0 {
+            self.clips[channel].fetch_add(clips, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Default for MeterReadings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-channel window accumulator
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelState {
+    peak: u16,
+    sum_squares: u64,
+    clips: u32,
+    hold: f32,
+    hold_remaining_ms: u32,
+}
+
+/// Audio-path side of the level meter
+///
+/// Call [`LevelMeter::process`] with every block of interleaved samples;
+/// the samples are not modified.
+pub struct LevelMeter<'a> {
+    config: MeterConfig,
+    format: AudioFormat,
+    readings: &'a MeterReadings,
+    channels: [ChannelState; MAX_METER_CHANNELS],
+    window_frames: u32,
+    frames: u32,
+    /// Peak-hold decay factor applied per window
+    decay: f32,
+}
+
+impl<'a> LevelMeter<'a> {
+    /// Create a meter publishing to `readings`
+    pub fn new(
+        config: MeterConfig,
+        format: AudioFormat,
+        readings: &'a MeterReadings,
+    ) -> Result<Self, MeterError> {
+        let mut meter = Self {
+            config,
+            format,
+            readings,
+            channels: [ChannelState::default(); MAX_METER_CHANNELS],
+            window_frames: 1,
+            frames: 0,
+            decay: 1.0,
+        };
+        meter.apply(config, format)?;
+        Ok(meter)
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> &MeterConfig {
+        &self.config
+    }
+
+    /// Change the configuration
+    ///
+    /// Restarts the current window.
+    pub fn set_config(&mut self, config: MeterConfig) -> Result<(), MeterError> {
+        self.apply(config, self.format)
+    }
+
+    /// Change the stream format
+    ///
+    /// Restarts the current window.
+    pub fn set_format(&mut self, format: AudioFormat) -> Result<(), MeterError> {
+        self.apply(self.config, format)
+    }
+
+    /// Discard the current window and peak-hold state
+    pub fn reset(&mut self) {
+        self.channels = [ChannelState::default(); MAX_METER_CHANNELS];
+        self.frames = 0;
+    }
+
+    /// Meter a block of interleaved samples
+    pub fn process(&mut self, samples: &[i16]) {
+        let channels = self.format.channels as usize;
+        if channels == 0 {
+            return;
+        }
+
+        let clip_level = self.config.clip_level.unsigned_abs();
+        for frame in samples.chunks_exact(channels) {
+            for (state, &sample) in self.channels.iter_mut().zip(frame) {
+                let level = sample.unsigned_abs();
+                state.peak = state.peak.max(level);
+                state.sum_squares += (sample as i32 * sample as i32) as u64;
+                if level >= clip_level {
+                    state.clips += 1;
+                }
+            }
+
+            self.frames += 1;
+            if self.frames >= self.window_frames {
+                self.publish();
+            }
+        }
+    }
+
+    /// End the window and publish its results
+    fn publish(&mut self) {
+        let window_ms = self.config.window_ms as u32;
+        let channels = (self.format.channels as usize).min(MAX_METER_CHANNELS);
+
+        for (ch, state) in self.channels.iter_mut().enumerate().take(channels) {
+            let rms = isqrt(state.sum_squares / self.frames as u64) as u16;
+
+            let peak = state.peak as f32;
+            if peak >= state.hold {
+                state.hold = peak;
+                state.hold_remaining_ms = self.config.peak_hold_ms as u32;
+            } else if state.hold_remaining_ms >= window_ms {
+                state.hold_remaining_ms -= window_ms;
+            } else {
+                state.hold_remaining_ms = 0;
+                state.hold = (state.hold * self.decay).max(peak);
+            }
+
+            self.readings
+                .publish(ch, state.peak, rms, state.hold as u16, state.clips);
+
+            state.peak = 0;
+            state.sum_squares = 0;
+            state.clips = 0;
+        }
+
+        self.frames = 0;
+        self.readings.windows.fetch_add(1, Ordering::Release);
+    }
+
+    fn apply(&mut self, config: MeterConfig, format: AudioFormat) -> Result<(), MeterError> {
+        config.validate()?;
+        if format.channels as usize > MAX_METER_CHANNELS {
+            return Err(MeterError::TooManyChannels);
+        }
+
+        let window_frames = format.sample_rate as u64 * config.window_ms as u64 / 1000;
+        let decay_db =
+            config.peak_decay_db_x10_per_s as f32 / 10.0 * config.window_ms as f32 / 1000.0;
+
+        self.config = config;
+        self.format = format;
+        self.window_frames = window_frames.max(1) as u32;
+        self.decay = math::db_to_linear(-decay_db);
+        self.reset();
+        Ok(())
+    }
+}
+
+/// Integer square root (floor)
+fn isqrt(value: u64) -> u64 {
+    if value < 2 {
+        return value;
+    }
+
+    // Newton's method from an overestimate
+    let mut x = 1u64 << ((64 - value.leading_zeros()).div_ceil(2));
+    loop {
+        let next = (x + value / x) / 2;
+        if next >= x {
+            return x;
+        }
+        x = next;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo() -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    fn config(window_ms: u16) -> MeterConfig {
+        MeterConfig {
+            window_ms,
+            ..MeterConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_isqrt() {
+        for value in [0u64, 1, 2, 3, 4, 15, 16, 17, 1 << 40, u32::MAX as u64 * 7] {
+            let root = isqrt(value);
+            assert!(root * root <= value);
+            assert!((root + 1) * (root + 1) > value);
+        }
+    }
+
+    #[test]
+    fn test_square_wave_levels() {
+        let readings = MeterReadings::new();
+        let mut meter = LevelMeter::new(config(10), stereo(), &readings).unwrap();
+
+        // 480 frames = one 10 ms window; left full square, right half
+        let mut block = [0i16; 960];
+        for (i, frame) in block.chunks_exact_mut(2).enumerate() {
+            let sign = if i % 2 == 0 { 1 } else { -1 };
+            frame[0] = 16384 * sign;
+            frame[1] = 8192 * sign;
+        }
+        meter.process(&block);
+
+        assert_eq!(readings.windows(), 1);
+        let left = readings.channel(0);
+        let right = readings.channel(1);
+        assert_eq!(left.peak, 16384);
+        assert_eq!(left.rms, 16384);
+        assert_eq!(right.rms, 8192);
+        assert_eq!(left.peak_db_x10(), -60);
+        assert_eq!(right.rms_db_x10(), -120);
+        assert_eq!(left.clips, 0);
+    }
+
+    #[test]
+    fn test_window_not_published_until_complete() {
+        let readings = MeterReadings::new();
+        let mut meter = LevelMeter::new(config(10), stereo(), &readings).unwrap();
+
+        meter.process(&[1000i16; 958]);
+        assert_eq!(readings.windows(), 0);
+        assert_eq!(readings.channel(0).peak, 0);
+
+        meter.process(&[1000i16; 2]);
+        assert_eq!(readings.windows(), 1);
+        assert_eq!(readings.channel(0).peak, 1000);
+    }
+
+    #[test]
+    fn test_clip_counting() {
+        let readings = MeterReadings::new();
+        let mut meter = LevelMeter::new(config(10), stereo(), &readings).unwrap();
+
+        let mut block = [0i16; 960];
+        block[0] = i16::MAX;
+        block[2] = i16::MIN;
+        block[3] = i16::MIN;
+        meter.process(&block);
+        meter.process(&block);
+
+        assert_eq!(readings.channel(0).clips, 4);
+        assert_eq!(readings.take_clips(1), 2);
+        assert_eq!(readings.channel(1).clips, 0);
+    }
+
+    #[test]
+    fn test_peak_hold_then_decay() {
+        let readings = MeterReadings::new();
+        let cfg = MeterConfig {
+            window_ms: 10,
+            peak_hold_ms: 20,
+            peak_decay_db_x10_per_s: 600,
+            clip_level: i16::MAX,
+        };
+        let mut meter = LevelMeter::new(cfg, stereo(), &readings).unwrap();
+
+        meter.process(&[16384i16; 960]);
+        assert_eq!(readings.channel(0).peak_hold, 16384);
+
+        // Held for two quiet windows
+        let silence = [0i16; 960];
+        meter.process(&silence);
+        meter.process(&silence);
+        assert_eq!(readings.channel(0).peak, 0);
+        assert_eq!(readings.channel(0).peak_hold, 16384);
+
+        // Then decays 0.6 dB per 10 ms window
+        meter.process(&silence);
+        let hold = readings.channel(0).peak_hold_db_x10();
+        assert!((-67..=-65).contains(&hold), "hold {}", hold);
+    }
+
+    #[test]
+    fn test_silence_and_display() {
+        let readings = MeterReadings::new();
+        let mut meter = LevelMeter::new(config(10), stereo(), &readings).unwrap();
+        meter.process(&[0i16; 960]);
+
+        let level = readings.channel(0);
+        assert_eq!(level.peak_db_x10(), SILENCE_DB_X10);
+
+        let mut text = Text::default();
+        fmt::write(&mut text, format_args!("{}", level)).unwrap();
+        assert_eq!(text.as_str(), "peak -inf rms -inf hold -inf dBFS clips 0");
+
+        let level = ChannelLevel {
+            peak: 16384,
+            rms: 16384,
+            peak_hold: 32767,
+            clips: 3,
+        };
+        let mut text = Text::default();
+        fmt::write(&mut text, format_args!("{}", level)).unwrap();
+        assert_eq!(text.as_str(), "peak -6.0 rms -6.0 hold 0.0 dBFS clips 3");
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let readings = MeterReadings::new();
+        assert_eq!(
+            LevelMeter::new(config(0), stereo(), &readings).err(),
+            Some(MeterError::InvalidTime)
+        );
+
+        let bad = MeterConfig {
+            clip_level: 0,
+            ..MeterConfig::default()
+        };
+        assert_eq!(bad.validate(), Err(MeterError::InvalidLevel));
+
+        let surround = AudioFormat {
+            channels: 6,
+            ..stereo()
+        };
+        assert_eq!(
+            LevelMeter::new(MeterConfig::default(), surround, &readings).err(),
+            Some(MeterError::TooManyChannels)
+        );
+    }
+
+    /// Fixed-size text sink for formatting tests
+    struct Text {
+        buf: [u8; 64],
+        len: usize,
+    }
+
+    impl Default for Text {
+        fn default() -> Self {
+            Self {
+                buf: [0; 64],
+                len: 0,
+            }
+        }
+    }
+
+    impl Text {
+        fn as_str(&self) -> &str {
+            core::str::from_utf8(&self.buf[..self.len]).unwrap()
+        }
+    }
+
+    impl fmt::Write for Text {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.len + s.len();
+            if end > self.buf.len() {
+                return Err(fmt::Error);
+            }
+            self.buf[self.len..end].copy_from_slice(s.as_bytes());
+            self.len = end;
+            Ok(())
+        }
+    }
+}
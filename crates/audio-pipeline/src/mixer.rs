@@ -0,0 +1,348 @@
+//! Multi-input mixer
+//!
+//! Sums several interleaved i16 streams (e.g. USB audio plus notification
+//! sounds) with independent gains. Inputs are summed in an i32 accumulator
+//! and saturated once at the output, so intermediate sums never wrap or
+//! clip early. An input without enough data contributes what it has and
+//! is reported as starved; the other inputs are unaffected.
+
+use crate::math;
+use crate::{AsyncRingBuffer, AudioFormat, RingBuffer};
+
+/// Highest gain an input may be given, in tenths of a dB
+pub const MAX_MIX_GAIN_DB_X10: i16 = 120;
+
+/// Unity gain in Q15
+const UNITY: i32 = 1 << 15;
+
+/// Mixer configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MixerError {
+    /// Input index out of range
+    InvalidInput,
+    /// Gain above [`MAX_MIX_GAIN_DB_X10`]
+    InvalidGain,
+}
+
+/// Source of interleaved i16 samples for the mixer (consumer side)
+pub trait MixInput {
+    /// Number of samples that can be read
+    fn available_read(&self) -> usize;
+
+    /// Read samples into `buf`, returning the number read
+    fn read(&self, buf: &mut [i16]) -> usize;
+}
+
+impl<const N: usize> MixInput for RingBuffer<i16, N> {
+    fn available_read(&self) -> usize {
+        RingBuffer::available_read(self)
+    }
+
+    fn read(&self, buf: &mut [i16]) -> usize {
+        RingBuffer::read(self, buf)
+    }
+}
+
+impl<const N: usize> MixInput for AsyncRingBuffer<i16, N> {
+    fn available_read(&self) -> usize {
+        AsyncRingBuffer::available_read(self)
+    }
+
+    fn read(&self, buf: &mut [i16]) -> usize {
+        AsyncRingBuffer::read(self, buf)
+    }
+}
+
+/// Outcome of one [`Mixer::mix`] call
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct MixReport {
+    /// Bit `i` is set if input `i` could not fill the whole block
+    pub starved: u32,
+    /// Output samples that had to be saturated
+    pub clipped: u32,
+}
+
+impl MixReport {
+    /// Check if `input` could not fill the whole block
+    pub fn is_starved(&self, input: usize) -> bool {
+        input < 32 && self.starved & (1 << input) != 0
+    }
+}
+
+/// Gain of one input, ramped per block to avoid zipper noise
+#[derive(Debug, Clone, Copy)]
+struct InputGain {
+    /// Gain at the end of the last block (Q15)
+    current: i32,
+    /// Requested gain (Q15)
+    target: i32,
+    db_x10: i16,
+}
+
+/// Mixer for `INPUTS` streams, processed in chunks of up to `BLOCK`
+/// samples
+pub struct Mixer<const INPUTS: usize, const BLOCK: usize> {
+    channels: usize,
+    gains: [InputGain; INPUTS],
+    scratch: [i16; BLOCK],
+    acc: [i32; BLOCK],
+}
+
+impl<const INPUTS: usize, const BLOCK: usize> Mixer<INPUTS, BLOCK> {
+    /// Create a mixer with all inputs at unity gain
+    pub const fn new(format: AudioFormat) -> Self {
+        assert!(INPUTS > 0 && INPUTS <= 32, "Mixer takes 1 to 32 inputs");
+        assert!(BLOCK >= 8, "Mixer block must hold at least 8 samples");
+
+        let channels = if format.channels == 0 {
+            1
+        } else {
+            format.channels as usize
+        };
+        Self {
+            channels,
+            gains: [InputGain {
+                current: UNITY,
+                target: UNITY,
+                db_x10: 0,
+            }; INPUTS],
+            scratch: [0; BLOCK],
+            acc: [0; BLOCK],
+        }
+    }
+
+    /// Change the stream format
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.channels = (format.channels as usize).max(1);
+    }
+
+    /// Set the gain of `input` in tenths of a dB
+    ///
+    /// The change is ramped in over the next mixed block.
+    pub fn set_gain_db_x10(&mut self, input: usize, db_x10: i16) -> Result<(), MixerError> {
+        if db_x10 > MAX_MIX_GAIN_DB_X10 {
+            return Err(MixerError::InvalidGain);
+        }
+        let gain = self.gains.get_mut(input).ok_or(MixerError::InvalidInput)?;
+        gain.db_x10 = db_x10;
+        gain.target = (math::db_to_linear(db_x10 as f32 / 10.0) * UNITY as f32 + 0.5) as i32;
+        Ok(())
+    }
+
+    /// Gain of `input` in tenths of a dB (`None` if muted or out of range)
+    pub fn gain_db_x10(&self, input: usize) -> Option<i16> {
+        self.gains
+            .get(input)
+            .filter(|gain| gain.target > 0)
+            .map(|gain| gain.db_x10)
+    }
+
+    /// Silence `input`
+    ///
+    /// A muted input is still drained so its producer never stalls.
+    pub fn mute(&mut self, input: usize) -> Result<(), MixerError> {
+        let gain = self.gains.get_mut(input).ok_or(MixerError::InvalidInput)?;
+        gain.target = 0;
+        Ok(())
+    }
+
+    /// Restore the gain of a muted `input`
+    pub fn unmute(&mut self, input: usize) -> Result<(), MixerError> {
+        let db_x10 = self
+            .gains
+            .get(input)
+            .ok_or(MixerError::InvalidInput)?
+            .db_x10;
+        self.set_gain_db_x10(input, db_x10)
+    }
+
+    /// Fill `out` with the mix of all inputs
+    ///
+    /// Only whole frames are written; a trailing partial frame in `out` is
+    /// left untouched.
+    pub fn mix(&mut self, inputs: [&dyn MixInput; INPUTS], out: &mut [i16]) -> MixReport {
+        let channels = self.channels;
+        let chunk_len = BLOCK - BLOCK % channels;
+        let whole = out.len() - out.len() % channels;
+
+        let mut report = MixReport::default();
+        if chunk_len == 0 {
+            return report;
+        }
+        for chunk in out[..whole].chunks_mut(chunk_len) {
+            self.mix_chunk(&inputs, chunk, &mut report);
+        }
+        report
+    }
+
+    fn mix_chunk(
+        &mut self,
+        inputs: &[&dyn MixInput; INPUTS],
+        out: &mut [i16],
+        report: &mut MixReport,
+    ) {
+        let channels = self.channels;
+        let len = out.len();
+        let frames = (len / channels) as i64;
+        self.acc[..len].fill(0);
+
+        for (index, (input, gain)) in inputs.iter().zip(self.gains.iter_mut()).enumerate() {
+            let wanted = input.available_read().min(len);
+            let read = input.read(&mut self.scratch[..wanted - wanted % channels]);
+            if read < len {
+                report.starved |= 1 << index;
+            }
+
+            // Ramp linearly from the previous gain to the target
+            let start = gain.current as i64;
+            let delta = gain.target as i64 - start;
+            for (i, (&sample, acc)) in self.scratch[..read]
+                .iter()
+                .zip(self.acc.iter_mut())
+                .enumerate()
+            {
+                let frame = (i / channels) as i64 + 1;
+                let g = start + delta * frame / frames;
+                *acc += ((sample as i64 * g + (1 << 14)) >> 15) as i32;
+            }
+            gain.current = gain.target;
+        }
+
+        for (sample, &acc) in out.iter_mut().zip(self.acc.iter()) {
+            let clamped = acc.clamp(i16::MIN as i32, i16::MAX as i32);
+            if clamped != acc {
+                report.clipped += 1;
+            }
+            *sample = clamped as i16;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo() -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    #[test]
+    fn test_sums_inputs() {
+        let main: RingBuffer<i16, 64> = RingBuffer::new();
+        let aux: RingBuffer<i16, 64> = RingBuffer::new();
+        main.write(&[1000, -1000, 2000, -2000]);
+        aux.write(&[10, 20, 30, 40]);
+
+        let mut mixer: Mixer<2, 32> = Mixer::new(stereo());
+        let mut out = [0i16; 4];
+        let report = mixer.mix([&main, &aux], &mut out);
+
+        assert_eq!(out, [1010, -980, 2030, -1960]);
+        assert_eq!(report, MixReport::default());
+        assert!(main.is_empty() && aux.is_empty());
+    }
+
+    #[test]
+    fn test_saturates_instead_of_wrapping() {
+        let a: RingBuffer<i16, 16> = RingBuffer::new();
+        let b: RingBuffer<i16, 16> = RingBuffer::new();
+        let c: RingBuffer<i16, 16> = RingBuffer::new();
+        a.write(&[30_000, -30_000]);
+        b.write(&[30_000, -30_000]);
+        // Third input pulls the sum back into range: no early clipping
+        c.write(&[-30_000, 30_000]);
+
+        let mut mixer: Mixer<3, 16> = Mixer::new(stereo());
+        let mut out = [0i16; 2];
+        let report = mixer.mix([&a, &b, &c], &mut out);
+        assert_eq!(out, [30_000, -30_000]);
+        assert_eq!(report.clipped, 0);
+
+        a.write(&[30_000, -30_000]);
+        b.write(&[30_000, -30_000]);
+        let report = mixer.mix([&a, &b, &c], &mut out);
+        assert_eq!(out, [i16::MAX, i16::MIN]);
+        assert_eq!(report.clipped, 2);
+        assert!(report.is_starved(2));
+    }
+
+    #[test]
+    fn test_starved_input_does_not_block_others() {
+        let main: RingBuffer<i16, 64> = RingBuffer::new();
+        let aux: RingBuffer<i16, 64> = RingBuffer::new();
+        main.write(&[100; 8]);
+        aux.write(&[5, 5, 5]);
+
+        let mut mixer: Mixer<2, 32> = Mixer::new(stereo());
+        let mut out = [0i16; 8];
+        let report = mixer.mix([&main, &aux], &mut out);
+
+        // Aux supplies one whole frame; its odd sample stays queued
+        assert_eq!(out, [105, 105, 100, 100, 100, 100, 100, 100]);
+        assert!(!report.is_starved(0));
+        assert!(report.is_starved(1));
+        assert_eq!(aux.available_read(), 1);
+    }
+
+    #[test]
+    fn test_gain_and_mute() {
+        let input: RingBuffer<i16, 64> = RingBuffer::new();
+        let mut mixer: Mixer<1, 8> = Mixer::new(stereo());
+        mixer.set_gain_db_x10(0, -60).unwrap();
+
+        // First block ramps from unity down to -6 dB
+        input.write(&[10_000; 8]);
+        let mut out = [0i16; 8];
+        mixer.mix([&input], &mut out);
+        assert!(out[0] < 10_000 && out[0] > 5012);
+        assert!(out.windows(2).all(|w| w[1] <= w[0]));
+        assert_eq!(out[7], 5012);
+
+        input.write(&[10_000; 8]);
+        mixer.mix([&input], &mut out);
+        assert!(out.iter().all(|&s| s == 5012));
+        assert_eq!(mixer.gain_db_x10(0), Some(-60));
+
+        mixer.mute(0).unwrap();
+        input.write(&[10_000; 8]);
+        mixer.mix([&input], &mut out);
+        input.write(&[10_000; 8]);
+        mixer.mix([&input], &mut out);
+        assert!(out.iter().all(|&s| s == 0));
+        assert!(input.is_empty());
+        assert_eq!(mixer.gain_db_x10(0), None);
+
+        mixer.unmute(0).unwrap();
+        assert_eq!(mixer.gain_db_x10(0), Some(-60));
+    }
+
+    #[test]
+    fn test_mixes_longer_than_block() {
+        let input: AsyncRingBuffer<i16, 128> = AsyncRingBuffer::new();
+        let values: [i16; 100] = core::array::from_fn(|i| i as i16);
+        input.write(&values);
+
+        // Block of 9 samples is processed as 4-frame chunks
+        let mut mixer: Mixer<1, 9> = Mixer::new(stereo());
+        let mut out = [0i16; 101];
+        let report = mixer.mix([&input], &mut out);
+        assert_eq!(out[..100], values);
+        assert_eq!(out[100], 0);
+        assert_eq!(report.starved, 0);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut mixer: Mixer<2, 16> = Mixer::new(stereo());
+        assert_eq!(mixer.set_gain_db_x10(2, 0), Err(MixerError::InvalidInput));
+        assert_eq!(mixer.set_gain_db_x10(0, 121), Err(MixerError::InvalidGain));
+        assert_eq!(mixer.mute(5), Err(MixerError::InvalidInput));
+        assert!(mixer.set_gain_db_x10(1, MAX_MIX_GAIN_DB_X10).is_ok());
+    }
+}
@@ -0,0 +1,574 @@
+//! Flash-resident audio prompts
+//!
+//! Short clips (chimes, voice prompts) are stored in a prompt bank: a flat
+//! image written to a reserved flash region and read in place, so clips
+//! never need RAM beyond the decoder state. Clips are either 16-bit PCM or
+//! 4-bit IMA ADPCM (a quarter of the size) and are converted to the stream
+//! format on the fly.
+//!
+//! # Bank layout (little-endian)
+//!
+//! | Offset | Size | Field |
+//! |--------|------|-------|
+//! | 0 | 4 | Magic `PRMT` |
+//! | 4 | 1 | Version (1) |
+//! | 5 | 1 | Clip count |
+//! | 6 | 2 | Reserved |
+//! | 8 | 16 × count | Clip table |
+//!
+//! Each clip table entry holds the clip id (u8), codec (u8, 0 = PCM16,
+//! 1 = IMA ADPCM), channel count (u8), a reserved byte, the sample rate
+//! (u32) and the offset from the start of the bank and length of the clip
+//! data in bytes (u32 each). IMA ADPCM clips are mono; their data starts
+//! with the initial predictor (i16) and step index (u8) plus a padding
+//! byte, followed by samples packed two per byte, low nibble first.
+
+use crate::AudioFormat;
+
+/// Prompt bank magic
+pub const PROMPT_BANK_MAGIC: [u8; 4] = *b"PRMT";
+
+/// Prompt bank format version
+pub const PROMPT_BANK_VERSION: u8 = 1;
+
+/// Bank header size in bytes
+const HEADER_SIZE: usize = 8;
+
+/// Clip table entry size in bytes
+const ENTRY_SIZE: usize = 16;
+
+/// Size of the IMA ADPCM preamble (predictor, step index, padding)
+const ADPCM_PREAMBLE: usize = 4;
+
+/// Q16 unity for the resampling phase
+const PHASE_ONE: u32 = 1 << 16;
+
+/// IMA ADPCM step sizes
+const IMA_STEPS: [i16; 89] = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408, 449,
+    494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066, 2272,
+    2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630, 9493,
+    10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794, 32767,
+];
+
+/// IMA ADPCM step index adjustment per code
+const IMA_INDEX: [i8; 8] = [-1, -1, -1, -1, 2, 4, 6, 8];
+
+/// Prompt bank errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PromptError {
+    /// Missing magic or unsupported version (e.g. erased flash)
+    InvalidBank,
+    /// Clip table or clip data outside the bank
+    Truncated,
+    /// Unknown codec or unsupported channel count
+    UnsupportedClip,
+}
+
+/// Clip encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum PromptCodec {
+    /// Interleaved signed 16-bit little-endian PCM
+    Pcm16,
+    /// 4-bit IMA ADPCM, mono
+    ImaAdpcm,
+}
+
+/// A clip stored in a prompt bank
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PromptClip<'a> {
+    /// Clip id
+    pub id: u8,
+    /// Encoding of `data`
+    pub codec: PromptCodec,
+    /// Channels in the clip (1 or 2)
+    pub channels: u8,
+    /// Sample rate of the clip in Hz
+    pub sample_rate: u32,
+    /// Encoded clip data
+    pub data: &'a [u8],
+}
+
+impl PromptClip<'_> {
+    /// Check the clip parameters
+    ///
+    /// Clips from [`PromptBank`] always pass; clips built by hand are
+    /// checked by [`PromptPlayer::play`].
+    pub fn validate(&self) -> Result<(), PromptError> {
+        let valid = match self.codec {
+            PromptCodec::Pcm16 => matches!(self.channels, 1 | 2),
+            PromptCodec::ImaAdpcm => self.channels == 1,
+        };
+        if !valid || self.sample_rate == 0 {
+            return Err(PromptError::UnsupportedClip);
+        }
+        if self.codec == PromptCodec::ImaAdpcm && self.data.len() < ADPCM_PREAMBLE {
+            return Err(PromptError::Truncated);
+        }
+        Ok(())
+    }
+
+    /// Number of frames in the clip
+    pub fn frames(&self) -> usize {
+        match self.codec {
+            PromptCodec::Pcm16 => self.data.len() / (2 * self.channels.max(1) as usize),
+            PromptCodec::ImaAdpcm => self.data.len().saturating_sub(ADPCM_PREAMBLE) * 2,
+        }
+    }
+
+    /// Clip duration in milliseconds
+    pub fn duration_ms(&self) -> u32 {
+        match self.sample_rate {
+            0 => 0,
+            rate => (self.frames() as u64 * 1000 / rate as u64) as u32,
+        }
+    }
+}
+
+/// Read-only view of a prompt bank
+#[derive(Debug, Clone, Copy)]
+pub struct PromptBank<'a> {
+    data: &'a [u8],
+    count: usize,
+}
+
+impl<'a> PromptBank<'a> {
+    /// Validate and wrap a bank image
+    ///
+    /// Every clip table entry is checked, so [`PromptBank::get`] can't fail
+    /// on a bank that parsed.
+    pub fn parse(data: &'a [u8]) -> Result<Self, PromptError> {
+        if data.len() < HEADER_SIZE
+            || data[0..4] != PROMPT_BANK_MAGIC
+            || data[4] != PROMPT_BANK_VERSION
+        {
+            return Err(PromptError::InvalidBank);
+        }
+
+        let count = data[5] as usize;
+        if data.len() < HEADER_SIZE + count * ENTRY_SIZE {
+            return Err(PromptError::Truncated);
+        }
+
+        let bank = Self { data, count };
+        for index in 0..count {
+            bank.entry(index)?;
+        }
+        Ok(bank)
+    }
+
+    /// Number of clips in the bank
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Check if the bank holds no clips
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// Look up a clip by id
+    pub fn get(&self, id: u8) -> Option<PromptClip<'a>> {
+        self.iter().find(|clip| clip.id == id)
+    }
+
+    /// Iterate over all clips
+    pub fn iter(&self) -> impl Iterator<Item = PromptClip<'a>> + '_ {
+        (0..self.count).filter_map(move |index| self.entry(index).ok())
+    }
+
+    fn entry(&self, index: usize) -> Result<PromptClip<'a>, PromptError> {
+        let e = &self.data[HEADER_SIZE + index * ENTRY_SIZE..][..ENTRY_SIZE];
+        let word = |at: usize| u32::from_le_bytes([e[at], e[at + 1], e[at + 2], e[at + 3]]);
+
+        let codec = match e[1] {
+            0 => PromptCodec::Pcm16,
+            1 => PromptCodec::ImaAdpcm,
+            _ => return Err(PromptError::UnsupportedClip),
+        };
+        let offset = word(8) as usize;
+        let len = word(12) as usize;
+        let data = offset
+            .checked_add(len)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or(PromptError::Truncated)?;
+
+        let clip = PromptClip {
+            id: e[0],
+            codec,
+            channels: e[2],
+            sample_rate: word(4),
+            data,
+        };
+        clip.validate()?;
+        Ok(clip)
+    }
+}
+
+/// IMA ADPCM decoder state
+#[derive(Debug, Clone, Copy, Default)]
+struct AdpcmState {
+    predictor: i32,
+    index: usize,
+}
+
+impl AdpcmState {
+    fn decode(&mut self, code: u8) -> i16 {
+        let step = IMA_STEPS[self.index] as i32;
+        let mut diff = step >> 3;
+        if code & 4 != 0 {
+            diff += step;
+        }
+        if code & 2 != 0 {
+            diff += step >> 1;
+        }
+        if code & 1 != 0 {
+            diff += step >> 2;
+        }
+        if code & 8 != 0 {
+            diff = -diff;
+        }
+
+        self.predictor = (self.predictor + diff).clamp(i16::MIN as i32, i16::MAX as i32);
+        let index = self.index as i32 + IMA_INDEX[(code & 7) as usize] as i32;
+        self.index = index.clamp(0, IMA_STEPS.len() as i32 - 1) as usize;
+        self.predictor as i16
+    }
+}
+
+/// Plays one prompt clip at a time, converted to the stream format
+pub struct PromptPlayer<'a> {
+    format: AudioFormat,
+    clip: Option<PromptClip<'a>>,
+    /// Next source frame to decode
+    position: usize,
+    adpcm: AdpcmState,
+    /// Source frames around the output position
+    prev: [i16; 2],
+    next: [i16; 2],
+    /// Output position between `prev` and `next` (Q16)
+    phase: u32,
+    /// Source frames per output frame (Q16)
+    step: u32,
+    /// Source exhausted; `next` is padding
+    draining: bool,
+}
+
+impl<'a> PromptPlayer<'a> {
+    /// Create an idle player producing `format`
+    pub const fn new(format: AudioFormat) -> Self {
+        Self {
+            format,
+            clip: None,
+            position: 0,
+            adpcm: AdpcmState {
+                predictor: 0,
+                index: 0,
+            },
+            prev: [0; 2],
+            next: [0; 2],
+            phase: 0,
+            step: PHASE_ONE,
+            draining: false,
+        }
+    }
+
+    /// Change the output format, stopping playback
+    pub fn set_format(&mut self, format: AudioFormat) {
+        self.format = format;
+        self.stop();
+    }
+
+    /// Start playing `clip` from the beginning, replacing any current clip
+    ///
+    /// An invalid clip is rejected and leaves the player unchanged.
+    pub fn play(&mut self, clip: PromptClip<'a>) -> Result<(), PromptError> {
+        clip.validate()?;
+        self.clip = Some(clip);
+        self.position = 0;
+        self.adpcm = AdpcmState::default();
+        if clip.codec == PromptCodec::ImaAdpcm {
+            self.adpcm.predictor = i16::from_le_bytes([clip.data[0], clip.data[1]]) as i32;
+            self.adpcm.index = (clip.data[2] as usize).min(IMA_STEPS.len() - 1);
+        }
+
+        let rate = self.format.sample_rate.max(1) as u64;
+        self.step = ((clip.sample_rate as u64 * PHASE_ONE as u64) / rate) as u32;
+        self.phase = 0;
+        self.draining = false;
+        self.prev = [0; 2];
+        self.next = [0; 2];
+        self.advance();
+        self.advance();
+        Ok(())
+    }
+
+    /// Stop playback
+    pub fn stop(&mut self) {
+        self.clip = None;
+    }
+
+    /// Check if a clip is playing
+    pub fn is_playing(&self) -> bool {
+        self.clip.is_some()
+    }
+
+    /// Id of the playing clip
+    pub fn current(&self) -> Option<u8> {
+        self.clip.map(|clip| clip.id)
+    }
+
+    /// Render interleaved output samples into `out`
+    ///
+    /// Returns the number of samples written, which is less than
+    /// `out.len()` (rounded down to whole frames) once the clip ends.
+    pub fn fill(&mut self, out: &mut [i16]) -> usize {
+        let channels = self.format.channels as usize;
+        if channels == 0 {
+            return 0;
+        }
+
+        let mut written = 0;
+        for frame in out.chunks_exact_mut(channels) {
+            if self.clip.is_none() {
+                break;
+            }
+
+            let stereo = self.clip.is_some_and(|clip| clip.channels == 2);
+            let mut values = [0i16; 2];
+            for (ch, value) in values.iter_mut().enumerate() {
+                let a = self.prev[ch] as i64;
+                let b = self.next[ch] as i64;
+                *value = (a + (((b - a) * self.phase as i64) >> 16)) as i16;
+            }
+
+            if channels == 1 {
+                frame[0] = ((values[0] as i32 + values[1] as i32) / 2) as i16;
+            } else {
+                for (ch, sample) in frame.iter_mut().enumerate() {
+                    *sample = if stereo { values[ch % 2] } else { values[0] };
+                }
+            }
+            written += channels;
+
+            self.phase += self.step;
+            while self.phase >= PHASE_ONE && self.clip.is_some() {
+                self.phase -= PHASE_ONE;
+                self.advance();
+            }
+        }
+        written
+    }
+
+    /// Shift `next` into `prev` and decode the following source frame
+    fn advance(&mut self) {
+        let Some(clip) = self.clip else {
+            return;
+        };
+
+        if self.draining {
+            // `prev` was the last real frame
+            self.clip = None;
+            return;
+        }
+
+        self.prev = self.next;
+        match self.decode(&clip) {
+            Some(frame) => self.next = frame,
+            None => {
+                self.next = [0; 2];
+                self.draining = true;
+            }
+        }
+    }
+
+    /// Decode the source frame at `position` (mono clips fill both slots)
+    fn decode(&mut self, clip: &PromptClip<'a>) -> Option<[i16; 2]> {
+        let index = self.position;
+        if index >= clip.frames() {
+            return None;
+        }
+        self.position += 1;
+
+        match clip.codec {
+            PromptCodec::Pcm16 => {
+                let channels = clip.channels as usize;
+                let sample = |ch: usize| {
+                    let at = (index * channels + ch) * 2;
+                    i16::from_le_bytes([clip.data[at], clip.data[at + 1]])
+                };
+                let left = sample(0);
+                let right = if channels == 2 { sample(1) } else { left };
+                Some([left, right])
+            }
+            PromptCodec::ImaAdpcm => {
+                let byte = clip.data[ADPCM_PREAMBLE + index / 2];
+                let code = if index % 2 == 0 {
+                    byte & 0x0F
+                } else {
+                    byte >> 4
+                };
+                let sample = self.adpcm.decode(code);
+                Some([sample, sample])
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format(sample_rate: u32, channels: u8) -> AudioFormat {
+        AudioFormat {
+            sample_rate,
+            channels,
+            bits_per_sample: 16,
+        }
+    }
+
+    /// Build a bank image from (id, codec, channels, rate, data) tuples
+    fn build_bank(clips: &[(u8, u8, u8, u32, &[u8])], out: &mut [u8]) -> usize {
+        out[0..4].copy_from_slice(&PROMPT_BANK_MAGIC);
+        out[4] = PROMPT_BANK_VERSION;
+        out[5] = clips.len() as u8;
+
+        let mut offset = HEADER_SIZE + clips.len() * ENTRY_SIZE;
+        for (i, &(id, codec, channels, rate, data)) in clips.iter().enumerate() {
+            let e = &mut out[HEADER_SIZE + i * ENTRY_SIZE..][..ENTRY_SIZE];
+            e[0] = id;
+            e[1] = codec;
+            e[2] = channels;
+            e[4..8].copy_from_slice(&rate.to_le_bytes());
+            e[8..12].copy_from_slice(&(offset as u32).to_le_bytes());
+            e[12..16].copy_from_slice(&(data.len() as u32).to_le_bytes());
+            out[offset..offset + data.len()].copy_from_slice(data);
+            offset += data.len();
+        }
+        offset
+    }
+
+    fn pcm(samples: &[i16], out: &mut [u8]) -> usize {
+        for (i, s) in samples.iter().enumerate() {
+            out[i * 2..i * 2 + 2].copy_from_slice(&s.to_le_bytes());
+        }
+        samples.len() * 2
+    }
+
+    #[test]
+    fn test_parse_and_lookup() {
+        let mut data = [0u8; 8];
+        let len = pcm(&[1, 2, 3, 4], &mut data);
+        let mut image = [0u8; 128];
+        let size = build_bank(
+            &[
+                (7, 0, 2, 48000, &data[..len]),
+                (9, 1, 1, 16000, &[0, 0, 0, 0, 0x77]),
+            ],
+            &mut image,
+        );
+
+        let bank = PromptBank::parse(&image[..size]).unwrap();
+        assert_eq!(bank.len(), 2);
+        let clip = bank.get(7).unwrap();
+        assert_eq!(clip.codec, PromptCodec::Pcm16);
+        assert_eq!(clip.frames(), 2);
+        assert_eq!(bank.get(9).unwrap().frames(), 2);
+        assert!(bank.get(8).is_none());
+    }
+
+    #[test]
+    fn test_rejects_bad_banks() {
+        // Erased flash
+        assert_eq!(
+            PromptBank::parse(&[0xFF; 64]).err(),
+            Some(PromptError::InvalidBank)
+        );
+
+        let mut image = [0u8; 64];
+        let size = build_bank(&[(1, 0, 1, 48000, &[0; 8])], &mut image);
+        assert_eq!(
+            PromptBank::parse(&image[..size - 1]).err(),
+            Some(PromptError::Truncated)
+        );
+
+        image[HEADER_SIZE + 2] = 3;
+        assert_eq!(
+            PromptBank::parse(&image[..size]).err(),
+            Some(PromptError::UnsupportedClip)
+        );
+    }
+
+    #[test]
+    fn test_plays_pcm_at_stream_rate() {
+        let mut data = [0u8; 16];
+        let len = pcm(&[100, -100, 200, -200, 300, -300], &mut data);
+        let mut image = [0u8; 64];
+        let size = build_bank(&[(1, 0, 2, 48000, &data[..len])], &mut image);
+        let bank = PromptBank::parse(&image[..size]).unwrap();
+
+        let mut player = PromptPlayer::new(format(48000, 2));
+        player.play(bank.get(1).unwrap()).unwrap();
+        assert_eq!(player.current(), Some(1));
+
+        let mut out = [0i16; 10];
+        assert_eq!(player.fill(&mut out), 6);
+        assert_eq!(out[..6], [100, -100, 200, -200, 300, -300]);
+        assert!(!player.is_playing());
+        assert_eq!(player.fill(&mut out), 0);
+    }
+
+    #[test]
+    fn test_upsamples_mono_to_stereo() {
+        let mut data = [0u8; 8];
+        let len = pcm(&[0, 1000, 2000], &mut data);
+        let mut image = [0u8; 64];
+        let size = build_bank(&[(1, 0, 1, 24000, &data[..len])], &mut image);
+        let bank = PromptBank::parse(&image[..size]).unwrap();
+
+        let mut player = PromptPlayer::new(format(48000, 2));
+        player.play(bank.get(1).unwrap()).unwrap();
+
+        let mut out = [0i16; 16];
+        let written = player.fill(&mut out);
+        assert_eq!(written, 12);
+        assert_eq!(
+            out[..12],
+            [0, 0, 500, 500, 1000, 1000, 1500, 1500, 2000, 2000, 1000, 1000]
+        );
+    }
+
+    #[test]
+    fn test_adpcm_decodes_ramp() {
+        // Code 4 adds a full step each sample, and the step grows
+        let mut image = [0u8; 64];
+        let size = build_bank(&[(2, 1, 1, 48000, &[0, 0, 0, 0, 0x44, 0x44])], &mut image);
+        let bank = PromptBank::parse(&image[..size]).unwrap();
+        let clip = bank.get(2).unwrap();
+        assert_eq!(clip.frames(), 4);
+
+        let mut player = PromptPlayer::new(format(48000, 1));
+        player.play(clip).unwrap();
+        let mut out = [0i16; 8];
+        assert_eq!(player.fill(&mut out), 4);
+        assert_eq!(out[..4], [7, 17, 29, 43]);
+
+        // Hand-built clip too short for the preamble
+        let short = PromptClip {
+            data: &[0, 0],
+            ..clip
+        };
+        assert_eq!(short.frames(), 0);
+        let unclocked = PromptClip {
+            sample_rate: 0,
+            ..clip
+        };
+        assert_eq!(unclocked.duration_ms(), 0);
+        assert_eq!(player.play(unclocked), Err(PromptError::UnsupportedClip));
+        assert_eq!(player.play(short), Err(PromptError::Truncated));
+        assert!(!player.is_playing());
+    }
+}
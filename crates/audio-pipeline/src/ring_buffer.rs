@@ -5,18 +5,73 @@
 
 use core::cell::UnsafeCell;
 use core::mem::MaybeUninit;
-use portable_atomic::{AtomicUsize, Ordering};
+use core::{ptr, slice};
+use portable_atomic::{AtomicU8, AtomicUsize, Ordering};
+
+/// What the producer does when a write does not fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum OverflowMode {
+    /// Write only what fits and leave existing data untouched
+    #[default]
+    Reject,
+    /// Drop the oldest unread items to make room
+    ///
+    /// The producer then moves the read position, so producer writes and
+    /// consumer reads run inside a critical section. Zero-copy reads via
+    /// `read_slices` can't be protected and are not allowed in this mode;
+    /// use `read` or `peek`.
+    OverwriteOldest,
+}
+
+/// Fill level relative to the configured watermarks
+///
+/// Maps onto `usb_audio::StreamState`: `Normal` is `Active`, `Underrun`
+/// and `Overrun` correspond directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum FillState {
+    /// Fill level between the watermarks
+    #[default]
+    Normal = 0,
+    /// Fill level at or below the low watermark
+    Underrun = 1,
+    /// Fill level at or above the high watermark
+    Overrun = 2,
+}
+
+impl FillState {
+    const fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Underrun,
+            2 => Self::Overrun,
+            _ => Self::Normal,
+        }
+    }
+}
 
 /// Lock-free SPSC ring buffer
 ///
+/// Watermarks default to empty (low) and full (high); either side may
+/// change them with [`RingBuffer::set_watermarks`] and poll for
+/// transitions with [`RingBuffer::poll_watermarks`].
+///
 /// # Safety
 /// This buffer is only safe for single-producer, single-consumer usage.
-/// The producer should only call `write` and `available_write`.
-/// The consumer should only call `read` and `available_read`.
+/// The producer should only call `write`, `write_slices`, `commit` and
+/// `available_write`.
+/// The consumer should only call `read`, `read_slices`, `consume`, `peek`,
+/// `skip` and `available_read`.
 pub struct RingBuffer<T, const N: usize> {
     buffer: UnsafeCell<[MaybeUninit<T>; N]>,
     head: AtomicUsize, // Write position (producer)
-    tail: AtomicUsize, // Read position (consumer)
+    tail: AtomicUsize, // Read position (consumer, or producer when overwriting)
+    mode: OverflowMode,
+    dropped: AtomicUsize,
+    low_watermark: AtomicUsize,
+    high_watermark: AtomicUsize,
+    fill_state: AtomicU8,
 }
 
 // Safety: RingBuffer is Sync because we use atomic operations for head/tail
@@ -29,6 +84,13 @@ impl<T: Copy, const N: usize> RingBuffer<T, N> {
     ///
     /// N must be a power of 2 for efficient modulo operations.
     pub const fn new() -> Self {
+        Self::with_mode(OverflowMode::Reject)
+    }
+
+    /// Create a new empty ring buffer with the given overflow behavior
+    ///
+    /// N must be a power of 2 for efficient modulo operations.
+    pub const fn with_mode(mode: OverflowMode) -> Self {
         assert!(N > 0, "Buffer size must be > 0");
         assert!(N.is_power_of_two(), "Buffer size must be power of 2");
 
@@ -39,14 +101,25 @@ impl<T: Copy, const N: usize> RingBuffer<T, N> {
             ),
             head: AtomicUsize::new(0),
             tail: AtomicUsize::new(0),
+            mode,
+            dropped: AtomicUsize::new(0),
+            low_watermark: AtomicUsize::new(0),
+            high_watermark: AtomicUsize::new(N - 1),
+            fill_state: AtomicU8::new(FillState::Underrun as u8),
         }
     }
 
+    /// Overflow behavior of this buffer
+    pub fn mode(&self) -> OverflowMode {
+        self.mode
+    }
+
     /// Number of items that can be read
     pub fn available_read(&self) -> usize {
-        let head = self.head.load(Ordering::Acquire);
+        // Tail first: it never passes head, so the difference can't wrap
         let tail = self.tail.load(Ordering::Acquire);
-        head.wrapping_sub(tail)
+        let head = self.head.load(Ordering::Acquire);
+        head.wrapping_sub(tail).min(N - 1)
     }
 
     /// Number of items that can be written
@@ -66,69 +139,255 @@ impl<T: Copy, const N: usize> RingBuffer<T, N> {
 
     /// Write items to the buffer (producer only)
     ///
+    /// In [`OverflowMode::OverwriteOldest`] the oldest unread items (and,
+    /// if `data` exceeds the capacity, the start of `data`) are dropped to
+    /// make room and counted in [`RingBuffer::dropped`].
+    ///
     /// Returns the number of items actually written.
     pub fn write(&self, data: &[T]) -> usize {
-        let available = self.available_write();
-        let to_write = data.len().min(available);
-
-        if to_write == 0 {
-            return 0;
+        match self.mode {
+            OverflowMode::Reject => self.write_free(data),
+            OverflowMode::OverwriteOldest => critical_section::with(|_| {
+                let skipped = data.len().saturating_sub(N - 1);
+                let data = &data[skipped..];
+                let overwritten = data.len().saturating_sub(self.available_write());
+
+                if overwritten > 0 {
+                    let tail = self.tail.load(Ordering::Relaxed);
+                    self.tail
+                        .store(tail.wrapping_add(overwritten), Ordering::Release);
+                }
+                if skipped + overwritten > 0 {
+                    self.dropped
+                        .fetch_add(skipped + overwritten, Ordering::Relaxed);
+                }
+
+                self.write_free(data)
+            }),
         }
+    }
 
-        let head = self.head.load(Ordering::Relaxed);
+    /// Total number of items dropped by overwriting since creation or the
+    /// last [`RingBuffer::take_dropped`]
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Return and reset the dropped-item counter (consumer only)
+    pub fn take_dropped(&self) -> usize {
+        self.dropped.swap(0, Ordering::Relaxed)
+    }
 
-        // Safety: We're the only producer, and we're writing to slots
-        // that are not being read (head to head + to_write).
-        let buffer = unsafe { &mut *self.buffer.get() };
+    /// Configure the watermarks used by [`RingBuffer::poll_watermarks`]
+    ///
+    /// `high` is clamped to the capacity and `low` to below `high`.
+    pub fn set_watermarks(&self, low: usize, high: usize) {
+        let high = high.clamp(1, N - 1);
+        self.low_watermark
+            .store(low.min(high - 1), Ordering::Relaxed);
+        self.high_watermark.store(high, Ordering::Relaxed);
+    }
 
-        // Bounded loop: to_write iterations
-        for i in 0..to_write {
-            let idx = (head + i) & (N - 1); // Fast modulo for power of 2
-            buffer[idx] = MaybeUninit::new(data[i]);
-        }
+    /// Current `(low, high)` watermarks
+    pub fn watermarks(&self) -> (usize, usize) {
+        (
+            self.low_watermark.load(Ordering::Relaxed),
+            self.high_watermark.load(Ordering::Relaxed),
+        )
+    }
 
-        // Publish the new head
-        self.head
-            .store(head.wrapping_add(to_write), Ordering::Release);
+    /// Fill state as of the last [`RingBuffer::poll_watermarks`]
+    pub fn fill_state(&self) -> FillState {
+        FillState::from_u8(self.fill_state.load(Ordering::Relaxed))
+    }
 
-        to_write
+    /// Classify the current fill level and report a transition
+    ///
+    /// Returns `Some(state)` only when the state differs from the previous
+    /// poll, so each underrun or overrun is reported once.
+    pub fn poll_watermarks(&self) -> Option<FillState> {
+        let fill = self.available_read();
+        let (low, high) = self.watermarks();
+
+        let state = if fill <= low {
+            FillState::Underrun
+        } else if fill >= high {
+            FillState::Overrun
+        } else {
+            FillState::Normal
+        };
+
+        let previous = self.fill_state.swap(state as u8, Ordering::Relaxed);
+        (previous != state as u8).then_some(state)
+    }
+
+    /// Write into free slots only
+    fn write_free(&self, data: &[T]) -> usize {
+        // Safety: Producer side, and the slices end with this call
+        let (first, second) = unsafe { self.write_slices() };
+        let n1 = data.len().min(first.len());
+        let n2 = (data.len() - n1).min(second.len());
+
+        // Safety: Both regions are free slots owned by the producer, and
+        // the source is a valid slice of initialized items.
+        unsafe {
+            ptr::copy_nonoverlapping(data.as_ptr(), first.as_mut_ptr() as *mut T, n1);
+            ptr::copy_nonoverlapping(data[n1..].as_ptr(), second.as_mut_ptr() as *mut T, n2);
+            self.commit(n1 + n2);
+        }
+
+        n1 + n2
     }
 
     /// Read items from the buffer (consumer only)
     ///
     /// Returns the number of items actually read.
     pub fn read(&self, buf: &mut [T]) -> usize {
-        let available = self.available_read();
-        let to_read = buf.len().min(available);
+        self.consumer_side(|| {
+            let read = self.peek_unguarded(buf);
+            self.consume_unguarded(read)
+        })
+    }
 
-        if to_read == 0 {
-            return 0;
-        }
+    /// Copy items out without removing them (consumer only)
+    ///
+    /// Returns the number of items copied.
+    pub fn peek(&self, buf: &mut [T]) -> usize {
+        self.consumer_side(|| self.peek_unguarded(buf))
+    }
 
+    /// Copy out readable items; an overwriting producer must be excluded
+    fn peek_unguarded(&self, buf: &mut [T]) -> usize {
         let tail = self.tail.load(Ordering::Relaxed);
+        let (idx, len1, len2) = Self::split(tail, self.available_read().min(buf.len()));
+        let base = self.buffer.get() as *const T;
+
+        // Safety: Slots from tail up to head are initialized, and the
+        // producer can't reuse them while the caller excludes it.
+        unsafe {
+            ptr::copy_nonoverlapping(base.add(idx), buf.as_mut_ptr(), len1);
+            ptr::copy_nonoverlapping(base, buf[len1..].as_mut_ptr(), len2);
+        }
 
-        // Safety: We're the only consumer, and we're reading from slots
-        // that are not being written (tail to tail + to_read).
-        let buffer = unsafe { &*self.buffer.get() };
+        len1 + len2
+    }
+
+    /// Discard up to `count` items without reading them (consumer only)
+    ///
+    /// Returns the number of items discarded.
+    pub fn skip(&self, count: usize) -> usize {
+        self.consume(count)
+    }
 
-        // Bounded loop: to_read iterations
-        for i in 0..to_read {
-            let idx = (tail + i) & (N - 1);
-            // Safety: This slot was written by the producer
-            buf[i] = unsafe { buffer[idx].assume_init() };
+    /// Free writable region as up to two contiguous slices (producer only)
+    ///
+    /// The second slice is non-empty only when the region wraps around the
+    /// end of the storage. Fill the slices (e.g. by DMA) front to back, then
+    /// publish the items with [`RingBuffer::commit`].
+    ///
+    /// # Safety
+    /// Only the single producer may call this, and the slices must be
+    /// dropped before it calls `write_slices`, `write` or `commit` again.
+    #[allow(clippy::mut_from_ref)] // Exclusivity is the caller's contract
+    pub unsafe fn write_slices(&self) -> (&mut [MaybeUninit<T>], &mut [MaybeUninit<T>]) {
+        let head = self.head.load(Ordering::Relaxed);
+        let (idx, len1, len2) = Self::split(head, self.available_write());
+        let base = self.buffer.get() as *mut MaybeUninit<T>;
+
+        // Safety: Slots from head up to the sentinel slot before tail are
+        // not visible to the consumer until the producer publishes them.
+        unsafe {
+            (
+                slice::from_raw_parts_mut(base.add(idx), len1),
+                slice::from_raw_parts_mut(base, len2),
+            )
         }
+    }
+
+    /// Publish `count` items written through [`RingBuffer::write_slices`]
+    /// (producer only)
+    ///
+    /// `count` is clamped to the free space. Returns the number of items
+    /// published.
+    ///
+    /// # Safety
+    /// The first `count` slots of the write region (the first slice, then
+    /// the second) must have been initialized.
+    pub unsafe fn commit(&self, count: usize) -> usize {
+        let count = count.min(self.available_write());
+        let head = self.head.load(Ordering::Relaxed);
+        self.head.store(head.wrapping_add(count), Ordering::Release);
+        count
+    }
+
+    /// Readable region as up to two contiguous slices (consumer only)
+    ///
+    /// The second slice is non-empty only when the data wraps around the
+    /// end of the storage. Release the items with [`RingBuffer::consume`]
+    /// once done with them.
+    ///
+    /// # Safety
+    /// Only the single consumer may call this, and the slices must be
+    /// dropped before it calls `consume`, `read` or `skip`. The buffer must
+    /// be in [`OverflowMode::Reject`], since an overwriting producer may
+    /// reuse the slots at any time.
+    pub unsafe fn read_slices(&self) -> (&[T], &[T]) {
+        debug_assert!(
+            self.mode == OverflowMode::Reject,
+            "read_slices on an overwriting buffer"
+        );
+        let tail = self.tail.load(Ordering::Relaxed);
+        let (idx, len1, len2) = Self::split(tail, self.available_read());
+        let base = self.buffer.get() as *const T;
+
+        // Safety: Slots from tail up to head were initialized and published
+        // by the producer, which won't touch them until the consumer
+        // advances tail.
+        unsafe {
+            (
+                slice::from_raw_parts(base.add(idx), len1),
+                slice::from_raw_parts(base, len2),
+            )
+        }
+    }
+
+    /// Release `count` items obtained from [`RingBuffer::read_slices`]
+    /// (consumer only)
+    ///
+    /// `count` is clamped to the readable amount. Returns the number of
+    /// items released.
+    pub fn consume(&self, count: usize) -> usize {
+        self.consumer_side(|| self.consume_unguarded(count))
+    }
+
+    fn consume_unguarded(&self, count: usize) -> usize {
+        let count = count.min(self.available_read());
+        let tail = self.tail.load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(count), Ordering::Release);
+        count
+    }
 
-        // Publish the new tail
-        self.tail
-            .store(tail.wrapping_add(to_read), Ordering::Release);
+    /// Run a consumer operation, excluding an overwriting producer
+    fn consumer_side<R>(&self, f: impl FnOnce() -> R) -> R {
+        match self.mode {
+            OverflowMode::Reject => f(),
+            OverflowMode::OverwriteOldest => critical_section::with(|_| f()),
+        }
+    }
 
-        to_read
+    /// Split a run of `len` items at ring position `pos` into the storage
+    /// index and the lengths before and after the wrap point
+    fn split(pos: usize, len: usize) -> (usize, usize, usize) {
+        let idx = pos & (N - 1); // Fast modulo for power of 2
+        let len1 = len.min(N - idx);
+        (idx, len1, len - len1)
     }
 
     /// Clear the buffer (both producer and consumer must be idle)
     pub fn clear(&self) {
         self.head.store(0, Ordering::Release);
         self.tail.store(0, Ordering::Release);
+        self.dropped.store(0, Ordering::Relaxed);
     }
 }
 
@@ -248,4 +507,162 @@ mod tests {
         assert_eq!(read, 128);
         assert_eq!(out, samples);
     }
+
+    #[test]
+    fn test_peek_does_not_consume() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::new();
+        buffer.write(&[1, 2, 3]);
+
+        let mut out = [0u8; 2];
+        assert_eq!(buffer.peek(&mut out), 2);
+        assert_eq!(out, [1, 2]);
+        assert_eq!(buffer.available_read(), 3);
+
+        let mut out = [0u8; 3];
+        assert_eq!(buffer.read(&mut out), 3);
+        assert_eq!(out, [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_skip() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::new();
+        buffer.write(&[1, 2, 3, 4]);
+
+        assert_eq!(buffer.skip(2), 2);
+        assert_eq!(buffer.skip(10), 2);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_write_slices_commit_wrap() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::new();
+        buffer.write(&[0; 6]);
+        buffer.skip(6);
+
+        // Head is at 6: two slots before the wrap, five after
+        // Safety: Sole producer, slices dropped before the commit
+        let (first, second) = unsafe { buffer.write_slices() };
+        assert_eq!(first.len(), 2);
+        assert_eq!(second.len(), 5);
+
+        first[0] = MaybeUninit::new(10);
+        first[1] = MaybeUninit::new(11);
+        second[0] = MaybeUninit::new(12);
+        // Safety: three slots initialized above
+        assert_eq!(unsafe { buffer.commit(3) }, 3);
+
+        let mut out = [0u8; 3];
+        assert_eq!(buffer.read(&mut out), 3);
+        assert_eq!(out, [10, 11, 12]);
+    }
+
+    #[test]
+    fn test_commit_clamps_to_free_space() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::new();
+        // Safety: Sole producer, slices dropped before the commit
+        let (first, _) = unsafe { buffer.write_slices() };
+        for slot in first.iter_mut() {
+            *slot = MaybeUninit::new(7);
+        }
+
+        // Safety: every free slot was initialized above
+        assert_eq!(unsafe { buffer.commit(100) }, 7);
+        assert!(buffer.is_full());
+    }
+
+    #[test]
+    fn test_read_slices_consume_wrap() {
+        let buffer: RingBuffer<i16, 8> = RingBuffer::new();
+        buffer.write(&[0; 5]);
+        buffer.skip(5);
+        buffer.write(&[1, 2, 3, 4, 5, 6]);
+
+        // Safety: Sole consumer, slices dropped before each consume
+        let (first, second) = unsafe { buffer.read_slices() };
+        assert_eq!(first, &[1, 2, 3]);
+        assert_eq!(second, &[4, 5, 6]);
+
+        assert_eq!(buffer.consume(4), 4);
+        let (first, second) = unsafe { buffer.read_slices() };
+        assert_eq!(first, &[5, 6]);
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn test_overwrite_drops_oldest() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::with_mode(OverflowMode::OverwriteOldest);
+        assert_eq!(buffer.write(&[1, 2, 3, 4, 5]), 5);
+        assert_eq!(buffer.write(&[6, 7, 8, 9]), 4);
+        assert_eq!(buffer.dropped(), 2);
+
+        let mut out = [0u8; 7];
+        assert_eq!(buffer.read(&mut out), 7);
+        assert_eq!(out, [3, 4, 5, 6, 7, 8, 9]);
+
+        assert_eq!(buffer.take_dropped(), 2);
+        assert_eq!(buffer.dropped(), 0);
+    }
+
+    #[test]
+    fn test_overwrite_larger_than_capacity() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::with_mode(OverflowMode::OverwriteOldest);
+        buffer.write(&[1, 2]);
+        assert_eq!(buffer.write(&[10, 11, 12, 13, 14, 15, 16, 17, 18]), 7);
+        // Both old items plus the first two of the new block
+        assert_eq!(buffer.dropped(), 4);
+
+        let mut out = [0u8; 8];
+        assert_eq!(buffer.read(&mut out), 7);
+        assert_eq!(out[..7], [12, 13, 14, 15, 16, 17, 18]);
+    }
+
+    #[test]
+    #[should_panic(expected = "read_slices on an overwriting buffer")]
+    fn test_overwrite_rejects_read_slices() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::with_mode(OverflowMode::OverwriteOldest);
+        buffer.write(&[1, 2]);
+        // Safety: Sole consumer; the call itself is what's under test
+        let _ = unsafe { buffer.read_slices() };
+    }
+
+    #[test]
+    fn test_reject_mode_never_drops() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::new();
+        assert_eq!(buffer.mode(), OverflowMode::Reject);
+        buffer.write(&[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(buffer.dropped(), 0);
+
+        let mut out = [0u8; 1];
+        buffer.read(&mut out);
+        assert_eq!(out, [1]);
+    }
+
+    #[test]
+    fn test_watermark_transitions() {
+        let buffer: RingBuffer<i16, 16> = RingBuffer::new();
+        buffer.set_watermarks(2, 12);
+        assert_eq!(buffer.watermarks(), (2, 12));
+
+        // Starts empty: already in underrun, so no transition is reported
+        assert_eq!(buffer.fill_state(), FillState::Underrun);
+        assert_eq!(buffer.poll_watermarks(), None);
+
+        buffer.write(&[0; 5]);
+        assert_eq!(buffer.poll_watermarks(), Some(FillState::Normal));
+        assert_eq!(buffer.poll_watermarks(), None);
+
+        buffer.write(&[0; 8]);
+        assert_eq!(buffer.poll_watermarks(), Some(FillState::Overrun));
+
+        buffer.skip(11);
+        assert_eq!(buffer.poll_watermarks(), Some(FillState::Underrun));
+        assert_eq!(buffer.fill_state(), FillState::Underrun);
+    }
+
+    #[test]
+    fn test_watermarks_clamped() {
+        let buffer: RingBuffer<u8, 8> = RingBuffer::new();
+        buffer.set_watermarks(10, 100);
+        assert_eq!(buffer.watermarks(), (6, 7));
+    }
 }
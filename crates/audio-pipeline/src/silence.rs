@@ -0,0 +1,364 @@
+//! Silence detection and pre-roll buffering
+//!
+//! [`SilenceDetector`] watches the PCM stream and reports when it has been
+//! below a threshold for the configured hold time, and again as soon as it
+//! rises above it. The application uses these events to suspend the AVDTP
+//! stream so the radio and headphones can sleep.
+//!
+//! Restarting a suspended stream takes an AVDTP START round trip, so audio
+//! arriving meanwhile (and a little before the trigger, where a transient's
+//! onset is still below the threshold) is kept in a [`PreRollBuffer`] and
+//! sent first once the stream is running again.
+
+use crate::math;
+use crate::AudioFormat;
+
+/// Silence detector configuration errors
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SilenceError {
+    /// Threshold above 0 dBFS
+    InvalidThreshold,
+    /// Hold time out of range
+    InvalidTime,
+}
+
+/// Silence detector parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct SilenceConfig {
+    /// Level below which audio counts as silent, in tenths of a dBFS
+    pub threshold_db_x10: i16,
+    /// Time the audio must stay below the threshold, in milliseconds
+    pub hold_ms: u32,
+}
+
+impl Default for SilenceConfig {
+    fn default() -> Self {
+        Self {
+            threshold_db_x10: -600,
+            hold_ms: 5000,
+        }
+    }
+}
+
+impl SilenceConfig {
+    /// Check that all parameters are in range
+    pub fn validate(&self) -> Result<(), SilenceError> {
+        if self.threshold_db_x10 > 0 {
+            return Err(SilenceError::InvalidThreshold);
+        }
+        if !(1..=600_000).contains(&self.hold_ms) {
+            return Err(SilenceError::InvalidTime);
+        }
+        Ok(())
+    }
+}
+
+/// Change in the detected audio activity
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum SilenceEvent {
+    /// Audio has been silent for the hold time
+    SilenceDetected,
+    /// Audio rose above the threshold after silence
+    AudioResumed,
+}
+
+/// Threshold-and-hold silence detector
+pub struct SilenceDetector {
+    config: SilenceConfig,
+    format: AudioFormat,
+    /// Absolute sample level at the threshold
+    threshold: u16,
+    hold_frames: u32,
+    silent_frames: u32,
+    silent: bool,
+}
+
+impl SilenceDetector {
+    /// Create a detector, initially reporting audio as present
+    pub fn new(config: SilenceConfig, format: AudioFormat) -> Result<Self, SilenceError> {
+        let mut detector = Self {
+            config,
+            format,
+            threshold: 0,
+            hold_frames: 1,
+            silent_frames: 0,
+            silent: false,
+        };
+        detector.apply(config, format)?;
+        Ok(detector)
+    }
+
+    /// Current configuration
+    pub fn config(&self) -> &SilenceConfig {
+        &self.config
+    }
+
+    /// Change the configuration
+    pub fn set_config(&mut self, config: SilenceConfig) -> Result<(), SilenceError> {
+        self.apply(config, self.format)
+    }
+
+    /// Change the stream format
+    pub fn set_format(&mut self, format: AudioFormat) -> Result<(), SilenceError> {
+        self.apply(self.config, format)
+    }
+
+    /// Check if the audio is currently considered silent
+    pub fn is_silent(&self) -> bool {
+        self.silent
+    }
+
+    /// Forget the silence history and report audio as present
+    pub fn reset(&mut self) {
+        self.silent_frames = 0;
+        self.silent = false;
+    }
+
+    /// Examine a block of interleaved samples
+    ///
+    /// Returns an event if the block changed the detected state.
+    pub fn process(&mut self, samples: &[i16]) -> Option<SilenceEvent> {
+        let channels = self.format.channels as usize;
+        if channels == 0 {
+            return None;
+        }
+
+        let was_silent = self.silent;
+        for frame in samples.chunks_exact(channels) {
+            if frame.iter().any(|s| s.unsigned_abs() > self.threshold) {
+                self.silent_frames = 0;
+                self.silent = false;
+            } else if !self.silent {
+                self.silent_frames += 1;
+                self.silent = self.silent_frames >= self.hold_frames;
+            }
+        }
+
+        match (was_silent, self.silent) {
+            (false, true) => Some(SilenceEvent::SilenceDetected),
+            (true, false) => Some(SilenceEvent::AudioResumed),
+            _ => None,
+        }
+    }
+
+    fn apply(&mut self, config: SilenceConfig, format: AudioFormat) -> Result<(), SilenceError> {
+        config.validate()?;
+
+        let level = math::db_to_linear(config.threshold_db_x10 as f32 / 10.0) * 32768.0;
+        let hold_frames = format.sample_rate as u64 * config.hold_ms as u64 / 1000;
+
+        self.config = config;
+        self.format = format;
+        self.threshold = level.min(u16::MAX as f32) as u16;
+        self.hold_frames = hold_frames.clamp(1, u32::MAX as u64) as u32;
+        self.reset();
+        Ok(())
+    }
+}
+
+/// Frame-aligned history of the most recent audio
+///
+/// Holds up to `N` samples; pushing more discards the oldest whole frames.
+/// Size it to cover the AVDTP restart time plus the desired lead-in.
+pub struct PreRollBuffer<const N: usize> {
+    data: [i16; N],
+    /// Index of the oldest sample
+    start: usize,
+    len: usize,
+    channels: usize,
+}
+
+impl<const N: usize> PreRollBuffer<N> {
+    /// Create an empty buffer for `channels`-channel audio
+    pub const fn new(channels: u8) -> Self {
+        let channels = if channels == 0 { 1 } else { channels as usize };
+        assert!(N >= channels, "Pre-roll must hold at least one frame");
+
+        Self {
+            data: [0; N],
+            start: 0,
+            len: 0,
+            channels,
+        }
+    }
+
+    /// Maximum number of samples held (a whole number of frames)
+    pub const fn capacity(&self) -> usize {
+        N - N % self.channels
+    }
+
+    /// Number of buffered samples
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the buffer is empty
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Change the channel count, discarding buffered audio
+    pub fn set_channels(&mut self, channels: u8) {
+        self.channels = (channels as usize).clamp(1, N);
+        self.clear();
+    }
+
+    /// Discard all buffered audio
+    pub fn clear(&mut self) {
+        self.start = 0;
+        self.len = 0;
+    }
+
+    /// Append interleaved samples, discarding the oldest frames if full
+    ///
+    /// A trailing partial frame in `samples` is ignored.
+    pub fn push(&mut self, samples: &[i16]) {
+        let capacity = self.capacity();
+        let whole = samples.len() - samples.len() % self.channels;
+        // Only the newest `capacity` samples can survive
+        let samples = &samples[whole.saturating_sub(capacity)..whole];
+
+        let overflow = (self.len + samples.len()).saturating_sub(capacity);
+        self.start = (self.start + overflow) % capacity;
+        self.len -= overflow;
+
+        for &sample in samples {
+            self.data[(self.start + self.len) % capacity] = sample;
+            self.len += 1;
+        }
+    }
+
+    /// Move the oldest buffered samples into `out`
+    ///
+    /// Only whole frames are moved; returns the number of samples.
+    pub fn read(&mut self, out: &mut [i16]) -> usize {
+        let capacity = self.capacity();
+        let count = self.len.min(out.len() - out.len() % self.channels);
+
+        for (i, slot) in out[..count].iter_mut().enumerate() {
+            *slot = self.data[(self.start + i) % capacity];
+        }
+        self.start = (self.start + count) % capacity;
+        self.len -= count;
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stereo() -> AudioFormat {
+        AudioFormat {
+            sample_rate: 48000,
+            channels: 2,
+            bits_per_sample: 16,
+        }
+    }
+
+    fn detector(hold_ms: u32) -> SilenceDetector {
+        let config = SilenceConfig {
+            threshold_db_x10: -600,
+            hold_ms,
+        };
+        SilenceDetector::new(config, stereo()).unwrap()
+    }
+
+    #[test]
+    fn test_silence_after_hold_time() {
+        let mut detector = detector(10);
+        let silence = [0i16; 480];
+
+        // 240 frames = 5 ms
+        assert_eq!(detector.process(&silence), None);
+        assert!(!detector.is_silent());
+        assert_eq!(
+            detector.process(&silence),
+            Some(SilenceEvent::SilenceDetected)
+        );
+        assert!(detector.is_silent());
+        assert_eq!(detector.process(&silence), None);
+    }
+
+    #[test]
+    fn test_noise_floor_counts_as_silence() {
+        let mut detector = detector(10);
+        // -60 dBFS is ~33; dither-level noise stays below it
+        let mut hiss = [0i16; 960];
+        for (i, s) in hiss.iter_mut().enumerate() {
+            *s = if i % 3 == 0 { 20 } else { -20 };
+        }
+        assert_eq!(detector.process(&hiss), Some(SilenceEvent::SilenceDetected));
+    }
+
+    #[test]
+    fn test_audio_resets_hold_and_resumes() {
+        let mut detector = detector(10);
+        let silence = [0i16; 480];
+
+        detector.process(&silence);
+        let mut block = [0i16; 480];
+        block[301] = 1000;
+        assert_eq!(detector.process(&block), None);
+        assert_eq!(detector.process(&silence), None);
+        assert!(!detector.is_silent());
+
+        detector.process(&silence);
+        assert!(detector.is_silent());
+
+        assert_eq!(detector.process(&block), Some(SilenceEvent::AudioResumed));
+        assert!(!detector.is_silent());
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let loud = SilenceConfig {
+            threshold_db_x10: 10,
+            ..SilenceConfig::default()
+        };
+        assert_eq!(
+            SilenceDetector::new(loud, stereo()).err(),
+            Some(SilenceError::InvalidThreshold)
+        );
+
+        let instant = SilenceConfig {
+            hold_ms: 0,
+            ..SilenceConfig::default()
+        };
+        assert_eq!(instant.validate(), Err(SilenceError::InvalidTime));
+    }
+
+    #[test]
+    fn test_pre_roll_keeps_newest_frames() {
+        // 9 samples hold 4 stereo frames
+        let mut pre_roll: PreRollBuffer<9> = PreRollBuffer::new(2);
+        assert_eq!(pre_roll.capacity(), 8);
+
+        pre_roll.push(&[1, -1, 2, -2, 3, -3]);
+        pre_roll.push(&[4, -4, 5, -5, 6]);
+        assert_eq!(pre_roll.len(), 8);
+
+        let mut out = [0i16; 5];
+        assert_eq!(pre_roll.read(&mut out), 4);
+        assert_eq!(out[..4], [2, -2, 3, -3]);
+
+        pre_roll.push(&[7, -7]);
+        let mut out = [0i16; 8];
+        assert_eq!(pre_roll.read(&mut out), 6);
+        assert_eq!(out[..6], [4, -4, 5, -5, 7, -7]);
+        assert!(pre_roll.is_empty());
+    }
+
+    #[test]
+    fn test_pre_roll_oversized_push() {
+        let mut pre_roll: PreRollBuffer<4> = PreRollBuffer::new(2);
+        pre_roll.push(&[1, -1, 2, -2, 3, -3, 4, -4]);
+
+        let mut out = [0i16; 4];
+        assert_eq!(pre_roll.read(&mut out), 4);
+        assert_eq!(out, [3, -3, 4, -4]);
+    }
+}
@@ -2,7 +2,7 @@
 //!
 //! High-level A2DP Source implementation.
 
-use crate::avdtp::{SbcCapability, SessionState, StreamEndpoint};
+use crate::avdtp::{DelayReport, SbcCapability, SessionState, StreamEndpoint};
 use crate::BdAddr;
 
 /// A2DP connection state
@@ -127,6 +127,8 @@ pub struct A2dpSource {
     pub sequence: u16,
     /// Media timestamp
     pub timestamp: u32,
+    /// Playback delay last reported by the sink, in microseconds
+    pub sink_delay_us: Option<u32>,
 }
 
 impl A2dpSource {
@@ -141,6 +143,7 @@ impl A2dpSource {
             avdtp_state: SessionState::Idle,
             sequence: 0,
             timestamp: 0,
+            sink_delay_us: None,
         }
     }
 
@@ -173,6 +176,16 @@ impl A2dpSource {
         self.timestamp = self.timestamp.wrapping_add(samples);
     }
 
+    /// Record a DELAY_REPORT from the sink
+    ///
+    /// The sink sends the report as INT, so its SEID names our (ACP)
+    /// endpoint; reports for other endpoints are ignored.
+    pub fn handle_delay_report(&mut self, report: &DelayReport) {
+        if report.seid == self.local_sep.seid {
+            self.sink_delay_us = Some(report.delay_us());
+        }
+    }
+
     /// Reset for new connection
     pub fn reset(&mut self) {
         self.state = A2dpState::Disconnected;
@@ -182,6 +195,7 @@ impl A2dpSource {
         self.avdtp_state = SessionState::Idle;
         self.sequence = 0;
         self.timestamp = 0;
+        self.sink_delay_us = None;
     }
 }
 
@@ -222,4 +236,23 @@ mod tests {
         assert_eq!(config.channels, 2);
         assert!(config.joint_stereo);
     }
+
+    #[test]
+    fn test_delay_report() {
+        let mut source = A2dpSource::new();
+        source.remote_seid = Some(2);
+
+        // Addressed to our SEID 1, 150.0 ms
+        let report = DelayReport::from_bytes(&[1 << 2, 0x05, 0xDC]).unwrap();
+        assert_eq!(report.delay_us(), 150_000);
+        source.handle_delay_report(&report);
+        assert_eq!(source.sink_delay_us, Some(150_000));
+
+        // The sink's own SEID is not ours
+        let remote = DelayReport::from_bytes(&[2 << 2, 0x00, 0x10]).unwrap();
+        source.handle_delay_report(&remote);
+        assert_eq!(source.sink_delay_us, Some(150_000));
+
+        assert!(DelayReport::from_bytes(&[0x08, 0x01]).is_none());
+    }
 }
@@ -251,6 +251,35 @@ impl MediaHeader {
     }
 }
 
+/// DELAY_REPORT command parameters (sent by the sink)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct DelayReport {
+    /// ACP SEID the report refers to
+    pub seid: u8,
+    /// Sink playback delay in 1/10 ms
+    pub delay: u16,
+}
+
+impl DelayReport {
+    /// Parse from command parameters
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 3 {
+            return None;
+        }
+
+        Some(Self {
+            seid: bytes[0] >> 2,
+            delay: u16::from_be_bytes([bytes[1], bytes[2]]),
+        })
+    }
+
+    /// Sink playback delay in microseconds
+    pub fn delay_us(&self) -> u32 {
+        self.delay as u32 * 100
+    }
+}
+
 /// AVDTP session state
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 #[cfg_attr(feature = "defmt", derive(defmt::Format))]
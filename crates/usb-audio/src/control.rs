@@ -0,0 +1,137 @@
+//! UAC2 class-specific control requests
+//!
+//! Requests to the Audio Control interface address an entity (clock,
+//! terminal or unit) in the high byte of `wIndex` and one of its controls
+//! in the high byte of `wValue`; the low byte of `wValue` selects the
+//! channel.
+
+use embassy_usb::control::{Recipient, Request, RequestType};
+
+/// Current value of a control
+pub(crate) const CUR: u8 = 0x01;
+/// Valid range of a control
+pub(crate) const RANGE: u8 = 0x02;
+
+/// Clock source sampling frequency control
+pub(crate) const CS_SAM_FREQ_CONTROL: u8 = 0x01;
+/// Clock source validity control
+pub(crate) const CS_CLOCK_VALID_CONTROL: u8 = 0x02;
+
+/// Feature unit mute control
+pub(crate) const FU_MUTE_CONTROL: u8 = 0x01;
+/// Feature unit volume control
+pub(crate) const FU_VOLUME_CONTROL: u8 = 0x02;
+
+/// UAC1 request codes; `SET_CUR` shares its value with [`CUR`]
+pub(crate) const UAC1_SET_CUR: u8 = 0x01;
+pub(crate) const UAC1_GET_CUR: u8 = 0x81;
+pub(crate) const UAC1_GET_MIN: u8 = 0x82;
+pub(crate) const UAC1_GET_MAX: u8 = 0x83;
+pub(crate) const UAC1_GET_RES: u8 = 0x84;
+
+/// UAC1 endpoint sampling frequency control
+pub(crate) const EP_SAMPLING_FREQ_CONTROL: u8 = 0x01;
+
+/// A class request addressed to an entity of the Audio Control interface
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct EntityRequest {
+    /// Request code ([`CUR`] or [`RANGE`])
+    pub request: u8,
+    /// Unit, terminal or clock ID
+    pub entity: u8,
+    /// Control selector
+    pub selector: u8,
+    /// Channel number, 0 for the master channel
+    pub channel: u8,
+}
+
+impl EntityRequest {
+    /// Decode `req` if it is a class request for `interface`
+    pub fn parse(req: &Request, interface: u8) -> Option<Self> {
+        if req.request_type != RequestType::Class
+            || req.recipient != Recipient::Interface
+            || req.index as u8 != interface
+        {
+            return None;
+        }
+        Some(Self {
+            request: req.request,
+            entity: (req.index >> 8) as u8,
+            selector: (req.value >> 8) as u8,
+            channel: req.value as u8,
+        })
+    }
+}
+
+/// Sampling frequency of a clock source `SET CUR` request
+pub(crate) fn clock_set_rate(req: &EntityRequest, data: &[u8]) -> Option<u32> {
+    if (req.request, req.selector) != (CUR, CS_SAM_FREQ_CONTROL) {
+        return None;
+    }
+    Some(u32::from_le_bytes(data.try_into().ok()?))
+}
+
+/// Answer a clock source `GET` request for a clock running at `rate`,
+/// one of `rates`
+pub(crate) fn clock_get(
+    req: &EntityRequest,
+    buf: &mut [u8],
+    rate: u32,
+    rates: &[u32],
+) -> Option<usize> {
+    match (req.request, req.selector) {
+        (CUR, CS_SAM_FREQ_CONTROL) => {
+            buf.get_mut(..4)?.copy_from_slice(&rate.to_le_bytes());
+            Some(4)
+        }
+        (RANGE, CS_SAM_FREQ_CONTROL) => Some(write_range_u32(
+            buf,
+            rates.iter().map(|&rate| (rate, rate, 0)),
+        )),
+        (CUR, CS_CLOCK_VALID_CONTROL) => {
+            *buf.first_mut()? = 1;
+            Some(1)
+        }
+        _ => None,
+    }
+}
+
+/// Write a RANGE parameter block of 4-byte `(min, max, res)` subranges
+///
+/// Subranges that don't fit in `buf` are left out. Returns the number of
+/// bytes written.
+pub(crate) fn write_range_u32(
+    buf: &mut [u8],
+    subranges: impl Iterator<Item = (u32, u32, u32)>,
+) -> usize {
+    if buf.len() < 2 {
+        return 0;
+    }
+    let mut count: u16 = 0;
+    let mut pos = 2;
+    for (min, max, res) in subranges {
+        let Some(slot) = buf.get_mut(pos..pos + 12) else {
+            break;
+        };
+        slot[0..4].copy_from_slice(&min.to_le_bytes());
+        slot[4..8].copy_from_slice(&max.to_le_bytes());
+        slot[8..12].copy_from_slice(&res.to_le_bytes());
+        count += 1;
+        pos += 12;
+    }
+    buf[..2].copy_from_slice(&count.to_le_bytes());
+    pos
+}
+
+/// Write a RANGE parameter block with a single 2-byte `(min, max, res)`
+/// subrange
+pub(crate) fn write_range_i16(buf: &mut [u8], min: i16, max: i16, res: i16) -> usize {
+    let Some(block) = buf.get_mut(..8) else {
+        return 0;
+    };
+    block[0..2].copy_from_slice(&1u16.to_le_bytes());
+    block[2..4].copy_from_slice(&min.to_le_bytes());
+    block[4..6].copy_from_slice(&max.to_le_bytes());
+    block[6..8].copy_from_slice(&res.to_le_bytes());
+    8
+}
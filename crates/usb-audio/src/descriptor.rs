@@ -1,5 +1,73 @@
 //! USB Audio Class 2.0 descriptors
 
+use crate::FeedbackFormat;
+
+/// Entity ID of the USB streaming input terminal
+pub(crate) const INPUT_TERMINAL_ID: u8 = 1;
+/// Entity ID of the speaker output terminal
+pub(crate) const OUTPUT_TERMINAL_ID: u8 = 2;
+/// Entity ID of the clock source
+pub(crate) const CLOCK_SOURCE_ID: u8 = 3;
+/// Entity ID of the volume/mute feature unit
+pub(crate) const FEATURE_UNIT_ID: u8 = 4;
+
+/// Channels with their own volume and mute controls (after the master)
+pub(crate) const FEATURE_CHANNELS: u8 = 2;
+
+/// USB Audio Class version presented to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AudioProtocol {
+    /// USB Audio Class 1.0, for legacy hosts
+    Uac1,
+    /// USB Audio Class 2.0
+    Uac2,
+}
+
+impl Default for AudioProtocol {
+    /// UAC2, or UAC1 with the `uac1` feature
+    fn default() -> Self {
+        if cfg!(feature = "uac1") {
+            Self::Uac1
+        } else {
+            Self::Uac2
+        }
+    }
+}
+
+/// PCM layout of a streaming alternate setting
+///
+/// Samples are MSB-justified in their subslot, so a 24-bit sample in a
+/// 4-byte subslot reads like a 32-bit one with a zero low byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct StreamFormat {
+    /// Bytes per sample (`bSubslotSize`)
+    pub subslot_size: u8,
+    /// Significant bits per sample (`bBitResolution`)
+    pub bit_resolution: u8,
+}
+
+impl StreamFormat {
+    /// 16-bit samples
+    pub const PCM16: Self = Self::new(2, 16);
+    /// 24-bit samples packed in 3 bytes
+    pub const PCM24_3: Self = Self::new(3, 24);
+    /// 24-bit samples in 4-byte subslots
+    pub const PCM24_4: Self = Self::new(4, 24);
+    /// 32-bit samples
+    pub const PCM32: Self = Self::new(4, 32);
+
+    /// Create a format with `subslot_size` bytes holding `bit_resolution`
+    /// bits
+    pub const fn new(subslot_size: u8, bit_resolution: u8) -> Self {
+        Self {
+            subslot_size,
+            bit_resolution,
+        }
+    }
+}
+
 /// UAC2 device configuration
 #[derive(Debug, Clone)]
 pub struct Uac2Config {
@@ -11,10 +79,16 @@ pub struct Uac2Config {
     pub pid: u16,
     /// Number of channels
     pub channels: u8,
-    /// Bits per sample
-    pub bit_depth: u8,
+    /// Sample formats, offered as streaming alternate settings 1, 2, ...
+    /// (at most four)
+    pub formats: &'static [StreamFormat],
     /// Supported sample rates
     pub sample_rates: &'static [u32],
+    /// Encoding of the feedback endpoint (follows the bus speed)
+    pub feedback_format: FeedbackFormat,
+    /// Audio class version, chosen at build time with the `uac1` feature
+    /// or at boot by setting this field
+    pub protocol: AudioProtocol,
 }
 
 impl Default for Uac2Config {
@@ -24,12 +98,106 @@ impl Default for Uac2Config {
             vid: 0x1209, // pid.codes test VID
             pid: 0xA2D0, // "A2D0" - A2DP-like
             channels: 2,
-            bit_depth: 16,
+            formats: &[
+                StreamFormat::PCM16,
+                StreamFormat::PCM24_3,
+                StreamFormat::PCM24_4,
+                StreamFormat::PCM32,
+            ],
             sample_rates: &[44100, 48000],
+            feedback_format: FeedbackFormat::Q10_14,
+            protocol: AudioProtocol::default(),
         }
     }
 }
 
+impl Uac2Config {
+    /// Format of a streaming alternate setting, `None` for the
+    /// zero-bandwidth setting 0 and settings that don't exist
+    pub fn format(&self, alt_setting: u8) -> Option<StreamFormat> {
+        let index = (alt_setting as usize).checked_sub(1)?;
+        self.formats.get(index).copied()
+    }
+
+    /// Largest packet in `format`: one frame at the highest sample rate,
+    /// plus one sample per channel of headroom for the feedback loop
+    pub fn max_packet_size(&self, format: StreamFormat) -> u16 {
+        let max_rate = self.sample_rates.iter().copied().max().unwrap_or(48000);
+        let frames = max_rate.div_ceil(1000) + 1;
+        (frames * self.channels as u32 * format.subslot_size as u32) as u16
+    }
+}
+
+/// Descriptor builder error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DescriptorError {
+    /// Buffer too small for the descriptor chain
+    BufferTooSmall,
+    /// Descriptor or chain too long for its length field
+    TooLong,
+}
+
+/// Lays out a descriptor chain in a buffer
+pub(crate) struct DescriptorWriter<'a> {
+    buf: &'a mut [u8],
+    pos: usize,
+}
+
+impl<'a> DescriptorWriter<'a> {
+    pub(crate) fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, pos: 0 }
+    }
+
+    /// Reserve the next descriptor, `len` bytes with `bLength` filled in
+    /// and the rest zeroed
+    pub(crate) fn alloc(&mut self, len: usize) -> Result<&mut [u8], DescriptorError> {
+        let length = u8::try_from(len).map_err(|_| DescriptorError::TooLong)?;
+        let desc = self
+            .buf
+            .get_mut(self.pos..self.pos + len)
+            .ok_or(DescriptorError::BufferTooSmall)?;
+        desc.fill(0);
+        desc[0] = length;
+        self.pos += len;
+        Ok(desc)
+    }
+
+    /// Bytes written so far
+    pub(crate) fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Store the length of everything from `start` on in the 16-bit
+    /// field at `field`, e.g. an AC header's `wTotalLength`
+    pub(crate) fn patch_total_length(
+        &mut self,
+        start: usize,
+        field: usize,
+    ) -> Result<(), DescriptorError> {
+        let total = u16::try_from(self.pos - start).map_err(|_| DescriptorError::TooLong)?;
+        self.buf[field..field + 2].copy_from_slice(&total.to_le_bytes());
+        Ok(())
+    }
+}
+
+/// Spatial locations of `channels` channels (`bmChannelConfig`)
+///
+/// Common layouts get their usual speaker positions, others none. UAC1's
+/// `wChannelConfig` uses the same bits for these layouts.
+pub(crate) const fn channel_config(channels: u8) -> u32 {
+    match channels {
+        1 => 0x0004, // FC
+        2 => 0x0003, // FL FR
+        3 => 0x0007, // FL FR FC
+        4 => 0x0033, // FL FR BL BR
+        5 => 0x0037, // FL FR FC BL BR
+        6 => 0x003F, // FL FR FC LFE BL BR
+        8 => 0x063F, // FL FR FC LFE BL BR SL SR
+        _ => 0,
+    }
+}
+
 /// Audio Control Interface descriptor builder
 pub struct AudioControlDescriptor {
     config: Uac2Config,
@@ -41,84 +209,93 @@ impl AudioControlDescriptor {
         Self { config }
     }
 
-    /// Build the descriptor bytes
-    pub fn build(&self, buf: &mut [u8]) -> usize {
-        // Audio Control Interface Header
-        // This is a simplified implementation
-
-        let mut pos = 0;
+    /// Build the descriptor bytes, returning their length
+    pub fn build(&self, buf: &mut [u8]) -> Result<usize, DescriptorError> {
+        let mut w = DescriptorWriter::new(buf);
 
         // Interface descriptor (Audio Control)
-        buf[pos] = 9; // bLength
-        buf[pos + 1] = 4; // bDescriptorType (Interface)
-        buf[pos + 2] = 0; // bInterfaceNumber
-        buf[pos + 3] = 0; // bAlternateSetting
-        buf[pos + 4] = 0; // bNumEndpoints
-        buf[pos + 5] = 0x01; // bInterfaceClass (Audio)
-        buf[pos + 6] = 0x01; // bInterfaceSubClass (Audio Control)
-        buf[pos + 7] = 0x20; // bInterfaceProtocol (UAC2)
-        buf[pos + 8] = 0; // iInterface
-        pos += 9;
+        let d = w.alloc(9)?;
+        d[1] = 4; // bDescriptorType (Interface)
+        d[2] = 0; // bInterfaceNumber
+        d[3] = 0; // bAlternateSetting
+        d[4] = 0; // bNumEndpoints
+        d[5] = 0x01; // bInterfaceClass (Audio)
+        d[6] = 0x01; // bInterfaceSubClass (Audio Control)
+        d[7] = 0x20; // bInterfaceProtocol (UAC2)
+        d[8] = 0; // iInterface
 
         // AC Interface Header
-        buf[pos] = 9; // bLength
-        buf[pos + 1] = 0x24; // bDescriptorType (CS_INTERFACE)
-        buf[pos + 2] = 0x01; // bDescriptorSubtype (HEADER)
-        buf[pos + 3] = 0x00; // bcdADC low
-        buf[pos + 4] = 0x02; // bcdADC high (2.0)
-        buf[pos + 5] = 0x08; // bCategory (I/O Box)
-        buf[pos + 6] = 0; // wTotalLength low (placeholder)
-        buf[pos + 7] = 0; // wTotalLength high
-        buf[pos + 8] = 0; // bmControls
-        pos += 9;
-
-        // Clock Source
-        buf[pos] = 8; // bLength
-        buf[pos + 1] = 0x24; // bDescriptorType
-        buf[pos + 2] = 0x0A; // bDescriptorSubtype (CLOCK_SOURCE)
-        buf[pos + 3] = 1; // bClockID
-        buf[pos + 4] = 0x01; // bmAttributes (internal fixed)
-        buf[pos + 5] = 0x01; // bmControls
-        buf[pos + 6] = 0; // bAssocTerminal
-        buf[pos + 7] = 0; // iClockSource
-        pos += 8;
+        let header = w.position();
+        let d = w.alloc(9)?;
+        d[1] = 0x24; // bDescriptorType (CS_INTERFACE)
+        d[2] = 0x01; // bDescriptorSubtype (HEADER)
+        d[3] = 0x00; // bcdADC low
+        d[4] = 0x02; // bcdADC high (2.0)
+        d[5] = 0x08; // bCategory (I/O Box)
+        d[6] = 0; // wTotalLength low (filled in below)
+        d[7] = 0; // wTotalLength high
+        d[8] = 0; // bmControls
+
+        // Clock Source: the host may switch rates when more than one is
+        // offered
+        let programmable = self.config.sample_rates.len() > 1;
+        let d = w.alloc(8)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x0A; // bDescriptorSubtype (CLOCK_SOURCE)
+        d[3] = CLOCK_SOURCE_ID; // bClockID
+        d[4] = if programmable { 0x03 } else { 0x01 }; // bmAttributes (internal)
+        d[5] = if programmable { 0x07 } else { 0x05 }; // bmControls (freq, validity)
+        d[6] = 0; // bAssocTerminal
+        d[7] = 0; // iClockSource
 
         // Input Terminal (USB streaming)
-        buf[pos] = 17; // bLength
-        buf[pos + 1] = 0x24; // bDescriptorType
-        buf[pos + 2] = 0x02; // bDescriptorSubtype (INPUT_TERMINAL)
-        buf[pos + 3] = 1; // bTerminalID
-        buf[pos + 4] = 0x01; // wTerminalType low (USB streaming)
-        buf[pos + 5] = 0x01; // wTerminalType high
-        buf[pos + 6] = 0; // bAssocTerminal
-        buf[pos + 7] = 1; // bCSourceID (clock)
-        buf[pos + 8] = self.config.channels; // bNrChannels
-        buf[pos + 9] = 0x03; // bmChannelConfig low (L+R)
-        buf[pos + 10] = 0x00;
-        buf[pos + 11] = 0x00;
-        buf[pos + 12] = 0x00;
-        buf[pos + 13] = 0; // iChannelNames
-        buf[pos + 14] = 0; // bmControls low
-        buf[pos + 15] = 0; // bmControls high
-        buf[pos + 16] = 0; // iTerminal
-        pos += 17;
+        let channels = self.config.channels;
+        let d = w.alloc(17)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x02; // bDescriptorSubtype (INPUT_TERMINAL)
+        d[3] = INPUT_TERMINAL_ID; // bTerminalID
+        d[4] = 0x01; // wTerminalType low (USB streaming)
+        d[5] = 0x01; // wTerminalType high
+        d[6] = 0; // bAssocTerminal
+        d[7] = CLOCK_SOURCE_ID; // bCSourceID
+        d[8] = channels; // bNrChannels
+        d[9..13].copy_from_slice(&channel_config(channels).to_le_bytes()); // bmChannelConfig
+        d[13] = 0; // iChannelNames
+        d[14] = 0; // bmControls low
+        d[15] = 0; // bmControls high
+        d[16] = 0; // iTerminal
+
+        // Feature Unit: mute and volume (read/write) on the master and each
+        // channel
+        let d = w.alloc(6 + (channels as usize + 1) * 4)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x06; // bDescriptorSubtype (FEATURE_UNIT)
+        d[3] = FEATURE_UNIT_ID; // bUnitID
+        d[4] = INPUT_TERMINAL_ID; // bSourceID
+        for channel in 0..=channels {
+            let controls: u32 = if channel <= FEATURE_CHANNELS { 0x0F } else { 0 };
+            let at = 5 + channel as usize * 4;
+            d[at..at + 4].copy_from_slice(&controls.to_le_bytes()); // bmaControls
+        }
+        // iFeature left 0
 
         // Output Terminal (Speaker)
-        buf[pos] = 12; // bLength
-        buf[pos + 1] = 0x24; // bDescriptorType
-        buf[pos + 2] = 0x03; // bDescriptorSubtype (OUTPUT_TERMINAL)
-        buf[pos + 3] = 2; // bTerminalID
-        buf[pos + 4] = 0x01; // wTerminalType low (Speaker)
-        buf[pos + 5] = 0x03; // wTerminalType high
-        buf[pos + 6] = 0; // bAssocTerminal
-        buf[pos + 7] = 1; // bSourceID (input terminal)
-        buf[pos + 8] = 1; // bCSourceID (clock)
-        buf[pos + 9] = 0; // bmControls low
-        buf[pos + 10] = 0; // bmControls high
-        buf[pos + 11] = 0; // iTerminal
-        pos += 12;
+        let d = w.alloc(12)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x03; // bDescriptorSubtype (OUTPUT_TERMINAL)
+        d[3] = OUTPUT_TERMINAL_ID; // bTerminalID
+        d[4] = 0x01; // wTerminalType low (Speaker)
+        d[5] = 0x03; // wTerminalType high
+        d[6] = 0; // bAssocTerminal
+        d[7] = FEATURE_UNIT_ID; // bSourceID
+        d[8] = CLOCK_SOURCE_ID; // bCSourceID
+        d[9] = 0; // bmControls low
+        d[10] = 0; // bmControls high
+        d[11] = 0; // iTerminal
 
-        pos
+        // The header and everything after it
+        w.patch_total_length(header, header + 6)?;
+        Ok(w.position())
     }
 }
 
@@ -134,90 +311,195 @@ impl AudioStreamingDescriptor {
     }
 
     /// Build the descriptor bytes for alternate setting 0 (zero bandwidth)
-    pub fn build_alt0(&self, buf: &mut [u8], interface_num: u8) -> usize {
-        let mut pos = 0;
+    pub fn build_alt0(&self, buf: &mut [u8], interface_num: u8) -> Result<usize, DescriptorError> {
+        let mut w = DescriptorWriter::new(buf);
 
         // Interface descriptor (zero bandwidth)
-        buf[pos] = 9;
-        buf[pos + 1] = 4; // Interface
-        buf[pos + 2] = interface_num;
-        buf[pos + 3] = 0; // bAlternateSetting
-        buf[pos + 4] = 0; // bNumEndpoints
-        buf[pos + 5] = 0x01; // Audio
-        buf[pos + 6] = 0x02; // Audio Streaming
-        buf[pos + 7] = 0x20; // UAC2
-        buf[pos + 8] = 0;
-        pos += 9;
+        let d = w.alloc(9)?;
+        d[1] = 4; // Interface
+        d[2] = interface_num;
+        d[3] = 0; // bAlternateSetting
+        d[4] = 0; // bNumEndpoints
+        d[5] = 0x01; // Audio
+        d[6] = 0x02; // Audio Streaming
+        d[7] = 0x20; // UAC2
+        d[8] = 0;
 
-        pos
+        Ok(w.position())
     }
 
     /// Build the descriptor bytes for alternate setting 1 (active streaming)
-    pub fn build_alt1(&self, buf: &mut [u8], interface_num: u8, ep_addr: u8) -> usize {
-        let mut pos = 0;
+    ///
+    /// `ep_addr` is the isochronous OUT data endpoint and `feedback_addr`
+    /// the IN endpoint carrying the asynchronous rate feedback.
+    pub fn build_alt1(
+        &self,
+        buf: &mut [u8],
+        interface_num: u8,
+        ep_addr: u8,
+        feedback_addr: u8,
+    ) -> Result<usize, DescriptorError> {
+        self.build_alt(buf, interface_num, 1, ep_addr, feedback_addr)
+    }
+
+    /// Build the descriptor bytes for streaming alternate setting
+    /// `alt_setting`, which carries [`Uac2Config::format`]
+    ///
+    /// Returns 0 if there is no such setting.
+    pub fn build_alt(
+        &self,
+        buf: &mut [u8],
+        interface_num: u8,
+        alt_setting: u8,
+        ep_addr: u8,
+        feedback_addr: u8,
+    ) -> Result<usize, DescriptorError> {
+        let Some(format) = self.config.format(alt_setting) else {
+            return Ok(0);
+        };
+        let mut w = DescriptorWriter::new(buf);
 
         // Interface descriptor (active)
-        buf[pos] = 9;
-        buf[pos + 1] = 4;
-        buf[pos + 2] = interface_num;
-        buf[pos + 3] = 1; // bAlternateSetting
-        buf[pos + 4] = 1; // bNumEndpoints (or 2 with feedback)
-        buf[pos + 5] = 0x01;
-        buf[pos + 6] = 0x02;
-        buf[pos + 7] = 0x20;
-        buf[pos + 8] = 0;
-        pos += 9;
+        let d = w.alloc(9)?;
+        d[1] = 4;
+        d[2] = interface_num;
+        d[3] = alt_setting; // bAlternateSetting
+        d[4] = 2; // bNumEndpoints (data + feedback)
+        d[5] = 0x01;
+        d[6] = 0x02;
+        d[7] = 0x20;
+        d[8] = 0;
 
         // AS Interface descriptor
-        buf[pos] = 16;
-        buf[pos + 1] = 0x24; // CS_INTERFACE
-        buf[pos + 2] = 0x01; // AS_GENERAL
-        buf[pos + 3] = 1; // bTerminalLink
-        buf[pos + 4] = 0; // bmControls
-        buf[pos + 5] = 0x01; // bFormatType (Type I)
-        buf[pos + 6] = 0x01; // bmFormats (PCM)
-        buf[pos + 7] = 0x00;
-        buf[pos + 8] = 0x00;
-        buf[pos + 9] = 0x00;
-        buf[pos + 10] = self.config.channels;
-        buf[pos + 11] = 0x03; // bmChannelConfig
-        buf[pos + 12] = 0x00;
-        buf[pos + 13] = 0x00;
-        buf[pos + 14] = 0x00;
-        buf[pos + 15] = 0;
-        pos += 16;
+        let channels = self.config.channels;
+        let d = w.alloc(16)?;
+        d[1] = 0x24; // CS_INTERFACE
+        d[2] = 0x01; // AS_GENERAL
+        d[3] = INPUT_TERMINAL_ID; // bTerminalLink
+        d[4] = 0; // bmControls
+        d[5] = 0x01; // bFormatType (Type I)
+        d[6..10].copy_from_slice(&1u32.to_le_bytes()); // bmFormats (PCM)
+        d[10] = channels; // bNrChannels
+        d[11..15].copy_from_slice(&channel_config(channels).to_le_bytes()); // bmChannelConfig
+        d[15] = 0; // iChannelNames
 
         // Format Type I descriptor
-        buf[pos] = 6;
-        buf[pos + 1] = 0x24;
-        buf[pos + 2] = 0x02; // FORMAT_TYPE
-        buf[pos + 3] = 0x01; // FORMAT_TYPE_I
-        buf[pos + 4] = self.config.bit_depth / 8; // bSubslotSize
-        buf[pos + 5] = self.config.bit_depth; // bBitResolution
-        pos += 6;
+        let d = w.alloc(6)?;
+        d[1] = 0x24;
+        d[2] = 0x02; // FORMAT_TYPE
+        d[3] = 0x01; // FORMAT_TYPE_I
+        d[4] = format.subslot_size; // bSubslotSize
+        d[5] = format.bit_resolution; // bBitResolution
 
         // Endpoint descriptor
-        let max_packet = 48 * (self.config.channels as u16) * 2 + 4; // 48kHz + margin
-        buf[pos] = 7;
-        buf[pos + 1] = 5; // Endpoint
-        buf[pos + 2] = ep_addr;
-        buf[pos + 3] = 0x05; // Isochronous, Async
-        buf[pos + 4] = (max_packet & 0xFF) as u8;
-        buf[pos + 5] = (max_packet >> 8) as u8;
-        buf[pos + 6] = 1; // bInterval (1ms)
-        pos += 7;
+        let max_packet = self.config.max_packet_size(format);
+        let d = w.alloc(7)?;
+        d[1] = 5; // Endpoint
+        d[2] = ep_addr;
+        d[3] = 0x05; // Isochronous, Async
+        d[4..6].copy_from_slice(&max_packet.to_le_bytes()); // wMaxPacketSize
+        d[6] = 1; // bInterval (1ms)
 
         // AS Isochronous Audio Data Endpoint descriptor
-        buf[pos] = 8;
-        buf[pos + 1] = 0x25; // CS_ENDPOINT
-        buf[pos + 2] = 0x01; // EP_GENERAL
-        buf[pos + 3] = 0; // bmAttributes
-        buf[pos + 4] = 0; // bmControls
-        buf[pos + 5] = 0; // bLockDelayUnits
-        buf[pos + 6] = 0; // wLockDelay low
-        buf[pos + 7] = 0; // wLockDelay high
-        pos += 8;
-
-        pos
+        let d = w.alloc(8)?;
+        d[1] = 0x25; // CS_ENDPOINT
+        d[2] = 0x01; // EP_GENERAL
+        d[3] = 0; // bmAttributes
+        d[4] = 0; // bmControls
+        d[5] = 0; // bLockDelayUnits
+        d[6] = 0; // wLockDelay low
+        d[7] = 0; // wLockDelay high
+
+        // Feedback endpoint descriptor
+        let feedback = self.config.feedback_format;
+        let d = w.alloc(7)?;
+        d[1] = 5; // Endpoint
+        d[2] = feedback_addr | 0x80; // IN
+        d[3] = 0x11; // Isochronous, No sync, Feedback
+        d[4] = feedback.packet_size() as u8;
+        d[5] = 0;
+        d[6] = match feedback {
+            FeedbackFormat::Q10_14 => 1, // every frame
+            FeedbackFormat::Q16_16 => 4, // every 8 microframes (1ms)
+        };
+
+        Ok(w.position())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::class;
+    use crate::parse;
+
+    #[test]
+    fn test_chains_are_well_formed() {
+        for channels in [1, 2, 6, 8] {
+            let config = Uac2Config {
+                channels,
+                formats: &[StreamFormat::PCM16],
+                ..Uac2Config::default()
+            };
+            let mut buf = [0u8; 128];
+            let len = AudioControlDescriptor::new(config.clone())
+                .build(&mut buf)
+                .unwrap();
+            assert_eq!(parse::check(&buf[..len]), Ok(6));
+            // Header through output terminal
+            let total = len as u16 - 9;
+            assert_eq!(buf[15..17], total.to_le_bytes());
+            assert_eq!(buf[35..39], channel_config(channels).to_le_bytes());
+
+            let streaming = AudioStreamingDescriptor::new(config);
+            let len = streaming.build_alt0(&mut buf, 1).unwrap();
+            assert_eq!(parse::check(&buf[..len]), Ok(1));
+            assert_eq!(
+                buf[5..8],
+                [class::AUDIO, class::AUDIO_STREAMING, class::UAC2_PROTOCOL]
+            );
+
+            let len = streaming.build_alt1(&mut buf, 1, 0x01, 0x01).unwrap();
+            let chain = &buf[..len];
+            assert_eq!(
+                parse::check_in(chain, class::AUDIO_STREAMING, class::UAC2_PROTOCOL),
+                Ok(6)
+            );
+            assert_eq!(chain[19], channels);
+            assert_eq!(chain[20..24], channel_config(channels).to_le_bytes());
+        }
+    }
+
+    #[test]
+    fn test_short_buffer() {
+        let config = Uac2Config::default();
+        let mut buf = [0u8; 128];
+        let len = AudioControlDescriptor::new(config.clone())
+            .build(&mut buf)
+            .unwrap();
+        assert_eq!(
+            AudioControlDescriptor::new(config.clone()).build(&mut buf[..len - 1]),
+            Err(DescriptorError::BufferTooSmall)
+        );
+        let streaming = AudioStreamingDescriptor::new(config.clone());
+        assert_eq!(
+            streaming.build_alt(&mut buf[..20], 1, 1, 0x01, 0x01),
+            Err(DescriptorError::BufferTooSmall)
+        );
+        assert_eq!(
+            streaming.build_alt0(&mut buf[..8], 1),
+            Err(DescriptorError::BufferTooSmall)
+        );
+
+        // A feature unit for 64 channels doesn't fit bLength
+        let wide = Uac2Config {
+            channels: 64,
+            ..config
+        };
+        let mut buf = [0u8; 512];
+        assert_eq!(
+            AudioControlDescriptor::new(wide).build(&mut buf),
+            Err(DescriptorError::TooLong)
+        );
     }
 }
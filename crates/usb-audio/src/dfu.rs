@@ -0,0 +1,1024 @@
+//! USB Device Firmware Upgrade (DFU 1.1) for field updates
+//!
+//! In normal operation the device carries a DFU runtime interface
+//! ([`DfuRuntime`]). A host tool such as `dfu-util` sends DFU_DETACH and
+//! the application reboots into update mode, where the device enumerates
+//! with a DFU mode interface ([`DfuMode`]) instead of the audio function.
+//! Downloaded blocks go through an [`ImageWriter`] into a staging region
+//! of flash. Once the image's trailer and CRC check out, the writer leaves
+//! an update record behind and [`apply_update`] copies the staged image
+//! over the active one on the next boot.
+//!
+//! An image is the firmware binary followed by a 12-byte trailer: the
+//! magic `A2DU`, the binary's length and its CRC-32 (as `zlib.crc32`),
+//! both little-endian. `scripts/dfu_image.py` appends it.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::driver::Driver;
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use portable_atomic::{AtomicBool, Ordering};
+
+/// Application specific interface class
+const CLASS_APPLICATION: u8 = 0xFE;
+/// Device Firmware Upgrade subclass
+const SUBCLASS_DFU: u8 = 0x01;
+/// Runtime protocol
+const PROTOCOL_RUNTIME: u8 = 0x01;
+/// DFU mode protocol
+const PROTOCOL_DFU_MODE: u8 = 0x02;
+
+/// DFU functional descriptor type
+const DESC_DFU_FUNCTIONAL: u8 = 0x21;
+/// bitCanDnload | bitManifestationTolerant | bitWillDetach
+const DFU_ATTRIBUTES: u8 = 0x0D;
+/// DFU specification release 1.1
+const BCD_DFU: u16 = 0x0110;
+/// Time the host should allow between DFU_DETACH and the device leaving
+const DETACH_TIMEOUT_MS: u16 = 1000;
+
+/// Largest DNLOAD block; the USB control buffer must hold one
+pub const DFU_TRANSFER_SIZE: usize = 256;
+
+const DFU_DETACH: u8 = 0x00;
+const DFU_DNLOAD: u8 = 0x01;
+const DFU_GETSTATUS: u8 = 0x03;
+const DFU_CLRSTATUS: u8 = 0x04;
+const DFU_GETSTATE: u8 = 0x05;
+const DFU_ABORT: u8 = 0x06;
+
+/// Magic at the start of the image trailer
+pub const IMAGE_MAGIC: [u8; 4] = *b"A2DU";
+/// Image trailer size
+pub const TRAILER_SIZE: usize = 12;
+
+/// Magic at the start of the update record
+const RECORD_MAGIC: [u8; 4] = *b"STGD";
+/// Update record: magic, length, CRC and the applied word, which stays
+/// erased until the update has been copied
+const RECORD_SIZE: usize = 16;
+/// Bytes read or copied at a time
+const CHUNK: usize = 256;
+
+/// DFU device state, as reported by DFU_GETSTATE
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DfuState {
+    /// Running the application
+    AppIdle = 0,
+    /// Detach requested, about to reboot into update mode
+    AppDetach = 1,
+    /// Update mode, waiting for a download
+    DfuIdle = 2,
+    /// Block received, waiting for DFU_GETSTATUS
+    DnloadSync = 3,
+    /// Block written
+    DnloadBusy = 4,
+    /// Waiting for the next block
+    DnloadIdle = 5,
+    /// Download complete, waiting for DFU_GETSTATUS to manifest
+    ManifestSync = 6,
+    /// Checking the image
+    Manifest = 7,
+    /// Waiting for a USB reset after manifestation
+    ManifestWaitReset = 8,
+    /// Uploading
+    UploadIdle = 9,
+    /// An error occurred; cleared by DFU_CLRSTATUS
+    Error = 10,
+}
+
+/// DFU status code, as reported by DFU_GETSTATUS
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum DfuStatus {
+    /// No error
+    Ok = 0x00,
+    /// File is not for this device or fails verification
+    ErrFile = 0x02,
+    /// Flash write failed
+    ErrWrite = 0x03,
+    /// Flash erase failed
+    ErrErase = 0x04,
+    /// Written image doesn't match its CRC
+    ErrVerify = 0x07,
+    /// Block out of sequence or beyond the staging region
+    ErrAddress = 0x08,
+    /// Download ended before the image was complete
+    ErrNotDone = 0x09,
+    /// Request not valid in the current state
+    ErrStalledPkt = 0x0F,
+}
+
+/// Firmware update error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum DfuError {
+    /// Flash erase failed
+    Erase,
+    /// Flash read or write failed
+    Flash,
+    /// Image doesn't fit in the staging region
+    TooLarge,
+    /// Image trailer missing or malformed
+    BadImage,
+    /// Staged image doesn't match its CRC
+    Crc,
+    /// Copied image doesn't match its CRC
+    Verify,
+}
+
+impl From<DfuError> for DfuStatus {
+    fn from(err: DfuError) -> Self {
+        match err {
+            DfuError::Erase => DfuStatus::ErrErase,
+            DfuError::Flash => DfuStatus::ErrWrite,
+            DfuError::TooLarge => DfuStatus::ErrAddress,
+            DfuError::BadImage => DfuStatus::ErrFile,
+            DfuError::Crc | DfuError::Verify => DfuStatus::ErrVerify,
+        }
+    }
+}
+
+/// Flash storage for firmware images
+///
+/// Offsets are from the start of flash. Bits can only be cleared by
+/// `write`; `erase` sets whole sectors back to `0xFF`.
+pub trait DfuFlash {
+    /// Erase sector size
+    const ERASE_SIZE: usize;
+
+    /// Erase `len` bytes at `offset`, both multiples of `ERASE_SIZE`
+    fn erase(&mut self, offset: u32, len: u32) -> Result<(), DfuError>;
+
+    /// Program `data` at `offset`
+    fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), DfuError>;
+
+    /// Read `buf.len()` bytes at `offset`
+    fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), DfuError>;
+}
+
+/// Where the running firmware and the staged update live
+///
+/// Both regions are `size` bytes and sector aligned. The last sector of
+/// the staging region holds the update record, so images can be at most
+/// one sector smaller than `size`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct FlashLayout {
+    /// Offset of the running firmware
+    pub active: u32,
+    /// Offset of the staging region
+    pub staging: u32,
+    /// Size of each region
+    pub size: u32,
+}
+
+impl FlashLayout {
+    /// Largest image, trailer included, that fits in the staging region
+    pub const fn capacity<F: DfuFlash>(&self) -> u32 {
+        self.size - F::ERASE_SIZE as u32
+    }
+
+    const fn record<F: DfuFlash>(&self) -> u32 {
+        self.staging + self.capacity::<F>()
+    }
+}
+
+/// CRC-32 (IEEE 802.3), as used by zlib
+struct Crc32(u32);
+
+impl Crc32 {
+    const TABLE: [u32; 16] = {
+        let mut table = [0; 16];
+        let mut i = 0;
+        while i < 16 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 4 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+
+    fn new() -> Self {
+        Self(!0)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        for &byte in data {
+            self.0 ^= byte as u32;
+            self.0 = (self.0 >> 4) ^ Self::TABLE[(self.0 & 0x0F) as usize];
+            self.0 = (self.0 >> 4) ^ Self::TABLE[(self.0 & 0x0F) as usize];
+        }
+    }
+
+    fn finish(self) -> u32 {
+        !self.0
+    }
+}
+
+/// CRC-32 of `len` bytes of flash at `offset`
+fn flash_crc<F: DfuFlash>(flash: &mut F, offset: u32, len: u32) -> Result<u32, DfuError> {
+    let mut crc = Crc32::new();
+    let mut buf = [0u8; CHUNK];
+    let mut pos = 0;
+    while pos < len {
+        let n = (len - pos).min(CHUNK as u32) as usize;
+        flash.read(offset + pos, &mut buf[..n])?;
+        crc.update(&buf[..n]);
+        pos += n as u32;
+    }
+    Ok(crc.finish())
+}
+
+fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Writes a downloaded image into the staging region
+///
+/// Blocks are appended in order, erasing sectors as they are reached.
+/// The first block also erases the update record, so an interrupted
+/// download never leaves a stale update pending.
+pub struct ImageWriter<F> {
+    flash: F,
+    layout: FlashLayout,
+    /// Bytes written
+    len: u32,
+    /// Bytes erased from the start of the staging region
+    erased: u32,
+}
+
+impl<F: DfuFlash> ImageWriter<F> {
+    /// Create a writer staging into `layout.staging`
+    pub fn new(flash: F, layout: FlashLayout) -> Self {
+        Self {
+            flash,
+            layout,
+            len: 0,
+            erased: 0,
+        }
+    }
+
+    /// Bytes written so far
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Check if nothing has been written yet
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Start over with a new image
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.erased = 0;
+    }
+
+    /// Append `data` to the image
+    pub fn write(&mut self, data: &[u8]) -> Result<(), DfuError> {
+        let end = self
+            .len
+            .checked_add(data.len() as u32)
+            .filter(|&end| end <= self.layout.capacity::<F>())
+            .ok_or(DfuError::TooLarge)?;
+        if self.erased == 0 {
+            self.flash
+                .erase(self.layout.record::<F>(), F::ERASE_SIZE as u32)
+                .map_err(|_| DfuError::Erase)?;
+        }
+        while self.erased < end {
+            self.flash
+                .erase(self.layout.staging + self.erased, F::ERASE_SIZE as u32)
+                .map_err(|_| DfuError::Erase)?;
+            self.erased += F::ERASE_SIZE as u32;
+        }
+        self.flash.write(self.layout.staging + self.len, data)?;
+        self.len = end;
+        Ok(())
+    }
+
+    /// Check the complete image and mark it for [`apply_update`]
+    ///
+    /// Returns the firmware length, trailer excluded.
+    pub fn finish(&mut self) -> Result<u32, DfuError> {
+        let image_len = self
+            .len
+            .checked_sub(TRAILER_SIZE as u32)
+            .ok_or(DfuError::BadImage)?;
+        let mut trailer = [0u8; TRAILER_SIZE];
+        self.flash
+            .read(self.layout.staging + image_len, &mut trailer)?;
+        if trailer[..4] != IMAGE_MAGIC || le_u32(&trailer[4..8]) != image_len {
+            return Err(DfuError::BadImage);
+        }
+        let crc = le_u32(&trailer[8..12]);
+        if flash_crc(&mut self.flash, self.layout.staging, image_len)? != crc {
+            return Err(DfuError::Crc);
+        }
+
+        let mut record = [0u8; RECORD_SIZE - 4];
+        record[..4].copy_from_slice(&RECORD_MAGIC);
+        record[4..8].copy_from_slice(&image_len.to_le_bytes());
+        record[8..12].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write(self.layout.record::<F>(), &record)?;
+        Ok(image_len)
+    }
+
+    /// Release the flash
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+}
+
+/// Copy a staged update over the running firmware
+///
+/// Call at boot, before anything else touches flash. Returns the length
+/// of the firmware installed, or `None` if no update is pending. An
+/// interrupted copy is redone on the next call; a staged image that no
+/// longer matches its CRC is dropped.
+///
+/// This overwrites the active region, so it must run from code that lives
+/// outside it: a boot stage of its own, or RAM.
+pub fn apply_update<F: DfuFlash>(
+    flash: &mut F,
+    layout: &FlashLayout,
+) -> Result<Option<u32>, DfuError> {
+    let mut record = [0u8; RECORD_SIZE];
+    flash.read(layout.record::<F>(), &mut record)?;
+    if record[..4] != RECORD_MAGIC || le_u32(&record[12..16]) != u32::MAX {
+        return Ok(None);
+    }
+    let len = le_u32(&record[4..8]);
+    let crc = le_u32(&record[8..12]);
+    let applied = layout.record::<F>() + RECORD_SIZE as u32 - 4;
+    if len > layout.capacity::<F>() || flash_crc(flash, layout.staging, len)? != crc {
+        flash.write(applied, &[0; 4])?;
+        return Err(DfuError::Crc);
+    }
+
+    let sectors = len.div_ceil(F::ERASE_SIZE as u32) * F::ERASE_SIZE as u32;
+    flash
+        .erase(layout.active, sectors)
+        .map_err(|_| DfuError::Erase)?;
+    let mut buf = [0u8; CHUNK];
+    let mut pos = 0;
+    while pos < len {
+        let n = (len - pos).min(CHUNK as u32) as usize;
+        flash.read(layout.staging + pos, &mut buf[..n])?;
+        flash.write(layout.active + pos, &buf[..n])?;
+        pos += n as u32;
+    }
+    if flash_crc(flash, layout.active, len)? != crc {
+        return Err(DfuError::Verify);
+    }
+
+    flash.write(applied, &[0; 4])?;
+    Ok(Some(len))
+}
+
+/// DFU mode request state machine
+///
+/// Runs the download side of DFU 1.1 on top of an [`ImageWriter`]. Blocks
+/// are written as they arrive, so the device is never busy when the host
+/// asks for its status; the image is checked on the DFU_GETSTATUS that
+/// follows the final zero-length block.
+pub struct DfuMachine<F> {
+    writer: ImageWriter<F>,
+    state: DfuState,
+    status: DfuStatus,
+    /// Number of the last block written
+    block: u16,
+    manifested: bool,
+}
+
+impl<F: DfuFlash> DfuMachine<F> {
+    /// Create a machine in `dfuIDLE`
+    pub fn new(writer: ImageWriter<F>) -> Self {
+        Self {
+            writer,
+            state: DfuState::DfuIdle,
+            status: DfuStatus::Ok,
+            block: 0,
+            manifested: false,
+        }
+    }
+
+    /// Current state
+    pub fn state(&self) -> DfuState {
+        self.state
+    }
+
+    /// Current status
+    pub fn status(&self) -> DfuStatus {
+        self.status
+    }
+
+    /// Check if a complete image has been staged
+    pub fn is_manifested(&self) -> bool {
+        self.manifested
+    }
+
+    fn fail(&mut self, status: DfuStatus) {
+        self.state = DfuState::Error;
+        self.status = status;
+    }
+
+    /// DFU_DNLOAD of `data` as block number `block`
+    ///
+    /// Returns false if the request should be stalled. Write errors are
+    /// reported by the next DFU_GETSTATUS instead.
+    pub fn download(&mut self, block: u16, data: &[u8]) -> bool {
+        match (self.state, data.is_empty()) {
+            (DfuState::DfuIdle, false) => {
+                self.writer.reset();
+                self.manifested = false;
+            }
+            (DfuState::DnloadIdle, false) if block == self.block.wrapping_add(1) => {}
+            (DfuState::DnloadIdle, false) => {
+                self.fail(DfuStatus::ErrAddress);
+                return true;
+            }
+            (DfuState::DnloadIdle, true) => {
+                self.state = DfuState::ManifestSync;
+                return true;
+            }
+            _ => {
+                self.fail(DfuStatus::ErrStalledPkt);
+                return false;
+            }
+        }
+        self.block = block;
+        match self.writer.write(data) {
+            Ok(()) => self.state = DfuState::DnloadSync,
+            Err(err) => self.fail(err.into()),
+        }
+        true
+    }
+
+    /// DFU_GETSTATUS response: bStatus, bwPollTimeout, bState, iString
+    pub fn get_status(&mut self) -> [u8; 6] {
+        match self.state {
+            DfuState::DnloadSync => self.state = DfuState::DnloadIdle,
+            DfuState::ManifestSync => match self.writer.finish() {
+                Ok(_) => {
+                    self.manifested = true;
+                    self.state = DfuState::DfuIdle;
+                }
+                Err(err) => self.fail(err.into()),
+            },
+            _ => {}
+        }
+        [self.status as u8, 0, 0, 0, self.state as u8, 0]
+    }
+
+    /// DFU_CLRSTATUS; returns false if the request should be stalled
+    pub fn clear_status(&mut self) -> bool {
+        if self.state != DfuState::Error {
+            return false;
+        }
+        self.state = DfuState::DfuIdle;
+        self.status = DfuStatus::Ok;
+        true
+    }
+
+    /// DFU_ABORT; returns false if the request should be stalled
+    pub fn abort(&mut self) -> bool {
+        match self.state {
+            DfuState::DfuIdle | DfuState::DnloadIdle | DfuState::ManifestSync => {
+                self.writer.reset();
+                self.state = DfuState::DfuIdle;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Add a DFU interface with its functional descriptor to `builder`
+fn add_interface<'d, D: Driver<'d>>(builder: &mut Builder<'d, D>, protocol: u8) -> InterfaceNumber {
+    let mut func = builder.function(CLASS_APPLICATION, SUBCLASS_DFU, protocol);
+    let mut iface = func.interface();
+    let number = iface.interface_number();
+    let mut alt = iface.alt_setting(CLASS_APPLICATION, SUBCLASS_DFU, protocol, None);
+    let [timeout_lo, timeout_hi] = DETACH_TIMEOUT_MS.to_le_bytes();
+    let [size_lo, size_hi] = (DFU_TRANSFER_SIZE as u16).to_le_bytes();
+    let [bcd_lo, bcd_hi] = BCD_DFU.to_le_bytes();
+    alt.descriptor(
+        DESC_DFU_FUNCTIONAL,
+        &[
+            DFU_ATTRIBUTES,
+            timeout_lo,
+            timeout_hi,
+            size_lo,
+            size_hi,
+            bcd_lo,
+            bcd_hi,
+        ],
+    );
+    number
+}
+
+/// Check that `req` is a class request for `interface`
+fn is_dfu_request(req: &Request, interface: InterfaceNumber) -> bool {
+    req.request_type == RequestType::Class
+        && req.recipient == Recipient::Interface
+        && req.index == interface.0 as u16
+}
+
+/// Set once and waited on by the application
+struct Flag {
+    set: AtomicBool,
+    waker: AtomicWaker,
+}
+
+impl Flag {
+    const fn new() -> Self {
+        Self {
+            set: AtomicBool::new(false),
+            waker: AtomicWaker::new(),
+        }
+    }
+
+    fn set(&self) {
+        self.set.store(true, Ordering::Relaxed);
+        self.waker.wake();
+    }
+
+    async fn wait(&self) {
+        poll_fn(|cx| {
+            self.waker.register(cx.waker());
+            if self.set.load(Ordering::Relaxed) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+/// Storage for [`DfuRuntime`], which must outlive the USB device
+pub struct DfuRuntimeState<'d> {
+    control: Option<RuntimeControl<'d>>,
+    detach: Flag,
+}
+
+impl Default for DfuRuntimeState<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DfuRuntimeState<'_> {
+    /// Create a new, unregistered state
+    pub const fn new() -> Self {
+        Self {
+            control: None,
+            detach: Flag::new(),
+        }
+    }
+}
+
+/// Answers DFU runtime requests
+struct RuntimeControl<'d> {
+    detach: &'d Flag,
+    interface: InterfaceNumber,
+}
+
+impl RuntimeControl<'_> {
+    fn state(&self) -> DfuState {
+        if self.detach.set.load(Ordering::Relaxed) {
+            DfuState::AppDetach
+        } else {
+            DfuState::AppIdle
+        }
+    }
+}
+
+impl Handler for RuntimeControl<'_> {
+    fn control_out(&mut self, req: Request, _data: &[u8]) -> Option<OutResponse> {
+        if !is_dfu_request(&req, self.interface) {
+            return None;
+        }
+        Some(match req.request {
+            DFU_DETACH => {
+                self.detach.set();
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !is_dfu_request(&req, self.interface) {
+            return None;
+        }
+        let len = match req.request {
+            DFU_GETSTATUS => {
+                buf[..6].copy_from_slice(&[0, 0, 0, 0, self.state() as u8, 0]);
+                6
+            }
+            DFU_GETSTATE => {
+                buf[0] = self.state() as u8;
+                1
+            }
+            _ => return Some(InResponse::Rejected),
+        };
+        Some(InResponse::Accepted(&buf[..len]))
+    }
+}
+
+/// DFU runtime interface, added to the application's USB device
+pub struct DfuRuntime<'d> {
+    detach: &'d Flag,
+}
+
+impl<'d> DfuRuntime<'d> {
+    /// Add the runtime interface to `builder`
+    pub fn register<D: Driver<'d>>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut DfuRuntimeState<'d>,
+    ) -> Self {
+        let interface = add_interface(builder, PROTOCOL_RUNTIME);
+        state.control = Some(RuntimeControl {
+            detach: &state.detach,
+            interface,
+        });
+        builder.handler(state.control.as_mut().unwrap());
+        Self {
+            detach: &state.detach,
+        }
+    }
+
+    /// Wait for the host to request update mode
+    ///
+    /// The device is expected to reboot into update mode on its own
+    /// (bitWillDetach), within the advertised detach timeout.
+    pub async fn wait_detach(&self) {
+        self.detach.wait().await
+    }
+}
+
+/// Storage for [`DfuMode`], which must outlive the USB device
+pub struct DfuModeState<'d, F> {
+    control: Option<ModeControl<'d, F>>,
+    manifested: Flag,
+}
+
+impl<F> Default for DfuModeState<'_, F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F> DfuModeState<'_, F> {
+    /// Create a new, unregistered state
+    pub const fn new() -> Self {
+        Self {
+            control: None,
+            manifested: Flag::new(),
+        }
+    }
+}
+
+/// Runs DFU mode requests through a [`DfuMachine`]
+struct ModeControl<'d, F> {
+    machine: DfuMachine<F>,
+    manifested: &'d Flag,
+    interface: InterfaceNumber,
+}
+
+impl<F: DfuFlash> Handler for ModeControl<'_, F> {
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if !is_dfu_request(&req, self.interface) {
+            return None;
+        }
+        let accepted = match req.request {
+            DFU_DNLOAD => self.machine.download(req.value, data),
+            DFU_CLRSTATUS => self.machine.clear_status(),
+            DFU_ABORT => self.machine.abort(),
+            _ => false,
+        };
+        Some(if accepted {
+            OutResponse::Accepted
+        } else {
+            OutResponse::Rejected
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        if !is_dfu_request(&req, self.interface) {
+            return None;
+        }
+        let len = match req.request {
+            DFU_GETSTATUS => {
+                buf[..6].copy_from_slice(&self.machine.get_status());
+                if self.machine.is_manifested() {
+                    self.manifested.set();
+                }
+                6
+            }
+            DFU_GETSTATE => {
+                buf[0] = self.machine.state() as u8;
+                1
+            }
+            _ => return Some(InResponse::Rejected),
+        };
+        Some(InResponse::Accepted(&buf[..len]))
+    }
+}
+
+/// DFU mode interface, the only function of the device in update mode
+///
+/// The USB control buffer must hold [`DFU_TRANSFER_SIZE`] bytes.
+pub struct DfuMode<'d> {
+    manifested: &'d Flag,
+}
+
+impl<'d> DfuMode<'d> {
+    /// Add the DFU mode interface to `builder`, staging downloads through
+    /// `writer`
+    pub fn register<D: Driver<'d>, F: DfuFlash + 'd>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut DfuModeState<'d, F>,
+        writer: ImageWriter<F>,
+    ) -> Self {
+        let interface = add_interface(builder, PROTOCOL_DFU_MODE);
+        state.control = Some(ModeControl {
+            machine: DfuMachine::new(writer),
+            manifested: &state.manifested,
+            interface,
+        });
+        builder.handler(state.control.as_mut().unwrap());
+        Self {
+            manifested: &state.manifested,
+        }
+    }
+
+    /// Wait for a complete, verified image to be staged
+    ///
+    /// Reboot afterwards to have [`apply_update`] install it.
+    pub async fn wait_manifested(&self) {
+        self.manifested.wait().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{setup, MockUsb};
+    use crate::parse;
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+    use embassy_usb::Config;
+    use heapless::Vec;
+
+    const SECTOR: usize = 64;
+
+    /// NOR flash in RAM: active region, then staging region
+    struct RamFlash {
+        data: [u8; 1024],
+        erases: usize,
+    }
+
+    impl RamFlash {
+        fn new() -> Self {
+            Self {
+                data: [0xFF; 1024],
+                erases: 0,
+            }
+        }
+    }
+
+    impl DfuFlash for &mut RamFlash {
+        const ERASE_SIZE: usize = SECTOR;
+
+        fn erase(&mut self, offset: u32, len: u32) -> Result<(), DfuError> {
+            assert_eq!((offset as usize % SECTOR, len as usize % SECTOR), (0, 0));
+            self.data[offset as usize..(offset + len) as usize].fill(0xFF);
+            self.erases += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, data: &[u8]) -> Result<(), DfuError> {
+            for (cell, byte) in self.data[offset as usize..].iter_mut().zip(data) {
+                *cell &= byte;
+            }
+            Ok(())
+        }
+
+        fn read(&mut self, offset: u32, buf: &mut [u8]) -> Result<(), DfuError> {
+            let offset = offset as usize;
+            buf.copy_from_slice(&self.data[offset..offset + buf.len()]);
+            Ok(())
+        }
+    }
+
+    const LAYOUT: FlashLayout = FlashLayout {
+        active: 0,
+        staging: 512,
+        size: 512,
+    };
+
+    /// `len` bytes of firmware followed by a trailer
+    fn image(len: usize) -> Vec<u8, 512> {
+        let mut image: Vec<u8, 512> = (0..len).map(|i| (i * 7) as u8).collect();
+        let mut crc = Crc32::new();
+        crc.update(&image);
+        image.extend_from_slice(&IMAGE_MAGIC).unwrap();
+        image
+            .extend_from_slice(&(len as u32).to_le_bytes())
+            .unwrap();
+        image
+            .extend_from_slice(&crc.finish().to_le_bytes())
+            .unwrap();
+        image
+    }
+
+    #[test]
+    fn test_stage_and_apply() {
+        let mut check = Crc32::new();
+        check.update(b"123456789");
+        assert_eq!(check.finish(), 0xCBF4_3926);
+
+        let mut flash = RamFlash::new();
+        let firmware = image(300);
+        let mut writer = ImageWriter::new(&mut flash, LAYOUT);
+        for chunk in firmware.chunks(50) {
+            writer.write(chunk).unwrap();
+        }
+        assert_eq!(writer.finish(), Ok(300));
+        // Record sector plus five image sectors
+        assert_eq!(writer.into_inner().erases, 6);
+
+        assert_eq!(apply_update(&mut &mut flash, &LAYOUT), Ok(Some(300)));
+        assert_eq!(flash.data[..300], firmware[..300]);
+        assert_eq!(apply_update(&mut &mut flash, &LAYOUT), Ok(None));
+
+        // Corrupt CRC
+        let mut bad = image(100);
+        bad[111] ^= 1;
+        let mut writer = ImageWriter::new(&mut flash, LAYOUT);
+        writer.write(&bad).unwrap();
+        assert_eq!(writer.finish(), Err(DfuError::Crc));
+        assert_eq!(apply_update(&mut &mut flash, &LAYOUT), Ok(None));
+
+        // Bad trailer, then too large for the staging region
+        let mut writer = ImageWriter::new(&mut flash, LAYOUT);
+        writer.write(&firmware[..200]).unwrap();
+        assert_eq!(writer.finish(), Err(DfuError::BadImage));
+        writer.write(&[0; 248]).unwrap();
+        assert_eq!(writer.write(&[0; 1]), Err(DfuError::TooLarge));
+
+        // A staged image damaged before boot is dropped
+        let mut writer = ImageWriter::new(&mut flash, LAYOUT);
+        writer.write(&image(100)).unwrap();
+        writer.finish().unwrap();
+        flash.data[513] = 0;
+        assert_eq!(apply_update(&mut &mut flash, &LAYOUT), Err(DfuError::Crc));
+        assert_eq!(apply_update(&mut &mut flash, &LAYOUT), Ok(None));
+        assert_eq!(flash.data[..300], firmware[..300]);
+    }
+
+    #[test]
+    fn test_download_states() {
+        let mut flash = RamFlash::new();
+        let firmware = image(120);
+        let mut dfu = DfuMachine::new(ImageWriter::new(&mut flash, LAYOUT));
+
+        // Manifesting without data and clearing a non-error are stalled
+        assert!(!dfu.download(0, &[]));
+        assert_eq!(dfu.get_status(), [0x0F, 0, 0, 0, 10, 0]);
+        assert!(dfu.clear_status());
+        assert!(!dfu.clear_status());
+
+        assert!(dfu.download(0, &firmware[..100]));
+        assert_eq!(dfu.state(), DfuState::DnloadSync);
+        assert_eq!(dfu.get_status(), [0, 0, 0, 0, 5, 0]);
+        // Block 2 before block 1
+        assert!(dfu.download(2, &firmware[100..]));
+        assert_eq!(dfu.get_status(), [0x08, 0, 0, 0, 10, 0]);
+        assert!(dfu.clear_status());
+
+        for (block, chunk) in firmware.chunks(64).enumerate() {
+            assert!(dfu.download(block as u16, chunk));
+            assert_eq!(dfu.get_status()[4], DfuState::DnloadIdle as u8);
+        }
+        assert!(dfu.download(2, &[]));
+        assert!(!dfu.is_manifested());
+        assert_eq!(dfu.get_status(), [0, 0, 0, 0, 2, 0]);
+        assert!(dfu.is_manifested());
+
+        // Aborting mid-download and a download that fails its check
+        assert!(dfu.download(0, &firmware[..64]));
+        dfu.get_status();
+        assert!(dfu.abort());
+        assert!(dfu.download(0, &firmware[..64]));
+        dfu.get_status();
+        assert!(dfu.download(1, &[]));
+        assert_eq!(dfu.get_status(), [0x02, 0, 0, 0, 10, 0]);
+        assert!(!dfu.abort());
+    }
+
+    #[test]
+    fn test_detach_and_download_over_usb() {
+        let mut config_buf = [0u8; 128];
+        let mut bos = [0u8; 64];
+        let mut control = [0u8; DFU_TRANSFER_SIZE];
+
+        {
+            let usb = MockUsb::new();
+            let mut state = DfuRuntimeState::new();
+            let mut builder = Builder::new(
+                usb.driver(),
+                Config::new(0x1209, 0xA2D0),
+                &mut config_buf,
+                &mut bos,
+                &mut [],
+                &mut control,
+            );
+            let runtime = DfuRuntime::register(&mut builder, &mut state);
+            let mut device = builder.build();
+
+            let host = async {
+                let config = usb.enumerate().await;
+                assert_eq!(parse::check(&config), Ok(4));
+                // IAD, interface, then functional descriptor
+                assert_eq!(config[17..26], [9, 0x04, 0, 0, 0, 0xFE, 0x01, 0x01, 0]);
+                assert_eq!(
+                    config[26..35],
+                    [9, 0x21, 0x0D, 0xE8, 0x03, 0, 1, 0x10, 0x01]
+                );
+                let get_state = setup(0xA1, DFU_GETSTATE, 0, 0, 1);
+                assert_eq!(usb.control_in(get_state).await.unwrap(), [0]);
+                let detach = setup(0x21, DFU_DETACH, DETACH_TIMEOUT_MS, 0, 0);
+                usb.control_out(detach, &[]).await.unwrap();
+                assert_eq!(usb.control_in(get_state).await.unwrap(), [1]);
+            };
+            if let Either::First(_) =
+                block_on(select(device.run(), join(host, runtime.wait_detach())))
+            {
+                unreachable!()
+            }
+        }
+
+        // After the reboot into update mode
+        let mut flash = RamFlash::new();
+        let firmware = image(300);
+        {
+            let usb = MockUsb::new();
+            let mut state = DfuModeState::new();
+            let mut builder = Builder::new(
+                usb.driver(),
+                Config::new(0x1209, 0xA2D0),
+                &mut config_buf,
+                &mut bos,
+                &mut [],
+                &mut control,
+            );
+            let writer = ImageWriter::new(&mut flash, LAYOUT);
+            let mode = DfuMode::register(&mut builder, &mut state, writer);
+            let mut device = builder.build();
+
+            let host = async {
+                let config = usb.enumerate().await;
+                assert_eq!(parse::check(&config), Ok(4));
+                assert_eq!(config[24], PROTOCOL_DFU_MODE);
+                let status = setup(0xA1, DFU_GETSTATUS, 0, 0, 6);
+                for (block, chunk) in firmware.chunks(DFU_TRANSFER_SIZE).enumerate() {
+                    let dnload = setup(0x21, DFU_DNLOAD, block as u16, 0, chunk.len() as u16);
+                    usb.control_out(dnload, chunk).await.unwrap();
+                    assert_eq!(usb.control_in(status).await.unwrap(), [0, 0, 0, 0, 5, 0]);
+                }
+                let done = setup(0x21, DFU_DNLOAD, 2, 0, 0);
+                usb.control_out(done, &[]).await.unwrap();
+                assert_eq!(usb.control_in(status).await.unwrap(), [0, 0, 0, 0, 2, 0]);
+            };
+            if let Either::First(_) =
+                block_on(select(device.run(), join(host, mode.wait_manifested())))
+            {
+                unreachable!()
+            }
+        }
+        assert_eq!(apply_update(&mut &mut flash, &LAYOUT), Ok(Some(300)));
+        assert_eq!(flash.data[..300], firmware[..300]);
+    }
+}
@@ -0,0 +1,229 @@
+//! Asynchronous isochronous feedback
+//!
+//! The speaker endpoint is asynchronous: samples are consumed at the
+//! Bluetooth media clock, not the host's. [`FeedbackController`] turns the
+//! PCM ring buffer fill level into the rate the host should send at, so
+//! the buffer settles at its target instead of slowly draining or
+//! overflowing as the two clocks drift apart. The microphone runs the same
+//! loop the other way round to size the packets it sends.
+
+/// Encoding of the feedback value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum FeedbackFormat {
+    /// Samples per 1 ms frame in 10.14 fixed point, sent as 3 bytes
+    /// (full-speed)
+    #[default]
+    Q10_14,
+    /// Samples per 125 us microframe in 16.16 fixed point, sent as 4 bytes
+    /// (high-speed)
+    Q16_16,
+}
+
+impl FeedbackFormat {
+    /// Size of a feedback packet in bytes
+    pub const fn packet_size(self) -> usize {
+        match self {
+            Self::Q10_14 => 3,
+            Self::Q16_16 => 4,
+        }
+    }
+
+    /// Fractional bits and (micro)frames per second
+    const fn scale(self) -> (u32, u64) {
+        match self {
+            Self::Q10_14 => (14, 1000),
+            Self::Q16_16 => (16, 8000),
+        }
+    }
+}
+
+/// Largest rate correction, in parts per million of the nominal rate
+pub const MAX_FEEDBACK_PPM: u32 = 1000;
+
+/// Fill level smoothing: each update moves 1/2^N of the way
+const FILL_SMOOTHING_SHIFT: u32 = 4;
+
+/// Computes feedback values from the ring buffer fill level
+///
+/// A fill error of one frame adjusts the requested rate by 1 Hz, so an
+/// error is worked off in about a second, capped at
+/// [`MAX_FEEDBACK_PPM`].
+pub struct FeedbackController {
+    format: FeedbackFormat,
+    sample_rate: u32,
+    channels: u8,
+    /// Smoothed fill level in frames (Q8), `None` until the first update
+    fill_q8: Option<i64>,
+    value: u32,
+}
+
+impl FeedbackController {
+    /// Create a controller for a stream at `sample_rate` with `channels`
+    pub fn new(format: FeedbackFormat, sample_rate: u32, channels: u8) -> Self {
+        let mut controller = Self {
+            format,
+            sample_rate,
+            channels: channels.max(1),
+            fill_q8: None,
+            value: 0,
+        };
+        controller.value = controller.nominal();
+        controller
+    }
+
+    /// Change the nominal rate, e.g. when the host switches sample rate
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = sample_rate;
+        self.reset();
+    }
+
+    /// Nominal sample rate
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Feedback format
+    pub fn format(&self) -> FeedbackFormat {
+        self.format
+    }
+
+    /// Forget the fill history and return to the nominal rate
+    pub fn reset(&mut self) {
+        self.fill_q8 = None;
+        self.value = self.nominal();
+    }
+
+    /// Feedback value for the nominal sample rate
+    pub fn nominal(&self) -> u32 {
+        self.rate_to_value((self.sample_rate as i64) << 8)
+    }
+
+    /// Most recent feedback value
+    pub fn value(&self) -> u32 {
+        self.value
+    }
+
+    /// Update from the ring buffer fill and target levels (in interleaved
+    /// samples) and return the new feedback value
+    pub fn update(&mut self, fill_samples: usize, target_samples: usize) -> u32 {
+        self.track(fill_samples, target_samples, false)
+    }
+
+    /// Like [`FeedbackController::update`], for a ring this side drains:
+    /// the rate rises as the ring fills up
+    pub fn update_drain(&mut self, fill_samples: usize, target_samples: usize) -> u32 {
+        self.track(fill_samples, target_samples, true)
+    }
+
+    fn track(&mut self, fill_samples: usize, target_samples: usize, drain: bool) -> u32 {
+        let channels = self.channels as usize;
+        let fill_q8 = ((fill_samples / channels) as i64) << 8;
+        let target_q8 = ((target_samples / channels) as i64) << 8;
+
+        let smoothed = match self.fill_q8 {
+            Some(previous) => previous + ((fill_q8 - previous) >> FILL_SMOOTHING_SHIFT),
+            None => fill_q8,
+        };
+        self.fill_q8 = Some(smoothed);
+
+        // Frames short of (or, draining, over) the target, as a rate offset
+        // in Hz (Q8)
+        let error_q8 = if drain {
+            smoothed - target_q8
+        } else {
+            target_q8 - smoothed
+        };
+        let max_q8 = ((self.sample_rate as i64) << 8) * MAX_FEEDBACK_PPM as i64 / 1_000_000;
+        let correction_q8 = error_q8.clamp(-max_q8, max_q8);
+
+        self.value = self.rate_to_value(((self.sample_rate as i64) << 8) + correction_q8);
+        self.value
+    }
+
+    /// Encode the current value into a feedback packet
+    ///
+    /// Returns the packet length ([`FeedbackFormat::packet_size`]).
+    pub fn write_packet(&self, buf: &mut [u8; 4]) -> usize {
+        let len = self.format.packet_size();
+        buf.copy_from_slice(&self.value.to_le_bytes());
+        len
+    }
+
+    /// Convert a rate in Hz (Q8) to the feedback encoding
+    fn rate_to_value(&self, rate_q8: i64) -> u32 {
+        let (frac_bits, frames_per_second) = self.format.scale();
+        let value = (rate_q8.max(0) as u64) << frac_bits >> 8;
+        (value / frames_per_second) as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nominal_values() {
+        let fs = FeedbackController::new(FeedbackFormat::Q10_14, 48000, 2);
+        assert_eq!(fs.nominal(), 48 << 14);
+        assert_eq!(fs.value(), 0x0C_0000);
+
+        let mut cd = FeedbackController::new(FeedbackFormat::Q10_14, 44100, 2);
+        assert_eq!(cd.nominal(), 722_534);
+        cd.set_sample_rate(48000);
+        assert_eq!(cd.value(), 48 << 14);
+
+        let hs = FeedbackController::new(FeedbackFormat::Q16_16, 48000, 2);
+        assert_eq!(hs.nominal(), 6 << 16);
+        assert_eq!(FeedbackFormat::Q16_16.packet_size(), 4);
+    }
+
+    #[test]
+    fn test_tracks_fill_level() {
+        let mut fb = FeedbackController::new(FeedbackFormat::Q10_14, 48000, 2);
+        let nominal = fb.nominal();
+
+        // At the target the host keeps the nominal rate
+        assert_eq!(fb.update(1024, 1024), nominal);
+
+        // 10 frames short: ask for 10 Hz more, once the smoothing settles
+        for _ in 0..200 {
+            fb.update(1004, 1024);
+        }
+        assert_eq!(fb.value(), nominal + 10 * (1 << 14) / 1000);
+
+        // Overfull: slow the host down
+        for _ in 0..200 {
+            fb.update(1100, 1024);
+        }
+        assert!(fb.value() < nominal);
+
+        // Draining the ring instead: overfull speeds up
+        fb.reset();
+        for _ in 0..200 {
+            fb.update_drain(1100, 1024);
+        }
+        assert!(fb.value() > nominal);
+    }
+
+    #[test]
+    fn test_correction_is_capped() {
+        let mut fb = FeedbackController::new(FeedbackFormat::Q10_14, 48000, 2);
+        let nominal = fb.nominal() as i64;
+
+        fb.update(0, 8192);
+        // 1000 ppm of 48 kHz is 48 Hz
+        assert_eq!(fb.value() as i64 - nominal, 48 * (1 << 14) / 1000);
+        fb.reset();
+        fb.update(8192, 0);
+        assert_eq!(nominal - fb.value() as i64, 48 * (1 << 14) / 1000 + 1);
+    }
+
+    #[test]
+    fn test_packet_encoding() {
+        let fb = FeedbackController::new(FeedbackFormat::Q10_14, 44100, 2);
+        let mut buf = [0u8; 4];
+        let len = fb.write_packet(&mut buf);
+        assert_eq!(buf[..len], [0x66, 0x06, 0x0B]);
+    }
+}
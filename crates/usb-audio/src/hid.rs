@@ -0,0 +1,246 @@
+//! HID consumer control interface for media keys
+//!
+//! Lets the headphones' play/pause, skip and volume buttons drive the
+//! host's media player. The Bluetooth side queues [`MediaKey`] taps on a
+//! shared [`MediaKeys`]; [`ConsumerControl`] sends each one to the host as
+//! a press report followed by a release report.
+
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_usb::class::hid::{self, HidWriter};
+use embassy_usb::driver::{Driver, EndpointError};
+use embassy_usb::Builder;
+
+/// Taps that can wait for the host to poll
+const KEY_QUEUE: usize = 8;
+
+/// Consumer control report size: one bit per key, padded to a byte
+const REPORT_SIZE: usize = 1;
+
+/// Interrupt endpoint polling interval in ms
+const POLL_MS: u8 = 10;
+
+/// HID report descriptor: a one-byte report with one bit per [`MediaKey`],
+/// in declaration order
+pub const CONSUMER_REPORT_DESCRIPTOR: [u8; 33] = [
+    0x05, 0x0C, // Usage Page (Consumer)
+    0x09, 0x01, // Usage (Consumer Control)
+    0xA1, 0x01, // Collection (Application)
+    0x15, 0x00, //   Logical Minimum (0)
+    0x25, 0x01, //   Logical Maximum (1)
+    0x75, 0x01, //   Report Size (1)
+    0x95, 0x06, //   Report Count (6)
+    0x09, 0xCD, //   Usage (Play/Pause)
+    0x09, 0xB5, //   Usage (Scan Next Track)
+    0x09, 0xB6, //   Usage (Scan Previous Track)
+    0x09, 0xE9, //   Usage (Volume Increment)
+    0x09, 0xEA, //   Usage (Volume Decrement)
+    0x09, 0xE2, //   Usage (Mute)
+    0x81, 0x02, //   Input (Data, Variable, Absolute)
+    0x95, 0x02, //   Report Count (2)
+    0x81, 0x01, //   Input (Constant): padding
+    0xC0, // End Collection
+];
+
+/// Media key reported to the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MediaKey {
+    /// Play/Pause
+    PlayPause,
+    /// Scan Next Track
+    NextTrack,
+    /// Scan Previous Track
+    PreviousTrack,
+    /// Volume Increment
+    VolumeUp,
+    /// Volume Decrement
+    VolumeDown,
+    /// Mute
+    Mute,
+}
+
+impl MediaKey {
+    /// Report with only this key pressed
+    pub const fn report(self) -> [u8; REPORT_SIZE] {
+        [1 << self as u8]
+    }
+}
+
+/// Media key error
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum MediaKeyError {
+    /// Too many taps waiting for the host
+    QueueFull,
+}
+
+/// Key taps waiting to be sent, shared between the Bluetooth and USB tasks
+pub struct MediaKeys {
+    queue: Channel<CriticalSectionRawMutex, MediaKey, KEY_QUEUE>,
+}
+
+impl MediaKeys {
+    /// Create an empty queue
+    pub const fn new() -> Self {
+        Self {
+            queue: Channel::new(),
+        }
+    }
+
+    /// Queue a press and release of `key`
+    pub fn tap(&self, key: MediaKey) -> Result<(), MediaKeyError> {
+        self.queue
+            .try_send(key)
+            .map_err(|_| MediaKeyError::QueueFull)
+    }
+
+    /// Drop taps the host hasn't collected yet
+    pub fn clear(&self) {
+        self.queue.clear();
+    }
+}
+
+impl Default for MediaKeys {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Storage for [`ConsumerControl`], which must outlive the USB device
+pub struct ConsumerControlState<'d> {
+    hid: hid::State<'d>,
+}
+
+impl ConsumerControlState<'_> {
+    /// Create the state
+    pub const fn new() -> Self {
+        Self {
+            hid: hid::State::new(),
+        }
+    }
+}
+
+impl Default for ConsumerControlState<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// HID consumer control interface
+pub struct ConsumerControl<'d, D: Driver<'d>> {
+    writer: HidWriter<'d, D, REPORT_SIZE>,
+    keys: &'d MediaKeys,
+}
+
+impl<'d, D: Driver<'d>> ConsumerControl<'d, D> {
+    /// Add the consumer control interface to `builder`, sending the taps
+    /// queued on `keys`
+    pub fn register(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut ConsumerControlState<'d>,
+        keys: &'d MediaKeys,
+    ) -> Self {
+        let config = hid::Config {
+            report_descriptor: &CONSUMER_REPORT_DESCRIPTOR,
+            request_handler: None,
+            poll_ms: POLL_MS,
+            max_packet_size: REPORT_SIZE as u16,
+        };
+        Self {
+            writer: HidWriter::new(builder, &mut state.hid, config),
+            keys,
+        }
+    }
+
+    /// Send a press and release of `key`
+    pub async fn send(&mut self, key: MediaKey) -> Result<(), EndpointError> {
+        self.writer.write(&key.report()).await?;
+        self.writer.write(&[0; REPORT_SIZE]).await
+    }
+
+    /// Send queued taps while the host has the device configured
+    ///
+    /// Taps queued while the bus is down are sent once it comes back.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            self.writer.ready().await;
+            let key = self.keys.queue.receive().await;
+            // A disabled endpoint means the host went away; the tap is lost
+            // along with it
+            self.send(key).await.ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{setup, usb_config, Buffers, MockUsb};
+    use crate::{parse, Speaker, SpeakerState, Uac2Config};
+    use embassy_futures::block_on;
+    use embassy_futures::select::select3;
+
+    #[test]
+    fn test_report_descriptor_keys() {
+        // Six one-bit usages, in MediaKey order
+        let usages: [u8; 6] = core::array::from_fn(|i| CONSUMER_REPORT_DESCRIPTOR[15 + 2 * i]);
+        assert_eq!(usages, [0xCD, 0xB5, 0xB6, 0xE9, 0xEA, 0xE2]);
+        assert_eq!(MediaKey::PlayPause.report(), [0x01]);
+        assert_eq!(MediaKey::Mute.report(), [0x20]);
+    }
+
+    #[test]
+    fn test_taps_reach_host() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut speaker_state = SpeakerState::new();
+        let mut hid_state = ConsumerControlState::new();
+        let keys = MediaKeys::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let _speaker = Speaker::register(&mut builder, &mut speaker_state, &Uac2Config::default());
+        let mut media = ConsumerControl::register(&mut builder, &mut hid_state, &keys);
+        let mut device = builder.build();
+
+        keys.tap(MediaKey::PlayPause).unwrap();
+        let host = async {
+            let config = usb.enumerate().await;
+            assert!(parse::check(&config).is_ok());
+            // HID interface after the two audio interfaces
+            let mut pos = 0;
+            while config[pos + 1] != 0x04 || config[pos + 5] != 0x03 {
+                pos += config[pos] as usize;
+            }
+            assert_eq!(config[pos + 2], 2);
+            let report = setup(0x81, 0x06, 0x2200, 2, 64);
+            assert_eq!(
+                usb.control_in(report).await.unwrap(),
+                CONSUMER_REPORT_DESCRIPTOR
+            );
+
+            // Speaker's feedback endpoints take IN 1 to 4
+            assert_eq!(usb.receive(0x85).await, [0x01]);
+            assert_eq!(usb.receive(0x85).await, [0x00]);
+            keys.tap(MediaKey::NextTrack).unwrap();
+            assert_eq!(usb.receive(0x85).await, [0x02]);
+            assert_eq!(usb.receive(0x85).await, [0x00]);
+        };
+
+        block_on(select3(device.run(), media.run(), host));
+
+        for _ in 0..KEY_QUEUE {
+            keys.tap(MediaKey::VolumeUp).unwrap();
+        }
+        assert_eq!(keys.tap(MediaKey::VolumeUp), Err(MediaKeyError::QueueFull));
+        keys.clear();
+        assert!(keys.tap(MediaKey::VolumeDown).is_ok());
+    }
+}
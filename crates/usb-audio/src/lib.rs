@@ -1,19 +1,59 @@
 //! USB Audio Class 2.0 (UAC2) device implementation
 //!
-//! Provides USB Audio Class support for receiving audio from a host computer.
-//! The device appears as a USB speaker/sound card to the host.
+//! Provides USB Audio Class support for receiving audio from a host computer,
+//! with a USB Audio Class 1.0 fallback for legacy hosts.
+//! The device appears as a USB speaker/sound card to the host; [`Speaker`]
+//! registers it with `embassy-usb`. [`ConsumerControl`] adds a HID
+//! interface to the same device for media keys, and [`SerialConsole`] a
+//! CDC-ACM port for the command console. [`Microphone`] is a second audio
+//! function sending audio to the host. [`DfuRuntime`] and [`DfuMode`]
+//! implement firmware updates over USB DFU.
 
 #![no_std]
 #![deny(unsafe_op_in_unsafe_fn)]
 
+mod control;
 mod descriptor;
+mod dfu;
+mod feedback;
+mod hid;
+mod microphone;
+#[cfg(test)]
+mod mock;
+#[cfg(test)]
+mod parse;
+mod serial;
+mod speaker;
+mod uac1;
 
-pub use descriptor::{AudioControlDescriptor, AudioStreamingDescriptor, Uac2Config};
+pub use descriptor::{
+    AudioControlDescriptor, AudioProtocol, AudioStreamingDescriptor, DescriptorError, StreamFormat,
+    Uac2Config,
+};
+pub use dfu::{
+    apply_update, DfuError, DfuFlash, DfuMachine, DfuMode, DfuModeState, DfuRuntime,
+    DfuRuntimeState, DfuState, DfuStatus, FlashLayout, ImageWriter, DFU_TRANSFER_SIZE, IMAGE_MAGIC,
+    TRAILER_SIZE,
+};
+pub use feedback::{FeedbackController, FeedbackFormat, MAX_FEEDBACK_PPM};
+pub use hid::{
+    ConsumerControl, ConsumerControlState, MediaKey, MediaKeyError, MediaKeys,
+    CONSUMER_REPORT_DESCRIPTOR,
+};
+pub use microphone::{
+    CaptureStats, Microphone, MicrophoneDescriptor, MicrophoneState, MicrophoneStream,
+};
+pub use serial::{SerialConsole, SerialConsoleState, SERIAL_PACKET};
+pub use speaker::{
+    ControlEvent, ControlMonitor, Speaker, SpeakerFeedback, SpeakerState, SpeakerStream,
+    STREAM_BITS_PER_SAMPLE, VOLUME_MAX_DB_X256, VOLUME_MIN_DB_X256, VOLUME_RES_DB_X256,
+};
+pub use uac1::{Uac1ControlDescriptor, Uac1StreamingDescriptor};
 
 use heapless::Vec;
 
-/// Maximum USB audio packet size (48kHz stereo 16-bit @ 1ms = 192 bytes + margin)
-pub const MAX_USB_AUDIO_PACKET: usize = 196;
+/// Maximum USB audio packet size (48kHz stereo 32-bit @ 1ms = 384 bytes + margin)
+pub const MAX_USB_AUDIO_PACKET: usize = 392;
 
 /// USB Audio Class codes
 pub mod class {
@@ -23,6 +63,8 @@ pub mod class {
     pub const AUDIO_CONTROL: u8 = 0x01;
     /// Audio Streaming subclass
     pub const AUDIO_STREAMING: u8 = 0x02;
+    /// UAC1 protocol
+    pub const UAC1_PROTOCOL: u8 = 0x00;
     /// UAC2 protocol
     pub const UAC2_PROTOCOL: u8 = 0x20;
 }
@@ -91,6 +133,16 @@ pub enum StreamState {
     Overrun,
 }
 
+impl From<audio_pipeline::FillState> for StreamState {
+    fn from(state: audio_pipeline::FillState) -> Self {
+        match state {
+            audio_pipeline::FillState::Normal => Self::Active,
+            audio_pipeline::FillState::Underrun => Self::Underrun,
+            audio_pipeline::FillState::Overrun => Self::Overrun,
+        }
+    }
+}
+
 /// USB Audio receiver statistics
 #[derive(Debug, Clone, Copy, Default)]
 pub struct AudioStats {
@@ -0,0 +1,615 @@
+//! UAC2 microphone class for `embassy-usb`
+//!
+//! A second audio function that sends audio to the host, e.g. a headset
+//! microphone or a phone streaming to us over Bluetooth. It has its own
+//! Audio Control interface (clock source, input terminal and USB streaming
+//! output terminal) and a streaming interface with one 16-bit alternate
+//! setting, whose isochronous IN endpoint is fed from a `RingBuffer`.
+//!
+//! The endpoint is asynchronous: the ring is filled at the Bluetooth side's
+//! clock, so each packet carries about one millisecond of audio, sized from
+//! the ring fill level by a [`FeedbackController`] to keep the ring near
+//! half full. The host accepts whatever packet sizes we send.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use audio_pipeline::RingBuffer;
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_usb::control::{InResponse, OutResponse, Request};
+use embassy_usb::descriptor::{SynchronizationType, UsageType};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use portable_atomic::{AtomicU32, AtomicU8, Ordering};
+
+use crate::control::{clock_get, clock_set_rate, EntityRequest};
+use crate::descriptor::{channel_config, DescriptorWriter, CLOCK_SOURCE_ID};
+use crate::feedback::{FeedbackController, FeedbackFormat};
+use crate::speaker::{descriptors, DESC_ENDPOINT, DESC_INTERFACE};
+use crate::{class, DescriptorError, StreamFormat, Uac2Config, MAX_USB_AUDIO_PACKET};
+
+/// Entity ID of the microphone input terminal
+const MIC_TERMINAL_ID: u8 = 1;
+/// Entity ID of the USB streaming output terminal
+const USB_TERMINAL_ID: u8 = 2;
+
+/// Samples are sent as 16-bit PCM
+const FORMAT: StreamFormat = StreamFormat::PCM16;
+
+/// Scratch space for one interface's descriptor chain
+const DESCRIPTOR_BUF: usize = 64;
+
+/// Capture descriptor builder
+pub struct MicrophoneDescriptor {
+    config: Uac2Config,
+}
+
+impl MicrophoneDescriptor {
+    /// Create a new capture descriptor builder
+    pub fn new(config: Uac2Config) -> Self {
+        Self { config }
+    }
+
+    /// Build the Audio Control interface: interface descriptor, header,
+    /// clock source, input and output terminal
+    pub fn build_control(&self, buf: &mut [u8]) -> Result<usize, DescriptorError> {
+        let mut w = DescriptorWriter::new(buf);
+
+        // Interface descriptor (Audio Control)
+        let d = w.alloc(9)?;
+        d[1] = 4; // bDescriptorType (Interface)
+        d[2] = 0; // bInterfaceNumber
+        d[3] = 0; // bAlternateSetting
+        d[4] = 0; // bNumEndpoints
+        d[5] = 0x01; // bInterfaceClass (Audio)
+        d[6] = 0x01; // bInterfaceSubClass (Audio Control)
+        d[7] = 0x20; // bInterfaceProtocol (UAC2)
+        d[8] = 0; // iInterface
+
+        // AC Interface Header
+        let header = w.position();
+        let d = w.alloc(9)?;
+        d[1] = 0x24; // bDescriptorType (CS_INTERFACE)
+        d[2] = 0x01; // bDescriptorSubtype (HEADER)
+        d[3] = 0x00; // bcdADC low
+        d[4] = 0x02; // bcdADC high (2.0)
+        d[5] = 0x03; // bCategory (Microphone)
+        d[6] = 0; // wTotalLength low (filled in below)
+        d[7] = 0; // wTotalLength high
+        d[8] = 0; // bmControls
+
+        // Clock Source
+        let programmable = self.config.sample_rates.len() > 1;
+        let d = w.alloc(8)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x0A; // bDescriptorSubtype (CLOCK_SOURCE)
+        d[3] = CLOCK_SOURCE_ID; // bClockID
+        d[4] = if programmable { 0x03 } else { 0x01 }; // bmAttributes (internal)
+        d[5] = if programmable { 0x07 } else { 0x05 }; // bmControls (freq, validity)
+        d[6] = 0; // bAssocTerminal
+        d[7] = 0; // iClockSource
+
+        // Input Terminal (Microphone)
+        let channels = self.config.channels;
+        let d = w.alloc(17)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x02; // bDescriptorSubtype (INPUT_TERMINAL)
+        d[3] = MIC_TERMINAL_ID; // bTerminalID
+        d[4] = 0x01; // wTerminalType low (Microphone)
+        d[5] = 0x02; // wTerminalType high
+        d[6] = 0; // bAssocTerminal
+        d[7] = CLOCK_SOURCE_ID; // bCSourceID
+        d[8] = channels; // bNrChannels
+        d[9..13].copy_from_slice(&channel_config(channels).to_le_bytes()); // bmChannelConfig
+        d[13] = 0; // iChannelNames
+        d[14] = 0; // bmControls low
+        d[15] = 0; // bmControls high
+        d[16] = 0; // iTerminal
+
+        // Output Terminal (USB streaming)
+        let d = w.alloc(12)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x03; // bDescriptorSubtype (OUTPUT_TERMINAL)
+        d[3] = USB_TERMINAL_ID; // bTerminalID
+        d[4] = 0x01; // wTerminalType low (USB streaming)
+        d[5] = 0x01; // wTerminalType high
+        d[6] = 0; // bAssocTerminal
+        d[7] = MIC_TERMINAL_ID; // bSourceID
+        d[8] = CLOCK_SOURCE_ID; // bCSourceID
+        d[9] = 0; // bmControls low
+        d[10] = 0; // bmControls high
+        d[11] = 0; // iTerminal
+
+        // The header and everything after it
+        w.patch_total_length(header, header + 6)?;
+        Ok(w.position())
+    }
+
+    /// Build the class-specific and endpoint descriptors of streaming
+    /// alternate setting 1, sending on IN endpoint `ep_addr`
+    pub fn build_alt1(&self, buf: &mut [u8], ep_addr: u8) -> Result<usize, DescriptorError> {
+        let mut w = DescriptorWriter::new(buf);
+
+        // AS Interface descriptor
+        let channels = self.config.channels;
+        let d = w.alloc(16)?;
+        d[1] = 0x24; // CS_INTERFACE
+        d[2] = 0x01; // AS_GENERAL
+        d[3] = USB_TERMINAL_ID; // bTerminalLink
+        d[4] = 0; // bmControls
+        d[5] = 0x01; // bFormatType (Type I)
+        d[6..10].copy_from_slice(&1u32.to_le_bytes()); // bmFormats (PCM)
+        d[10] = channels; // bNrChannels
+        d[11..15].copy_from_slice(&channel_config(channels).to_le_bytes()); // bmChannelConfig
+        d[15] = 0; // iChannelNames
+
+        // Format Type I descriptor
+        let d = w.alloc(6)?;
+        d[1] = 0x24;
+        d[2] = 0x02; // FORMAT_TYPE
+        d[3] = 0x01; // FORMAT_TYPE_I
+        d[4] = FORMAT.subslot_size; // bSubslotSize
+        d[5] = FORMAT.bit_resolution; // bBitResolution
+
+        // Endpoint descriptor
+        let max_packet = self.max_packet_size();
+        let d = w.alloc(7)?;
+        d[1] = 5; // Endpoint
+        d[2] = ep_addr | 0x80; // IN
+        d[3] = 0x05; // Isochronous, Async
+        d[4..6].copy_from_slice(&max_packet.to_le_bytes()); // wMaxPacketSize
+        d[6] = 1; // bInterval (1ms)
+
+        // AS Isochronous Audio Data Endpoint descriptor
+        let d = w.alloc(8)?;
+        d[1] = 0x25; // CS_ENDPOINT
+        d[2] = 0x01; // EP_GENERAL
+        d[3] = 0; // bmAttributes
+        d[4] = 0; // bmControls
+        d[5] = 0; // bLockDelayUnits
+        d[6] = 0; // wLockDelay low
+        d[7] = 0; // wLockDelay high
+
+        Ok(w.position())
+    }
+
+    /// Largest packet: one USB frame at the highest sample rate, plus one
+    /// sample per channel of headroom for the fill level tracking
+    pub fn max_packet_size(&self) -> u16 {
+        let max_rate = self
+            .config
+            .sample_rates
+            .iter()
+            .copied()
+            .max()
+            .unwrap_or(48000);
+        let frames = max_rate.div_ceil(1000) + 1;
+        (frames * self.config.channels as u32 * FORMAT.subslot_size as u32) as u16
+    }
+}
+
+/// Capture statistics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CaptureStats {
+    /// Total packets sent
+    pub packets_sent: u32,
+    /// Total samples sent, silence padding included
+    pub samples_sent: u64,
+    /// Packets padded with silence because the ring ran dry
+    pub underruns: u32,
+}
+
+/// Internal state of the microphone class
+pub struct MicrophoneState<'d> {
+    control: Option<MicControl<'d>>,
+    shared: MicShared,
+}
+
+impl Default for MicrophoneState<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MicrophoneState<'_> {
+    /// Create a new, unregistered state
+    pub const fn new() -> Self {
+        Self {
+            control: None,
+            shared: MicShared {
+                alt_setting: AtomicU8::new(0),
+                sample_rate: AtomicU32::new(0),
+                waker: AtomicWaker::new(),
+            },
+        }
+    }
+}
+
+/// State shared between the control handler and the stream
+struct MicShared {
+    /// Alternate setting selected on the streaming interface
+    alt_setting: AtomicU8,
+    /// Sampling frequency selected on the clock source
+    sample_rate: AtomicU32,
+    /// Wakes the stream on alt setting changes
+    waker: AtomicWaker,
+}
+
+impl MicShared {
+    fn set_alt_setting(&self, alt_setting: u8) {
+        self.alt_setting.store(alt_setting, Ordering::Relaxed);
+        self.waker.wake();
+    }
+}
+
+/// Handles bus events, interface selection and clock requests for the
+/// microphone
+struct MicControl<'d> {
+    shared: &'d MicShared,
+    control_interface: InterfaceNumber,
+    streaming_interface: InterfaceNumber,
+    sample_rates: &'static [u32],
+}
+
+impl Handler for MicControl<'_> {
+    fn reset(&mut self) {
+        self.shared.set_alt_setting(0);
+    }
+
+    fn configured(&mut self, configured: bool) {
+        if !configured {
+            self.shared.set_alt_setting(0);
+        }
+    }
+
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        if iface == self.streaming_interface {
+            self.shared.set_alt_setting(alternate_setting);
+        }
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        let req = EntityRequest::parse(&req, self.control_interface.0)?;
+        let rate = match req.entity {
+            CLOCK_SOURCE_ID => clock_set_rate(&req, data),
+            _ => None,
+        };
+        Some(match rate {
+            Some(rate) if self.sample_rates.contains(&rate) => {
+                self.shared.sample_rate.store(rate, Ordering::Relaxed);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        let req = EntityRequest::parse(&req, self.control_interface.0)?;
+        let rate = self.shared.sample_rate.load(Ordering::Relaxed);
+        let len = match req.entity {
+            CLOCK_SOURCE_ID => clock_get(&req, buf, rate, self.sample_rates),
+            _ => None,
+        };
+        Some(match len {
+            Some(len) => InResponse::Accepted(&buf[..len]),
+            None => InResponse::Rejected,
+        })
+    }
+}
+
+/// USB Audio Class 2.0 microphone
+pub struct Microphone;
+
+impl Microphone {
+    /// Add the microphone function to `builder`
+    ///
+    /// Uses the channel count and sample rates of `config`; the stream is
+    /// always 16-bit.
+    pub fn register<'d, D: Driver<'d>>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut MicrophoneState<'d>,
+        config: &Uac2Config,
+    ) -> MicrophoneStream<'d, D> {
+        let descriptor = MicrophoneDescriptor::new(config.clone());
+        let max_packet = descriptor.max_packet_size();
+        assert!(
+            max_packet as usize <= MAX_USB_AUDIO_PACKET,
+            "capture format exceeds MAX_USB_AUDIO_PACKET"
+        );
+        let mut buf = [0u8; DESCRIPTOR_BUF];
+        let mut func = builder.function(class::AUDIO, 0x00, class::UAC2_PROTOCOL);
+
+        // Audio Control interface
+        let mut iface = func.interface();
+        let control_interface = iface.interface_number();
+        let mut alt = iface.alt_setting(
+            class::AUDIO,
+            class::AUDIO_CONTROL,
+            class::UAC2_PROTOCOL,
+            None,
+        );
+        let len = descriptor
+            .build_control(&mut buf)
+            .expect("DESCRIPTOR_BUF too small for the configuration");
+        for desc in descriptors(&buf[..len]).filter(|desc| desc[1] != DESC_INTERFACE) {
+            alt.descriptor(desc[1], &desc[2..]);
+        }
+
+        // Audio Streaming interface: zero-bandwidth alt 0 and alt 1
+        let mut iface = func.interface();
+        let streaming_interface = iface.interface_number();
+        iface.alt_setting(
+            class::AUDIO,
+            class::AUDIO_STREAMING,
+            class::UAC2_PROTOCOL,
+            None,
+        );
+        let mut alt = iface.alt_setting(
+            class::AUDIO,
+            class::AUDIO_STREAMING,
+            class::UAC2_PROTOCOL,
+            None,
+        );
+        let endpoint = alt.alloc_endpoint_in(EndpointType::Isochronous, None, max_packet, 1);
+        let len = descriptor
+            .build_alt1(&mut buf, endpoint.info().addr.into())
+            .expect("DESCRIPTOR_BUF too small for the configuration");
+        for desc in descriptors(&buf[..len]) {
+            match desc[1] {
+                DESC_ENDPOINT => alt.endpoint_descriptor(
+                    endpoint.info(),
+                    SynchronizationType::Asynchronous,
+                    UsageType::DataEndpoint,
+                    &[],
+                ),
+                descriptor_type => alt.descriptor(descriptor_type, &desc[2..]),
+            }
+        }
+        drop(func);
+
+        let rate = config.sample_rates.first().copied().unwrap_or(48000);
+        state.shared.sample_rate.store(rate, Ordering::Relaxed);
+        state.control = Some(MicControl {
+            shared: &state.shared,
+            control_interface,
+            streaming_interface,
+            sample_rates: config.sample_rates,
+        });
+        builder.handler(state.control.as_mut().unwrap());
+
+        MicrophoneStream {
+            endpoint,
+            shared: &state.shared,
+            channels: config.channels.max(1) as usize,
+            pacer: Pacer::new(rate, config.channels),
+            stats: CaptureStats::default(),
+        }
+    }
+}
+
+/// Sends captured audio to the host
+pub struct MicrophoneStream<'d, D: Driver<'d>> {
+    endpoint: D::EndpointIn,
+    shared: &'d MicShared,
+    channels: usize,
+    pacer: Pacer,
+    stats: CaptureStats,
+}
+
+/// Frames per packet, following the ring fill level
+struct Pacer {
+    controller: FeedbackController,
+    /// Fractional frames carried between packets (Q14)
+    fraction: u32,
+}
+
+impl Pacer {
+    fn new(sample_rate: u32, channels: u8) -> Self {
+        Self {
+            controller: FeedbackController::new(FeedbackFormat::Q10_14, sample_rate, channels),
+            fraction: 0,
+        }
+    }
+
+    fn reset(&mut self, sample_rate: u32) {
+        self.controller.set_sample_rate(sample_rate);
+        self.fraction = 0;
+    }
+
+    /// Frames for the next packet from the ring fill and target levels
+    fn next(&mut self, fill_samples: usize, target_samples: usize) -> usize {
+        // Frames per millisecond in 10.14 fixed point
+        self.fraction += self.controller.update_drain(fill_samples, target_samples);
+        let frames = self.fraction >> 14;
+        self.fraction &= (1 << 14) - 1;
+        frames as usize
+    }
+}
+
+impl<'d, D: Driver<'d>> MicrophoneStream<'d, D> {
+    /// Wait for the host to open the stream
+    pub async fn wait_connection(&mut self) {
+        poll_fn(|cx| {
+            self.shared.waker.register(cx.waker());
+            if self.shared.alt_setting.load(Ordering::Relaxed) == 1 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await;
+        self.endpoint.wait_enabled().await;
+        self.pacer.reset(self.sample_rate());
+    }
+
+    /// Sampling frequency selected by the host, in Hz
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Send about one millisecond of audio from `ring`
+    ///
+    /// The frame count follows the sample rate (44 or 45 frames at
+    /// 44.1 kHz) and is nudged up or down to keep `ring` half full. Only
+    /// whole frames are taken; if the ring runs dry the packet is padded
+    /// with silence. Returns the number of samples sent, or
+    /// `EndpointError::Disabled` once the host closes the stream.
+    pub async fn send<const N: usize>(
+        &mut self,
+        ring: &RingBuffer<i16, N>,
+    ) -> Result<usize, EndpointError> {
+        let rate = self.sample_rate();
+        if rate != self.pacer.controller.sample_rate() {
+            self.pacer.reset(rate);
+        }
+        let fill = ring.available_read();
+        let frames = self
+            .pacer
+            .next(fill, N / 2)
+            .min(MAX_USB_AUDIO_PACKET / 2 / self.channels);
+        let count = frames * self.channels;
+
+        let mut samples = [0i16; MAX_USB_AUDIO_PACKET / 2];
+        let whole = fill / self.channels * self.channels;
+        if ring.read(&mut samples[..count.min(whole)]) < count {
+            self.stats.underruns = self.stats.underruns.wrapping_add(1);
+        }
+        let mut packet = [0u8; MAX_USB_AUDIO_PACKET];
+        for (bytes, sample) in packet.chunks_exact_mut(2).zip(&samples[..count]) {
+            bytes.copy_from_slice(&sample.to_le_bytes());
+        }
+        self.endpoint.write(&packet[..count * 2]).await?;
+
+        self.stats.packets_sent = self.stats.packets_sent.wrapping_add(1);
+        self.stats.samples_sent = self.stats.samples_sent.wrapping_add(count as u64);
+        Ok(count)
+    }
+
+    /// Send audio from `ring` forever, idling while the host has the
+    /// stream closed
+    pub async fn run<const N: usize>(&mut self, ring: &RingBuffer<i16, N>) -> ! {
+        loop {
+            self.wait_connection().await;
+            while self.send(ring).await.is_ok() {}
+        }
+    }
+
+    /// Capture statistics
+    pub fn stats(&self) -> CaptureStats {
+        self.stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{setup, usb_config, Buffers, MockUsb};
+    use crate::{parse, Speaker, SpeakerState};
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+
+    #[test]
+    fn test_control_descriptors() {
+        let mut buf = [0u8; DESCRIPTOR_BUF];
+        let descriptor = MicrophoneDescriptor::new(Uac2Config::default());
+        let len = descriptor.build_control(&mut buf).unwrap();
+        assert_eq!(len, 9 + 9 + 8 + 17 + 12);
+        assert_eq!(parse::check(&buf[..len]), Ok(5));
+        // wTotalLength covers everything after the interface descriptor
+        assert_eq!(buf[9 + 6..9 + 8], [46, 0]);
+        // Microphone -> USB streaming
+        assert_eq!(buf[26 + 4..26 + 6], [0x01, 0x02]);
+        assert_eq!(buf[43 + 7], MIC_TERMINAL_ID);
+        assert_eq!(descriptor.max_packet_size(), 196);
+    }
+
+    #[test]
+    fn test_packet_size_follows_fill() {
+        let mut pacer = Pacer::new(48000, 2);
+        // Half full: the nominal 48 frames
+        assert!((0..100).all(|_| pacer.next(128, 128) == 48));
+        // Fuller ring: drain up to 1000 ppm faster
+        let frames: usize = (0..1000).map(|_| pacer.next(256, 128)).sum();
+        assert!((48_040..=48_048).contains(&frames), "{frames} frames");
+
+        pacer.reset(44100);
+        let frames: usize = (0..1000).map(|_| pacer.next(128, 128)).sum();
+        assert!(frames.abs_diff(44_100) <= 1);
+    }
+
+    #[test]
+    fn test_streams_to_host() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut speaker_state = SpeakerState::new();
+        let mut mic_state = MicrophoneState::new();
+        let ring: RingBuffer<i16, 256> = RingBuffer::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let _speaker = Speaker::register(&mut builder, &mut speaker_state, &Uac2Config::default());
+        let mut mic = Microphone::register(&mut builder, &mut mic_state, &Uac2Config::default());
+        let mut device = builder.build();
+
+        // Half a frame past 44 stereo frames
+        let samples: [i16; 89] = core::array::from_fn(|i| i as i16 - 45);
+        ring.write(&samples);
+        let host = async {
+            let config = usb.enumerate().await;
+            assert!(parse::check(&config).is_ok());
+            // Audio streaming interface 3, isochronous async IN after the
+            // speaker's four feedback endpoints
+            let endpoint = descriptors(&config)
+                .find(|desc| desc[1] == DESC_ENDPOINT && desc[2] == 0x85)
+                .unwrap();
+            assert_eq!(endpoint[3..7], [0x05, 196, 0, 1]);
+
+            // 44.1 kHz by default, switched by the host on clock source 3
+            // of interface 2
+            let get_rate = setup(0xA1, 0x01, 0x0100, 0x0302, 4);
+            assert_eq!(usb.control_in(get_rate).await.unwrap(), [0x44, 0xAC, 0, 0]);
+            let set_rate = setup(0x21, 0x01, 0x0100, 0x0302, 4);
+            assert!(usb
+                .control_out(set_rate, &32000u32.to_le_bytes())
+                .await
+                .is_err());
+
+            usb.set_interface(3, 1).await.unwrap();
+            let first = usb.receive(0x85).await;
+            let second = usb.receive(0x85).await;
+            usb.control_out(set_rate, &48000u32.to_le_bytes())
+                .await
+                .unwrap();
+            (first, second)
+        };
+        let send = async {
+            mic.wait_connection().await;
+            for _ in 0..2 {
+                mic.send(&ring).await.unwrap();
+            }
+        };
+
+        let (first, second) = match block_on(select(device.run(), join(host, send))) {
+            Either::First(_) => unreachable!(),
+            Either::Second((packets, _)) => packets,
+        };
+        // 44 stereo frames, then the ring has no whole frame left and the
+        // second packet is silence; the odd sample stays behind
+        assert_eq!(first.len(), 44 * 2 * 2);
+        assert_eq!(first[..4], [0xD3, 0xFF, 0xD4, 0xFF]);
+        assert_eq!(second.len(), 44 * 2 * 2);
+        assert!(second.iter().all(|&b| b == 0));
+        assert_eq!(ring.available_read(), 1);
+        assert_eq!(mic.stats().packets_sent, 2);
+        assert_eq!(mic.stats().underruns, 1);
+        assert_eq!(mic.sample_rate(), 48000);
+    }
+}
@@ -0,0 +1,498 @@
+//! Host-side mock of an `embassy-usb` driver
+//!
+//! Lets tests play the USB host: queue bus events and control transfers,
+//! send isochronous packets to OUT endpoints and collect what the device
+//! writes to IN endpoints. Futures return `Pending` until the other side
+//! has acted, so run the device and the test script together under
+//! `embassy_futures::block_on`, which polls continuously.
+
+use core::cell::RefCell;
+use core::future::poll_fn;
+use core::task::Poll;
+
+use embassy_usb::driver::{
+    Bus, ControlPipe, Direction, Driver, Endpoint, EndpointAddress, EndpointAllocError,
+    EndpointError, EndpointIn, EndpointInfo, EndpointOut, EndpointType, Event, Unsupported,
+};
+use embassy_usb::Config;
+use heapless::{Deque, Vec};
+
+/// Largest packet the mock carries on any endpoint
+pub const MOCK_PACKET: usize = 1024;
+
+/// Largest control transfer data stage
+pub const MOCK_CONTROL: usize = 512;
+
+const MAX_ENDPOINTS: usize = 16;
+
+/// Control transfer stalled by the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stalled;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Accepted,
+    Rejected,
+}
+
+struct Inner {
+    events: Deque<Event, 4>,
+    setup: Option<[u8; 8]>,
+    out_data: Vec<u8, MOCK_CONTROL>,
+    out_pos: usize,
+    response: Vec<u8, MOCK_CONTROL>,
+    outcome: Option<Outcome>,
+    address: u8,
+    /// Enabled flags for OUT (index 0) and IN (index 1) endpoints
+    enabled: [[bool; MAX_ENDPOINTS]; 2],
+    stalled: [[bool; MAX_ENDPOINTS]; 2],
+    to_device: Deque<(u8, Vec<u8, MOCK_PACKET>), 8>,
+    from_device: Deque<(u8, Vec<u8, MOCK_PACKET>), 8>,
+}
+
+/// Shared state between the mock driver and the test acting as host
+pub struct MockUsb {
+    inner: RefCell<Inner>,
+}
+
+impl MockUsb {
+    /// Create a powered-down bus
+    pub fn new() -> Self {
+        Self {
+            inner: RefCell::new(Inner {
+                events: Deque::new(),
+                setup: None,
+                out_data: Vec::new(),
+                out_pos: 0,
+                response: Vec::new(),
+                outcome: None,
+                address: 0,
+                enabled: [[false; MAX_ENDPOINTS]; 2],
+                stalled: [[false; MAX_ENDPOINTS]; 2],
+                to_device: Deque::new(),
+                from_device: Deque::new(),
+            }),
+        }
+    }
+
+    /// Driver to hand to `embassy_usb::Builder`
+    pub fn driver(&self) -> MockDriver<'_> {
+        MockDriver {
+            usb: self,
+            next_out: 1,
+            next_in: 1,
+        }
+    }
+
+    /// Queue a bus event
+    pub fn event(&self, event: Event) {
+        self.inner.borrow_mut().events.push_back(event).unwrap();
+    }
+
+    /// Address assigned by SET_ADDRESS
+    pub fn address(&self) -> u8 {
+        self.inner.borrow().address
+    }
+
+    /// Check if the device has enabled an endpoint
+    pub fn is_enabled(&self, addr: u8) -> bool {
+        let addr = EndpointAddress::from(addr);
+        self.inner.borrow().enabled[addr.is_in() as usize][addr.index()]
+    }
+
+    /// Run a device-to-host control transfer
+    pub async fn control_in(&self, setup: [u8; 8]) -> Result<Vec<u8, MOCK_CONTROL>, Stalled> {
+        self.start_control(setup, &[]);
+        match self.finish_control().await {
+            Outcome::Accepted => Ok(self.inner.borrow().response.clone()),
+            Outcome::Rejected => Err(Stalled),
+        }
+    }
+
+    /// Run a host-to-device control transfer
+    pub async fn control_out(&self, setup: [u8; 8], data: &[u8]) -> Result<(), Stalled> {
+        self.start_control(setup, data);
+        match self.finish_control().await {
+            Outcome::Accepted => Ok(()),
+            Outcome::Rejected => Err(Stalled),
+        }
+    }
+
+    /// Power up, reset, address and configure the device
+    ///
+    /// Returns the configuration descriptor.
+    pub async fn enumerate(&self) -> Vec<u8, MOCK_CONTROL> {
+        self.event(Event::PowerDetected);
+        self.event(Event::Reset);
+        self.control_out(setup(0x00, 0x05, 7, 0, 0), &[])
+            .await
+            .unwrap();
+        let config = self
+            .control_in(setup(0x80, 0x06, 0x0200, 0, MOCK_CONTROL as u16))
+            .await
+            .unwrap();
+        self.control_out(setup(0x00, 0x09, 1, 0, 0), &[])
+            .await
+            .unwrap();
+        config
+    }
+
+    /// Select an alternate setting of an interface
+    pub async fn set_interface(&self, interface: u8, alt: u8) -> Result<(), Stalled> {
+        self.control_out(setup(0x01, 0x0B, alt as u16, interface as u16, 0), &[])
+            .await
+    }
+
+    /// Send an isochronous packet to an OUT endpoint, waiting for room
+    pub async fn send(&self, addr: u8, data: &[u8]) {
+        let packet = (addr, Vec::from_slice(data).unwrap());
+        let mut packet = Some(packet);
+        poll_fn(|_| {
+            let mut inner = self.inner.borrow_mut();
+            if inner.to_device.is_full() {
+                return Poll::Pending;
+            }
+            inner.to_device.push_back(packet.take().unwrap()).ok();
+            Poll::Ready(())
+        })
+        .await
+    }
+
+    /// Wait until the device has consumed every packet sent to it
+    pub async fn flush(&self) {
+        poll_fn(|_| {
+            if self.inner.borrow().to_device.is_empty() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Take the next packet the device wrote to an IN endpoint
+    pub async fn receive(&self, addr: u8) -> Vec<u8, MOCK_PACKET> {
+        poll_fn(|_| {
+            let mut inner = self.inner.borrow_mut();
+            match inner.from_device.front() {
+                Some((ep, _)) if *ep == addr => {
+                    Poll::Ready(inner.from_device.pop_front().unwrap().1)
+                }
+                _ => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    fn start_control(&self, setup: [u8; 8], data: &[u8]) {
+        let mut inner = self.inner.borrow_mut();
+        inner.setup = Some(setup);
+        inner.out_data = Vec::from_slice(data).unwrap();
+        inner.out_pos = 0;
+        inner.response.clear();
+        inner.outcome = None;
+    }
+
+    async fn finish_control(&self) -> Outcome {
+        poll_fn(|_| match self.inner.borrow_mut().outcome.take() {
+            Some(outcome) => Poll::Ready(outcome),
+            None => Poll::Pending,
+        })
+        .await
+    }
+}
+
+/// Build a SETUP packet
+pub fn setup(request_type: u8, request: u8, value: u16, index: u16, length: u16) -> [u8; 8] {
+    let [value_lo, value_hi] = value.to_le_bytes();
+    let [index_lo, index_hi] = index.to_le_bytes();
+    let [length_lo, length_hi] = length.to_le_bytes();
+    [
+        request_type,
+        request,
+        value_lo,
+        value_hi,
+        index_lo,
+        index_hi,
+        length_lo,
+        length_hi,
+    ]
+}
+
+/// Descriptor and control buffers for `embassy_usb::Builder`
+pub struct Buffers {
+    pub config: [u8; 512],
+    pub bos: [u8; 64],
+    pub msos: [u8; 0],
+    pub control: [u8; 64],
+}
+
+impl Buffers {
+    pub fn new() -> Self {
+        Self {
+            config: [0; 512],
+            bos: [0; 64],
+            msos: [],
+            control: [0; 64],
+        }
+    }
+}
+
+/// Composite device configuration the application uses: functions grouped
+/// by IADs under the 0xEF/0x02/0x01 device class
+pub fn usb_config() -> Config<'static> {
+    let mut config = Config::new(0x1209, 0xA2D0);
+    config.composite_with_iads = true;
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config
+}
+
+/// Mock `embassy-usb` driver
+pub struct MockDriver<'d> {
+    usb: &'d MockUsb,
+    next_out: u8,
+    next_in: u8,
+}
+
+impl<'d> Driver<'d> for MockDriver<'d> {
+    type EndpointOut = MockEndpoint<'d>;
+    type EndpointIn = MockEndpoint<'d>;
+    type ControlPipe = MockControl<'d>;
+    type Bus = MockBus<'d>;
+
+    fn alloc_endpoint_out(
+        &mut self,
+        ep_type: EndpointType,
+        ep_addr: Option<EndpointAddress>,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointOut, EndpointAllocError> {
+        let addr = ep_addr.unwrap_or_else(|| {
+            self.next_out += 1;
+            EndpointAddress::from_parts(self.next_out as usize - 1, Direction::Out)
+        });
+        self.endpoint(addr, ep_type, max_packet_size, interval_ms)
+    }
+
+    fn alloc_endpoint_in(
+        &mut self,
+        ep_type: EndpointType,
+        ep_addr: Option<EndpointAddress>,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<Self::EndpointIn, EndpointAllocError> {
+        let addr = ep_addr.unwrap_or_else(|| {
+            self.next_in += 1;
+            EndpointAddress::from_parts(self.next_in as usize - 1, Direction::In)
+        });
+        self.endpoint(addr, ep_type, max_packet_size, interval_ms)
+    }
+
+    fn start(self, control_max_packet_size: u16) -> (Self::Bus, Self::ControlPipe) {
+        (
+            MockBus { usb: self.usb },
+            MockControl {
+                usb: self.usb,
+                max_packet_size: control_max_packet_size as usize,
+            },
+        )
+    }
+}
+
+impl<'d> MockDriver<'d> {
+    fn endpoint(
+        &self,
+        addr: EndpointAddress,
+        ep_type: EndpointType,
+        max_packet_size: u16,
+        interval_ms: u8,
+    ) -> Result<MockEndpoint<'d>, EndpointAllocError> {
+        if addr.index() >= MAX_ENDPOINTS || max_packet_size as usize > MOCK_PACKET {
+            return Err(EndpointAllocError);
+        }
+        Ok(MockEndpoint {
+            usb: self.usb,
+            info: EndpointInfo {
+                addr,
+                ep_type,
+                max_packet_size,
+                interval_ms,
+            },
+        })
+    }
+}
+
+/// Mock bus
+pub struct MockBus<'d> {
+    usb: &'d MockUsb,
+}
+
+impl Bus for MockBus<'_> {
+    async fn enable(&mut self) {}
+
+    async fn disable(&mut self) {}
+
+    async fn poll(&mut self) -> Event {
+        poll_fn(|_| match self.usb.inner.borrow_mut().events.pop_front() {
+            Some(event) => Poll::Ready(event),
+            None => Poll::Pending,
+        })
+        .await
+    }
+
+    fn endpoint_set_enabled(&mut self, ep_addr: EndpointAddress, enabled: bool) {
+        self.usb.inner.borrow_mut().enabled[ep_addr.is_in() as usize][ep_addr.index()] = enabled;
+    }
+
+    fn endpoint_set_stalled(&mut self, ep_addr: EndpointAddress, stalled: bool) {
+        self.usb.inner.borrow_mut().stalled[ep_addr.is_in() as usize][ep_addr.index()] = stalled;
+    }
+
+    fn endpoint_is_stalled(&mut self, ep_addr: EndpointAddress) -> bool {
+        self.usb.inner.borrow().stalled[ep_addr.is_in() as usize][ep_addr.index()]
+    }
+
+    async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+        Err(Unsupported)
+    }
+}
+
+/// Mock endpoint (either direction)
+pub struct MockEndpoint<'d> {
+    usb: &'d MockUsb,
+    info: EndpointInfo,
+}
+
+impl MockEndpoint<'_> {
+    fn enabled(&self) -> bool {
+        let addr = self.info.addr;
+        self.usb.inner.borrow().enabled[addr.is_in() as usize][addr.index()]
+    }
+}
+
+impl Endpoint for MockEndpoint<'_> {
+    fn info(&self) -> &EndpointInfo {
+        &self.info
+    }
+
+    async fn wait_enabled(&mut self) {
+        poll_fn(|_| {
+            if self.enabled() {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+}
+
+impl EndpointOut for MockEndpoint<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, EndpointError> {
+        let addr = u8::from(self.info.addr);
+        poll_fn(|_| {
+            if !self.enabled() {
+                return Poll::Ready(Err(EndpointError::Disabled));
+            }
+            let mut inner = self.usb.inner.borrow_mut();
+            match inner.to_device.front() {
+                Some((ep, _)) if *ep == addr => {
+                    let (_, packet) = inner.to_device.pop_front().unwrap();
+                    if packet.len() > buf.len() {
+                        return Poll::Ready(Err(EndpointError::BufferOverflow));
+                    }
+                    buf[..packet.len()].copy_from_slice(&packet);
+                    Poll::Ready(Ok(packet.len()))
+                }
+                _ => Poll::Pending,
+            }
+        })
+        .await
+    }
+}
+
+impl EndpointIn for MockEndpoint<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<(), EndpointError> {
+        let addr = u8::from(self.info.addr);
+        poll_fn(|_| {
+            if !self.enabled() {
+                return Poll::Ready(Err(EndpointError::Disabled));
+            }
+            let mut inner = self.usb.inner.borrow_mut();
+            if inner.from_device.is_full() {
+                return Poll::Pending;
+            }
+            let packet = Vec::from_slice(buf).map_err(|_| EndpointError::BufferOverflow)?;
+            inner.from_device.push_back((addr, packet)).ok();
+            Poll::Ready(Ok(()))
+        })
+        .await
+    }
+}
+
+/// Mock control pipe
+pub struct MockControl<'d> {
+    usb: &'d MockUsb,
+    max_packet_size: usize,
+}
+
+impl ControlPipe for MockControl<'_> {
+    fn max_packet_size(&self) -> usize {
+        self.max_packet_size
+    }
+
+    async fn setup(&mut self) -> [u8; 8] {
+        poll_fn(|_| match self.usb.inner.borrow_mut().setup.take() {
+            Some(setup) => Poll::Ready(setup),
+            None => Poll::Pending,
+        })
+        .await
+    }
+
+    async fn data_out(
+        &mut self,
+        buf: &mut [u8],
+        _first: bool,
+        _last: bool,
+    ) -> Result<usize, EndpointError> {
+        let mut inner = self.usb.inner.borrow_mut();
+        let start = inner.out_pos;
+        let len = (inner.out_data.len() - start).min(buf.len());
+        buf[..len].copy_from_slice(&inner.out_data[start..start + len]);
+        inner.out_pos += len;
+        Ok(len)
+    }
+
+    async fn data_in(
+        &mut self,
+        data: &[u8],
+        _first: bool,
+        last: bool,
+    ) -> Result<(), EndpointError> {
+        let mut inner = self.usb.inner.borrow_mut();
+        inner
+            .response
+            .extend_from_slice(data)
+            .map_err(|_| EndpointError::BufferOverflow)?;
+        if last {
+            inner.outcome = Some(Outcome::Accepted);
+        }
+        Ok(())
+    }
+
+    async fn accept(&mut self) {
+        self.usb.inner.borrow_mut().outcome = Some(Outcome::Accepted);
+    }
+
+    async fn reject(&mut self) {
+        self.usb.inner.borrow_mut().outcome = Some(Outcome::Rejected);
+    }
+
+    async fn accept_set_address(&mut self, addr: u8) {
+        let mut inner = self.usb.inner.borrow_mut();
+        inner.address = addr;
+        inner.outcome = Some(Outcome::Accepted);
+    }
+}
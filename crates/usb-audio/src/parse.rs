@@ -0,0 +1,245 @@
+//! Descriptor chain parser for tests
+//!
+//! Walks a chain as a host would and checks that it is well-formed: every
+//! `bLength` fits the chain and matches what the descriptor type needs,
+//! audio `wTotalLength` fields cover exactly the descriptors they claim,
+//! and channel counts agree with their spatial locations. Class-specific
+//! descriptors are only checked inside audio interfaces.
+
+use crate::class;
+
+const DESC_CONFIGURATION: u8 = 0x02;
+const DESC_INTERFACE: u8 = 0x04;
+const DESC_ENDPOINT: u8 = 0x05;
+const DESC_IAD: u8 = 0x0B;
+const DESC_CS_INTERFACE: u8 = 0x24;
+const DESC_CS_ENDPOINT: u8 = 0x25;
+
+/// Chain defect, with the offset of the offending descriptor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    /// `bLength` below 2 or past the end of the chain
+    Truncated(usize),
+    /// `bLength` doesn't match the descriptor's contents
+    Length { at: usize, expected: usize },
+    /// `wTotalLength` doesn't match the descriptors it covers
+    TotalLength { at: usize, expected: usize },
+    /// `bNrChannels` doesn't match the bits set in `bmChannelConfig`
+    ChannelConfig(usize),
+}
+
+/// Interface the class-specific descriptors belong to
+#[derive(Clone, Copy)]
+struct Interface {
+    class: u8,
+    subclass: u8,
+    protocol: u8,
+}
+
+impl Interface {
+    fn audio(&self, subclass: u8) -> bool {
+        self.class == class::AUDIO && self.subclass == subclass
+    }
+
+    fn uac1(&self) -> bool {
+        self.protocol == class::UAC1_PROTOCOL
+    }
+}
+
+/// Check a chain starting outside any interface, e.g. a configuration
+/// descriptor; returns the number of descriptors
+pub fn check(chain: &[u8]) -> Result<usize, ParseError> {
+    walk(chain, None)
+}
+
+/// Check a chain belonging to an audio interface of `subclass` and
+/// `protocol`, such as a builder's class-specific descriptors
+pub fn check_in(chain: &[u8], subclass: u8, protocol: u8) -> Result<usize, ParseError> {
+    let interface = Interface {
+        class: class::AUDIO,
+        subclass,
+        protocol,
+    };
+    walk(chain, Some(interface))
+}
+
+fn le16(desc: &[u8], at: usize) -> usize {
+    u16::from_le_bytes([desc[at], desc[at + 1]]) as usize
+}
+
+fn le32(desc: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([desc[at], desc[at + 1], desc[at + 2], desc[at + 3]])
+}
+
+fn walk(chain: &[u8], mut interface: Option<Interface>) -> Result<usize, ParseError> {
+    // Channels of the last input terminal, which feeds the feature unit
+    let mut channels = 0;
+    let mut count = 0;
+    let mut pos = 0;
+    while pos < chain.len() {
+        let len = chain[pos] as usize;
+        if len < 2 || pos + len > chain.len() {
+            return Err(ParseError::Truncated(pos));
+        }
+        let desc = &chain[pos..pos + len];
+        let expect = |expected: usize| {
+            if len == expected {
+                Ok(())
+            } else {
+                Err(ParseError::Length { at: pos, expected })
+            }
+        };
+        let channel_config = |nr: u8, config: u32| {
+            if config == 0 || config.count_ones() == nr as u32 {
+                Ok(())
+            } else {
+                Err(ParseError::ChannelConfig(pos))
+            }
+        };
+
+        match (desc[1], interface) {
+            (DESC_CONFIGURATION, _) => {
+                expect(9)?;
+                if le16(desc, 2) != chain.len() - pos {
+                    return Err(ParseError::TotalLength {
+                        at: pos,
+                        expected: chain.len() - pos,
+                    });
+                }
+            }
+            (DESC_IAD, _) => expect(8)?,
+            (DESC_INTERFACE, _) => {
+                expect(9)?;
+                interface = Some(Interface {
+                    class: desc[5],
+                    subclass: desc[6],
+                    protocol: desc[7],
+                });
+            }
+            (DESC_ENDPOINT, Some(iface)) if iface.audio(class::AUDIO_STREAMING) && iface.uac1() => {
+                expect(9)?
+            }
+            (DESC_ENDPOINT, _) => expect(7)?,
+            (DESC_CS_INTERFACE, Some(iface)) if iface.audio(class::AUDIO_CONTROL) => {
+                let uac1 = iface.uac1();
+                match desc.get(2) {
+                    // Header: the total covers it and the class-specific
+                    // descriptors that follow
+                    Some(0x01) => {
+                        let field = if uac1 {
+                            expect(8 + *desc.get(7).unwrap_or(&0) as usize)?;
+                            5
+                        } else {
+                            expect(9)?;
+                            6
+                        };
+                        let mut covered = 0;
+                        while let Some(&next_len) = chain.get(pos + covered) {
+                            if chain.get(pos + covered + 1) != Some(&DESC_CS_INTERFACE)
+                                || next_len < 2
+                            {
+                                break;
+                            }
+                            covered += next_len as usize;
+                        }
+                        if le16(desc, field) != covered {
+                            return Err(ParseError::TotalLength {
+                                at: pos,
+                                expected: covered,
+                            });
+                        }
+                    }
+                    // Input terminal
+                    Some(0x02) if uac1 => {
+                        expect(12)?;
+                        channels = desc[7];
+                        channel_config(channels, le16(desc, 8) as u32)?;
+                    }
+                    Some(0x02) => {
+                        expect(17)?;
+                        channels = desc[8];
+                        channel_config(channels, le32(desc, 9))?;
+                    }
+                    // Output terminal
+                    Some(0x03) => expect(if uac1 { 9 } else { 12 })?,
+                    // Feature unit: controls for the master and each channel
+                    Some(0x06) if uac1 => {
+                        expect(7 + (channels as usize + 1) * *desc.get(5).unwrap_or(&0) as usize)?
+                    }
+                    Some(0x06) => expect(6 + (channels as usize + 1) * 4)?,
+                    // Clock source
+                    Some(0x0A) => expect(8)?,
+                    _ => {}
+                }
+            }
+            (DESC_CS_INTERFACE, Some(iface)) if iface.audio(class::AUDIO_STREAMING) => {
+                match (desc.get(2), iface.uac1()) {
+                    // AS_GENERAL
+                    (Some(0x01), true) => expect(7)?,
+                    (Some(0x01), false) => {
+                        expect(16)?;
+                        channel_config(desc[10], le32(desc, 11))?;
+                    }
+                    // FORMAT_TYPE: UAC1 lists discrete rates, or a range
+                    (Some(0x02), true) => {
+                        let rates = match *desc.get(7).unwrap_or(&0) {
+                            0 => 2,
+                            n => n as usize,
+                        };
+                        expect(8 + rates * 3)?;
+                    }
+                    (Some(0x02), false) => expect(6)?,
+                    _ => {}
+                }
+            }
+            (DESC_CS_ENDPOINT, Some(iface)) if iface.audio(class::AUDIO_STREAMING) => {
+                expect(if iface.uac1() { 7 } else { 8 })?
+            }
+            _ => {}
+        }
+
+        pos += len;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AudioControlDescriptor, Uac2Config};
+
+    #[test]
+    fn test_rejects_malformed_chains() {
+        let mut buf = [0u8; 128];
+        let len = AudioControlDescriptor::new(Uac2Config::default())
+            .build(&mut buf)
+            .unwrap();
+        assert_eq!(check(&buf[..len]), Ok(6));
+        assert_eq!(check(&buf[..len - 1]), Err(ParseError::Truncated(61)));
+
+        let mut bad = buf;
+        bad[15] += 1;
+        assert_eq!(
+            check(&bad[..len]),
+            Err(ParseError::TotalLength {
+                at: 9,
+                expected: 64
+            })
+        );
+        let mut bad = buf;
+        bad[35] = 0x07;
+        assert_eq!(check(&bad[..len]), Err(ParseError::ChannelConfig(26)));
+        // Output terminal a byte short, with the total to match
+        let mut bad = buf;
+        bad[15] -= 1;
+        bad[61] -= 1;
+        assert_eq!(
+            check(&bad[..len - 1]),
+            Err(ParseError::Length {
+                at: 61,
+                expected: 12
+            })
+        );
+    }
+}
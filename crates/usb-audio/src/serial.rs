@@ -0,0 +1,140 @@
+//! CDC-ACM serial port for the command console
+//!
+//! Adds a virtual serial port to the composite device next to the audio
+//! function. [`SerialConsole`] moves bytes only; the command shell lives
+//! in the application.
+
+use embassy_usb::class::cdc_acm::{self, CdcAcmClass};
+use embassy_usb::driver::{Driver, EndpointError};
+use embassy_usb::Builder;
+
+/// Bulk endpoint packet size
+pub const SERIAL_PACKET: usize = 64;
+
+/// Storage for [`SerialConsole`], which must outlive the USB device
+pub struct SerialConsoleState<'d> {
+    cdc: cdc_acm::State<'d>,
+}
+
+impl SerialConsoleState<'_> {
+    /// Create the state
+    pub const fn new() -> Self {
+        Self {
+            cdc: cdc_acm::State::new(),
+        }
+    }
+}
+
+impl Default for SerialConsoleState<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// CDC-ACM serial port
+pub struct SerialConsole<'d, D: Driver<'d>> {
+    class: CdcAcmClass<'d, D>,
+}
+
+impl<'d, D: Driver<'d>> SerialConsole<'d, D> {
+    /// Add the serial port's interfaces to `builder`
+    pub fn register(builder: &mut Builder<'d, D>, state: &'d mut SerialConsoleState<'d>) -> Self {
+        Self {
+            class: CdcAcmClass::new(builder, &mut state.cdc, SERIAL_PACKET as u16),
+        }
+    }
+
+    /// Wait for the host to configure the device
+    pub async fn wait_connection(&mut self) {
+        self.class.wait_connection().await;
+    }
+
+    /// Check if a terminal has the port open (DTR set)
+    pub fn is_open(&self) -> bool {
+        self.class.dtr()
+    }
+
+    /// Receive the next packet of input into `buf`
+    pub async fn read(&mut self, buf: &mut [u8; SERIAL_PACKET]) -> Result<usize, EndpointError> {
+        self.class.read_packet(buf).await
+    }
+
+    /// Send `data`, split into packets
+    ///
+    /// A transfer that ends on a full packet is closed with a zero-length
+    /// packet so the host doesn't wait for more.
+    pub async fn write(&mut self, data: &[u8]) -> Result<(), EndpointError> {
+        for chunk in data.chunks(SERIAL_PACKET) {
+            self.class.write_packet(chunk).await?;
+        }
+        if !data.is_empty() && data.len() % SERIAL_PACKET == 0 {
+            self.class.write_packet(&[]).await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{setup, usb_config, Buffers, MockUsb};
+    use crate::{parse, Speaker, SpeakerState, Uac2Config};
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+
+    #[test]
+    fn test_console_round_trip() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut speaker_state = SpeakerState::new();
+        let mut serial_state = SerialConsoleState::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let _speaker = Speaker::register(&mut builder, &mut speaker_state, &Uac2Config::default());
+        let mut serial = SerialConsole::register(&mut builder, &mut serial_state);
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            assert!(parse::check(&config).is_ok());
+            // Communications interface after the two audio interfaces
+            let mut pos = 0;
+            while config[pos + 1] != 0x04 || config[pos + 5] != 0x02 {
+                pos += config[pos] as usize;
+            }
+            assert_eq!(config[pos + 2], 2);
+
+            // Open the port: SET_CONTROL_LINE_STATE with DTR
+            let open = setup(0x21, 0x22, 0x0001, 2, 0);
+            usb.control_out(open, &[]).await.unwrap();
+            // Speaker's OUT 1 to 4 and IN 1 to 4, then the notification
+            // endpoint takes IN 5
+            usb.send(0x05, b"status\r").await;
+            assert_eq!(usb.receive(0x86).await, [b'x'; SERIAL_PACKET]);
+            assert_eq!(usb.receive(0x86).await, [b'x'; 3]);
+            assert_eq!(usb.receive(0x86).await, [b'y'; SERIAL_PACKET]);
+            assert_eq!(usb.receive(0x86).await, []);
+        };
+        let console = async {
+            serial.wait_connection().await;
+            let mut buf = [0; SERIAL_PACKET];
+            let len = serial.read(&mut buf).await.unwrap();
+            assert_eq!(&buf[..len], b"status\r");
+            assert!(serial.is_open());
+            serial.write(&[b'x'; SERIAL_PACKET + 3]).await.unwrap();
+            serial.write(&[b'y'; SERIAL_PACKET]).await.unwrap();
+        };
+
+        if let Either::First(_) = block_on(select(device.run(), join(host, console))) {
+            unreachable!()
+        }
+    }
+}
@@ -0,0 +1,1396 @@
+//! UAC2 speaker class for `embassy-usb`
+//!
+//! Registers the Audio Control and Audio Streaming interfaces produced by
+//! [`AudioControlDescriptor`] and [`AudioStreamingDescriptor`] with an
+//! `embassy_usb::Builder`. `embassy-usb` writes the standard interface and
+//! endpoint descriptors itself, so only the class-specific ones are copied
+//! from the builders' output; endpoints are allocated where the builders
+//! declare them.
+//!
+//! The Audio Control interface answers the clock source's sampling
+//! frequency requests, so the host can switch between the configured
+//! sample rates, and the feature unit's volume and mute requests, which
+//! give the device a volume slider. [`ControlMonitor`] reports these
+//! settings to the application as [`ControlEvent`]s.
+//!
+//! With [`AudioProtocol::Uac1`] the same function is described with the
+//! [`Uac1ControlDescriptor`] and [`Uac1StreamingDescriptor`] builders
+//! instead; the sample rate is then set on the data endpoint, and the
+//! streaming and feedback paths are unchanged.
+//!
+//! The device must be built with `Config::composite_with_iads` set (and the
+//! matching IAD device class) so the two interfaces are grouped into one
+//! audio function.
+
+use core::future::poll_fn;
+use core::task::Poll;
+
+use audio_pipeline::{FillState, RingBuffer};
+use embassy_sync::waitqueue::AtomicWaker;
+use embassy_usb::control::{InResponse, OutResponse, Recipient, Request, RequestType};
+use embassy_usb::descriptor::{SynchronizationType, UsageType};
+use embassy_usb::driver::{Driver, Endpoint, EndpointError, EndpointIn, EndpointOut, EndpointType};
+use embassy_usb::types::InterfaceNumber;
+use embassy_usb::{Builder, Handler};
+use heapless::Vec;
+use portable_atomic::{AtomicBool, AtomicI16, AtomicU32, AtomicU8, Ordering};
+
+use crate::control::{
+    clock_get, clock_set_rate, write_range_i16, EntityRequest, CUR, EP_SAMPLING_FREQ_CONTROL,
+    FU_MUTE_CONTROL, FU_VOLUME_CONTROL, RANGE, UAC1_GET_CUR, UAC1_GET_MAX, UAC1_GET_MIN,
+    UAC1_GET_RES, UAC1_SET_CUR,
+};
+use crate::descriptor::{CLOCK_SOURCE_ID, FEATURE_CHANNELS, FEATURE_UNIT_ID};
+use crate::{
+    class, AudioControlDescriptor, AudioProtocol, AudioStats, AudioStreamingDescriptor,
+    FeedbackController, StreamFormat, StreamState, Uac1ControlDescriptor, Uac1StreamingDescriptor,
+    Uac2Config, MAX_USB_AUDIO_PACKET,
+};
+
+/// Standard interface descriptor type
+pub(crate) const DESC_INTERFACE: u8 = 0x04;
+/// Standard endpoint descriptor type
+pub(crate) const DESC_ENDPOINT: u8 = 0x05;
+
+/// Streaming alternate settings (formats) a speaker can offer
+pub(crate) const MAX_STREAM_FORMATS: usize = 4;
+
+/// Scratch space for one interface's descriptor chain
+const DESCRIPTOR_BUF: usize = 128;
+
+/// Lowest volume offered to the host, in 1/256 dB
+pub const VOLUME_MIN_DB_X256: i16 = -60 * 256;
+/// Highest volume offered to the host, in 1/256 dB
+pub const VOLUME_MAX_DB_X256: i16 = 0;
+/// Volume step, in 1/256 dB
+pub const VOLUME_RES_DB_X256: i16 = 128;
+/// Width of the samples [`SpeakerStream::receive`] delivers
+///
+/// Every format is narrowed to its top 16 bits, whatever its subslot size.
+pub const STREAM_BITS_PER_SAMPLE: u8 = 16;
+
+/// Master plus per-channel feature unit controls
+const FEATURE_CONTROLS: usize = FEATURE_CHANNELS as usize + 1;
+
+/// Pending event bits
+const PENDING_ALT_SETTING: u32 = 1 << 0;
+const PENDING_SAMPLE_RATE: u32 = 1 << 1;
+const PENDING_VOLUME: u32 = 1 << 2;
+const PENDING_MUTE: u32 = PENDING_VOLUME << FEATURE_CONTROLS;
+
+/// A setting changed by the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ControlEvent {
+    /// Format of the streaming alternate setting, `None` when the host
+    /// closed the stream
+    Format(Option<StreamFormat>),
+    /// Clock source sampling frequency in Hz
+    SampleRate(u32),
+    /// Volume of a channel (0 = master), in 1/256 dB
+    Volume { channel: u8, volume_db_x256: i16 },
+    /// Mute of a channel (0 = master)
+    Mute { channel: u8, muted: bool },
+}
+
+/// Internal state of the speaker class
+pub struct SpeakerState<'d> {
+    control: Option<Control<'d>>,
+    shared: Shared,
+}
+
+impl Default for SpeakerState<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SpeakerState<'_> {
+    /// Create a new, unregistered state
+    pub const fn new() -> Self {
+        Self {
+            control: None,
+            shared: Shared {
+                alt_setting: AtomicU8::new(0),
+                sample_rate: AtomicU32::new(0),
+                volume: [AtomicI16::new(0), AtomicI16::new(0), AtomicI16::new(0)],
+                mute: [
+                    AtomicBool::new(false),
+                    AtomicBool::new(false),
+                    AtomicBool::new(false),
+                ],
+                pending: AtomicU32::new(0),
+                waker: AtomicWaker::new(),
+                stream_waker: AtomicWaker::new(),
+                feedback_waker: AtomicWaker::new(),
+            },
+        }
+    }
+}
+
+/// State shared between the control handler and the application
+struct Shared {
+    /// Alternate setting selected on the streaming interface
+    alt_setting: AtomicU8,
+    /// Sampling frequency selected on the clock source
+    sample_rate: AtomicU32,
+    /// Feature unit volume per channel (0 = master), in 1/256 dB
+    volume: [AtomicI16; FEATURE_CONTROLS],
+    /// Feature unit mute per channel (0 = master)
+    mute: [AtomicBool; FEATURE_CONTROLS],
+    /// `PENDING_*` bits of settings changed since the application looked
+    pending: AtomicU32,
+    /// Wakes the [`ControlMonitor`]
+    waker: AtomicWaker,
+    /// Wake the stream and feedback tasks on alt setting changes
+    stream_waker: AtomicWaker,
+    feedback_waker: AtomicWaker,
+}
+
+impl Shared {
+    fn set_alt_setting(&self, alt_setting: u8) {
+        self.alt_setting.store(alt_setting, Ordering::Relaxed);
+        self.stream_waker.wake();
+        self.feedback_waker.wake();
+        self.notify(PENDING_ALT_SETTING);
+    }
+
+    /// Index of the format selected by the host, if the stream is open
+    fn active_format(&self, formats: usize) -> Option<usize> {
+        let alt_setting = self.alt_setting.load(Ordering::Relaxed) as usize;
+        (1..=formats)
+            .contains(&alt_setting)
+            .then(|| alt_setting - 1)
+    }
+
+    /// Wait for the host to open the stream and return the format index
+    async fn wait_open(&self, waker: &AtomicWaker, formats: usize) -> usize {
+        poll_fn(|cx| {
+            waker.register(cx.waker());
+            match self.active_format(formats) {
+                Some(index) => Poll::Ready(index),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.store(sample_rate, Ordering::Relaxed);
+        self.notify(PENDING_SAMPLE_RATE);
+    }
+
+    fn set_volume(&self, channel: usize, volume_db_x256: i16) {
+        self.volume[channel].store(volume_db_x256, Ordering::Relaxed);
+        self.notify(PENDING_VOLUME << channel);
+    }
+
+    fn set_mute(&self, channel: usize, muted: bool) {
+        self.mute[channel].store(muted, Ordering::Relaxed);
+        self.notify(PENDING_MUTE << channel);
+    }
+
+    fn notify(&self, bits: u32) {
+        self.pending.fetch_or(bits, Ordering::Release);
+        self.waker.wake();
+    }
+
+    /// Current value of the setting behind a single pending bit
+    fn event(&self, bit: u32, formats: &[StreamFormat]) -> ControlEvent {
+        if bit == PENDING_ALT_SETTING {
+            let alt_setting = self.alt_setting.load(Ordering::Relaxed);
+            ControlEvent::Format(stream_format(formats, alt_setting))
+        } else if bit == PENDING_SAMPLE_RATE {
+            ControlEvent::SampleRate(self.sample_rate.load(Ordering::Relaxed))
+        } else if bit < PENDING_MUTE {
+            let channel = (bit / PENDING_VOLUME).trailing_zeros() as usize;
+            ControlEvent::Volume {
+                channel: channel as u8,
+                volume_db_x256: self.volume[channel].load(Ordering::Relaxed),
+            }
+        } else {
+            let channel = (bit / PENDING_MUTE).trailing_zeros() as usize;
+            ControlEvent::Mute {
+                channel: channel as u8,
+                muted: self.mute[channel].load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+/// Handles bus events, interface selection and class requests for the
+/// speaker
+struct Control<'d> {
+    shared: &'d Shared,
+    control_interface: InterfaceNumber,
+    streaming_interface: InterfaceNumber,
+    /// Data endpoint address of each streaming alt setting
+    data_endpoints: Vec<u8, MAX_STREAM_FORMATS>,
+    protocol: AudioProtocol,
+    sample_rates: &'static [u32],
+    channels: u8,
+}
+
+impl Control<'_> {
+    fn clock_out(&self, req: EntityRequest, data: &[u8]) -> OutResponse {
+        match clock_set_rate(&req, data) {
+            Some(rate) => self.set_sample_rate(rate),
+            None => OutResponse::Rejected,
+        }
+    }
+
+    fn set_sample_rate(&self, rate: u32) -> OutResponse {
+        if !self.sample_rates.contains(&rate) {
+            return OutResponse::Rejected;
+        }
+        if rate != self.shared.sample_rate.load(Ordering::Relaxed) {
+            self.shared.set_sample_rate(rate);
+        }
+        OutResponse::Accepted
+    }
+
+    /// Decode a UAC1 request to a streaming data endpoint's sampling
+    /// frequency control
+    fn endpoint_request(&self, req: &Request) -> Option<u8> {
+        let addressed = req.request_type == RequestType::Class
+            && req.recipient == Recipient::Endpoint
+            && self.data_endpoints.contains(&(req.index as u8))
+            && (req.value >> 8) as u8 == EP_SAMPLING_FREQ_CONTROL;
+        addressed.then_some(req.request)
+    }
+
+    /// UAC1 sampling frequency requests carry the rate in 3 bytes
+    fn endpoint_out(&self, request: u8, data: &[u8]) -> OutResponse {
+        match (request, data) {
+            (UAC1_SET_CUR, &[b0, b1, b2]) => {
+                self.set_sample_rate(u32::from_le_bytes([b0, b1, b2, 0]))
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn endpoint_in(&self, request: u8, buf: &mut [u8]) -> Option<usize> {
+        if request != UAC1_GET_CUR {
+            return None;
+        }
+        let rate = self.shared.sample_rate.load(Ordering::Relaxed);
+        buf.get_mut(..3)?.copy_from_slice(&rate.to_le_bytes()[..3]);
+        Some(3)
+    }
+
+    fn clock_in(&self, req: EntityRequest, buf: &mut [u8]) -> Option<usize> {
+        let rate = self.shared.sample_rate.load(Ordering::Relaxed);
+        clock_get(&req, buf, rate, self.sample_rates)
+    }
+
+    /// Feature unit control index for a request's channel
+    fn feature_channel(&self, req: &EntityRequest) -> Option<usize> {
+        (req.channel <= self.channels.min(FEATURE_CHANNELS)).then_some(req.channel as usize)
+    }
+
+    fn feature_out(&self, req: EntityRequest, data: &[u8]) -> OutResponse {
+        let Some(channel) = self.feature_channel(&req) else {
+            return OutResponse::Rejected;
+        };
+        match (req.request, req.selector, data) {
+            (CUR, FU_MUTE_CONTROL, &[muted]) => {
+                self.shared.set_mute(channel, muted != 0);
+                OutResponse::Accepted
+            }
+            (CUR, FU_VOLUME_CONTROL, &[lo, hi]) => {
+                // Clamp into range and onto a step
+                let volume =
+                    i16::from_le_bytes([lo, hi]).clamp(VOLUME_MIN_DB_X256, VOLUME_MAX_DB_X256);
+                let steps = (volume - VOLUME_MIN_DB_X256) / VOLUME_RES_DB_X256;
+                self.shared
+                    .set_volume(channel, VOLUME_MIN_DB_X256 + steps * VOLUME_RES_DB_X256);
+                OutResponse::Accepted
+            }
+            _ => OutResponse::Rejected,
+        }
+    }
+
+    fn feature_in(&self, req: EntityRequest, buf: &mut [u8]) -> Option<usize> {
+        let channel = self.feature_channel(&req)?;
+        // UAC1 asks for each end of the range separately
+        let uac1_volume = match req.request {
+            UAC1_GET_MIN => Some(VOLUME_MIN_DB_X256),
+            UAC1_GET_MAX => Some(VOLUME_MAX_DB_X256),
+            UAC1_GET_RES => Some(VOLUME_RES_DB_X256),
+            _ => None,
+        };
+        if let Some(volume) = uac1_volume.filter(|_| req.selector == FU_VOLUME_CONTROL) {
+            buf.get_mut(..2)?.copy_from_slice(&volume.to_le_bytes());
+            return Some(2);
+        }
+        match (req.request, req.selector) {
+            (CUR, FU_MUTE_CONTROL) => {
+                *buf.first_mut()? = self.shared.mute[channel].load(Ordering::Relaxed) as u8;
+                Some(1)
+            }
+            (CUR, FU_VOLUME_CONTROL) => {
+                let volume = self.shared.volume[channel].load(Ordering::Relaxed);
+                buf.get_mut(..2)?.copy_from_slice(&volume.to_le_bytes());
+                Some(2)
+            }
+            (RANGE, FU_VOLUME_CONTROL) => Some(write_range_i16(
+                buf,
+                VOLUME_MIN_DB_X256,
+                VOLUME_MAX_DB_X256,
+                VOLUME_RES_DB_X256,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl Handler for Control<'_> {
+    fn reset(&mut self) {
+        self.shared.set_alt_setting(0);
+    }
+
+    fn configured(&mut self, configured: bool) {
+        if !configured {
+            self.shared.set_alt_setting(0);
+        }
+    }
+
+    fn set_alternate_setting(&mut self, iface: InterfaceNumber, alternate_setting: u8) {
+        if iface == self.streaming_interface {
+            self.shared.set_alt_setting(alternate_setting);
+        }
+    }
+
+    fn control_out(&mut self, req: Request, data: &[u8]) -> Option<OutResponse> {
+        if self.protocol == AudioProtocol::Uac1 {
+            if let Some(request) = self.endpoint_request(&req) {
+                return Some(self.endpoint_out(request, data));
+            }
+        }
+        let req = EntityRequest::parse(&req, self.control_interface.0)?;
+        Some(match (self.protocol, req.entity) {
+            (AudioProtocol::Uac2, CLOCK_SOURCE_ID) => self.clock_out(req, data),
+            // UAC1 SET_CUR is UAC2 CUR
+            (_, FEATURE_UNIT_ID) => self.feature_out(req, data),
+            _ => OutResponse::Rejected,
+        })
+    }
+
+    fn control_in<'a>(&'a mut self, req: Request, buf: &'a mut [u8]) -> Option<InResponse<'a>> {
+        let len = match self.endpoint_request(&req) {
+            Some(request) if self.protocol == AudioProtocol::Uac1 => self.endpoint_in(request, buf),
+            _ => {
+                let mut req = EntityRequest::parse(&req, self.control_interface.0)?;
+                if self.protocol == AudioProtocol::Uac1 && req.request == UAC1_GET_CUR {
+                    req.request = CUR;
+                }
+                match (self.protocol, req.entity) {
+                    (AudioProtocol::Uac2, CLOCK_SOURCE_ID) => self.clock_in(req, buf),
+                    (_, FEATURE_UNIT_ID) => self.feature_in(req, buf),
+                    _ => None,
+                }
+            }
+        };
+        Some(match len {
+            Some(len) => InResponse::Accepted(&buf[..len]),
+            None => InResponse::Rejected,
+        })
+    }
+}
+
+/// USB Audio Class speaker, UAC2 or UAC1 as [`Uac2Config::protocol`]
+/// selects
+pub struct Speaker;
+
+impl Speaker {
+    /// Add the speaker function to `builder`
+    ///
+    /// Returns the stream that receives audio, the feedback endpoint that
+    /// paces the host and a monitor for interface changes made by the host.
+    pub fn register<'d, D: Driver<'d>>(
+        builder: &mut Builder<'d, D>,
+        state: &'d mut SpeakerState<'d>,
+        config: &Uac2Config,
+    ) -> (
+        SpeakerStream<'d, D>,
+        SpeakerFeedback<'d, D>,
+        ControlMonitor<'d>,
+    ) {
+        let mut buf = [0u8; DESCRIPTOR_BUF];
+        let protocol = match config.protocol {
+            AudioProtocol::Uac1 => class::UAC1_PROTOCOL,
+            AudioProtocol::Uac2 => class::UAC2_PROTOCOL,
+        };
+        let mut func = builder.function(class::AUDIO, 0x00, protocol);
+
+        // Audio Control interface. The streaming interface is allocated
+        // right after it.
+        let mut iface = func.interface();
+        let control_interface = iface.interface_number();
+        let mut alt = iface.alt_setting(class::AUDIO, class::AUDIO_CONTROL, protocol, None);
+        let len = match config.protocol {
+            AudioProtocol::Uac1 => {
+                Uac1ControlDescriptor::new(config.clone()).build(&mut buf, control_interface.0 + 1)
+            }
+            AudioProtocol::Uac2 => AudioControlDescriptor::new(config.clone()).build(&mut buf),
+        }
+        .expect("DESCRIPTOR_BUF too small for the configuration");
+        for desc in descriptors(&buf[..len]).filter(|desc| desc[1] != DESC_INTERFACE) {
+            alt.descriptor(desc[1], &desc[2..]);
+        }
+
+        // Audio Streaming interface: zero-bandwidth alt 0, then one
+        // streaming alt setting per format. `embassy-usb` enables endpoints
+        // by the alt setting their descriptor appears in, so each setting
+        // gets its own data and feedback endpoints.
+        assert!(
+            config.formats.len() <= MAX_STREAM_FORMATS,
+            "more streaming formats than MAX_STREAM_FORMATS"
+        );
+        let mut iface = func.interface();
+        let streaming_interface = iface.interface_number();
+        let build_alt = |buf: &mut [u8], alt_setting, ep_addr, feedback_addr| {
+            match config.protocol {
+                AudioProtocol::Uac1 => Uac1StreamingDescriptor::new(config.clone()).build_alt(
+                    buf,
+                    streaming_interface.0,
+                    alt_setting,
+                    ep_addr,
+                    feedback_addr,
+                ),
+                AudioProtocol::Uac2 => AudioStreamingDescriptor::new(config.clone()).build_alt(
+                    buf,
+                    streaming_interface.0,
+                    alt_setting,
+                    ep_addr,
+                    feedback_addr,
+                ),
+            }
+            .expect("DESCRIPTOR_BUF too small for the configuration")
+        };
+        let mut alt = iface.alt_setting(class::AUDIO, class::AUDIO_STREAMING, protocol, None);
+        let len = match config.protocol {
+            AudioProtocol::Uac1 => Uac1StreamingDescriptor::new(config.clone())
+                .build_alt0(&mut buf, streaming_interface.0),
+            AudioProtocol::Uac2 => AudioStreamingDescriptor::new(config.clone())
+                .build_alt0(&mut buf, streaming_interface.0),
+        }
+        .expect("DESCRIPTOR_BUF too small for the configuration");
+        for desc in descriptors(&buf[..len]).filter(|desc| desc[1] != DESC_INTERFACE) {
+            alt.descriptor(desc[1], &desc[2..]);
+        }
+        let mut endpoints: Vec<D::EndpointOut, MAX_STREAM_FORMATS> = Vec::new();
+        let mut feedback_endpoints: Vec<D::EndpointIn, MAX_STREAM_FORMATS> = Vec::new();
+        let mut data_endpoints = Vec::new();
+        for alt_setting in 1..=config.formats.len() as u8 {
+            let mut alt = iface.alt_setting(class::AUDIO, class::AUDIO_STREAMING, protocol, None);
+
+            // Allocate the endpoints first: UAC1's data endpoint descriptor
+            // names its feedback endpoint.
+            let len = build_alt(&mut buf, alt_setting, 0, 0);
+            for desc in descriptors(&buf[..len]).filter(|desc| desc[1] == DESC_ENDPOINT) {
+                let max_packet = u16::from_le_bytes([desc[4], desc[5]]);
+                if desc[2] & 0x80 != 0 {
+                    let ep =
+                        alt.alloc_endpoint_in(EndpointType::Isochronous, None, max_packet, desc[6]);
+                    feedback_endpoints.push(ep).ok();
+                } else {
+                    assert!(
+                        max_packet as usize <= MAX_USB_AUDIO_PACKET,
+                        "streaming format exceeds MAX_USB_AUDIO_PACKET"
+                    );
+                    let ep = alt.alloc_endpoint_out(
+                        EndpointType::Isochronous,
+                        None,
+                        max_packet,
+                        desc[6],
+                    );
+                    endpoints.push(ep).ok();
+                }
+            }
+            let (Some(data), Some(feedback)) = (endpoints.last(), feedback_endpoints.last()) else {
+                continue;
+            };
+            let (data, feedback) = (*data.info(), *feedback.info());
+            data_endpoints.push(data.addr.into()).ok();
+
+            // Standard endpoint descriptors keep any audio class fields
+            // (UAC1's bRefresh and bSynchAddress) past the first 7 bytes
+            let len = build_alt(
+                &mut buf,
+                alt_setting,
+                data.addr.into(),
+                feedback.addr.into(),
+            );
+            for desc in descriptors(&buf[..len]) {
+                match desc[1] {
+                    DESC_INTERFACE => {}
+                    DESC_ENDPOINT if desc[2] & 0x80 != 0 => alt.endpoint_descriptor(
+                        &feedback,
+                        SynchronizationType::NoSynchronization,
+                        UsageType::FeedbackEndpoint,
+                        &desc[7..],
+                    ),
+                    DESC_ENDPOINT => alt.endpoint_descriptor(
+                        &data,
+                        synchronization_type(desc[3]),
+                        UsageType::DataEndpoint,
+                        &desc[7..],
+                    ),
+                    descriptor_type => alt.descriptor(descriptor_type, &desc[2..]),
+                }
+            }
+        }
+        drop(func);
+
+        let rate = config.sample_rates.first().copied().unwrap_or(48000);
+        state.shared.sample_rate.store(rate, Ordering::Relaxed);
+        state.control = Some(Control {
+            shared: &state.shared,
+            control_interface,
+            streaming_interface,
+            data_endpoints,
+            protocol: config.protocol,
+            sample_rates: config.sample_rates,
+            channels: config.channels,
+        });
+        builder.handler(state.control.as_mut().unwrap());
+
+        let stream = SpeakerStream {
+            endpoints,
+            active: None,
+            shared: &state.shared,
+            formats: config.formats,
+            stats: AudioStats::default(),
+            state: StreamState::Idle,
+        };
+        let feedback = SpeakerFeedback {
+            endpoints: feedback_endpoints,
+            active: None,
+            shared: &state.shared,
+            controller: FeedbackController::new(config.feedback_format, rate, config.channels),
+        };
+        (
+            stream,
+            feedback,
+            ControlMonitor {
+                shared: &state.shared,
+                formats: config.formats,
+            },
+        )
+    }
+}
+
+/// Receives isochronous audio packets from the host
+pub struct SpeakerStream<'d, D: Driver<'d>> {
+    /// Data endpoint of each streaming alt setting
+    endpoints: Vec<D::EndpointOut, MAX_STREAM_FORMATS>,
+    /// Index of the alt setting being received
+    active: Option<usize>,
+    shared: &'d Shared,
+    formats: &'static [StreamFormat],
+    stats: AudioStats,
+    state: StreamState,
+}
+
+impl<'d, D: Driver<'d>> SpeakerStream<'d, D> {
+    /// Wait for the host to select a streaming alternate setting
+    pub async fn wait_connection(&mut self) {
+        let formats = self.endpoints.len();
+        let index = self
+            .shared
+            .wait_open(&self.shared.stream_waker, formats)
+            .await;
+        self.endpoints[index].wait_enabled().await;
+        self.active = Some(index);
+        self.state = StreamState::Active;
+    }
+
+    /// Format being received, `None` while the stream is closed
+    pub fn format(&self) -> Option<StreamFormat> {
+        self.formats.get(self.active?).copied()
+    }
+
+    /// Receive one packet into `ring` as 16-bit samples
+    ///
+    /// Samples wider than 16 bits are truncated to their top 16 bits.
+    /// Returns the number of samples received, or
+    /// `EndpointError::Disabled` once the host leaves the alternate setting
+    /// (to close the stream or switch format).
+    pub async fn receive<const N: usize>(
+        &mut self,
+        ring: &RingBuffer<i16, N>,
+    ) -> Result<usize, EndpointError> {
+        let Some(index) = self.active else {
+            return Err(EndpointError::Disabled);
+        };
+        let mut packet = [0u8; MAX_USB_AUDIO_PACKET];
+        let len = match self.endpoints[index].read(&mut packet).await {
+            Ok(len) => len,
+            Err(err) => {
+                if err == EndpointError::Disabled {
+                    self.active = None;
+                    self.state = StreamState::Idle;
+                }
+                return Err(err);
+            }
+        };
+
+        let subslot = (self.formats[index].subslot_size as usize).max(2);
+        let mut samples = [0i16; MAX_USB_AUDIO_PACKET / 2];
+        let mut count = 0;
+        for (sample, bytes) in samples.iter_mut().zip(packet[..len].chunks_exact(subslot)) {
+            let top = &bytes[subslot - 2..];
+            *sample = i16::from_le_bytes([top[0], top[1]]);
+            count += 1;
+        }
+
+        ring.write(&samples[..count]);
+        self.stats.packets_received = self.stats.packets_received.wrapping_add(1);
+        self.stats.samples_received = self.stats.samples_received.wrapping_add(count as u64);
+
+        if let Some(fill) = ring.poll_watermarks() {
+            match fill {
+                FillState::Underrun => self.stats.underruns += 1,
+                FillState::Overrun => self.stats.overruns += 1,
+                FillState::Normal => {}
+            }
+            self.state = fill.into();
+        }
+        Ok(count)
+    }
+
+    /// Receive audio into `ring` forever, idling while the host has the
+    /// stream closed and following format switches
+    pub async fn run<const N: usize>(&mut self, ring: &RingBuffer<i16, N>) -> ! {
+        loop {
+            self.wait_connection().await;
+            while self.receive(ring).await.is_ok() {}
+        }
+    }
+
+    /// Reception statistics
+    pub fn stats(&self) -> AudioStats {
+        self.stats
+    }
+
+    /// Current stream state
+    pub fn state(&self) -> StreamState {
+        self.state
+    }
+}
+
+/// Sends rate feedback to the host
+pub struct SpeakerFeedback<'d, D: Driver<'d>> {
+    /// Feedback endpoint of each streaming alt setting
+    endpoints: Vec<D::EndpointIn, MAX_STREAM_FORMATS>,
+    /// Index of the alt setting being paced
+    active: Option<usize>,
+    shared: &'d Shared,
+    controller: FeedbackController,
+}
+
+impl<'d, D: Driver<'d>> SpeakerFeedback<'d, D> {
+    /// Wait for the host to select a streaming alternate setting
+    ///
+    /// Restarts the controller at the nominal rate.
+    pub async fn wait_connection(&mut self) {
+        let formats = self.endpoints.len();
+        let index = self
+            .shared
+            .wait_open(&self.shared.feedback_waker, formats)
+            .await;
+        self.endpoints[index].wait_enabled().await;
+        self.active = Some(index);
+        self.controller.reset();
+    }
+
+    /// Feedback controller, e.g. to change its sample rate
+    pub fn controller(&mut self) -> &mut FeedbackController {
+        &mut self.controller
+    }
+
+    /// Send one feedback packet steering `ring` towards half full
+    ///
+    /// Follows sample rate switches made by the host.
+    pub async fn send<const N: usize>(
+        &mut self,
+        ring: &RingBuffer<i16, N>,
+    ) -> Result<(), EndpointError> {
+        let rate = self.shared.sample_rate.load(Ordering::Relaxed);
+        if rate != self.controller.sample_rate() {
+            self.controller.set_sample_rate(rate);
+        }
+        self.controller.update(ring.available_read(), N / 2);
+        let Some(index) = self.active else {
+            return Err(EndpointError::Disabled);
+        };
+        let mut packet = [0u8; 4];
+        let len = self.controller.write_packet(&mut packet);
+        let result = self.endpoints[index].write(&packet[..len]).await;
+        if result == Err(EndpointError::Disabled) {
+            self.active = None;
+        }
+        result
+    }
+
+    /// Send feedback for `ring` forever, idling while the host has the
+    /// stream closed
+    pub async fn run<const N: usize>(&mut self, ring: &RingBuffer<i16, N>) -> ! {
+        loop {
+            self.wait_connection().await;
+            while self.send(ring).await.is_ok() {}
+        }
+    }
+}
+
+/// Observes interface and sample rate changes made by the host
+pub struct ControlMonitor<'d> {
+    shared: &'d Shared,
+    formats: &'static [StreamFormat],
+}
+
+impl ControlMonitor<'_> {
+    /// Alternate setting selected on the streaming interface
+    pub fn alt_setting(&self) -> u8 {
+        self.shared.alt_setting.load(Ordering::Relaxed)
+    }
+
+    /// Format of the alternate setting selected by the host, `None` while
+    /// the stream is closed
+    pub fn format(&self) -> Option<StreamFormat> {
+        stream_format(self.formats, self.alt_setting())
+    }
+
+    /// Check if the host has opened the stream
+    pub fn is_streaming(&self) -> bool {
+        self.alt_setting() != 0
+    }
+
+    /// Sample rate selected by the host
+    pub fn sample_rate(&self) -> u32 {
+        self.shared.sample_rate.load(Ordering::Relaxed)
+    }
+
+    /// Volume of a channel (0 = master) in 1/256 dB, `None` for channels
+    /// without a volume control
+    pub fn volume_db_x256(&self, channel: u8) -> Option<i16> {
+        let volume = self.shared.volume.get(channel as usize)?;
+        Some(volume.load(Ordering::Relaxed))
+    }
+
+    /// Mute of a channel (0 = master), `None` for channels without a mute
+    /// control
+    pub fn is_muted(&self, channel: u8) -> Option<bool> {
+        let mute = self.shared.mute.get(channel as usize)?;
+        Some(mute.load(Ordering::Relaxed))
+    }
+
+    /// Wait for the host to change any setting (or reset)
+    ///
+    /// Discards the pending [`ControlEvent`]s; use either this or
+    /// [`next_event`](Self::next_event), not both.
+    pub async fn changed(&self) {
+        poll_fn(|cx| {
+            self.shared.waker.register(cx.waker());
+            if self.shared.pending.swap(0, Ordering::Acquire) != 0 {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        })
+        .await
+    }
+
+    /// Wait for the next setting changed by the host
+    ///
+    /// Repeated changes to one setting are coalesced into a single event
+    /// carrying its latest value, so a slow reader never falls behind.
+    pub async fn next_event(&self) -> ControlEvent {
+        poll_fn(|cx| {
+            self.shared.waker.register(cx.waker());
+            let pending = self.shared.pending.load(Ordering::Acquire);
+            if pending == 0 {
+                return Poll::Pending;
+            }
+            let bit = pending & pending.wrapping_neg();
+            self.shared.pending.fetch_and(!bit, Ordering::AcqRel);
+            Poll::Ready(self.shared.event(bit, self.formats))
+        })
+        .await
+    }
+}
+
+/// Format carried by a streaming alternate setting
+fn stream_format(formats: &[StreamFormat], alt_setting: u8) -> Option<StreamFormat> {
+    let index = (alt_setting as usize).checked_sub(1)?;
+    formats.get(index).copied()
+}
+
+/// Split a descriptor chain into individual descriptors
+pub(crate) fn descriptors(mut chain: &[u8]) -> impl Iterator<Item = &[u8]> {
+    core::iter::from_fn(move || {
+        let len = *chain.first()? as usize;
+        if len < 2 || len > chain.len() {
+            return None;
+        }
+        let (desc, rest) = chain.split_at(len);
+        chain = rest;
+        Some(desc)
+    })
+}
+
+/// Decode the synchronization type bits of an endpoint's `bmAttributes`
+fn synchronization_type(attributes: u8) -> SynchronizationType {
+    match (attributes >> 2) & 0x03 {
+        0b01 => SynchronizationType::Asynchronous,
+        0b10 => SynchronizationType::Adaptive,
+        0b11 => SynchronizationType::Synchronous,
+        _ => SynchronizationType::NoSynchronization,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{setup, usb_config, Buffers, MockUsb};
+    use crate::parse;
+    use embassy_futures::block_on;
+    use embassy_futures::join::join;
+    use embassy_futures::select::{select, Either};
+    use embassy_usb::driver::Event;
+
+    /// Class-specific interface descriptor type
+    const DESC_CS_INTERFACE: u8 = 0x24;
+
+    /// Find the descriptor of a given type and subtype in a chain
+    fn find(chain: &[u8], desc_type: u8, subtype: u8) -> Option<&[u8]> {
+        descriptors(chain).find(|desc| desc[1] == desc_type && desc.get(2) == Some(&subtype))
+    }
+
+    #[test]
+    fn test_enumerates_and_streams() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+        let ring: RingBuffer<i16, 256> = RingBuffer::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (mut stream, _feedback, monitor) =
+            Speaker::register(&mut builder, &mut state, &Uac2Config::default());
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            assert!(parse::check(&config).is_ok());
+            assert_eq!(usb.address(), 7);
+
+            // IAD groups the AC and AS interfaces as a UAC2 function
+            let iad = descriptors(&config).find(|desc| desc[1] == 0x0B).unwrap();
+            assert_eq!(iad[2..7], [0, 2, 0x01, 0x00, 0x20]);
+            let header = find(&config, DESC_CS_INTERFACE, 0x01).unwrap();
+            assert_eq!(
+                u16::from_le_bytes([header[6], header[7]]),
+                9 + 8 + 17 + 18 + 12
+            );
+            let endpoint = descriptors(&config)
+                .find(|desc| desc[1] == DESC_ENDPOINT && desc[2] & 0x80 == 0)
+                .unwrap();
+            let ep_addr = endpoint[2];
+            assert_eq!(endpoint[3], 0x05);
+            assert!(find(&config, 0x25, 0x01).is_some());
+
+            assert!(!usb.is_enabled(ep_addr));
+            usb.set_interface(1, 1).await.unwrap();
+            assert!(usb.is_enabled(ep_addr));
+            assert!(monitor.is_streaming());
+
+            let mut packet = [0u8; 192];
+            for (i, chunk) in packet.chunks_exact_mut(2).enumerate() {
+                chunk.copy_from_slice(&(i as i16 * 100).to_le_bytes());
+            }
+            usb.send(ep_addr, &packet).await;
+            usb.send(ep_addr, &packet).await;
+            usb.flush().await;
+
+            usb.set_interface(1, 0).await.unwrap();
+            assert!(!monitor.is_streaming());
+        };
+
+        let receive = async {
+            stream.wait_connection().await;
+            assert_eq!(stream.state(), StreamState::Active);
+            while stream.receive(&ring).await.is_ok() {}
+        };
+
+        block_on(select(device.run(), join(host, receive)));
+
+        assert_eq!(stream.state(), StreamState::Idle);
+        let stats = stream.stats();
+        assert_eq!(stats.packets_received, 2);
+        assert_eq!(stats.samples_received, 192);
+        assert_eq!(ring.available_read(), 192);
+        let mut out = [0i16; 4];
+        ring.read(&mut out);
+        assert_eq!(out, [0, 100, 200, 300]);
+    }
+
+    #[test]
+    fn test_reset_closes_stream() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (_stream, _feedback, monitor) =
+            Speaker::register(&mut builder, &mut state, &Uac2Config::default());
+        let mut device = builder.build();
+
+        let host = async {
+            usb.enumerate().await;
+            usb.set_interface(1, 1).await.unwrap();
+            monitor.changed().await;
+            assert_eq!(monitor.alt_setting(), 1);
+
+            // One alt setting per format, and no more
+            assert!(usb.set_interface(1, 5).await.is_err());
+
+            usb.event(Event::Reset);
+            monitor.changed().await;
+            assert!(!monitor.is_streaming());
+        };
+
+        block_on(select(device.run(), host));
+    }
+
+    #[test]
+    fn test_sample_rate_requests() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+        let ring: RingBuffer<i16, 256> = RingBuffer::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (_stream, mut feedback, monitor) =
+            Speaker::register(&mut builder, &mut state, &Uac2Config::default());
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            // Internal programmable clock with read/write frequency control
+            let clock = find(&config, DESC_CS_INTERFACE, 0x0A).unwrap();
+            assert_eq!(clock[3..6], [CLOCK_SOURCE_ID, 0x03, 0x07]);
+            assert_eq!(monitor.sample_rate(), 44100);
+
+            // Transfers as sent by a Linux host probing clock source 3 on
+            // interface 0: RANGE header first, then the full block
+            let range_header = [0xA1, 0x02, 0x00, 0x01, 0x00, 0x03, 0x02, 0x00];
+            assert_eq!(usb.control_in(range_header).await.unwrap(), [2, 0]);
+            let range = [0xA1, 0x02, 0x00, 0x01, 0x00, 0x03, 0x1A, 0x00];
+            assert_eq!(
+                usb.control_in(range).await.unwrap(),
+                [
+                    0x02, 0x00, // wNumSubRanges
+                    0x44, 0xAC, 0x00, 0x00, 0x44, 0xAC, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80,
+                    0xBB, 0x00, 0x00, 0x80, 0xBB, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                ]
+            );
+            let get_cur = [0xA1, 0x01, 0x00, 0x01, 0x00, 0x03, 0x04, 0x00];
+            assert_eq!(usb.control_in(get_cur).await.unwrap(), [0x44, 0xAC, 0, 0]);
+            let get_valid = [0xA1, 0x01, 0x00, 0x02, 0x00, 0x03, 0x01, 0x00];
+            assert_eq!(usb.control_in(get_valid).await.unwrap(), [1]);
+
+            // Switch to 48 kHz
+            let set_cur = [0x21, 0x01, 0x00, 0x01, 0x00, 0x03, 0x04, 0x00];
+            usb.control_out(set_cur, &[0x80, 0xBB, 0x00, 0x00])
+                .await
+                .unwrap();
+            monitor.changed().await;
+            assert_eq!(monitor.sample_rate(), 48000);
+            assert_eq!(usb.control_in(get_cur).await.unwrap(), [0x80, 0xBB, 0, 0]);
+
+            // Rates that aren't offered, read-only controls and other
+            // entities are refused
+            let cd_rate = 32000u32.to_le_bytes();
+            assert!(usb.control_out(set_cur, &cd_rate).await.is_err());
+            let set_valid = setup(0x21, CUR, 0x0200, 0x0300, 1);
+            assert!(usb.control_out(set_valid, &[0]).await.is_err());
+            let get_terminal = setup(0xA1, CUR, 0x0100, 0x0100, 4);
+            assert!(usb.control_in(get_terminal).await.is_err());
+            assert_eq!(monitor.sample_rate(), 48000);
+
+            usb.set_interface(1, 1).await.unwrap();
+            let endpoint = descriptors(&config)
+                .find(|desc| desc[1] == DESC_ENDPOINT && desc[2] & 0x80 != 0)
+                .unwrap();
+            usb.receive(endpoint[2]).await
+        };
+        let send = async {
+            feedback.wait_connection().await;
+            feedback.send(&ring).await.unwrap();
+        };
+
+        let packet = match block_on(select(device.run(), join(host, send))) {
+            Either::First(_) => unreachable!(),
+            Either::Second((packet, _)) => packet,
+        };
+        // Feedback follows the new rate
+        assert_eq!(feedback.controller().sample_rate(), 48000);
+        let value = u32::from_le_bytes([packet[0], packet[1], packet[2], 0]);
+        assert_eq!(value, (48 << 14) + 48 * (1 << 14) / 1000);
+    }
+
+    #[test]
+    fn test_volume_and_mute_requests() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (_stream, _feedback, monitor) =
+            Speaker::register(&mut builder, &mut state, &Uac2Config::default());
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            // Enumeration resets the bus
+            assert_eq!(monitor.next_event().await, ControlEvent::Format(None));
+
+            // Input terminal -> feature unit -> output terminal
+            let unit = find(&config, DESC_CS_INTERFACE, 0x06).unwrap();
+            assert_eq!(unit[..5], [18, 0x24, 0x06, FEATURE_UNIT_ID, 1]);
+            assert_eq!(unit[5..9], [0x0F, 0, 0, 0]);
+            let output = find(&config, DESC_CS_INTERFACE, 0x03).unwrap();
+            assert_eq!(output[7], FEATURE_UNIT_ID);
+
+            // Transfers as sent by a Linux host probing the master volume
+            // of unit 4 on interface 0
+            let range = [0xA1, 0x02, 0x00, 0x02, 0x00, 0x04, 0x08, 0x00];
+            assert_eq!(
+                usb.control_in(range).await.unwrap(),
+                [0x01, 0x00, 0x00, 0xC4, 0x00, 0x00, 0x80, 0x00]
+            );
+            let get_cur = [0xA1, 0x01, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            assert_eq!(usb.control_in(get_cur).await.unwrap(), [0, 0]);
+
+            // -12 dB, then -10 dB before the application looks
+            let set_cur = [0x21, 0x01, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            usb.control_out(set_cur, &[0x00, 0xF4]).await.unwrap();
+            usb.control_out(set_cur, &[0x00, 0xF6]).await.unwrap();
+            // Mute the right channel
+            let mute_right = [0x21, 0x01, 0x01, 0x01, 0x00, 0x04, 0x01, 0x00];
+            usb.control_out(mute_right, &[1]).await.unwrap();
+
+            assert_eq!(
+                monitor.next_event().await,
+                ControlEvent::Volume {
+                    channel: 0,
+                    volume_db_x256: -10 * 256
+                }
+            );
+            assert_eq!(
+                monitor.next_event().await,
+                ControlEvent::Mute {
+                    channel: 1,
+                    muted: true
+                }
+            );
+            assert_eq!(usb.control_in(get_cur).await.unwrap(), [0x00, 0xF6]);
+            let get_mute = setup(0xA1, CUR, 0x0101, 0x0400, 1);
+            assert_eq!(usb.control_in(get_mute).await.unwrap(), [1]);
+            assert_eq!(monitor.is_muted(2), Some(false));
+
+            // Out of range volumes are clamped onto a step
+            usb.control_out(set_cur, &(6 * 256i16).to_le_bytes())
+                .await
+                .unwrap();
+            assert_eq!(monitor.volume_db_x256(0), Some(0));
+            usb.control_out(set_cur, &(-1000i16).to_le_bytes())
+                .await
+                .unwrap();
+            assert_eq!(monitor.volume_db_x256(0), Some(-1024));
+
+            // No third channel and no mute range
+            let volume_ch3 = setup(0x21, CUR, 0x0203, 0x0400, 2);
+            assert!(usb.control_out(volume_ch3, &[0, 0]).await.is_err());
+            let mute_range = setup(0xA1, RANGE, 0x0100, 0x0400, 8);
+            assert!(usb.control_in(mute_range).await.is_err());
+        };
+
+        block_on(select(device.run(), host));
+    }
+
+    #[test]
+    fn test_wide_samples_keep_top_bits() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+        let ring: RingBuffer<i16, 64> = RingBuffer::new();
+        let config = Uac2Config {
+            formats: &[StreamFormat::PCM24_3],
+            ..Default::default()
+        };
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (mut stream, _feedback, _monitor) =
+            Speaker::register(&mut builder, &mut state, &config);
+        let mut device = builder.build();
+
+        let host = async {
+            usb.enumerate().await;
+            usb.set_interface(1, 1).await.unwrap();
+            usb.send(1, &[0x56, 0x34, 0x12, 0xFF, 0xFF, 0x80]).await;
+            usb.flush().await;
+        };
+        let receive = async {
+            stream.wait_connection().await;
+            stream.receive(&ring).await.unwrap()
+        };
+
+        let received = match block_on(select(device.run(), join(host, receive))) {
+            Either::First(_) => unreachable!(),
+            Either::Second((_, received)) => received,
+        };
+        assert_eq!(received, 2);
+        let mut out = [0i16; 2];
+        ring.read(&mut out);
+        assert_eq!(out, [0x1234, i16::MIN + 0xFF]);
+    }
+
+    #[test]
+    fn test_format_alt_settings() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+        let ring: RingBuffer<i16, 64> = RingBuffer::new();
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (mut stream, _feedback, monitor) =
+            Speaker::register(&mut builder, &mut state, &Uac2Config::default());
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            assert!(parse::check(&config).is_ok());
+            assert_eq!(monitor.next_event().await, ControlEvent::Format(None));
+
+            // Subslot size, resolution and packet size (49 frames of
+            // stereo) per alt setting, each with its own data endpoint
+            let formats = descriptors(&config)
+                .filter(|desc| desc[1] == DESC_CS_INTERFACE && desc[2] == 0x02 && desc.len() == 6)
+                .map(|desc| (desc[4], desc[5]));
+            assert!(formats.eq([(2, 16), (3, 24), (4, 24), (4, 32)]));
+            let endpoints = descriptors(&config)
+                .filter(|desc| desc[1] == DESC_ENDPOINT && desc[2] & 0x80 == 0)
+                .map(|desc| (desc[2], u16::from_le_bytes([desc[4], desc[5]])));
+            assert!(endpoints.eq([(1, 196), (2, 294), (3, 392), (4, 392)]));
+
+            // 24-bit samples in 4-byte subslots
+            usb.set_interface(1, 3).await.unwrap();
+            assert_eq!(
+                monitor.next_event().await,
+                ControlEvent::Format(Some(StreamFormat::PCM24_4))
+            );
+            assert!(usb.is_enabled(3) && !usb.is_enabled(1));
+            usb.send(3, &[0x00, 0x56, 0x34, 0x12, 0x00, 0xFF, 0xFF, 0x80])
+                .await;
+            usb.flush().await;
+        };
+        let receive = async {
+            stream.wait_connection().await;
+            stream.receive(&ring).await.unwrap()
+        };
+
+        let received = match block_on(select(device.run(), join(host, receive))) {
+            Either::First(_) => unreachable!(),
+            Either::Second((_, received)) => received,
+        };
+        assert_eq!(received, 2);
+        assert_eq!(stream.format(), Some(StreamFormat::PCM24_4));
+        let mut out = [0i16; 2];
+        ring.read(&mut out);
+        assert_eq!(out, [0x1234, i16::MIN + 0xFF]);
+    }
+
+    #[test]
+    fn test_feedback_endpoint() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+        let ring: RingBuffer<i16, 256> = RingBuffer::new();
+        let config = Uac2Config {
+            sample_rates: &[48000],
+            ..Default::default()
+        };
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (_stream, mut feedback, _monitor) =
+            Speaker::register(&mut builder, &mut state, &config);
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            let endpoint = descriptors(&config)
+                .find(|desc| desc[1] == DESC_ENDPOINT && desc[2] & 0x80 != 0)
+                .unwrap();
+            // Isochronous feedback endpoint carrying 10.14 values every frame
+            assert_eq!(endpoint[3..7], [0x11, 3, 0, 1]);
+            let alt1 = descriptors(&config)
+                .filter(|desc| desc[1] == DESC_INTERFACE)
+                .last()
+                .unwrap();
+            assert_eq!(alt1[4], 2);
+
+            usb.set_interface(1, 1).await.unwrap();
+            usb.receive(endpoint[2]).await
+        };
+        let send = async {
+            feedback.wait_connection().await;
+            // An empty buffer asks for the fastest allowed rate
+            feedback.send(&ring).await.unwrap();
+        };
+
+        let packet = match block_on(select(device.run(), join(host, send))) {
+            Either::First(_) => unreachable!(),
+            Either::Second((packet, _)) => packet,
+        };
+        let value = u32::from_le_bytes([packet[0], packet[1], packet[2], 0]);
+        assert_eq!(value, (48 << 14) + 48 * (1 << 14) / 1000);
+    }
+
+    #[test]
+    fn test_uac1_fallback() {
+        let usb = MockUsb::new();
+        let mut buffers = Buffers::new();
+        let mut state = SpeakerState::new();
+        let ring: RingBuffer<i16, 64> = RingBuffer::new();
+        let config = Uac2Config {
+            protocol: AudioProtocol::Uac1,
+            ..Default::default()
+        };
+
+        let mut builder = Builder::new(
+            usb.driver(),
+            usb_config(),
+            &mut buffers.config,
+            &mut buffers.bos,
+            &mut buffers.msos,
+            &mut buffers.control,
+        );
+        let (mut stream, _feedback, monitor) = Speaker::register(&mut builder, &mut state, &config);
+        let mut device = builder.build();
+
+        let host = async {
+            let config = usb.enumerate().await;
+            assert!(parse::check(&config).is_ok());
+            // UAC1 header naming streaming interface 1, no clock source
+            let header = find(&config, DESC_CS_INTERFACE, 0x01).unwrap();
+            assert_eq!(header[3..5], [0x00, 0x01]);
+            assert_eq!(header[7..], [1, 1]);
+            assert!(find(&config, DESC_CS_INTERFACE, 0x0A).is_none());
+            // Audio class data endpoint pointing at its feedback endpoint
+            let endpoint = descriptors(&config)
+                .find(|desc| desc[1] == DESC_ENDPOINT && desc[2] == 0x01)
+                .unwrap();
+            assert_eq!(endpoint[..], [9, 5, 0x01, 0x05, 196, 0, 1, 0, 0x81]);
+            let feedback = descriptors(&config)
+                .find(|desc| desc[1] == DESC_ENDPOINT && desc[2] == 0x81)
+                .unwrap();
+            assert_eq!(feedback[6..], [1, 1, 0]);
+
+            // Transfers as sent by a Linux host setting 48 kHz on
+            // endpoint 0x01
+            let set_rate = [0x22, 0x01, 0x00, 0x01, 0x01, 0x00, 0x03, 0x00];
+            usb.control_out(set_rate, &[0x80, 0xBB, 0x00])
+                .await
+                .unwrap();
+            let get_rate = [0xA2, 0x81, 0x00, 0x01, 0x01, 0x00, 0x03, 0x00];
+            assert_eq!(usb.control_in(get_rate).await.unwrap(), [0x80, 0xBB, 0x00]);
+            assert_eq!(monitor.sample_rate(), 48000);
+            assert!(usb
+                .control_out(set_rate, &[0x00, 0x7D, 0x00])
+                .await
+                .is_err());
+
+            // Volume range comes as separate MIN, MAX and RES requests
+            let get_min = [0xA1, 0x82, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            assert_eq!(usb.control_in(get_min).await.unwrap(), [0x00, 0xC4]);
+            let get_max = [0xA1, 0x83, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            assert_eq!(usb.control_in(get_max).await.unwrap(), [0x00, 0x00]);
+            let get_res = [0xA1, 0x84, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            assert_eq!(usb.control_in(get_res).await.unwrap(), [0x80, 0x00]);
+            let set_volume = [0x21, 0x01, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            usb.control_out(set_volume, &[0x00, 0xF4]).await.unwrap();
+            let get_volume = [0xA1, 0x81, 0x00, 0x02, 0x00, 0x04, 0x02, 0x00];
+            assert_eq!(usb.control_in(get_volume).await.unwrap(), [0x00, 0xF4]);
+            assert_eq!(monitor.volume_db_x256(0), Some(-12 * 256));
+
+            // Streaming is shared with UAC2
+            usb.set_interface(1, 1).await.unwrap();
+            usb.send(1, &[0x34, 0x12, 0xCC, 0xED]).await;
+            usb.flush().await;
+        };
+        let receive = async {
+            stream.wait_connection().await;
+            stream.receive(&ring).await.unwrap()
+        };
+
+        let received = match block_on(select(device.run(), join(host, receive))) {
+            Either::First(_) => unreachable!(),
+            Either::Second((_, received)) => received,
+        };
+        assert_eq!(received, 2);
+        let mut out = [0i16; 2];
+        ring.read(&mut out);
+        assert_eq!(out, [0x1234, -0x1234]);
+    }
+}
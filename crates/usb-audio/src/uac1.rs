@@ -0,0 +1,264 @@
+//! USB Audio Class 1.0 descriptors
+//!
+//! Fallback for hosts without UAC2 support. The topology matches the UAC2
+//! one (input terminal, feature unit, output terminal) without the clock
+//! source; UAC1 sets the sample rate on the data endpoint instead, and
+//! lists the rates in the format descriptor.
+
+use crate::descriptor::{
+    channel_config, DescriptorWriter, FEATURE_CHANNELS, FEATURE_UNIT_ID, INPUT_TERMINAL_ID,
+    OUTPUT_TERMINAL_ID,
+};
+use crate::{DescriptorError, Uac2Config};
+
+/// Feedback period as a power of two in ms (`bRefresh`)
+const FEEDBACK_REFRESH: u8 = 1;
+
+/// UAC1 Audio Control Interface descriptor builder
+pub struct Uac1ControlDescriptor {
+    config: Uac2Config,
+}
+
+impl Uac1ControlDescriptor {
+    /// Create a new Audio Control descriptor builder
+    pub fn new(config: Uac2Config) -> Self {
+        Self { config }
+    }
+
+    /// Build the descriptor bytes for an audio function whose streaming
+    /// interface is `streaming_interface`, returning their length
+    pub fn build(&self, buf: &mut [u8], streaming_interface: u8) -> Result<usize, DescriptorError> {
+        let mut w = DescriptorWriter::new(buf);
+
+        // Interface descriptor (Audio Control)
+        let d = w.alloc(9)?;
+        d[1] = 4; // bDescriptorType (Interface)
+        d[2] = 0; // bInterfaceNumber
+        d[3] = 0; // bAlternateSetting
+        d[4] = 0; // bNumEndpoints
+        d[5] = 0x01; // bInterfaceClass (Audio)
+        d[6] = 0x01; // bInterfaceSubClass (Audio Control)
+        d[7] = 0x00; // bInterfaceProtocol (UAC1)
+        d[8] = 0; // iInterface
+
+        // AC Interface Header
+        let header = w.position();
+        let d = w.alloc(9)?;
+        d[1] = 0x24; // bDescriptorType (CS_INTERFACE)
+        d[2] = 0x01; // bDescriptorSubtype (HEADER)
+        d[3] = 0x00; // bcdADC low
+        d[4] = 0x01; // bcdADC high (1.0)
+        d[5] = 0; // wTotalLength low (filled in below)
+        d[6] = 0; // wTotalLength high
+        d[7] = 1; // bInCollection
+        d[8] = streaming_interface; // baInterfaceNr(1)
+
+        // Input Terminal (USB streaming)
+        let channels = self.config.channels;
+        let d = w.alloc(12)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x02; // bDescriptorSubtype (INPUT_TERMINAL)
+        d[3] = INPUT_TERMINAL_ID; // bTerminalID
+        d[4] = 0x01; // wTerminalType low (USB streaming)
+        d[5] = 0x01; // wTerminalType high
+        d[6] = 0; // bAssocTerminal
+        d[7] = channels; // bNrChannels
+        d[8..10].copy_from_slice(&(channel_config(channels) as u16).to_le_bytes()); // wChannelConfig
+        d[10] = 0; // iChannelNames
+        d[11] = 0; // iTerminal
+
+        // Feature Unit: mute and volume on the master and each channel
+        let d = w.alloc(7 + channels as usize + 1)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x06; // bDescriptorSubtype (FEATURE_UNIT)
+        d[3] = FEATURE_UNIT_ID; // bUnitID
+        d[4] = INPUT_TERMINAL_ID; // bSourceID
+        d[5] = 1; // bControlSize
+        for channel in 0..=channels {
+            let controls = if channel <= FEATURE_CHANNELS { 0x03 } else { 0 };
+            d[6 + channel as usize] = controls; // bmaControls
+        }
+        // iFeature left 0
+
+        // Output Terminal (Speaker)
+        let d = w.alloc(9)?;
+        d[1] = 0x24; // bDescriptorType
+        d[2] = 0x03; // bDescriptorSubtype (OUTPUT_TERMINAL)
+        d[3] = OUTPUT_TERMINAL_ID; // bTerminalID
+        d[4] = 0x01; // wTerminalType low (Speaker)
+        d[5] = 0x03; // wTerminalType high
+        d[6] = 0; // bAssocTerminal
+        d[7] = FEATURE_UNIT_ID; // bSourceID
+        d[8] = 0; // iTerminal
+
+        // The header and everything after it
+        w.patch_total_length(header, header + 5)?;
+        Ok(w.position())
+    }
+}
+
+/// UAC1 Audio Streaming Interface descriptor builder
+pub struct Uac1StreamingDescriptor {
+    config: Uac2Config,
+}
+
+impl Uac1StreamingDescriptor {
+    /// Create a new Audio Streaming descriptor builder
+    pub fn new(config: Uac2Config) -> Self {
+        Self { config }
+    }
+
+    /// Build the descriptor bytes for alternate setting 0 (zero bandwidth)
+    pub fn build_alt0(&self, buf: &mut [u8], interface_num: u8) -> Result<usize, DescriptorError> {
+        let mut w = DescriptorWriter::new(buf);
+        let d = w.alloc(9)?;
+        d[1] = 4; // Interface
+        d[2] = interface_num;
+        d[3] = 0; // bAlternateSetting
+        d[4] = 0; // bNumEndpoints
+        d[5] = 0x01; // Audio
+        d[6] = 0x02; // Audio Streaming
+        d[7] = 0x00; // UAC1
+        d[8] = 0;
+        Ok(w.position())
+    }
+
+    /// Build the descriptor bytes for streaming alternate setting
+    /// `alt_setting`, which carries [`Uac2Config::format`]
+    ///
+    /// `ep_addr` is the isochronous OUT data endpoint and `feedback_addr`
+    /// its synch endpoint. Returns 0 if there is no such setting.
+    pub fn build_alt(
+        &self,
+        buf: &mut [u8],
+        interface_num: u8,
+        alt_setting: u8,
+        ep_addr: u8,
+        feedback_addr: u8,
+    ) -> Result<usize, DescriptorError> {
+        let Some(format) = self.config.format(alt_setting) else {
+            return Ok(0);
+        };
+        let mut w = DescriptorWriter::new(buf);
+
+        // Interface descriptor (active)
+        let d = w.alloc(9)?;
+        d[1] = 4;
+        d[2] = interface_num;
+        d[3] = alt_setting; // bAlternateSetting
+        d[4] = 2; // bNumEndpoints (data + feedback)
+        d[5] = 0x01;
+        d[6] = 0x02;
+        d[7] = 0x00;
+        d[8] = 0;
+
+        // AS General descriptor
+        let d = w.alloc(7)?;
+        d[1] = 0x24; // CS_INTERFACE
+        d[2] = 0x01; // AS_GENERAL
+        d[3] = INPUT_TERMINAL_ID; // bTerminalLink
+        d[4] = 1; // bDelay (frames)
+        d[5] = 0x01; // wFormatTag low (PCM)
+        d[6] = 0x00; // wFormatTag high
+
+        // Format Type I descriptor with discrete sample rates
+        let rates = self.config.sample_rates;
+        let d = w.alloc(8 + rates.len() * 3)?;
+        d[1] = 0x24;
+        d[2] = 0x02; // FORMAT_TYPE
+        d[3] = 0x01; // FORMAT_TYPE_I
+        d[4] = self.config.channels; // bNrChannels
+        d[5] = format.subslot_size; // bSubframeSize
+        d[6] = format.bit_resolution; // bBitResolution
+        d[7] = rates.len() as u8; // bSamFreqType
+        for (i, rate) in rates.iter().enumerate() {
+            let at = 8 + i * 3;
+            d[at..at + 3].copy_from_slice(&rate.to_le_bytes()[..3]); // tSamFreq
+        }
+
+        // Endpoint descriptor (audio class version, with bRefresh and
+        // bSynchAddress)
+        let max_packet = self.config.max_packet_size(format);
+        let d = w.alloc(9)?;
+        d[1] = 5; // Endpoint
+        d[2] = ep_addr;
+        d[3] = 0x05; // Isochronous, Async
+        d[4..6].copy_from_slice(&max_packet.to_le_bytes()); // wMaxPacketSize
+        d[6] = 1; // bInterval (1ms)
+        d[7] = 0; // bRefresh
+        d[8] = feedback_addr | 0x80; // bSynchAddress
+
+        // AS Isochronous Audio Data Endpoint descriptor
+        let d = w.alloc(7)?;
+        d[1] = 0x25; // CS_ENDPOINT
+        d[2] = 0x01; // EP_GENERAL
+        d[3] = 0x01; // bmAttributes (sampling frequency control)
+        d[4] = 0; // bLockDelayUnits
+        d[5] = 0; // wLockDelay low
+        d[6] = 0; // wLockDelay high
+
+        // Synch (feedback) endpoint descriptor
+        let d = w.alloc(9)?;
+        d[1] = 5; // Endpoint
+        d[2] = feedback_addr | 0x80; // IN
+        d[3] = 0x11; // Isochronous, No sync, Feedback
+        d[4] = self.config.feedback_format.packet_size() as u8;
+        d[5] = 0;
+        d[6] = 1; // bInterval
+        d[7] = FEEDBACK_REFRESH; // bRefresh
+        d[8] = 0; // bSynchAddress
+
+        Ok(w.position())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{class, parse};
+
+    #[test]
+    fn test_control_lengths() {
+        let mut buf = [0u8; 128];
+        let len = Uac1ControlDescriptor::new(Uac2Config::default())
+            .build(&mut buf, 1)
+            .unwrap();
+        assert_eq!(parse::check(&buf[..len]), Ok(5));
+        // Header, input terminal, feature unit (3 controls), output terminal
+        assert_eq!(len, 9 + 9 + 12 + 10 + 9);
+        assert_eq!(buf[9..18], [9, 0x24, 0x01, 0x00, 0x01, 40, 0, 1, 1]);
+        assert_eq!(buf[30..40], [10, 0x24, 0x06, 4, 1, 1, 3, 3, 3, 0]);
+    }
+
+    #[test]
+    fn test_streaming_alt() {
+        let mut buf = [0u8; 128];
+        let streaming = Uac1StreamingDescriptor::new(Uac2Config::default());
+        let len = streaming.build_alt0(&mut buf, 1).unwrap();
+        assert_eq!(parse::check(&buf[..len]), Ok(1));
+        assert_eq!(
+            buf[5..8],
+            [class::AUDIO, class::AUDIO_STREAMING, class::UAC1_PROTOCOL]
+        );
+
+        let len = streaming.build_alt(&mut buf, 1, 2, 0x01, 0x02).unwrap();
+        let chain = &buf[..len];
+        assert_eq!(
+            parse::check_in(chain, class::AUDIO_STREAMING, class::UAC1_PROTOCOL),
+            Ok(6)
+        );
+        assert_eq!(len, 9 + 7 + 14 + 9 + 7 + 9);
+        // 24-bit in 3 bytes at 44.1 and 48 kHz
+        assert_eq!(
+            buf[16..30],
+            [14, 0x24, 0x02, 0x01, 2, 3, 24, 2, 0x44, 0xAC, 0x00, 0x80, 0xBB, 0x00]
+        );
+        assert_eq!(buf[30..39], [9, 5, 0x01, 0x05, 0x26, 0x01, 1, 0, 0x82]);
+        assert_eq!(buf[46..55], [9, 5, 0x82, 0x11, 3, 0, 1, 1, 0]);
+        assert_eq!(streaming.build_alt(&mut buf, 1, 5, 0x01, 0x02), Ok(0));
+        assert_eq!(
+            streaming.build_alt(&mut buf[..len - 1], 1, 2, 0x01, 0x02),
+            Err(DescriptorError::BufferTooSmall)
+        );
+    }
+}
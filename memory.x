@@ -1,9 +1,21 @@
 MEMORY {
     /*
      * The RP2350 has either external or internal flash.
-     * Pico 2 W has 4 MiB.
+     * Pico 2 W has 4 MiB. The boot stage (crates/a2dp-boot) comes
+     * first; it installs staged updates and starts the firmware.
      */
-    FLASH : ORIGIN = 0x10000000, LENGTH = 4096K
+    BOOT : ORIGIN = 0x10000000, LENGTH = 64K
+    FLASH : ORIGIN = 0x10010000, LENGTH = 1760K
+    /*
+     * Staging area for firmware updates received over USB DFU
+     * (see a2dp_app::update). Must match FLASH in size.
+     */
+    DFU : ORIGIN = 0x101C8000, LENGTH = 1760K
+    /*
+     * Audio prompt bank (see audio_pipeline::PromptBank), programmed
+     * separately from the firmware image.
+     */
+    PROMPTS : ORIGIN = 0x10380000, LENGTH = 512K
     /*
      * RAM consists of 8 banks, SRAM0-SRAM7, with a striped mapping.
      * This is usually good for performance, as it distributes load on
@@ -71,3 +83,6 @@ SECTIONS {
 
 PROVIDE(start_to_end = __end_block_addr - __start_block_addr);
 PROVIDE(end_to_start = __start_block_addr - __end_block_addr);
+
+__prompts_start = ORIGIN(PROMPTS);
+__prompts_end = ORIGIN(PROMPTS) + LENGTH(PROMPTS);
@@ -0,0 +1,37 @@
+#!/usr/bin/env python3
+"""Turn a firmware binary into an image for USB DFU updates.
+
+Usage:
+    dfu_image.py a2dp-source.bin a2dp-source.dfu
+
+Appends the trailer `usb_audio::ImageWriter` checks before staging an
+update: the magic "A2DU", then the binary's length and CRC-32, both
+little-endian. Download the result with:
+
+    dfu-util -d 1209:a2d0 -D a2dp-source.dfu
+"""
+import struct
+import sys
+import zlib
+
+MAGIC = b"A2DU"
+ERASE_SIZE = 4096
+# FLASH and DFU regions in memory.x, less the update record's sector
+CAPACITY = 1760 * 1024 - ERASE_SIZE
+
+
+def main():
+    if len(sys.argv) != 3:
+        sys.exit(__doc__)
+    with open(sys.argv[1], "rb") as f:
+        firmware = f.read()
+    image = firmware + MAGIC + struct.pack("<II", len(firmware), zlib.crc32(firmware))
+    if len(image) > CAPACITY:
+        sys.exit(f"Image is {len(image)} bytes, staging region holds {CAPACITY}")
+    with open(sys.argv[2], "wb") as f:
+        f.write(image)
+    print(f"{sys.argv[2]}: {len(firmware)} bytes, crc32 {zlib.crc32(firmware):08x}")
+
+
+if __name__ == "__main__":
+    main()
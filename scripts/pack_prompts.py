@@ -0,0 +1,112 @@
+#!/usr/bin/env python3
+"""Pack WAV clips into a prompt bank image for the PROMPTS flash region.
+
+Usage:
+    pack_prompts.py prompts.bin 1=pairing.wav 2=connected.wav:adpcm ...
+
+Each argument maps a prompt id (see `a2dp_app::prompts::Prompt`) to a
+16-bit PCM WAV file. Append `:adpcm` to store a mono clip as 4-bit IMA
+ADPCM. Convert the result for flashing with:
+
+    uf2conv.py -b 0x10380000 -f RP2350_ARM_S -c prompts.bin -o prompts.uf2
+"""
+import struct
+import sys
+import wave
+
+MAGIC = b"PRMT"
+VERSION = 1
+HEADER_SIZE = 8
+ENTRY_SIZE = 16
+REGION_SIZE = 512 * 1024
+
+IMA_STEPS = [
+    7, 8, 9, 10, 11, 12, 13, 14, 16, 17, 19, 21, 23, 25, 28, 31, 34, 37, 41, 45, 50, 55, 60, 66,
+    73, 80, 88, 97, 107, 118, 130, 143, 157, 173, 190, 209, 230, 253, 279, 307, 337, 371, 408,
+    449, 494, 544, 598, 658, 724, 796, 876, 963, 1060, 1166, 1282, 1411, 1552, 1707, 1878, 2066,
+    2272, 2499, 2749, 3024, 3327, 3660, 4026, 4428, 4871, 5358, 5894, 6484, 7132, 7845, 8630,
+    9493, 10442, 11487, 12635, 13899, 15289, 16818, 18500, 20350, 22385, 24623, 27086, 29794,
+    32767,
+]
+IMA_INDEX = [-1, -1, -1, -1, 2, 4, 6, 8]
+
+
+def encode_adpcm(samples):
+    """Encode mono samples, mirroring the decoder in audio-pipeline."""
+    predictor = samples[0] if samples else 0
+    index = 0
+    out = bytearray(struct.pack("<hBx", predictor, index))
+    nibbles = []
+    for sample in samples:
+        step = IMA_STEPS[index]
+        diff = sample - predictor
+        code = 8 if diff < 0 else 0
+        diff = abs(diff)
+        delta = step >> 3
+        if diff >= step:
+            code |= 4
+            diff -= step
+            delta += step
+        if diff >= step >> 1:
+            code |= 2
+            diff -= step >> 1
+            delta += step >> 1
+        if diff >= step >> 2:
+            code |= 1
+            delta += step >> 2
+        predictor += -delta if code & 8 else delta
+        predictor = max(-32768, min(32767, predictor))
+        index = max(0, min(len(IMA_STEPS) - 1, index + IMA_INDEX[code & 7]))
+        nibbles.append(code)
+    if len(nibbles) % 2:
+        nibbles.append(0)
+    for low, high in zip(nibbles[::2], nibbles[1::2]):
+        out.append(low | (high << 4))
+    return bytes(out)
+
+
+def load_clip(spec):
+    target, _, codec = spec.partition(":")
+    clip_id, _, path = target.partition("=")
+    with wave.open(path, "rb") as wav:
+        if wav.getsampwidth() != 2:
+            sys.exit(f"{path}: only 16-bit WAV files are supported")
+        channels = wav.getnchannels()
+        rate = wav.getframerate()
+        pcm = wav.readframes(wav.getnframes())
+
+    if codec == "adpcm":
+        if channels != 1:
+            sys.exit(f"{path}: ADPCM clips must be mono")
+        samples = struct.unpack(f"<{len(pcm) // 2}h", pcm)
+        return int(clip_id), 1, channels, rate, encode_adpcm(samples)
+    if channels not in (1, 2):
+        sys.exit(f"{path}: clips must be mono or stereo")
+    return int(clip_id), 0, channels, rate, pcm
+
+
+def main():
+    if len(sys.argv) < 3:
+        sys.exit(__doc__)
+
+    clips = [load_clip(spec) for spec in sys.argv[2:]]
+    table = bytearray()
+    data = bytearray()
+    offset = HEADER_SIZE + ENTRY_SIZE * len(clips)
+    for clip_id, codec, channels, rate, payload in clips:
+        table += struct.pack("<BBBxIII", clip_id, codec, channels, rate,
+                             offset + len(data), len(payload))
+        data += payload
+        # Keep clip data 4-byte aligned
+        data += bytes(-len(data) % 4)
+
+    image = MAGIC + struct.pack("<BBxx", VERSION, len(clips)) + table + data
+    if len(image) > REGION_SIZE:
+        sys.exit(f"Bank is {len(image)} bytes, region holds {REGION_SIZE}")
+    with open(sys.argv[1], "wb") as f:
+        f.write(image)
+    print(f"Wrote {len(clips)} clips, {len(image)} bytes")
+
+
+if __name__ == "__main__":
+    main()